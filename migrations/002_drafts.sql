@@ -0,0 +1,7 @@
+CREATE TABLE draft (
+    user_id UUID NOT NULL REFERENCES "user"(id),
+    article_name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    updated TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY(user_id, article_name)
+);
@@ -0,0 +1,3 @@
+-- Users logging in through OpenID Connect don't have a password
+ALTER TABLE "user" ALTER COLUMN pw_hash DROP NOT NULL;
+ALTER TABLE "user" ADD COLUMN oidc_subject TEXT NULL CONSTRAINT user_oidc_subject_unique UNIQUE;
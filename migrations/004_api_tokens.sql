@@ -0,0 +1,5 @@
+CREATE TABLE api_token (
+    token UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES "user"(id),
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
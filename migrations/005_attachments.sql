@@ -0,0 +1,8 @@
+CREATE TABLE attachment (
+    id UUID PRIMARY KEY,
+    article_id UUID NOT NULL REFERENCES article(id),
+    uploader_id UUID NOT NULL REFERENCES "user"(id),
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
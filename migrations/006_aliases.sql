@@ -0,0 +1,4 @@
+CREATE TABLE alias (
+    name TEXT PRIMARY KEY,
+    article_id UUID NOT NULL REFERENCES article(id)
+);
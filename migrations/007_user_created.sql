@@ -0,0 +1,2 @@
+-- Existing users get the time of the migration, since we don't know better
+ALTER TABLE "user" ADD COLUMN created TIMESTAMP NOT NULL DEFAULT now();
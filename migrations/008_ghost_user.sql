@@ -0,0 +1,2 @@
+-- Stands in for authors whose account is gone or who never had one here
+INSERT INTO "user"(id, name, pw_hash) VALUES ('00000000-0000-0000-0000-000000000000', '[ghost]', NULL);
@@ -0,0 +1,3 @@
+-- Sessions expire some time after logging in, or after being unused for a while
+ALTER TABLE session ADD COLUMN created TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE session ADD COLUMN last_seen TIMESTAMP NOT NULL DEFAULT now();
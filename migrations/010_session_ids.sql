@@ -0,0 +1,3 @@
+-- The session id is the login secret, so sessions get a separate id for
+-- being listed and revoked
+ALTER TABLE session ADD COLUMN id BIGSERIAL NOT NULL CONSTRAINT session_id_unique UNIQUE;
@@ -0,0 +1,6 @@
+CREATE TABLE article_tag (
+    article_id UUID NOT NULL REFERENCES article(id),
+    tag TEXT NOT NULL,
+    PRIMARY KEY(article_id, tag)
+);
+CREATE INDEX article_tag_tag ON article_tag(tag);
@@ -0,0 +1,6 @@
+ALTER TABLE "user" ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE TABLE email_verification (
+    token UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES "user"(id),
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+-- Flags which aren't just on or off, like the featured article
+ALTER TABLE flags ADD COLUMN text_value TEXT NULL;
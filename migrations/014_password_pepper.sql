@@ -0,0 +1,3 @@
+-- Whether the password hash was made with the configured pepper.
+-- Older hashes are upgraded on the next login.
+ALTER TABLE "user" ADD COLUMN pw_peppered BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- When the user last looked at the recent changes, for marking what's new
+ALTER TABLE "user" ADD COLUMN last_visit TIMESTAMP NULL;
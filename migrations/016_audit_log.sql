@@ -0,0 +1,9 @@
+-- What admins did in the settings
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor_id UUID NOT NULL REFERENCES "user"(id),
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX audit_log_created ON audit_log(created);
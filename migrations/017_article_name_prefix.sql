@@ -0,0 +1,3 @@
+-- Lets prefix matches on names (e.g. for autocompletion) use an index,
+-- regardless of the database's collation
+CREATE INDEX article_name_prefix ON article(name text_pattern_ops);
@@ -0,0 +1,5 @@
+-- Words searches treat as the same, e.g. k8s and kubernetes
+CREATE TABLE synonym_group (
+    id SERIAL PRIMARY KEY,
+    terms TEXT[] NOT NULL
+);
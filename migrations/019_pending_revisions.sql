@@ -0,0 +1,8 @@
+-- Edits by untrusted users on moderated wikis, waiting for an admin
+CREATE TABLE pending_revision (
+    id BIGSERIAL PRIMARY KEY,
+    article_name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    author_id UUID NOT NULL REFERENCES "user"(id),
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- Sessions from logging in with "remember me" last this many seconds
+-- instead of the configured limits
+ALTER TABLE session ADD COLUMN lifetime BIGINT NULL;
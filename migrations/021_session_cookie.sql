@@ -0,0 +1,3 @@
+-- When the session cookie was last sent with a new expiry, so cookies of
+-- remembered sessions can be renewed while they're being used
+ALTER TABLE session ADD COLUMN cookie_issued TIMESTAMP NOT NULL DEFAULT now();
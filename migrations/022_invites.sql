@@ -0,0 +1,6 @@
+-- Single-use codes for registering while the wiki is invite-only
+CREATE TABLE invite (
+    code UUID PRIMARY KEY,
+    created_by UUID NOT NULL REFERENCES "user"(id) ON DELETE CASCADE,
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
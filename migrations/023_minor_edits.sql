@@ -0,0 +1,3 @@
+-- Edits marked as minor, which don't count for when an article was last
+-- edited and aren't listed in recent changes
+ALTER TABLE revision ADD COLUMN minor BOOLEAN NOT NULL DEFAULT FALSE;
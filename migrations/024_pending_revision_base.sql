@@ -0,0 +1,13 @@
+-- The revision a queued edit was based on (none for new articles), so
+-- approving it can't overwrite edits made in the meantime, and the rename
+-- and tags submitted with it
+ALTER TABLE pending_revision
+    ADD COLUMN base_rev BIGINT,
+    ADD COLUMN new_name TEXT,
+    ADD COLUMN tags TEXT;
+-- Edits queued before this was tracked are taken as based on what's there now
+UPDATE pending_revision p SET base_rev = (
+    SELECT MAX(r.num) FROM revision r
+    INNER JOIN article a ON a.id = r.article_id
+    WHERE a.name = p.article_name
+);
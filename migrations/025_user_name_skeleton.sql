@@ -0,0 +1,5 @@
+-- What the username looks like (see confusables::skeleton), so registering
+-- only compares against names of about the same length. It's computed in
+-- the application, which fills it for existing users on startup.
+ALTER TABLE "user" ADD COLUMN name_skeleton TEXT;
+CREATE INDEX user_name_skeleton_length ON "user"(char_length(name_skeleton));
@@ -0,0 +1,4 @@
+-- How many revisions each user wrote, kept separately since old revisions
+-- can be pruned (see max_revisions) without the edits stopping to count
+ALTER TABLE "user" ADD COLUMN edit_count BIGINT NOT NULL DEFAULT 0;
+UPDATE "user" u SET edit_count = (SELECT COUNT(*) FROM revision WHERE author_id = u.id);
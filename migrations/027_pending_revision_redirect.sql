@@ -0,0 +1,2 @@
+-- Whether a queued rename leaves a redirect at the old name
+ALTER TABLE pending_revision ADD COLUMN leave_redirect BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,530 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rocket::{
+    catch, get, http::Status, post, put, response::status, serde::json::Json, Catcher, Request,
+    Route, State,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    articles::{self, redirect_content, InvalidTitle},
+    client_ip::ClientIp,
+    db::{
+        self,
+        articles::{ArticleInfo, RevId},
+        users::ApiUser,
+        Db, Txn,
+    },
+    diff::{self, Hunk},
+    error::GuardError,
+    search::{without_stop_words, SearchResult},
+    ArticleIndex, Cache, Config, Error, IdenticalSaves, Result,
+};
+
+pub fn routes() -> Vec<Route> {
+    rocket::routes![
+        article_diff,
+        article_info,
+        complete_article_name,
+        bulk_search,
+        tags,
+        link_graph,
+        save_article,
+        move_article
+    ]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    rocket::catchers![api_error]
+}
+
+/// Errors which don't come from our own `Error`, like unknown routes or
+/// a `None` from a route, in the same format.
+#[catch(default)]
+fn api_error(status: Status, request: &Request<'_>) -> Json<serde_json::Value> {
+    let GuardError(message) = request.local_cache(|| GuardError(None));
+    Json(serde_json::json! {{
+        "status": status.code,
+        "error": message.as_deref().or_else(|| status.reason()).unwrap_or("Unknown error"),
+    }})
+}
+
+#[derive(Serialize)]
+struct ArticleDiff {
+    from: i64,
+    to: i64,
+    hunks: Vec<Hunk>,
+}
+
+#[get("/articles/<article_name>/diff?<from>&<to>")]
+async fn article_diff(
+    db: &State<Db>,
+    article_name: String,
+    from: i64,
+    to: i64,
+) -> Result<Option<Json<ArticleDiff>>> {
+    let old = db::articles::get_revision(db, &article_name, from).await?;
+    let new = db::articles::get_revision(db, &article_name, to).await?;
+    // If either revision doesn't exist, this will 404
+    Ok(old.zip(new).map(|(old, new)| {
+        Json(ArticleDiff {
+            from,
+            to,
+            hunks: diff::hunks(&old.content, &new.content),
+        })
+    }))
+}
+
+#[get("/articles/<article_name>/info")]
+async fn article_info(db: &State<Db>, article_name: String) -> Result<Option<Json<ArticleInfo>>> {
+    Ok(db::articles::info(db, &article_name).await?.map(Json))
+}
+
+/// How many names the autocompletion returns at most.
+const COMPLETION_LIMIT: i64 = 10;
+
+/// Article names starting with `q`, for completing links while editing.
+/// Unlike the search, this only looks at the names.
+#[get("/articles/complete?<q>")]
+async fn complete_article_name(db: &State<Db>, q: &str) -> Result<Json<Vec<String>>> {
+    if q.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+    Ok(Json(
+        db::articles::names_starting_with(db, q, COMPLETION_LIMIT).await?,
+    ))
+}
+
+/// How many queries one bulk search may contain.
+const MAX_BULK_QUERIES: usize = 20;
+
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+pub struct BulkSearchRequest {
+    pub queries: Vec<String>,
+    /// How many results to return per query at most.
+    pub limit: Option<usize>,
+}
+#[derive(Serialize)]
+struct QueryResults {
+    query: String,
+    results: Vec<SearchResult>,
+}
+
+/// Searches for several queries at once, returning their results in the
+/// same order. Every query counts against the search rate limit.
+#[post("/search", data = "<request>")]
+async fn bulk_search(
+    cfg: &State<Config>,
+    cache: &State<Cache>,
+    index: &State<ArticleIndex>,
+    client_ip: ClientIp,
+    user: Option<ApiUser>,
+    request: Json<BulkSearchRequest>,
+) -> Result<Json<Vec<QueryResults>>> {
+    let BulkSearchRequest { queries, limit } = request.into_inner();
+    if queries.len() > MAX_BULK_QUERIES {
+        return Err(Error::TooManySearchQueries(MAX_BULK_QUERIES));
+    }
+    let rate_limit = match user {
+        Some(_) => cfg.search_rate_limit_logged_in,
+        None => cfg.search_rate_limit,
+    };
+    let mut results = Vec::with_capacity(queries.len());
+    for query in queries {
+        if let (Some(ip), true) = (client_ip.0, rate_limit > 0) {
+            let window = Duration::from_secs(cfg.search_rate_window.into());
+            if !cache.count_search(ip, rate_limit, window) {
+                return Err(Error::SearchRateLimited(cfg.search_rate_window));
+            }
+        }
+        let text = without_stop_words(&query, &cfg.search_stop_words);
+        let mut found = if text.trim().is_empty() {
+            Vec::new()
+        } else {
+            index.search_by_text(
+                &text,
+                None,
+                cfg.search_default_sort,
+                cfg.search_snippets,
+                &cfg.search_highlight_tag,
+            )?
+        };
+        if let Some(limit) = limit {
+            found.truncate(limit);
+        }
+        results.push(QueryResults {
+            query,
+            results: found,
+        });
+    }
+    Ok(Json(results))
+}
+
+#[derive(Serialize)]
+struct TagWithCount {
+    tag: String,
+    article_count: i64,
+}
+
+/// All tags, the most used first, e.g. for building a tag cloud.
+#[get("/tags")]
+async fn tags(db: &State<Db>) -> Result<Json<Vec<TagWithCount>>> {
+    let tags = db::tags::by_count(db).await?;
+    Ok(Json(
+        tags.into_iter()
+            .map(|tag| TagWithCount {
+                tag: tag.tag,
+                article_count: tag.articles,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Serialize)]
+struct GraphNode {
+    name: String,
+    is_redirect: bool,
+    /// How many articles this one links to.
+    links_out: usize,
+    /// How many articles link to this one.
+    links_in: usize,
+}
+#[derive(Serialize)]
+struct GraphEdge {
+    from: String,
+    to: String,
+}
+#[derive(Serialize)]
+struct LinkGraph {
+    node_count: usize,
+    edge_count: usize,
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+/// Every article and the links between them, for visualizing the wiki.
+/// Links to articles which don't exist are left out; so are redirects with
+/// `exclude_redirects`. Only for admins.
+#[get("/linkgraph?<exclude_redirects>")]
+async fn link_graph(
+    db: &State<Db>,
+    exclude_redirects: Option<bool>,
+    user: ApiUser,
+) -> Result<Json<LinkGraph>> {
+    if !db::users::is_admin(db, user.user_id).await? {
+        return Err(Error::AdminRequired);
+    }
+    let exclude_redirects = exclude_redirects.unwrap_or(false);
+    let mut pages = db.list_articles().await?;
+    pages.sort_by(|a, b| a.name.cmp(&b.name));
+    let redirects = pages
+        .iter()
+        .filter(|article| articles::redirect_target(&article.content).is_some())
+        .map(|article| article.name.as_str())
+        .collect::<HashSet<_>>();
+    let mut nodes = pages
+        .iter()
+        .filter(|article| !exclude_redirects || !redirects.contains(article.name.as_str()))
+        .map(|article| {
+            let node = GraphNode {
+                name: article.name.clone(),
+                is_redirect: redirects.contains(article.name.as_str()),
+                links_out: 0,
+                links_in: 0,
+            };
+            (article.name.as_str(), node)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut edges = Vec::new();
+    for article in &pages {
+        if !nodes.contains_key(article.name.as_str()) {
+            continue;
+        }
+        for target in articles::internal_links(&article.content) {
+            let target = db::articles::normalize_name(&target);
+            if target == article.name || !nodes.contains_key(target.as_str()) {
+                continue;
+            }
+            nodes.get_mut(target.as_str()).unwrap().links_in += 1;
+            nodes.get_mut(article.name.as_str()).unwrap().links_out += 1;
+            edges.push(GraphEdge {
+                from: article.name.clone(),
+                to: target,
+            });
+        }
+    }
+    Ok(Json(LinkGraph {
+        node_count: nodes.len(),
+        edge_count: edges.len(),
+        nodes: nodes.into_values().collect(),
+        edges,
+    }))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+pub struct SaveRequest {
+    pub content: String,
+}
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Warning {
+    /// The content links to an article which doesn't exist.
+    BrokenLink { target: String },
+}
+#[derive(Serialize)]
+struct SaveResponse {
+    name: String,
+    /// Missing while the edit waits for approval.
+    rev_id: Option<i64>,
+    warnings: Vec<Warning>,
+}
+
+/// Creates an article or adds a new revision to it.
+/// With `check_links`, links to missing articles are reported as warnings;
+/// the article is saved either way.
+/// Edits needing approval (see `Config::moderate_edits`) are queued, which
+/// is answered with 202.
+#[put("/articles/<article_name>?<check_links>", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+async fn save_article(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    cache: &State<Cache>,
+    search_index: &State<ArticleIndex>,
+    article_name: String,
+    check_links: Option<bool>,
+    request: Json<SaveRequest>,
+    user: ApiUser,
+    mut txn: Txn,
+) -> Result<status::Custom<Json<SaveResponse>>> {
+    if cfg.edit_requires_verified_email && !db::users::email_verified(db, user.user_id).await? {
+        return Err(Error::EmailNotVerified);
+    }
+    let content = request.into_inner().content;
+    let name = db::articles::normalize_name(&article_name);
+    let is_admin = db::users::is_admin(db, user.user_id).await?;
+    if name.starts_with(db::articles::TEMPLATE_PREFIX) && !is_admin {
+        return Err(Error::TemplateEditRequiresAdmin);
+    }
+    let redirect = articles::redirect_target(&content)
+        .filter(|_| cfg.check_redirects)
+        .map(db::articles::normalize_name);
+    if redirect.as_deref() == Some(&name) {
+        return Err(Error::RedirectToItself(name));
+    }
+    let article_id = db::articles::id_by_name(&mut txn, &name).await?;
+    let latest = match article_id {
+        Some(article_id) => db::articles::latest_revision(&mut txn, article_id).await?,
+        None => None,
+    };
+    let old_content = latest.as_ref().map(|rev| rev.content.as_str());
+    let unchanged = old_content == Some(content.as_str());
+    if article_id.is_none() {
+        check_title(cfg, &name)?;
+        articles::check_creation_cooldown(db, cfg, user.user_id, is_admin).await?;
+    }
+    if !unchanged {
+        articles::check_comment(cfg, &name, old_content, &content)?;
+    }
+    // Untrusted users' edits wait for an admin, like in the editor
+    if !unchanged && articles::needs_approval(db, cfg, user.user_id, is_admin).await? {
+        let submission = db::pending::Submission {
+            article_name: &name,
+            content: &content,
+            author_id: user.user_id,
+            base_rev: latest.as_ref().map(|rev| rev.num),
+            new_name: None,
+            tags: None,
+            leave_redirect: false,
+        };
+        db::pending::submit(&mut txn, submission).await?;
+        articles::count_comment(cfg, cache, user.user_id, &name)?;
+        txn.commit().await?;
+        let warnings = broken_link_warnings(db, &content, check_links).await?;
+        return Ok(status::Custom(
+            Status::Accepted,
+            Json(SaveResponse {
+                name,
+                rev_id: None,
+                warnings,
+            }),
+        ));
+    }
+    // The article only has to be reindexed if something changed
+    let (rev_id, changed) = match (article_id, latest.filter(|rev| rev.content == content)) {
+        (Some(_), Some(latest)) => match cfg.identical_saves {
+            IdenticalSaves::Ignore => (latest.num, None),
+            IdenticalSaves::Reject => return Err(Error::IdenticalNewRevision),
+        },
+        (Some(article_id), None) => {
+            let (db::articles::RevId(_, rev_id), rev) = db::articles::add_revision(
+                &mut txn,
+                article_id,
+                user.user_id,
+                &content,
+                false,
+                cfg.max_revisions,
+            )
+            .await?;
+            (rev_id, Some((article_id, rev.date)))
+        }
+        (None, _) => {
+            let (db::articles::RevId(article_id, rev_id), rev) =
+                db::articles::create(&mut txn, &name, &content, user.user_id).await?;
+            (rev_id, Some((article_id, rev.date)))
+        }
+    };
+    if changed.is_some() {
+        articles::count_comment(cfg, cache, user.user_id, &name)?;
+    }
+    txn.commit().await?;
+    if let Some((article_id, date)) = changed {
+        let tags = db::tags::for_article(db, &name).await?;
+        search_index.add_or_update_article(article_id, &name, &content, &tags, date)?;
+    }
+
+    let warnings = broken_link_warnings(db, &content, check_links).await?;
+    Ok(status::Custom(
+        Status::Ok,
+        Json(SaveResponse {
+            name,
+            rev_id: Some(rev_id),
+            warnings,
+        }),
+    ))
+}
+
+/// The links to missing articles, if they were asked for.
+async fn broken_link_warnings(
+    db: &Db,
+    content: &str,
+    check_links: Option<bool>,
+) -> Result<Vec<Warning>> {
+    if !check_links.unwrap_or(false) {
+        return Ok(Vec::new());
+    }
+    let links = articles::internal_links(content);
+    let existing = db::articles::existing_names(db, &links).await?;
+    Ok(links
+        .into_iter()
+        .filter(|link| !existing.contains(&db::articles::normalize_name(link)))
+        .map(|target| Warning::BrokenLink { target })
+        .collect())
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+pub struct MoveRequest {
+    pub new_name: String,
+    /// Leave a page at the old name redirecting to the new one.
+    #[serde(default)]
+    pub leave_redirect: bool,
+}
+#[derive(Serialize)]
+struct MoveResponse {
+    name: String,
+}
+
+/// Fails with the reason the web form would give for not accepting the title.
+fn check_title(cfg: &Config, name: &str) -> Result<()> {
+    match articles::check_title(cfg, name) {
+        Some(InvalidTitle::TooLong) => Err(Error::ArticleTitleTooLong(cfg.max_title_length)),
+        Some(InvalidTitle::Reserved) => Err(Error::ArticleTitleReserved(name.to_owned())),
+        Some(InvalidTitle::Blocked) => Err(Error::ArticleTitleBlocked(name.to_owned())),
+        None => Ok(()),
+    }
+}
+
+/// Renames an article.
+/// Returns 404 if the article doesn't exist and 409 if the new name is taken.
+/// Renames needing approval are queued like edits, which is answered with
+/// 202 and the name the article still has.
+#[post("/articles/<article_name>/move", data = "<request>")]
+async fn move_article(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    article_name: String,
+    request: Json<MoveRequest>,
+    user: ApiUser,
+    mut txn: Txn,
+) -> Result<Option<status::Custom<Json<MoveResponse>>>> {
+    let MoveRequest {
+        new_name,
+        leave_redirect,
+    } = request.into_inner();
+    let new_name = db::articles::normalize_name(&new_name);
+    check_title(cfg, &new_name)?;
+    let is_admin = db::users::is_admin(db, user.user_id).await?;
+    let is_template = |name: &str| name.starts_with(db::articles::TEMPLATE_PREFIX);
+    if (is_template(&article_name) || is_template(&new_name)) && !is_admin {
+        return Err(Error::TemplateEditRequiresAdmin);
+    }
+    let article_id = match db::articles::id_by_name(&mut txn, &article_name).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    // Whether the new name is free is only checked on approval
+    if articles::needs_approval(db, cfg, user.user_id, is_admin).await? {
+        let latest = db::articles::latest_revision(&mut txn, article_id)
+            .await?
+            .ok_or(Error::ArticleDataInconsistent(article_id))?;
+        let article_name = db::articles::normalize_name(&article_name);
+        let submission = db::pending::Submission {
+            article_name: &article_name,
+            content: &latest.content,
+            author_id: user.user_id,
+            base_rev: Some(latest.num),
+            new_name: Some(&new_name),
+            tags: None,
+            leave_redirect,
+        };
+        db::pending::submit(&mut txn, submission).await?;
+        txn.commit().await?;
+        return Ok(Some(status::Custom(
+            Status::Accepted,
+            Json(MoveResponse { name: article_name }),
+        )));
+    }
+    let res = db::articles::change_name(&mut txn, article_id, &new_name).await;
+    if let Err(Error::SqlxError(sqlx::Error::Database(err))) = &res {
+        if err.constraint() == Some("article_name_unique") {
+            return Err(Error::DuplicateArticleName(new_name));
+        }
+    }
+    res?;
+    let mut redirect = None;
+    if leave_redirect {
+        let content = redirect_content(&new_name);
+        let (RevId(redirect_id, _), rev) =
+            db::articles::create(&mut txn, &article_name, &content, user.user_id).await?;
+        redirect = Some((redirect_id, content, rev.date));
+    }
+    txn.commit().await?;
+
+    // The index needs the content again, since it replaces the whole document
+    let rev = db
+        .get_current_rev(&new_name)
+        .await?
+        .ok_or(Error::ArticleDataInconsistent(article_id))?;
+    search_index.add_or_update_article(
+        article_id,
+        &new_name,
+        &rev.content,
+        &db::tags::for_article(db, &new_name).await?,
+        DateTime::from_utc(rev.created, Utc),
+    )?;
+    if let Some((redirect_id, content, date)) = redirect {
+        search_index.add_or_update_article(redirect_id, &article_name, &content, &[], date)?;
+    }
+    Ok(Some(status::Custom(
+        Status::Ok,
+        Json(MoveResponse { name: new_name }),
+    )))
+}
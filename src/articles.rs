@@ -1,37 +1,63 @@
-use chrono::{DateTime, Utc};
-use pulldown_cmark::{html, BrokenLink, Options, Parser};
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use pulldown_cmark::{html, BrokenLink, CowStr, Event, Options, Parser, Tag};
 use rocket::{
     form::Form,
     get,
-    http::Status,
+    http::{uri::Origin, ContentType, Header, RawStr, Status},
     post,
-    response::{status, Redirect},
-    FromForm, Route, State,
+    response::{status, Redirect, Responder},
+    uri, FromForm, Route, State,
 };
 use rocket_dyn_templates::Template;
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::{
+    attachments,
+    cache::{CachedSearch, SearchKey},
+    client_ip::ClientIp,
     db::{
         self,
-        articles::{DisplayRevision, RevId},
-        users::{LoggedUser, UserSession},
-        Db,
+        articles::{DisplayRevision, Editor, RecentChange, RevId, RevisionOrder},
+        audit::AuditAction,
+        users::{LoggedAdmin, LoggedUser, UserSession},
+        Db, Txn,
+    },
+    details::{self, Block},
+    diff,
+    pagination::Page,
+    search::{
+        matches_pattern, without_stop_words, EmptySearch, SearchResult, SearchSort, MAIN_NAMESPACE,
     },
-    ArticleIndex, Config, Error, Result,
+    transclusion, ArticleIndex, Cache, Config, Error, IdenticalSaves, Result,
 };
 
 pub fn routes() -> Vec<Route> {
     rocket::routes![
         search,
         create,
+        wanted,
         get,
         edit_page,
         edit_form,
+        save_draft,
+        discard_draft,
+        draft_diff,
+        preview_diff,
         redirect_to_login_get,
         redirect_to_login_post,
         revs,
-        rev
+        revs_feed,
+        rev,
+        download_rev,
+        compare,
+        undo,
+        redact
     ]
 }
 
@@ -49,25 +75,334 @@ fn render_404(
     status::Custom(Status::NotFound, Template::render("article_404", context))
 }
 
-fn markdown_to_html(input: &str) -> String {
-    let callback = &mut |broken_link: BrokenLink| {
-        Some((
-            ("/".to_string() + broken_link.reference).into(),
-            broken_link.reference.to_owned().into(),
-        ))
+/// Redirect pages consist of a line like `#REDIRECT [Target]`.
+/// Returns the target if the given content is a redirect.
+pub(crate) fn redirect_target(content: &str) -> Option<&str> {
+    let content = content.trim_start();
+    if !content.get(..9)?.eq_ignore_ascii_case("#redirect") {
+        return None;
+    }
+    let target = content[9..].trim_start().strip_prefix('[')?;
+    let target = target[..target.find(']')?].trim();
+    if target.is_empty() {
+        None
+    } else {
+        Some(target)
+    }
+}
+/// Creates the content of a page redirecting to the given target.
+pub(crate) fn redirect_content(target: &str) -> String {
+    format!("#REDIRECT [{}]", target)
+}
+
+/// Points images with a `file:` link to the uploaded attachment.
+fn resolve_attachment(tag: Tag) -> Tag {
+    match tag {
+        Tag::Image(kind, dest, title) => match attachments::resolve_link(&dest) {
+            Some(url) => Tag::Image(kind, url.into(), title),
+            None => Tag::Image(kind, dest, title),
+        },
+        _ => tag,
+    }
+}
+
+/// Whether the link leaves the wiki, i.e. has a scheme (other than `file:`
+/// for attachments) or is protocol-relative.
+fn is_external_link(dest: &str) -> bool {
+    if dest.starts_with("//") {
+        return true;
+    }
+    let scheme = match dest.find(':') {
+        Some(index) => &dest[..index],
+        None => return false,
+    };
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        && !scheme.eq_ignore_ascii_case("file")
+}
+
+/// Whether the link's scheme (if it has one) is allowed by the config.
+/// Browsers ignore whitespace and control characters in schemes, so e.g.
+/// `java\tscript:` counts as `javascript:` here as well.
+fn allowed_scheme(dest: &str, cfg: &Config) -> bool {
+    let scheme = match dest.find([':', '/', '?', '#']) {
+        Some(index) if dest[index..].starts_with(':') => &dest[..index],
+        _ => return true,
     };
+    let scheme = scheme
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>();
+    scheme.eq_ignore_ascii_case("file")
+        || cfg
+            .allowed_link_schemes
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&scheme))
+}
+
+/// Renders the opening tag of an external link, since pulldown-cmark can't
+/// add attributes to the ones it renders.
+fn external_link_start(dest: &str, title: &str, cfg: &Config) -> String {
+    use pulldown_cmark::escape::{escape_href, escape_html};
+
+    // Writing to a String can't fail
+    let mut html = String::from("<a href=\"");
+    escape_href(&mut html, dest).unwrap();
+    html.push('"');
+    if !title.is_empty() {
+        html.push_str(" title=\"");
+        escape_html(&mut html, title).unwrap();
+        html.push('"');
+    }
+    if !cfg.external_link_rel.is_empty() {
+        html.push_str(" rel=\"");
+        escape_html(&mut html, &cfg.external_link_rel).unwrap();
+        html.push('"');
+    }
+    if cfg.external_links_new_tab {
+        html.push_str(" target=\"_blank\"");
+    }
+    html.push('>');
+    html
+}
+
+/// How article content is rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArticleFormat {
+    #[default]
+    Markdown,
+    /// Shown as written, keeping its line breaks.
+    Plain,
+}
+
+/// Renders an article's content in the configured format.
+pub(crate) fn render_content(input: &str, cfg: &Config) -> String {
+    use pulldown_cmark::escape::escape_html;
+
+    match cfg.article_format {
+        ArticleFormat::Markdown => markdown_to_html(input, cfg),
+        ArticleFormat::Plain => {
+            let mut output =
+                String::from("<div class=\"plain-text\" style=\"white-space: pre-wrap\">");
+            escape_html(&mut output, input).unwrap();
+            output.push_str("</div>\n");
+            output
+        }
+    }
+}
+
+fn markdown_to_html(input: &str, cfg: &Config) -> String {
+    let mut output = String::new();
+    let mut ids = HeadingIds::default();
+    push_blocks(&mut output, &details::parse(input), &mut ids, cfg);
+    output
+}
+
+/// Renders collapsible sections as `<details>`, with their body rendered
+/// like any other Markdown.
+fn push_blocks(output: &mut String, blocks: &[Block], ids: &mut HeadingIds, cfg: &Config) {
+    use pulldown_cmark::escape::escape_html;
+
+    for block in blocks {
+        match block {
+            Block::Markdown(text) => push_markdown(output, text, ids, cfg),
+            Block::Details { summary, body } => {
+                output.push_str("<details>\n");
+                if !summary.is_empty() {
+                    output.push_str("<summary>");
+                    escape_html(&mut *output, summary).unwrap();
+                    output.push_str("</summary>\n");
+                }
+                push_blocks(output, body, ids, cfg);
+                output.push_str("</details>\n");
+            }
+        }
+    }
+}
+
+/// Turns `[Name]` into a link to the article with that name.
+fn wiki_link<'a>(broken_link: BrokenLink<'_>) -> Option<(CowStr<'a>, CowStr<'a>)> {
+    Some((
+        ("/".to_string() + broken_link.reference).into(),
+        broken_link.reference.to_owned().into(),
+    ))
+}
+
+/// A section of an article, for jumping to it.
+#[derive(Debug, serde::Serialize)]
+struct Heading {
+    level: u32,
+    text: String,
+    id: String,
+}
+
+/// The text of the heading whose start is right before the events.
+fn heading_text(events: &[Event]) -> String {
+    events
+        .iter()
+        .take_while(|ev| !matches!(ev, Event::End(Tag::Heading(_))))
+        .filter_map(|ev| match ev {
+            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The id of a heading's element, made from its words.
+fn heading_id(text: &str) -> String {
+    let id = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    if id.is_empty() {
+        "section".into()
+    } else {
+        id
+    }
+}
+
+/// Hands out the ids of an article's headings in order, numbering those
+/// whose text came up before, like `usage-2`.
+#[derive(Default)]
+struct HeadingIds(HashSet<String>);
+impl HeadingIds {
+    fn next(&mut self, text: &str) -> String {
+        let base = heading_id(text);
+        let mut id = base.clone();
+        let mut number = 2;
+        while !self.0.insert(id.clone()) {
+            id = format!("{}-{}", base, number);
+            number += 1;
+        }
+        id
+    }
+}
+
+/// Every heading in the content, including those in collapsible sections.
+fn headings(input: &str) -> Vec<Heading> {
+    let flat = details::flatten(input);
+    let callback = &mut wiki_link;
+    let events = Parser::new_with_broken_link_callback(&flat, Options::all(), Some(callback))
+        .collect::<Vec<_>>();
+    let mut ids = HeadingIds::default();
+    events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, ev)| match ev {
+            Event::Start(Tag::Heading(level)) => {
+                let text = heading_text(&events[i + 1..]);
+                Some(Heading {
+                    level: *level,
+                    id: ids.next(&text),
+                    text,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn push_markdown(output: &mut String, input: &str, ids: &mut HeadingIds, cfg: &Config) {
+    let callback = &mut wiki_link;
     let parser =
         Parser::new_with_broken_link_callback(input, Options::all(), Some(callback)).map(|ev| {
             match ev {
-                pulldown_cmark::Event::SoftBreak => pulldown_cmark::Event::HardBreak,
+                Event::SoftBreak => Event::HardBreak,
+                // Only the article's own HTML, external links become HTML below
+                Event::Html(html) if !cfg.allow_html => Event::Text(html),
+                Event::Start(Tag::Link(_, dest, _))
+                    if transclusion::placeholder_link(&dest).is_some() =>
+                {
+                    let (class, href) = transclusion::placeholder_link(&dest).unwrap();
+                    Event::Html(transclusion::placeholder_link_start(class, href).into())
+                }
+                Event::End(Tag::Link(_, dest, _))
+                    if transclusion::placeholder_link(&dest).is_some() =>
+                {
+                    Event::Html("</a>".into())
+                }
+                Event::Start(Tag::Link(kind, dest, title)) if !allowed_scheme(&dest, cfg) => {
+                    Event::Start(Tag::Link(kind, "#".into(), title))
+                }
+                Event::End(Tag::Link(kind, dest, title)) if !allowed_scheme(&dest, cfg) => {
+                    Event::End(Tag::Link(kind, "#".into(), title))
+                }
+                Event::Start(Tag::Image(kind, dest, title)) if !allowed_scheme(&dest, cfg) => {
+                    Event::Start(Tag::Image(kind, "".into(), title))
+                }
+                Event::Start(Tag::Link(_, dest, title)) if is_external_link(&dest) => {
+                    Event::Html(external_link_start(&dest, &title, cfg).into())
+                }
+                Event::End(Tag::Link(_, dest, _)) if is_external_link(&dest) => {
+                    Event::Html("</a>".into())
+                }
+                Event::Start(tag) => Event::Start(resolve_attachment(tag)),
+                Event::End(tag) => Event::End(resolve_attachment(tag)),
                 _ => ev,
             }
         });
-    let mut output = String::new();
-    html::push_html(&mut output, parser);
-    output
+    let mut events = parser.collect::<Vec<_>>();
+    // Give headings an id, so they can be linked to
+    let heading_ids = events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, ev)| match ev {
+            Event::Start(Tag::Heading(level)) => {
+                Some((i, *level, ids.next(&heading_text(&events[i + 1..]))))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (i, level, id) in heading_ids {
+        events[i] = Event::Html(format!("<h{} id=\"{}\">", level, id).into());
+    }
+    html::push_html(output, events.into_iter());
+}
+
+/// Returns the names of the articles the content links to, each only once.
+/// Wiki links like `[Name]` count as well; external links don't.
+pub(crate) fn internal_links(input: &str) -> Vec<String> {
+    let callback = &mut wiki_link;
+    let mut links = Vec::new();
+    for event in Parser::new_with_broken_link_callback(input, Options::all(), Some(callback)) {
+        if let Event::Start(Tag::Link(_, dest, _)) = event {
+            if let Some(name) = link_target(&dest) {
+                if !links.contains(&name) {
+                    links.push(name);
+                }
+            }
+        }
+    }
+    links
+}
+
+/// Returns the article a link points to, if it points to one.
+fn link_target(dest: &str) -> Option<String> {
+    let path = dest.split(['#', '?']).next()?;
+    // Urls with a scheme (including `file:` attachments) are never articles
+    let name = match path.strip_prefix('/') {
+        Some(name) => name,
+        None if path.contains(':') => return None,
+        None => path,
+    };
+    // Other routes like `/u/...` or `/<article>/revs` aren't articles either
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some(RawStr::new(name).percent_decode_lossy().into_owned())
 }
 
+/// How many related articles are shown below an article.
+const SIMILAR_ARTICLES: usize = 5;
+const LANDING_RECENT_CHANGES: i64 = 10;
+const SEARCH_RESULTS_PER_PAGE: usize = 10;
+const REVISIONS_PER_PAGE: usize = 50;
+
 /// Context used to render an existing article revision.
 #[derive(serde::Serialize)]
 struct RevContext<'a> {
@@ -80,29 +415,267 @@ struct RevContext<'a> {
     author: String,
     date: DateTime<Utc>,
     specific_rev: bool,
+    /// The short hash of the (not yet expanded) content.
+    content_hash: String,
+    /// Used for linking old revisions to a comparison with the current one.
+    current_rev_id: i64,
+    /// The revision undoing this one goes back to; only set when viewing a
+    /// specific revision.
+    previous_rev_id: Option<i64>,
+    /// Related articles; only shown for the current revision.
+    similar: Vec<SearchResult>,
+    /// The authors with the most edits; only shown for the current revision.
+    editors: Vec<Editor>,
+    tags: Vec<String>,
+    /// Whether the rendered content is over the configured size.
+    long_article: bool,
+    /// The sections to jump to; only listed for long articles.
+    sections: Vec<Heading>,
+}
+
+#[derive(Responder)]
+#[allow(clippy::large_enum_variant)]
+enum ArticleResponse {
+    Page(status::Custom<Template>),
+    Redirect(Redirect),
 }
 
-#[get("/search?<q>", rank = 0)]
-fn search(
+/// `ns` scopes the search to one namespace; leaving it out or empty searches
+/// all of them. `sort` defaults to the configured order.
+#[get("/search?<q>&<page>&<ns>&<sort>", rank = 0)]
+#[allow(clippy::too_many_arguments)]
+async fn search(
     cfg: &State<Config>,
+    db: &State<Db>,
+    cache: &State<Cache>,
     index: &State<ArticleIndex>,
+    client_ip: ClientIp,
     user: Option<LoggedUser>,
     q: String,
+    page: Option<usize>,
+    ns: Option<String>,
+    sort: Option<SearchSort>,
 ) -> Result<Template> {
-    let results = index.search_by_text(&q)?;
-    let exact_match = results.iter().any(|r| r.title == q);
+    let limit = match user {
+        Some(_) => cfg.search_rate_limit_logged_in,
+        None => cfg.search_rate_limit,
+    };
+    if let (Some(ip), true) = (client_ip.0, limit > 0) {
+        let window = Duration::from_secs(cfg.search_rate_window.into());
+        if !cache.count_search(ip, limit, window) {
+            return Err(Error::SearchRateLimited(cfg.search_rate_window));
+        }
+    }
+    let key = SearchKey {
+        query: q.split_whitespace().collect::<Vec<_>>().join(" "),
+        namespace: ns.filter(|ns| !ns.is_empty()),
+        sort: sort.unwrap_or(cfg.search_default_sort),
+        page: page.unwrap_or(1).max(1),
+        generation: index.generation(),
+    };
+    let namespace = key.namespace.clone().unwrap_or_default();
+    let query = key.query.clone();
+    let sort = key.sort;
+    // Without an index, the rest of the page still works
+    let search_unavailable = !index.is_ready();
+    // There's nothing to search for, so this lists articles instead
+    let empty_query = query.is_empty();
+    // Short queries, or ones of only stop words, would match almost anything
+    let searched = without_stop_words(&query, &cfg.search_stop_words);
+    let too_short = !empty_query
+        && (searched.is_empty() || searched.chars().count() < cfg.search_min_query_length);
+    let CachedSearch {
+        exact_match,
+        results,
+    } = if empty_query {
+        // The namespace is only known here, so the limit has to wait for it
+        let limit = match (cfg.empty_search, &key.namespace) {
+            (EmptySearch::Recent, None) => Some(SEARCH_RESULTS_PER_PAGE as i64),
+            _ => None,
+        };
+        let mut articles = db::articles::recently_edited(db, limit)
+            .await?
+            .into_iter()
+            .filter(|name| !index.is_excluded(name))
+            .filter(|name| match &key.namespace {
+                Some(ns) => crate::search::namespace(name) == ns,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        if cfg.empty_search == EmptySearch::Recent {
+            articles.truncate(SEARCH_RESULTS_PER_PAGE);
+        }
+        // Without a query nothing is more relevant, so that's by name
+        if sort == SearchSort::Relevance {
+            articles.sort();
+        }
+        CachedSearch {
+            exact_match: true,
+            results: json!(Page::from_items(
+                articles,
+                key.page,
+                SEARCH_RESULTS_PER_PAGE
+            )),
+        }
+    } else if too_short || search_unavailable {
+        CachedSearch {
+            exact_match: db.article_id_by_name(&key.query).await?.is_some(),
+            results: json!(Page::<SearchResult>::from_items(
+                Vec::new(),
+                1,
+                SEARCH_RESULTS_PER_PAGE
+            )),
+        }
+    } else {
+        search_page(cfg, cache, index, key)?
+    };
     let context = json! {{
+        "search_unavailable": search_unavailable,
+        "empty_query": empty_query,
+        "too_short": too_short,
         "site_name": &cfg.site_name,
         "default_path": &cfg.default_path,
         "exact_match": exact_match,
-        "results": index.search_by_text(&q)?,
+        "results": results,
         "page_name": "Search",
         "user": user,
-        "query": q,
+        "query": query,
+        "namespace": namespace,
+        "sort": sort,
+        "namespaces": db::articles::namespaces(db).await?,
+        "main_namespace": MAIN_NAMESPACE,
     }};
     Ok(Template::render("search", context))
 }
 
+/// Runs the search for one page of results, or takes it from the cache if
+/// that's enabled.
+fn search_page(
+    cfg: &Config,
+    cache: &Cache,
+    index: &ArticleIndex,
+    key: SearchKey,
+) -> Result<CachedSearch> {
+    let ttl = Duration::from_secs(cfg.search_cache_ttl.into());
+    if cfg.search_cache_ttl > 0 {
+        if let Some(search) = cache.cached_search(&key, ttl) {
+            return Ok(search);
+        }
+    }
+    let results = index.search_by_text(
+        &without_stop_words(&key.query, &cfg.search_stop_words),
+        key.namespace.as_deref(),
+        key.sort,
+        cfg.search_snippets,
+        &cfg.search_highlight_tag,
+    )?;
+    let search = CachedSearch {
+        exact_match: results.iter().any(|r| r.title == key.query),
+        results: json!(Page::from_items(results, key.page, SEARCH_RESULTS_PER_PAGE)),
+    };
+    if cfg.search_cache_ttl > 0 {
+        cache.cache_search(key, search.clone());
+    }
+    Ok(search)
+}
+
+#[derive(serde::Serialize)]
+struct MarkedChange {
+    #[serde(flatten)]
+    change: RecentChange,
+    /// Made since the user's last visit.
+    new: bool,
+}
+
+/// Picks the article of the given day from the candidates, so the same day
+/// always gets the same article as long as the candidates stay the same.
+pub(crate) fn article_of_the_day(candidates: &[String], date: NaiveDate) -> Option<&str> {
+    use sha1::{Digest, Sha1};
+
+    if candidates.is_empty() {
+        return None;
+    }
+    let mut sorted = candidates.iter().map(String::as_str).collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let hash = Sha1::digest(date.to_string().as_bytes());
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&hash[..8]);
+    let index = u64::from_le_bytes(seed) % sorted.len() as u64;
+    Some(sorted[index as usize])
+}
+
+/// The articles which can be the article of the day.
+async fn article_of_the_day_candidates(db: &Db, cfg: &Config) -> Result<Vec<String>> {
+    Ok(db
+        .list_articles()
+        .await?
+        .into_iter()
+        .filter(|article| redirect_target(&article.content).is_none())
+        .map(|article| article.name)
+        .filter(|name| {
+            !cfg.article_of_the_day_exclude
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name))
+        })
+        .collect())
+}
+
+/// Renders the landing page shown at `/` instead of redirecting to the
+/// main page, if it's enabled.
+/// It features the article pinned by an admin, the article of the day, or
+/// the main page.
+/// Logged in users see which changes were made since they last came here.
+pub(crate) async fn landing(db: &Db, cfg: &Config, user: Option<LoggedUser>) -> Result<Template> {
+    let last_visit = match &user {
+        Some(user) => db::users::record_visit(db, user.id()).await?,
+        None => None,
+    };
+    let recent_changes = db::articles::recent_changes(db, LANDING_RECENT_CHANGES)
+        .await?
+        .into_iter()
+        .map(|change| MarkedChange {
+            new: last_visit.is_some_and(|last_visit| change.date > last_visit),
+            change,
+        })
+        .collect::<Vec<_>>();
+    let mut daily = false;
+    let featured_name = match db.featured_article().await? {
+        Some(name) => name,
+        None if cfg.article_of_the_day => {
+            let candidates = article_of_the_day_candidates(db, cfg).await?;
+            match article_of_the_day(&candidates, Utc::today().naive_utc()) {
+                Some(name) => {
+                    daily = true;
+                    name.to_string()
+                }
+                None => cfg.main_page.clone(),
+            }
+        }
+        None => cfg.main_page.clone(),
+    };
+    let featured = match db.get_current_rev(&featured_name).await? {
+        Some(rev) => {
+            let content =
+                transclusion::expand(db, &featured_name, &rev.content, cfg.article_format).await?;
+            Some(json! {{
+                "name": featured_name,
+                "content": render_content(&content, cfg),
+                "daily": daily,
+            }})
+        }
+        None => None,
+    };
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "page_name": &cfg.site_name,
+        "user": user,
+        "recent_changes": recent_changes,
+        "featured": featured,
+    }};
+    Ok(Template::render(cfg.landing_template.clone(), context))
+}
+
 #[get("/create", rank = 0)]
 fn create(cfg: &State<Config>, user: Option<LoggedUser>) -> Template {
     let context = json! {{
@@ -110,25 +683,195 @@ fn create(cfg: &State<Config>, user: Option<LoggedUser>) -> Template {
         "default_path": &cfg.default_path,
         "page_name": "New Article",
         "user": user,
+        "max_title_length": cfg.max_title_length,
     }};
     Template::render("article_create", context)
 }
 
+#[derive(serde::Serialize)]
+struct WantedPage {
+    name: String,
+    /// The articles linking to it, sorted by name.
+    linked_from: Vec<String>,
+    /// Whether the name can be used for an article at all.
+    can_create: bool,
+}
+
+/// Lists the articles which are linked to, but don't exist yet, starting
+/// with those linked to most often.
+#[get("/wanted", rank = 0)]
+async fn wanted(
+    cfg: &State<Config>,
+    db: &State<Db>,
+    cache: &State<Cache>,
+    index: &State<ArticleIndex>,
+    user: Option<LoggedUser>,
+) -> Result<Template> {
+    // Finding them goes through every article, so that's only done again
+    // once the index saw them change
+    let generation = index.generation();
+    let pages = match cache.cached_wanted_pages(generation) {
+        Some(pages) => pages,
+        None => {
+            let pages = json!(wanted_pages(cfg, db).await?);
+            cache.cache_wanted_pages(generation, pages.clone());
+            pages
+        }
+    };
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "page_name": "Wanted Articles",
+        "user": user,
+        "pages": pages,
+    }};
+    Ok(Template::render("article_wanted", context))
+}
+
+async fn wanted_pages(cfg: &Config, db: &Db) -> Result<Vec<WantedPage>> {
+    let mut links = BTreeMap::<String, Vec<String>>::new();
+    for article in db.list_articles().await? {
+        for target in internal_links(&article.content) {
+            let target = db::articles::normalize_name(&target);
+            let linked_from = links.entry(target).or_default();
+            if !linked_from.contains(&article.name) {
+                linked_from.push(article.name.clone());
+            }
+        }
+    }
+    let targets = links.keys().cloned().collect::<Vec<_>>();
+    let existing = db::articles::existing_names(db, &targets).await?;
+    let mut pages = links
+        .into_iter()
+        .filter(|(name, _)| !existing.contains(name))
+        .map(|(name, mut linked_from)| {
+            linked_from.sort();
+            WantedPage {
+                can_create: check_title(cfg, &name).is_none(),
+                name,
+                linked_from,
+            }
+        })
+        .collect::<Vec<_>>();
+    // Stable, so equally wanted pages stay sorted by name
+    pages.sort_by_key(|page| std::cmp::Reverse(page.linked_from.len()));
+    Ok(pages)
+}
+
+/// Where following redirect pages ends up.
+enum RedirectChain {
+    /// The first article which isn't a redirect, or doesn't exist.
+    Target(String),
+    /// The redirects went in a circle or too far; these are the articles
+    /// visited on the way, in order.
+    Broken { names: Vec<String>, is_loop: bool },
+}
+
+/// Follows the redirect pages starting at `target`, which `start` redirects
+/// to, so the browser only has to be redirected once.
+async fn follow_redirects(
+    db: &Db,
+    start: &str,
+    target: &str,
+    max_hops: u32,
+) -> Result<RedirectChain> {
+    let mut names = vec![db::articles::normalize_name(start)];
+    let mut next = db::articles::normalize_name(target);
+    loop {
+        let is_loop = names.contains(&next);
+        if is_loop || names.len() > max_hops as usize {
+            names.push(next);
+            return Ok(RedirectChain::Broken { names, is_loop });
+        }
+        let mut rev = db.get_current_rev(&next).await?;
+        if rev.is_none() {
+            if let Some(alias_target) = db::aliases::resolve(db, &next).await? {
+                rev = db.get_current_rev(&alias_target).await?;
+            }
+        }
+        let target = rev
+            .as_ref()
+            .and_then(|rev| redirect_target(&rev.content))
+            .map(db::articles::normalize_name);
+        match target {
+            Some(target) => names.push(std::mem::replace(&mut next, target)),
+            None => return Ok(RedirectChain::Target(next)),
+        }
+    }
+}
+
 #[get("/<article_name>", rank = 3)]
 async fn get(
     db: &State<Db>,
     cfg: &State<Config>,
+    index: &State<ArticleIndex>,
     article_name: String,
     user: Option<LoggedUser>,
-) -> Result<status::Custom<Template>> {
-    if let Some(rev) = db.get_current_rev(&article_name).await? {
+) -> Result<ArticleResponse> {
+    let mut rev = db.get_current_rev(&article_name).await?;
+    // Aliases show their article's content under their own url
+    let mut article_name = article_name;
+    if rev.is_none() {
+        if let Some(target) = db::aliases::resolve(db, &article_name).await? {
+            rev = db.get_current_rev(&target).await?;
+            article_name = target;
+        }
+    }
+    if let Some(rev) = rev {
+        if let Some(target) = redirect_target(&rev.content) {
+            let max_hops = cfg.max_redirect_hops.max(1);
+            return match follow_redirects(db, &article_name, target, max_hops).await? {
+                RedirectChain::Target(target) => {
+                    Ok(ArticleResponse::Redirect(Redirect::to(uri!(get(target)))))
+                }
+                RedirectChain::Broken { names, is_loop } => {
+                    let context = json! {{
+                        "site_name": &cfg.site_name,
+                        "default_path": &cfg.default_path,
+                        "article_name": article_name,
+                        "user": user,
+                        "names": names,
+                        "is_loop": is_loop,
+                        "max_hops": max_hops,
+                    }};
+                    // Too long chains are most likely loops as well
+                    Ok(ArticleResponse::Page(status::Custom(
+                        Status::LoopDetected,
+                        Template::render("article_redirect_loop", context),
+                    )))
+                }
+            };
+        }
         let DisplayRevision {
             rev_id,
-            author_name,
+            mut author_name,
             content,
-            created,
+            mut created,
         } = rev;
+        // The footer credits the last edit which wasn't just a typo fix
+        if cfg.minor_edits {
+            if let Some(major) = db::articles::get_last_major_rev(db, &article_name).await? {
+                author_name = major.author_name;
+                created = major.created;
+            }
+        }
         let date = DateTime::from_utc(created, Utc);
+        let content_hash = db::articles::short_hash(&content);
+        let content = transclusion::expand(db, &article_name, &content, cfg.article_format).await?;
+        let similar = match db.article_id_by_name(&article_name).await? {
+            Some(id) => index.similar(id, SIMILAR_ARTICLES)?,
+            None => Vec::new(),
+        };
+        let editors = db::articles::top_editors(db, &article_name, cfg.top_editors.into()).await?;
+        let tags = db::tags::for_article(db, &article_name).await?;
+        let html = render_content(&content, cfg);
+        let long_article = cfg.long_article_size != 0 && html.len() > cfg.long_article_size;
+        // Plain text has no headings to jump to
+        let sections = if long_article && cfg.article_format == ArticleFormat::Markdown {
+            headings(&content)
+        } else {
+            Vec::new()
+        };
         let context = RevContext {
             site_name: &cfg.site_name,
             default_path: &cfg.default_path,
@@ -136,14 +879,22 @@ async fn get(
             article_name,
             user,
             rev_id,
-            content: markdown_to_html(&content),
+            content: html,
             date,
             specific_rev: false,
+            content_hash,
+            current_rev_id: rev_id,
+            previous_rev_id: None,
+            similar,
+            editors,
+            tags,
+            long_article,
+            sections,
         };
-        Ok(status::Custom(
+        Ok(ArticleResponse::Page(status::Custom(
             Status::Ok,
             Template::render("article", context),
-        ))
+        )))
     } else if article_name == cfg.main_page {
         let context = RevContext {
             site_name: &cfg.site_name,
@@ -152,24 +903,73 @@ async fn get(
             article_name,
             user,
             rev_id: 0,
-            content: markdown_to_html(&format!(
-                "Welcome to your new wiki!
+            content: markdown_to_html(
+                &format!(
+                    "Welcome to your new wiki!
 
 There's nothing here yet.
 
 To create your main page, go to [{}/edit].  
 Have fun!",
-                cfg.main_page
-            )),
+                    cfg.main_page
+                ),
+                cfg,
+            ),
             date: Utc::now(),
             specific_rev: false,
+            content_hash: String::new(),
+            current_rev_id: 0,
+            previous_rev_id: None,
+            similar: Vec::new(),
+            editors: Vec::new(),
+            tags: Vec::new(),
+            long_article: false,
+            sections: Vec::new(),
         };
-        Ok(status::Custom(
+        Ok(ArticleResponse::Page(status::Custom(
             Status::Ok,
             Template::render("article", context),
-        ))
+        )))
     } else {
-        Ok(render_404(&*cfg, &article_name, &user))
+        Ok(ArticleResponse::Page(render_404(cfg, &article_name, &user)))
+    }
+}
+
+/// Why a title can't be used for a new or renamed article.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InvalidTitle {
+    TooLong,
+    Reserved,
+    Blocked,
+}
+
+pub(crate) fn check_title(cfg: &Config, title: &str) -> Option<InvalidTitle> {
+    if title.chars().count() > cfg.max_title_length {
+        return Some(InvalidTitle::TooLong);
+    }
+    let reserved =
+        cfg.reserved_title_prefixes
+            .iter()
+            .any(|prefix| match prefix.strip_prefix('/') {
+                Some(segment) => match title.strip_prefix(segment) {
+                    Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                    None => false,
+                },
+                None => title.starts_with(prefix.as_str()),
+            });
+    if reserved {
+        return Some(InvalidTitle::Reserved);
+    }
+    let lowercase = title.to_lowercase();
+    let blocked = cfg
+        .blocked_title_terms
+        .iter()
+        .any(|term| !term.is_empty() && lowercase.contains(&term.to_lowercase()));
+    if blocked {
+        Some(InvalidTitle::Blocked)
+    } else {
+        None
     }
 }
 
@@ -182,27 +982,63 @@ struct NewRevContext<'a> {
     old_content: String,
     new_article: bool,
     invalid_name_change: bool,
+    invalid_title: Option<InvalidTitle>,
+    max_title_length: usize,
+    draft_date: Option<DateTime<Utc>>,
+    /// The templates a new article can be started from.
+    templates: Vec<String>,
+    /// Whether the edit can be marked as minor.
+    minor_edits: bool,
 }
-#[get("/<article_name>/edit")]
+#[get("/<article_name>/edit?<template>")]
 async fn edit_page(
     db: &State<Db>,
     cfg: &State<Config>,
     article_name: String,
+    template: Option<String>,
     // This route will only be called when a user is logged in.
     user: LoggedUser,
 ) -> Result<Template> {
-    // For a new article, the only difference is the content being empty string.
-    let (old_content, new_article) = sqlx::query_scalar!(
-        "SELECT content FROM revision r
+    let current = sqlx::query!(
+        "SELECT r.content, r.created FROM revision r
         INNER JOIN article a ON a.id = r.article_id
         WHERE a.name = $1
         AND num = (SELECT MAX(num) FROM revision WHERE article_id = a.id)",
-        article_name
+        db::articles::normalize_name(&article_name)
     )
     .fetch_optional(&db.pool)
-    .await?
-    .map(|content| (content, false))
-    .unwrap_or_else(|| (String::default(), true));
+    .await?;
+    let new_article = current.is_none();
+    // A draft is only interesting if nobody saved a newer revision since.
+    let draft = db::drafts::get(db, user.id(), &article_name)
+        .await?
+        .filter(|draft| {
+            current
+                .as_ref()
+                .map(|rev| draft.updated > rev.created)
+                .unwrap_or(true)
+        });
+    // For a new article, the only difference is the content being empty string.
+    let (old_content, draft_date) = match (draft, current) {
+        (Some(draft), _) => (draft.content, Some(DateTime::from_utc(draft.updated, Utc))),
+        (None, Some(rev)) => (rev.content, None),
+        (None, None) => match template {
+            Some(template) => {
+                let template_name = db::articles::TEMPLATE_PREFIX.to_string() + &template;
+                let rev = db
+                    .get_current_rev(&template_name)
+                    .await?
+                    .ok_or(Error::TemplateNotFound(template))?;
+                (rev.content, None)
+            }
+            None => (String::default(), None),
+        },
+    };
+    let templates = if new_article {
+        db::articles::templates(db).await?
+    } else {
+        Vec::new()
+    };
     let context = NewRevContext {
         site_name: &cfg.site_name,
         default_path: &cfg.default_path,
@@ -211,34 +1047,243 @@ async fn edit_page(
         old_content,
         new_article,
         invalid_name_change: false,
+        invalid_title: None,
+        max_title_length: cfg.max_title_length,
+        draft_date,
+        templates,
+        minor_edits: cfg.minor_edits,
     };
     Ok(Template::render("article_edit", context))
 }
+/// The page asking the user to verify their email address first, if editing
+/// requires that (see `Config::edit_requires_verified_email`).
+async fn require_verified_email(
+    db: &Db,
+    cfg: &Config,
+    user: &LoggedUser,
+) -> Result<Option<status::Custom<Template>>> {
+    if !cfg.edit_requires_verified_email || db::users::email_verified(db, user.id()).await? {
+        return Ok(None);
+    }
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": user,
+    }};
+    Ok(Some(status::Custom(
+        Status::Forbidden,
+        Template::render("verify_email_required", context),
+    )))
+}
+
+/// Whether the user's edits have to wait for an admin's approval (see
+/// `Config::moderate_edits`), which they don't once they made enough edits.
+pub(crate) async fn needs_approval(
+    db: &Db,
+    cfg: &Config,
+    user_id: Uuid,
+    is_admin: bool,
+) -> Result<bool> {
+    if !cfg.moderate_edits || is_admin {
+        return Ok(false);
+    }
+    let edits = db::articles::edit_count(db, user_id).await?;
+    Ok(edits < i64::from(cfg.trusted_edit_count))
+}
+
+/// Fails if the user's account is too new for creating articles.
+pub(crate) async fn check_creation_cooldown(
+    db: &Db,
+    cfg: &Config,
+    user_id: Uuid,
+    is_admin: bool,
+) -> Result<()> {
+    let cooldown = cfg.article_creation_cooldown;
+    if cooldown == 0 || is_admin {
+        return Ok(());
+    }
+    let registered = match db::users::registered_at(db, user_id).await? {
+        Some(registered) => registered,
+        None => return Ok(()),
+    };
+    let left = registered + chrono::Duration::seconds(cooldown.into()) - Utc::now().naive_utc();
+    if left > chrono::Duration::zero() {
+        // Rounded up, so it never says 0 minutes
+        let minutes = (left.num_milliseconds() + 59_999) / 60_000;
+        return Err(Error::ArticleCreationCooldown(minutes as u32));
+    }
+    Ok(())
+}
+
+/// Edits to talk pages count as comments, which are limited in how much
+/// they add and how often a user can make them.
+pub(crate) fn check_comment(
+    cfg: &Config,
+    article_name: &str,
+    old_content: Option<&str>,
+    new_content: &str,
+) -> Result<()> {
+    if !article_name.starts_with(db::articles::TALK_PREFIX) {
+        return Ok(());
+    }
+    let old_len = old_content.map_or(0, |content| content.chars().count());
+    let added = new_content.chars().count().saturating_sub(old_len);
+    if cfg.max_comment_length > 0 && added > cfg.max_comment_length {
+        return Err(Error::CommentTooLong(cfg.max_comment_length));
+    }
+    Ok(())
+}
+
+/// Counts a change to a talk page against the user's comment rate limit.
+/// This comes last before saving, so changes rejected for anything else
+/// don't use up the limit.
+pub(crate) fn count_comment(
+    cfg: &Config,
+    cache: &Cache,
+    user_id: Uuid,
+    article_name: &str,
+) -> Result<()> {
+    if !article_name.starts_with(db::articles::TALK_PREFIX) {
+        return Ok(());
+    }
+    let window = Duration::from_secs(cfg.comment_rate_window.into());
+    if cfg.comment_rate_limit > 0 && !cache.count_comment(user_id, cfg.comment_rate_limit, window) {
+        return Err(Error::CommentRateLimited(cfg.comment_rate_window));
+    }
+    Ok(())
+}
+
 #[derive(FromForm)]
-#[cfg_attr(test, derive(serde::Serialize))]
+#[cfg_attr(test, derive(serde::Serialize, Default))]
 pub struct AddRevRequest {
     pub title: Option<String>,
     pub content: String,
+    /// Comma-separated tags to add to the article; it keeps the ones it has.
+    pub tags: Option<String>,
+    /// Only has an effect if minor edits are enabled.
+    pub minor: bool,
 }
 #[post("/<article_name>/edit", data = "<form>")]
+#[allow(clippy::too_many_arguments)]
 async fn edit_form(
     db: &State<Db>,
     cfg: &State<Config>,
+    cache: &State<Cache>,
     search_index: &State<ArticleIndex>,
     article_name: String,
     form: Form<AddRevRequest>,
     session: &UserSession,
     user: LoggedUser,
+    mut txn: Txn,
 ) -> Result<status::Custom<Template>> {
+    if let Some(page) = require_verified_email(db, cfg, &user).await? {
+        return Ok(page);
+    }
+
     // Get the article's id if it already exists.
     let article_id = db.article_id_by_name(&article_name).await?;
 
     let AddRevRequest {
         title: new_title,
         content: new_content,
+        tags,
+        minor,
     } = form.into_inner();
+    let minor = minor && cfg.minor_edits;
 
-    let mut txn = db.begin().await?;
+    // Existing articles keep their title even if the rules changed since
+    let final_name = new_title.as_deref().unwrap_or(&article_name);
+    let is_template = |name: &str| name.starts_with(db::articles::TEMPLATE_PREFIX);
+    if (is_template(&article_name) || is_template(final_name)) && !user.is_admin() {
+        return Err(Error::TemplateEditRequiresAdmin);
+    }
+    if article_id.is_none() {
+        check_creation_cooldown(db, cfg, user.id(), user.is_admin()).await?;
+    }
+    let redirect = redirect_target(&new_content)
+        .filter(|_| cfg.check_redirects)
+        .map(db::articles::normalize_name);
+    if redirect.as_deref() == Some(&db::articles::normalize_name(final_name)) {
+        return Err(Error::RedirectToItself(final_name.to_owned()));
+    }
+    let latest = match article_id {
+        Some(article_id) => db::articles::latest_revision(&mut txn, article_id).await?,
+        None => None,
+    };
+    let unchanged = latest
+        .as_ref()
+        .filter(|rev| rev.content == new_content)
+        .map(|rev| rev.created);
+    if unchanged.is_some()
+        && final_name == article_name
+        && cfg.identical_saves == IdenticalSaves::Reject
+    {
+        return Err(Error::IdenticalNewRevision);
+    }
+    if unchanged.is_none() {
+        let old_content = latest.as_ref().map(|rev| rev.content.as_str());
+        check_comment(cfg, &article_name, old_content, &new_content)?;
+    }
+    if article_id.is_none() || final_name != article_name {
+        if let Some(invalid_title) = check_title(cfg, final_name) {
+            let context = NewRevContext {
+                site_name: &cfg.site_name,
+                default_path: &cfg.default_path,
+                article_name: article_name.clone(),
+                user,
+                old_content: new_content,
+                new_article: article_id.is_none(),
+                invalid_name_change: false,
+                invalid_title: Some(invalid_title),
+                max_title_length: cfg.max_title_length,
+                draft_date: None,
+                templates: Vec::new(),
+                minor_edits: cfg.minor_edits,
+            };
+            return Ok(status::Custom(
+                Status::BadRequest,
+                Template::render("article_edit", context),
+            ));
+        }
+    }
+
+    // Untrusted users' edits wait for an admin, along with the new title and
+    // tags. Whether the title is free is only checked on approval.
+    if needs_approval(db, cfg, user.id(), user.is_admin()).await? {
+        let new_name = Some(final_name).filter(|&name| name != article_name);
+        let tags = tags.filter(|tags| !db::tags::parse_tags(tags).is_empty());
+        let queued = unchanged.is_none() || new_name.is_some() || tags.is_some();
+        if queued {
+            let article_name = db::articles::normalize_name(&article_name);
+            let submission = db::pending::Submission {
+                article_name: &article_name,
+                content: &new_content,
+                author_id: session.user_id,
+                base_rev: latest.as_ref().map(|rev| rev.num),
+                new_name,
+                tags: tags.as_deref(),
+                leave_redirect: false,
+            };
+            db::pending::submit(&mut txn, submission).await?;
+        }
+        db::drafts::delete(&mut txn, session.user_id, &article_name).await?;
+        if unchanged.is_none() {
+            count_comment(cfg, cache, user.id(), &article_name)?;
+        }
+        txn.commit().await?;
+        let context = json! {{
+            "site_name": &cfg.site_name,
+            "default_path": &cfg.default_path,
+            "article_name": article_name,
+            "user": user,
+            "queued": queued,
+        }};
+        let status = if queued { Status::Accepted } else { Status::Ok };
+        return Ok(status::Custom(
+            status,
+            Template::render("article_edit_pending", context),
+        ));
+    }
 
     // Here we check if the "new_name" is valid and also change it in case
     // the article already exists. If it doesn't, we check if there is an
@@ -254,6 +1299,11 @@ async fn edit_form(
                     old_content: new_content.clone(),
                     new_article: article_id.is_none(),
                     invalid_name_change: true,
+                    invalid_title: None,
+                    max_title_length: cfg.max_title_length,
+                    draft_date: None,
+                    templates: Vec::new(),
+                    minor_edits: cfg.minor_edits,
                 };
                 status::Custom(
                     Status::BadRequest,
@@ -289,15 +1339,62 @@ async fn edit_form(
         false
     };
 
-    let article_name = new_title.as_deref().unwrap_or(&article_name);
-    let (RevId(article_id, rev_id), rev) = if let Some(article_id) = article_id {
-        db::articles::add_revision(&mut txn, article_id, session.user_id, &new_content).await?
-    } else {
-        db::articles::create(&mut txn, article_name, &new_content, session.user_id).await?
+    // The draft was saved under the name the editor was opened with.
+    db::drafts::delete(&mut txn, session.user_id, &article_name).await?;
+
+    let article_name = db::articles::normalize_name(new_title.as_deref().unwrap_or(&article_name));
+    // Unchanged content doesn't make for a new revision, just maybe a rename
+    let (article_id, rev_id, date) = match (article_id, unchanged) {
+        (Some(article_id), Some(created)) => (article_id, None, DateTime::from_utc(created, Utc)),
+        (Some(article_id), None) => {
+            let (RevId(article_id, rev_id), rev) = db::articles::add_revision(
+                &mut txn,
+                article_id,
+                session.user_id,
+                &new_content,
+                minor,
+                cfg.max_revisions,
+            )
+            .await?;
+            // Searching by date finds the article where it was before
+            let date = if minor {
+                DateTime::from_utc(
+                    db::articles::last_major_edit(&mut txn, article_id).await?,
+                    Utc,
+                )
+            } else {
+                rev.date
+            };
+            (article_id, Some(rev_id), date)
+        }
+        (None, _) => {
+            let (RevId(article_id, rev_id), rev) =
+                db::articles::create(&mut txn, &article_name, &new_content, session.user_id)
+                    .await?;
+            (article_id, Some(rev_id), rev.date)
+        }
     };
+    if let Some(tags) = tags {
+        let tags = db::tags::parse_tags(&tags);
+        db::tags::add_to_article(&mut txn, article_id, &tags, cfg.max_tags_per_article).await?;
+    }
+    if rev_id.is_some() {
+        count_comment(cfg, cache, user.id(), &article_name)?;
+    }
 
     txn.commit().await?;
 
+    // Saved anyway, since the target might just not be written yet
+    let broken_redirect = match redirect {
+        Some(target) => {
+            let exists = !db::articles::existing_names(db, std::slice::from_ref(&target))
+                .await?
+                .is_empty()
+                || db::aliases::resolve(db, &target).await?.is_some();
+            Some(target).filter(|_| !exists)
+        }
+        None => None,
+    };
     let context = json! {{
         "site_name": &cfg.site_name,
         "default_path": &cfg.default_path,
@@ -305,10 +1402,12 @@ async fn edit_form(
         "user": user,
         "rev_id": rev_id,
         "new_name": new_name,
+        "broken_redirect": broken_redirect,
     }};
 
     // TODO do we really want to return on error here?
-    search_index.add_or_update_article(article_id, article_name, &new_content, rev.date)?;
+    let tags = db::tags::for_article(db, &article_name).await?;
+    search_index.add_or_update_article(article_id, &article_name, &new_content, &tags, date)?;
 
     Ok(status::Custom(
         Status::Ok,
@@ -316,24 +1415,106 @@ async fn edit_form(
     ))
 }
 
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct DraftRequest {
+    pub content: String,
+}
+/// Stores the editor's current content so it isn't lost; this is called
+/// periodically by the editor page.
+#[post("/<article_name>/draft", data = "<form>")]
+async fn save_draft(
+    db: &State<Db>,
+    article_name: String,
+    form: Form<DraftRequest>,
+    user: LoggedUser,
+) -> Result<Status> {
+    db::drafts::save(db, user.id(), &article_name, &form.content).await?;
+    Ok(Status::NoContent)
+}
+#[post("/<article_name>/draft/discard")]
+async fn discard_draft(db: &State<Db>, article_name: String, user: LoggedUser) -> Result<Redirect> {
+    let mut conn = db.acquire().await?;
+    db::drafts::delete(&mut conn, user.id(), &article_name).await?;
+    Ok(Redirect::to(uri!(edit_page(&article_name, _))))
+}
+/// Shows how the user's draft differs from the current content, so they can
+/// decide whether to keep it.
+#[get("/<article_name>/draft/diff")]
+async fn draft_diff(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    article_name: String,
+    user: LoggedUser,
+) -> Result<Template> {
+    let draft = db::drafts::get(db, user.id(), &article_name)
+        .await?
+        .ok_or_else(|| Error::DraftNotFound(article_name.clone()))?;
+    // New articles are compared to an empty one
+    let current = db
+        .get_current_rev(&article_name)
+        .await?
+        .map(|rev| rev.content)
+        .unwrap_or_default();
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "article_name": article_name,
+        "user": user,
+        "draft_date": DateTime::<Utc>::from_utc(draft.updated, Utc),
+        "hunks": diff::hunks(&current, &draft.content),
+    }};
+    Ok(Template::render("article_draft_diff", context))
+}
+
+/// Shows what saving the editor's content would change, as an HTML
+/// fragment for the editor page to insert.
+#[post("/<article_name>/preview-diff", data = "<form>")]
+async fn preview_diff(
+    db: &State<Db>,
+    article_name: String,
+    form: Form<DraftRequest>,
+    _user: LoggedUser,
+) -> Result<Template> {
+    // New articles are compared to an empty one
+    let current = db
+        .get_current_rev(&article_name)
+        .await?
+        .map(|rev| rev.content)
+        .unwrap_or_default();
+    let context = json! {{
+        "hunks": diff::hunks(&current, &form.content),
+    }};
+    Ok(Template::render("article_preview_diff", context))
+}
+
 #[get("/<_article_name>/edit", rank = 2)]
-fn redirect_to_login_get(_article_name: String) -> Redirect {
-    Redirect::to("/u/login")
+fn redirect_to_login_get(_article_name: String, origin: &Origin<'_>) -> Redirect {
+    Redirect::to(crate::users::login_uri(origin))
 }
 #[post("/<_article_name>/edit", rank = 2)]
-fn redirect_to_login_post(_article_name: String) -> Redirect {
-    Redirect::to("/u/login")
+fn redirect_to_login_post(_article_name: String, origin: &Origin<'_>) -> Redirect {
+    Redirect::to(crate::users::login_uri(origin))
 }
 
-#[get("/<article_name>/revs")]
+#[get("/<article_name>/revs?<page>&<author>&<order>")]
 async fn revs(
     db: &State<Db>,
     cfg: &State<Config>,
     article_name: String,
+    page: Option<usize>,
+    author: Option<String>,
+    order: Option<RevisionOrder>,
     user: Option<LoggedUser>,
 ) -> Result<status::Custom<Template>> {
-    let revisions = db::articles::list_revisions(db, &article_name).await?;
-    if revisions.is_empty() {
+    let author = author.filter(|author| !author.is_empty());
+    let order = order.unwrap_or_default();
+    let revisions =
+        db::articles::list_revisions(db, &article_name, author.as_deref(), order).await?;
+    // Only a missing article is a 404; an author without revisions isn't
+    if revisions.is_empty()
+        && (author.is_none() || db.article_id_by_name(&article_name).await?.is_none())
+    {
         return Ok(render_404(&*cfg, &article_name, &user));
     }
     let context = json! {{
@@ -341,7 +1522,9 @@ async fn revs(
         "default_path": &cfg.default_path,
         "article_name": article_name,
         "user": user,
-        "revs": revisions,
+        "author": author.unwrap_or_default(),
+        "order": order,
+        "revs": Page::from_items(revisions, page.unwrap_or(1), REVISIONS_PER_PAGE),
     }};
     Ok(status::Custom(
         Status::Ok,
@@ -349,6 +1532,44 @@ async fn revs(
     ))
 }
 
+/// The article's revisions as an Atom feed, newest first, so people can
+/// follow the changes to a single page.
+#[get("/<article_name>/revs.atom")]
+async fn revs_feed(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    article_name: String,
+) -> Result<Option<(ContentType, Template)>> {
+    let revisions =
+        db::articles::list_revisions(db, &article_name, None, RevisionOrder::Asc).await?;
+    let entries = revisions
+        .iter()
+        .rev()
+        .map(|rev| {
+            json! {{
+                "num": rev.num,
+                "author_name": rev.author_name,
+                "updated": DateTime::<Utc>::from_utc(rev.date, Utc).to_rfc3339(),
+            }}
+        })
+        .collect::<Vec<_>>();
+    let updated = match entries.first() {
+        Some(newest) => newest["updated"].clone(),
+        None => return Ok(None),
+    };
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "site_url": cfg.site_url.trim_end_matches('/'),
+        "article_name": article_name,
+        "updated": updated,
+        "entries": entries,
+    }};
+    Ok(Some((
+        ContentType::new("application", "atom+xml"),
+        Template::render("article_revs_atom", context),
+    )))
+}
+
 // TODO: You can manually put in a rev_id from a different article and you'll
 // get that article instead of the current one, but with the wrong title. lol.
 #[get("/<article_name>/rev/<rev_id>")]
@@ -360,6 +1581,13 @@ async fn rev(
     user: Option<LoggedUser>,
 ) -> Result<status::Custom<Template>> {
     if let Some(rev) = db::articles::get_revision(db, &article_name, rev_id).await? {
+        let current_rev_id = db
+            .get_current_rev(&article_name)
+            .await?
+            .map_or(rev.rev_id, |current| current.rev_id);
+        let previous_rev_id = db::articles::get_previous_revision(db, &article_name, rev_id)
+            .await?
+            .map(|previous| previous.rev_id);
         let DisplayRevision {
             rev_id,
             author_name,
@@ -367,6 +1595,8 @@ async fn rev(
             created,
         } = rev;
         let date = DateTime::from_utc(created, Utc);
+        let content_hash = db::articles::short_hash(&content);
+        let content = transclusion::expand(db, &article_name, &content, cfg.article_format).await?;
         let context = RevContext {
             site_name: &cfg.site_name,
             default_path: &cfg.default_path,
@@ -374,9 +1604,17 @@ async fn rev(
             article_name,
             user,
             rev_id,
-            content: markdown_to_html(&content),
+            content: render_content(&content, cfg),
             date,
             specific_rev: true,
+            content_hash,
+            current_rev_id,
+            previous_rev_id,
+            similar: Vec::new(),
+            editors: Vec::new(),
+            tags: Vec::new(),
+            long_article: false,
+            sections: Vec::new(),
         };
         Ok(status::Custom(
             Status::Ok,
@@ -386,3 +1624,222 @@ async fn rev(
         Ok(render_404(&*cfg, &article_name, &user))
     }
 }
+
+/// A revision's content as a Markdown file.
+#[derive(Responder)]
+struct RevisionDownload {
+    content: (ContentType, String),
+    disposition: Header<'static>,
+    last_modified: Header<'static>,
+}
+
+/// Turns an article name into something safe to use as a file name.
+pub(crate) fn file_name(article_name: &str) -> String {
+    article_name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[get("/<article_name>/rev/<rev_id>/download")]
+async fn download_rev(
+    db: &State<Db>,
+    article_name: String,
+    rev_id: i64,
+) -> Result<Option<RevisionDownload>> {
+    let rev = match db::articles::get_revision(db, &article_name, rev_id).await? {
+        Some(rev) => rev,
+        None => return Ok(None),
+    };
+    let disposition = format!(
+        "attachment; filename=\"{}-rev{}.md\"",
+        file_name(&article_name),
+        rev.rev_id
+    );
+    let created = DateTime::<Utc>::from_utc(rev.created, Utc);
+    Ok(Some(RevisionDownload {
+        content: (ContentType::new("text", "markdown"), rev.content),
+        disposition: Header::new("Content-Disposition", disposition),
+        last_modified: Header::new(
+            "Last-Modified",
+            created.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        ),
+    }))
+}
+
+#[get("/<article_name>/compare?<from>&<to>")]
+async fn compare(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    article_name: String,
+    from: i64,
+    to: i64,
+    user: Option<LoggedUser>,
+) -> Result<status::Custom<Template>> {
+    let old = db::articles::get_revision(db, &article_name, from).await?;
+    let new = db::articles::get_revision(db, &article_name, to).await?;
+    if let Some((old, new)) = old.zip(new) {
+        let context = json! {{
+            "site_name": &cfg.site_name,
+            "default_path": &cfg.default_path,
+            "article_name": article_name,
+            "user": user,
+            "from": from,
+            "to": to,
+            "hunks": diff::hunks(&old.content, &new.content),
+        }};
+        Ok(status::Custom(
+            Status::Ok,
+            Template::render("article_compare", context),
+        ))
+    } else {
+        Ok(render_404(cfg, &article_name, &user))
+    }
+}
+
+/// Reverts just the change made in the given revision, as a new revision.
+#[post("/<article_name>/rev/<rev_id>/undo")]
+#[allow(clippy::too_many_arguments)]
+async fn undo(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    cache: &State<Cache>,
+    search_index: &State<ArticleIndex>,
+    article_name: String,
+    rev_id: i64,
+    user: LoggedUser,
+    mut txn: Txn,
+) -> Result<ArticleResponse> {
+    if let Some(page) = require_verified_email(db, cfg, &user).await? {
+        return Ok(ArticleResponse::Page(page));
+    }
+    let article_id = db.article_id_by_name(&article_name).await?;
+    let undone = db::articles::get_revision(db, &article_name, rev_id).await?;
+    // The first revision can't be undone, since there's nothing before it
+    let before = db::articles::get_previous_revision(db, &article_name, rev_id).await?;
+    let current = db.get_current_rev(&article_name).await?;
+    let (article_id, undone, before, current) = match (article_id, undone, before, current) {
+        (Some(id), Some(undone), Some(before), Some(current)) => (id, undone, before, current),
+        _ => {
+            return Ok(ArticleResponse::Page(render_404(
+                cfg,
+                &article_name,
+                &Some(user),
+            )))
+        }
+    };
+    if article_name.starts_with(db::articles::TEMPLATE_PREFIX) && !user.is_admin() {
+        return Err(Error::TemplateEditRequiresAdmin);
+    }
+    // Undoing a redacted change would restore (or mangle) other content
+    if undone.content == db::articles::REDACTED_CONTENT
+        || before.content == db::articles::REDACTED_CONTENT
+    {
+        return Err(Error::RevisionRedacted(article_name, rev_id));
+    }
+    let new_content = match diff::undo(&before.content, &undone.content, &current.content) {
+        Some(content) if content == current.content => return Err(Error::IdenticalNewRevision),
+        Some(content) => content,
+        None => {
+            let context = json! {{
+                "site_name": &cfg.site_name,
+                "default_path": &cfg.default_path,
+                "article_name": article_name,
+                "user": user,
+                "rev_id": rev_id,
+                "hunks": diff::hunks(&before.content, &undone.content),
+            }};
+            return Ok(ArticleResponse::Page(status::Custom(
+                Status::Conflict,
+                Template::render("article_undo_conflict", context),
+            )));
+        }
+    };
+
+    let article_name = db::articles::normalize_name(&article_name);
+    check_comment(cfg, &article_name, Some(&current.content), &new_content)?;
+    // Undoing is just another edit for the moderation queue
+    if needs_approval(db, cfg, user.id(), user.is_admin()).await? {
+        let submission = db::pending::Submission {
+            article_name: &article_name,
+            content: &new_content,
+            author_id: user.id(),
+            base_rev: Some(current.rev_id),
+            new_name: None,
+            tags: None,
+            leave_redirect: false,
+        };
+        db::pending::submit(&mut txn, submission).await?;
+        count_comment(cfg, cache, user.id(), &article_name)?;
+        txn.commit().await?;
+        let context = json! {{
+            "site_name": &cfg.site_name,
+            "default_path": &cfg.default_path,
+            "article_name": article_name,
+            "user": user,
+            "queued": true,
+        }};
+        return Ok(ArticleResponse::Page(status::Custom(
+            Status::Accepted,
+            Template::render("article_edit_pending", context),
+        )));
+    }
+
+    let (_, rev) = db::articles::add_revision(
+        &mut txn,
+        article_id,
+        user.id(),
+        &new_content,
+        false,
+        cfg.max_revisions,
+    )
+    .await?;
+    count_comment(cfg, cache, user.id(), &article_name)?;
+    txn.commit().await?;
+    let tags = db::tags::for_article(db, &article_name).await?;
+    search_index.add_or_update_article(article_id, &article_name, &new_content, &tags, rev.date)?;
+    Ok(ArticleResponse::Redirect(Redirect::to(uri!(get(
+        &article_name
+    )))))
+}
+
+/// Permanently removes a revision's content, e.g. for legal reasons.
+/// The revision itself stays, so the numbering doesn't change.
+#[post("/<article_name>/rev/<rev_id>/redact")]
+async fn redact(
+    db: &State<Db>,
+    search_index: &State<ArticleIndex>,
+    article_name: String,
+    rev_id: i64,
+    admin: LoggedAdmin,
+) -> Result<Option<Redirect>> {
+    let article_id = match db.article_id_by_name(&article_name).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let article_name = db::articles::normalize_name(&article_name);
+    let mut txn = db.begin().await?;
+    if !db::articles::redact(&mut txn, article_id, rev_id).await? {
+        return Ok(None);
+    }
+    let target = format!("{}#{}", article_name, rev_id);
+    db::audit::record(&mut txn, admin.id(), AuditAction::RedactRevision, &target).await?;
+    txn.commit().await?;
+    // Only the current revision is searchable
+    if let Some(current) = db.get_current_rev(&article_name).await? {
+        if current.rev_id == rev_id {
+            let date = DateTime::from_utc(current.created, Utc);
+            search_index.add_or_update_article(
+                article_id,
+                &article_name,
+                &current.content,
+                &db::tags::for_article(db, &article_name).await?,
+                date,
+            )?;
+        }
+    }
+    Ok(Some(Redirect::to(uri!(rev(&article_name, &rev_id)))))
+}
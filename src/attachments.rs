@@ -0,0 +1,223 @@
+//! Files (currently images) uploaded to articles.
+//! Articles refer to them with a `file:` link, e.g. `![A cat](file:<id>)`.
+use std::path::{Path, PathBuf};
+
+use image::{imageops::FilterType, io::Reader as ImageReader, GenericImageView, ImageFormat};
+
+use rocket::{
+    data::Capped,
+    form::Form,
+    fs::{NamedFile, TempFile},
+    get,
+    http::{ContentType, Header},
+    post,
+    tokio::{fs::File, io::AsyncReadExt},
+    FromForm, Responder, Route, State,
+};
+use rocket_dyn_templates::Template;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    db::{self, users::LoggedUser, Db},
+    Config, Error, Result,
+};
+
+pub fn routes() -> Vec<Route> {
+    rocket::routes![upload, file, thumbnail]
+}
+
+/// The prefix used for referencing attachments in markdown.
+pub const LINK_PREFIX: &str = "file:";
+
+/// Turns a `file:<id>` link into the url the attachment is served from.
+/// Returns None for any other link.
+pub fn resolve_link(dest: &str) -> Option<String> {
+    let id = dest.strip_prefix(LINK_PREFIX)?;
+    Some(format!("/files/{}", id))
+}
+
+fn file_path(cfg: &Config, id: Uuid) -> PathBuf {
+    cfg.upload_dir.join(id.to_simple().to_string())
+}
+/// Where generated thumbnails are cached.
+pub fn thumbnail_dir(cfg: &Config) -> PathBuf {
+    cfg.upload_dir.join("thumbs")
+}
+fn thumbnail_path(cfg: &Config, id: Uuid, width: u32) -> PathBuf {
+    thumbnail_dir(cfg).join(format!("{}-{}.png", id.to_simple(), width))
+}
+
+/// The raster image formats which can be uploaded, with their content types.
+/// Whatever the browser claims, uploads are only taken for what their
+/// content looks like.
+const IMAGE_TYPES: &[(ImageFormat, &str)] = &[
+    (ImageFormat::Png, "image/png"),
+    (ImageFormat::Jpeg, "image/jpeg"),
+    (ImageFormat::Gif, "image/gif"),
+    (ImageFormat::WebP, "image/webp"),
+    (ImageFormat::Bmp, "image/bmp"),
+    (ImageFormat::Tiff, "image/tiff"),
+];
+/// Enough for every format's magic number.
+const SNIFF_LEN: u64 = 32;
+
+/// The content type of the image in the file, going by its first bytes.
+async fn sniff_image_type(path: &Path) -> Result<Option<&'static str>> {
+    let mut start = Vec::new();
+    File::open(path)
+        .await?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut start)
+        .await?;
+    Ok(image::guess_format(&start).ok().and_then(|format| {
+        IMAGE_TYPES
+            .iter()
+            .find(|(known, _)| *known == format)
+            .map(|(_, content_type)| *content_type)
+    }))
+}
+
+/// Deletes an attachment's file and thumbnails after its record is gone.
+/// Files which are already missing are skipped.
+pub async fn remove_files(cfg: &Config, id: Uuid) -> Result<()> {
+    let thumbnails = cfg
+        .thumbnail_widths
+        .iter()
+        .map(|&width| thumbnail_path(cfg, id, width));
+    for path in std::iter::once(file_path(cfg, id)).chain(thumbnails) {
+        match rocket::tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(FromForm)]
+pub struct UploadRequest<'r> {
+    file: Capped<TempFile<'r>>,
+}
+
+#[post("/<article_name>/upload", data = "<form>")]
+async fn upload(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    article_name: String,
+    mut form: Form<UploadRequest<'_>>,
+    user: LoggedUser,
+) -> Result<Option<Template>> {
+    let article_id = match db.article_id_by_name(&article_name).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let file = &mut form.file;
+    // If Rocket's own limit cut the file short, it's too large as well
+    if !file.is_complete() || file.len() > cfg.upload_max_size {
+        return Err(Error::AttachmentTooLarge(cfg.upload_max_size));
+    }
+    // The file has to be somewhere to look at it, but gets its id only once
+    // it's accepted
+    let staged = cfg
+        .upload_dir
+        .join(format!("{}.upload", Uuid::new_v4().to_simple()));
+    file.copy_to(&staged).await?;
+    let content_type = match sniff_image_type(&staged).await {
+        Ok(Some(content_type)) if cfg.upload_allowed_types.iter().any(|t| t == content_type) => {
+            content_type
+        }
+        res => {
+            rocket::tokio::fs::remove_file(&staged).await?;
+            let found = res?.unwrap_or("unknown");
+            return Err(Error::AttachmentTypeNotAllowed(found.to_owned()));
+        }
+    };
+
+    let size = file.len() as i64;
+    let id = db::attachments::create(db, article_id, user.id(), content_type, size).await?;
+    if let Err(e) = rocket::tokio::fs::rename(&staged, file_path(cfg, id)).await {
+        db::attachments::delete(db, id).await?;
+        rocket::tokio::fs::remove_file(&staged).await.ok();
+        return Err(e.into());
+    }
+
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "article_name": article_name,
+        "user": user,
+        "link": format!("{}{}", LINK_PREFIX, id.to_simple()),
+    }};
+    Ok(Some(Template::render("attachment_success", context)))
+}
+
+#[derive(Responder)]
+struct AttachmentFile {
+    file: (ContentType, NamedFile),
+    disposition: Header<'static>,
+    nosniff: Header<'static>,
+}
+
+/// Serves an attachment. Only images are shown in the browser; anything else
+/// (e.g. from before uploads were checked) is downloaded as plain bytes.
+#[get("/files/<id>", rank = 1)]
+async fn file(db: &State<Db>, cfg: &State<Config>, id: Uuid) -> Result<Option<AttachmentFile>> {
+    let attachment = match db::attachments::get(db, id).await? {
+        Some(attachment) => attachment,
+        None => return Ok(None),
+    };
+    let is_image = IMAGE_TYPES
+        .iter()
+        .any(|(_, content_type)| *content_type == attachment.content_type);
+    let (content_type, disposition) = match ContentType::parse_flexible(&attachment.content_type) {
+        Some(content_type) if is_image => (content_type, "inline"),
+        _ => (ContentType::Binary, "attachment"),
+    };
+    let file = NamedFile::open(file_path(cfg, attachment.id)).await?;
+    Ok(Some(AttachmentFile {
+        file: (content_type, file),
+        disposition: Header::new("Content-Disposition", disposition),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    }))
+}
+
+/// Serves a thumbnail of an uploaded image, generating it on first use.
+/// Thumbnails are always PNGs; images are never scaled up.
+#[get("/files/<id>/thumb?<w>", rank = 1)]
+async fn thumbnail(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    id: Uuid,
+    w: u32,
+) -> Result<Option<(ContentType, NamedFile)>> {
+    if !cfg.thumbnail_widths.contains(&w) {
+        return Err(Error::ThumbnailWidthNotAllowed(w));
+    }
+    let attachment = match db::attachments::get(db, id).await? {
+        Some(attachment) if attachment.content_type.starts_with("image/") => attachment,
+        _ => return Ok(None),
+    };
+    let path = thumbnail_path(cfg, attachment.id, w);
+    if rocket::tokio::fs::metadata(&path).await.is_err() {
+        let source = file_path(cfg, attachment.id);
+        let target = path.clone();
+        rocket::tokio::task::spawn_blocking(move || generate_thumbnail(&source, &target, w))
+            .await??;
+    }
+    Ok(Some((ContentType::PNG, NamedFile::open(path).await?)))
+}
+
+fn generate_thumbnail(source: &Path, target: &Path, width: u32) -> Result<()> {
+    let image = ImageReader::open(source)?.with_guessed_format()?.decode()?;
+    let thumbnail = if image.width() > width {
+        image.resize(width, u32::MAX, FilterType::Lanczos3)
+    } else {
+        image
+    };
+    // Write to a temporary file first, so concurrent requests never see a
+    // half-written thumbnail
+    let tmp = target.with_extension(format!("{}.tmp", Uuid::new_v4().to_simple()));
+    thumbnail.save_with_format(&tmp, ImageFormat::Png)?;
+    std::fs::rename(tmp, target)?;
+    Ok(())
+}
@@ -1,40 +1,166 @@
-use std::sync::Arc;
+use std::{
+    hash::Hash,
+    net::IpAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
-use dashmap::DashMap;
+use lru::LruCache;
+use parking_lot::Mutex;
 use uuid::Uuid;
 
-/// In-memory sled database used for caching various things
-#[derive(Default)]
+use crate::search::SearchSort;
+
+/// How many entries each cache holds if nothing else is configured.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Identifies a page of search results.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SearchKey {
+    /// With whitespace normalized.
+    pub query: String,
+    pub namespace: Option<String>,
+    pub sort: SearchSort,
+    pub page: usize,
+    /// The search index's generation the results were found in.
+    pub generation: u64,
+}
+
+/// A page of search results, ready for the template.
+#[derive(Debug, Clone)]
+pub struct CachedSearch {
+    pub exact_match: bool,
+    pub results: serde_json::Value,
+}
+
+/// In-memory storage used for caching various things.
+/// Every cache is bounded; when one is full, its oldest entries are evicted.
 pub struct Cache {
-    captcha_solution: Arc<DashMap<Uuid, String>>,
+    captcha_solution: Mutex<LruCache<Uuid, String>>,
+    /// When each address's current search window started, and how many
+    /// searches it made since.
+    searches: Mutex<LruCache<IpAddr, (Instant, u32)>>,
+    /// The same for each user's comments.
+    comments: Mutex<LruCache<Uuid, (Instant, u32)>>,
+    search_results: Mutex<LruCache<SearchKey, (Instant, CachedSearch)>>,
+    search_result_hits: AtomicUsize,
+    /// The wanted articles, with the search index's generation they were
+    /// found in.
+    wanted_pages: Mutex<Option<(u64, serde_json::Value)>>,
+}
+
+/// Counts one more for the key, returning false if that's more than `limit`
+/// in the current window.
+fn count_in_window<K: Hash + Eq>(
+    counts: &Mutex<LruCache<K, (Instant, u32)>>,
+    key: K,
+    limit: u32,
+    window: Duration,
+) -> bool {
+    let now = Instant::now();
+    let mut counts = counts.lock();
+    match counts.get_mut(&key) {
+        Some((start, count)) if now.duration_since(*start) < window => {
+            *count = count.saturating_add(1);
+            *count <= limit
+        }
+        _ => {
+            counts.put(key, (now, 1));
+            limit > 0
+        }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
 }
 
 impl Cache {
+    /// Creates a cache where every part holds at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            captcha_solution: Mutex::new(LruCache::new(capacity)),
+            searches: Mutex::new(LruCache::new(capacity)),
+            comments: Mutex::new(LruCache::new(capacity)),
+            search_results: Mutex::new(LruCache::new(capacity)),
+            search_result_hits: AtomicUsize::new(0),
+            wanted_pages: Mutex::new(None),
+        }
+    }
     /// Registers a captcha in the cache to be validated later.
     pub fn register_captcha(&self, id: Uuid, solution: &str) {
-        self.captcha_solution.insert(id, solution.to_string());
+        self.captcha_solution.lock().put(id, solution.to_string());
     }
     /// Drop the captcha for the given id out of the cache and validate the given solution.
     /// You can only ever validate a single captcha once.
     pub fn validate_captcha(&self, id: Uuid, given_solution: &str) -> bool {
-        let stored_solution = self.captcha_solution.remove(&id);
+        let stored_solution = self.captcha_solution.lock().pop(&id);
         // If we don't know the captcha, just return false
         stored_solution
-            .map(|(_, stored_solution)| given_solution == stored_solution)
+            .map(|stored_solution| given_solution == stored_solution)
             .unwrap_or(false)
     }
+    /// Counts a search from the given address.
+    /// Returns false if it made more than `limit` searches in the current
+    /// window, which starts with its first search and lasts `window`.
+    pub fn count_search(&self, ip: IpAddr, limit: u32, window: Duration) -> bool {
+        count_in_window(&self.searches, ip, limit, window)
+    }
+    /// Counts a comment by the given user, like `count_search`.
+    pub fn count_comment(&self, user_id: Uuid, limit: u32, window: Duration) -> bool {
+        count_in_window(&self.comments, user_id, limit, window)
+    }
+    /// Stores a page of search results.
+    pub fn cache_search(&self, key: SearchKey, search: CachedSearch) {
+        self.search_results
+            .lock()
+            .put(key, (Instant::now(), search));
+    }
+    /// Returns the search results stored for the key, unless they're older
+    /// than `ttl`.
+    pub fn cached_search(&self, key: &SearchKey, ttl: Duration) -> Option<CachedSearch> {
+        // Expired results are replaced when the search is stored again
+        let search = self
+            .search_results
+            .lock()
+            .get(key)
+            .filter(|(stored, _)| stored.elapsed() < ttl)
+            .map(|(_, search)| search.clone());
+        if search.is_some() {
+            self.search_result_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        search
+    }
+    /// Stores the wanted articles found in the given index generation.
+    pub fn cache_wanted_pages(&self, generation: u64, pages: serde_json::Value) {
+        *self.wanted_pages.lock() = Some((generation, pages));
+    }
+    /// Returns the wanted articles, unless the articles changed since.
+    pub fn cached_wanted_pages(&self, generation: u64) -> Option<serde_json::Value> {
+        self.wanted_pages
+            .lock()
+            .as_ref()
+            .filter(|(stored, _)| *stored == generation)
+            .map(|(_, pages)| pages.clone())
+    }
+    /// How many searches were answered from the cache.
+    #[cfg(test)]
+    pub fn search_result_hits(&self) -> usize {
+        self.search_result_hits.load(Ordering::Relaxed)
+    }
     // Used for testing the register routes
     #[cfg(test)]
     pub fn get_solution(&self, id: Uuid) -> Option<String> {
-        self.captcha_solution
-            .get(&id)
-            .map(|entry| entry.to_string())
+        self.captcha_solution.lock().peek(&id).cloned()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Cache;
+    use std::time::Duration;
     use uuid::Uuid;
 
     #[test]
@@ -66,4 +192,31 @@ mod tests {
         cache.register_captcha(id1, solution1);
         cache.register_captcha(id2, solution2);
     }
+
+    #[test]
+    fn search_limit() {
+        let cache = Cache::default();
+        let ip = "203.0.113.7".parse().unwrap();
+        let window = Duration::from_secs(60);
+        assert!(cache.count_search(ip, 2, window));
+        assert!(cache.count_search(ip, 2, window));
+        assert!(!cache.count_search(ip, 2, window));
+        // Other addresses have their own limit
+        assert!(cache.count_search("203.0.113.8".parse().unwrap(), 2, window));
+        // And once the window is over, it starts again
+        assert!(cache.count_search(ip, 2, Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn bounded_captchas() {
+        let cache = Cache::new(2);
+        let (id1, id2, id3) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        cache.register_captcha(id1, "11111");
+        cache.register_captcha(id2, "22222");
+        // This one doesn't fit anymore, so the oldest one is evicted
+        cache.register_captcha(id3, "33333");
+        assert!(!cache.validate_captcha(id1, "11111"));
+        assert!(cache.validate_captcha(id2, "22222"));
+        assert!(cache.validate_captcha(id3, "33333"));
+    }
 }
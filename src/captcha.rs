@@ -0,0 +1,239 @@
+//! The captchas shown when registering.
+//! Either the built-in image captcha, or a widget from an external provider
+//! which is verified server-side through the provider's siteverify endpoint.
+use std::sync::Arc;
+
+use rocket::tokio::sync::Semaphore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Cache, Error, Result};
+
+/// Which kind of captcha registrations have to solve.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaKind {
+    #[default]
+    Builtin,
+    Turnstile,
+    Hcaptcha,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExternalCaptchaConfig {
+    /// The public key, embedded in the registration form.
+    pub site_key: String,
+    pub secret: String,
+    /// Overrides the provider's siteverify url.
+    pub verify_url: Option<String>,
+}
+
+/// What the registration form needs for showing a captcha.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Challenge {
+    Image {
+        id: Uuid,
+        base64: String,
+    },
+    Widget {
+        id: Uuid,
+        provider: CaptchaKind,
+        site_key: String,
+    },
+}
+
+#[rocket::async_trait]
+pub trait CaptchaProvider: Send + Sync {
+    /// Creates a new challenge for the registration form.
+    async fn challenge(&self, cache: &Cache) -> Result<Challenge>;
+    /// Checks the solution given for the challenge with the given id.
+    /// Like the cache's captchas, every challenge can only be checked once.
+    async fn verify(&self, cache: &Cache, id: Uuid, solution: &str) -> bool;
+}
+
+/// Creates the provider selected in the config.
+/// `concurrency` limits how many image captchas are generated at once.
+pub fn from_config(
+    kind: CaptchaKind,
+    config: Option<&ExternalCaptchaConfig>,
+    concurrency: usize,
+) -> Result<Box<dyn CaptchaProvider>> {
+    let default_verify_url = match kind {
+        CaptchaKind::Builtin => return Ok(Box::new(ImageCaptcha::new(concurrency))),
+        CaptchaKind::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+        CaptchaKind::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+    };
+    let config = config.ok_or(Error::CaptchaConfigMissing)?;
+    Ok(Box::new(ExternalCaptcha {
+        kind,
+        site_key: config.site_key.clone(),
+        secret: config.secret.clone(),
+        verify_url: config
+            .verify_url
+            .clone()
+            .unwrap_or_else(|| default_verify_url.into()),
+        client: reqwest::Client::new(),
+    }))
+}
+
+/// Generate a captcha.
+/// Returns the captcha as base64 and the characters it contains.
+fn generate_captcha() -> Result<(String, String)> {
+    use captcha::{
+        filters::{Dots, Noise, Wave},
+        Captcha,
+    };
+    use rand::Rng;
+
+    let mut captcha = Captcha::new();
+    let mut rng = rand::thread_rng();
+    captcha
+        .add_chars(5)
+        .apply_filter(Noise::new(0.4))
+        .apply_filter(Wave::new(rng.gen_range(1.0..3.0), rng.gen_range(10.0..30.0)).horizontal())
+        .apply_filter(Wave::new(rng.gen_range(1.0..3.0), rng.gen_range(10.0..30.0)).vertical())
+        .view(220, 120)
+        .apply_filter(Dots::new(rng.gen_range(3..6)));
+    let result = (
+        captcha.chars_as_string(),
+        captcha.as_base64().ok_or(Error::CaptchaPngError)?,
+    );
+    Ok(result)
+}
+
+/// The built-in image captcha; solutions are kept in the cache.
+struct ImageCaptcha {
+    /// Bounds the blocking tasks generating captchas, so a flood of
+    /// registration attempts can't tie up the whole blocking pool.
+    permits: Arc<Semaphore>,
+}
+
+impl ImageCaptcha {
+    fn new(concurrency: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl CaptchaProvider for ImageCaptcha {
+    /// Generates a captcha on tokio's threadpool and stores it in the cache.
+    /// Fails right away if too many captchas are being generated already.
+    async fn challenge(&self, cache: &Cache) -> Result<Challenge> {
+        let permit = self
+            .permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Error::CaptchaBusy)?;
+        let (solution, base64) = rocket::tokio::task::spawn_blocking(move || {
+            // Only given back once the captcha is done, even if nobody
+            // waits for it anymore
+            let _permit = permit;
+            generate_captcha()
+        })
+        .await??;
+        let id = Uuid::new_v4();
+        cache.register_captcha(id, &solution);
+        Ok(Challenge::Image { id, base64 })
+    }
+    async fn verify(&self, cache: &Cache, id: Uuid, solution: &str) -> bool {
+        cache.validate_captcha(id, solution)
+    }
+}
+
+/// Cloudflare Turnstile or hCaptcha; both speak the same siteverify protocol.
+struct ExternalCaptcha {
+    kind: CaptchaKind,
+    site_key: String,
+    secret: String,
+    verify_url: String,
+    client: reqwest::Client,
+}
+
+impl ExternalCaptcha {
+    async fn siteverify(&self, token: &str) -> reqwest::Result<bool> {
+        #[derive(Deserialize)]
+        struct VerifyResponse {
+            success: bool,
+        }
+        let response: VerifyResponse = self
+            .client
+            .post(&self.verify_url)
+            .form(&[("secret", self.secret.as_str()), ("response", token)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.success)
+    }
+}
+
+#[rocket::async_trait]
+impl CaptchaProvider for ExternalCaptcha {
+    async fn challenge(&self, cache: &Cache) -> Result<Challenge> {
+        // The id only makes sure the form was actually shown to someone
+        let id = Uuid::new_v4();
+        cache.register_captcha(id, "");
+        Ok(Challenge::Widget {
+            id,
+            provider: self.kind,
+            site_key: self.site_key.clone(),
+        })
+    }
+    /// Unlike the pwned passwords check, this fails closed: if the provider
+    /// can't be reached, nobody can register.
+    async fn verify(&self, cache: &Cache, id: Uuid, solution: &str) -> bool {
+        if !cache.validate_captcha(id, "") || solution.is_empty() {
+            return false;
+        }
+        match self.siteverify(solution).await {
+            Ok(success) => success,
+            Err(e) => {
+                log::warn!("Failed to verify captcha: {}", e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_captcha, CaptchaProvider, Challenge, ImageCaptcha};
+    use crate::{Cache, Error};
+
+    #[test]
+    fn captcha_generation() {
+        // Do it 5 times to be sure.
+        for _ in 0..5 {
+            let (solution, base64) = generate_captcha().expect("captcha generation failed");
+            // Check if it's valid base64
+            assert!(base64::decode(&base64).is_ok());
+            // We always call add_chars(5)
+            assert_eq!(solution.len(), 5);
+            // And I'm pretty sure it should only do alphanumerical characters
+            assert!(solution.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+    }
+
+    #[test]
+    fn captcha_concurrency() {
+        let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+        let cache = Cache::new(10);
+        let captcha = ImageCaptcha::new(1);
+        // One at a time works, over and over
+        for _ in 0..3 {
+            let challenge = runtime.block_on(captcha.challenge(&cache));
+            assert!(matches!(challenge, Ok(Challenge::Image { .. })));
+        }
+        // While the only permit is taken, there's no captcha
+        let permit = captcha.permits.clone().try_acquire_owned().unwrap();
+        let challenge = runtime.block_on(captcha.challenge(&cache));
+        assert!(matches!(challenge, Err(Error::CaptchaBusy)));
+        drop(permit);
+        let challenge = runtime.block_on(captcha.challenge(&cache));
+        assert!(matches!(challenge, Ok(Challenge::Image { .. })));
+    }
+}
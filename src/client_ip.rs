@@ -0,0 +1,109 @@
+//! Finding out which address a request really came from.
+//! Behind a reverse proxy, every request's peer is the proxy itself;
+//! the `trusted_proxies` config option lists the proxies whose
+//! `X-Forwarded-For` header can be believed.
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use rocket::{
+    request::{FromRequest, Outcome},
+    Request,
+};
+
+use crate::Config;
+
+/// Determines the client's address from the immediate peer and the
+/// `X-Forwarded-For` header.
+/// The header is walked from the right, skipping trusted proxies, so a
+/// client can't spoof its address by sending the header itself.
+pub fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let mut client = peer;
+    let hops = forwarded_for
+        .into_iter()
+        .flat_map(|header| header.rsplit(','))
+        .map(str::trim);
+    for hop in hops {
+        match hop.parse() {
+            Ok(ip) => {
+                client = ip;
+                if !is_trusted(&ip) {
+                    break;
+                }
+            }
+            // Garbage in the header; the last good address is all we know
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// The address of the client making the request, respecting the config.
+/// This is `None` if Rocket doesn't know the peer address (e.g. in tests).
+pub fn of_request(request: &Request<'_>) -> Option<IpAddr> {
+    let peer = request.client_ip()?;
+    let trusted = request
+        .rocket()
+        .state::<Config>()
+        .map(|cfg| &cfg.trusted_proxies[..])
+        .unwrap_or_default();
+    let forwarded_for = request.headers().get_one("X-Forwarded-For");
+    Some(client_ip(peer, forwarded_for, trusted))
+}
+
+/// Request guard for the client's address, as found by `of_request`.
+pub struct ClientIp(pub Option<IpAddr>);
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientIp {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientIp(of_request(request)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        // Without a trusted proxy, the header is ignored
+        assert_eq!(
+            client_ip(ip("203.0.113.7"), Some("198.51.100.1"), &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            client_ip(ip("203.0.113.7"), Some("198.51.100.1"), &[]),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn trusted_proxy() {
+        let trusted = ["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()];
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), Some("198.51.100.1"), &trusted),
+            ip("198.51.100.1")
+        );
+        // A client-supplied address in front of the real one doesn't count
+        assert_eq!(
+            client_ip(ip("::1"), Some("1.2.3.4, 198.51.100.1, 10.0.0.2"), &trusted),
+            ip("198.51.100.1")
+        );
+        // Without the header, the proxy is all we know
+        assert_eq!(client_ip(ip("10.0.0.1"), None, &trusted), ip("10.0.0.1"));
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), Some("garbage"), &trusted),
+            ip("10.0.0.1")
+        );
+    }
+}
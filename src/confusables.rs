@@ -0,0 +1,106 @@
+//! Detecting usernames which only differ from another one by characters
+//! that look alike, like `Adm1n` or `Аdmin` (with a Cyrillic А) for `Admin`.
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Maps a character to the plain lowercase letter it's easily mistaken for.
+fn fold(c: char) -> char {
+    match c {
+        '0' | 'о' | 'ο' | 'О' | 'Ο' => 'o',
+        '1' | 'i' | 'I' | '|' | '!' | 'і' | 'І' | 'ӏ' | 'ι' | 'Ι' => 'l',
+        '3' | 'е' | 'Е' | 'ε' | 'Ε' => 'e',
+        '4' | '@' | 'а' | 'А' | 'α' | 'Α' => 'a',
+        '5' | '$' | 'ѕ' | 'Ѕ' => 's',
+        '7' | 'т' | 'Т' | 'τ' | 'Τ' => 't',
+        '8' | 'в' | 'В' | 'β' | 'Β' => 'b',
+        'р' | 'Р' | 'ρ' | 'Ρ' => 'p',
+        'с' | 'С' => 'c',
+        'х' | 'Х' | 'χ' | 'Χ' => 'x',
+        'у' | 'У' | 'γ' => 'y',
+        'к' | 'К' | 'κ' | 'Κ' => 'k',
+        'м' | 'М' | 'Μ' => 'm',
+        'н' | 'Н' | 'Η' => 'h',
+        'Ν' => 'n',
+        'ԁ' => 'd',
+        'ɡ' => 'g',
+        'ν' => 'v',
+        'ω' => 'w',
+        'ζ' | 'Ζ' => 'z',
+        c => c.to_lowercase().next().unwrap_or(c),
+    }
+}
+
+/// What a name looks like, ignoring case, accents, look-alike characters
+/// and separators.
+pub fn skeleton(name: &str) -> String {
+    name.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .filter(|c| !c.is_whitespace() && !matches!(c, '_' | '-' | '.'))
+        .map(fold)
+        .collect::<String>()
+        // These look like single letters in many fonts
+        .replace("rn", "m")
+        .replace("vv", "w")
+}
+
+/// The Levenshtein distance between two strings, in characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the first of the existing names which looks too much like the
+/// new one, i.e. their skeletons are at most `max_distance` edits apart.
+pub fn similar_name<'a>(
+    name: &str,
+    existing: &'a [String],
+    max_distance: usize,
+) -> Option<&'a str> {
+    let name = skeleton(name);
+    existing
+        .iter()
+        .find(|other| distance(&name, &skeleton(other)) <= max_distance)
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{distance, similar_name, skeleton};
+
+    #[test]
+    fn skeletons() {
+        assert_eq!(skeleton("Admin"), skeleton("Adm1n"));
+        assert_eq!(skeleton("Admin"), skeleton("ADMIN"));
+        assert_eq!(skeleton("Admin"), skeleton("Аdmin"));
+        assert_eq!(skeleton("Admin"), skeleton("Âdmín"));
+        assert_eq!(skeleton("Admin"), skeleton("Ad_min"));
+        assert_eq!(skeleton("Admin"), skeleton("Adrnin"));
+        assert_ne!(skeleton("Admin"), skeleton("Admiral"));
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("abc", ""), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("äöü", "aöü"), 1);
+    }
+
+    #[test]
+    fn similar_names() {
+        let existing = vec!["Admin".to_string(), "Someone".to_string()];
+        assert_eq!(similar_name("Adm1n", &existing, 0), Some("Admin"));
+        assert_eq!(similar_name("Admins", &existing, 0), None);
+        assert_eq!(similar_name("Admins", &existing, 1), Some("Admin"));
+        assert_eq!(similar_name("S0me0ne", &existing, 0), Some("Someone"));
+        assert_eq!(similar_name("Newcomer", &existing, 2), None);
+    }
+}
@@ -0,0 +1,69 @@
+use sqlx::{PgConnection, PgPool};
+
+use super::articles::normalize_name;
+use crate::{Error, Result};
+
+#[derive(Debug, serde::Serialize)]
+pub struct Alias {
+    pub name: String,
+    pub article_name: String,
+}
+
+/// Makes `name` point to the article called `target`.
+/// Fails if an article called `name` exists, since articles always win
+/// over aliases.
+pub async fn create(conn: &mut PgConnection, name: &str, target: &str) -> Result<()> {
+    let name = normalize_name(name);
+    if super::articles::id_by_name(conn, &name).await?.is_some() {
+        return Err(Error::AliasNameTaken(name));
+    }
+    let article_id = super::articles::id_by_name(conn, target)
+        .await?
+        .ok_or_else(|| Error::AliasTargetUnknown(target.to_string()))?;
+    let res = sqlx::query!(
+        "INSERT INTO alias(name, article_id) VALUES($1, $2)",
+        name,
+        article_id,
+    )
+    .execute(&mut *conn)
+    .await;
+    if let Err(sqlx::Error::Database(err)) = &res {
+        if err.constraint() == Some("alias_pkey") {
+            return Err(Error::AliasNameTaken(name));
+        }
+    }
+    res?;
+    Ok(())
+}
+
+pub async fn delete(conn: &mut PgConnection, name: &str) -> Result<()> {
+    sqlx::query!("DELETE FROM alias WHERE name = $1", normalize_name(name))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Returns the name of the article the given alias points to, if it is one.
+pub async fn resolve(pool: &PgPool, name: &str) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT a.name
+        FROM alias al
+        INNER JOIN article a ON a.id = al.article_id
+        WHERE al.name = $1",
+        normalize_name(name)
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn list(pool: &PgPool) -> Result<Vec<Alias>> {
+    Ok(sqlx::query_as!(
+        Alias,
+        "SELECT al.name, a.name AS article_name
+        FROM alias al
+        INNER JOIN article a ON a.id = al.article_id
+        ORDER BY al.name"
+    )
+    .fetch_all(pool)
+    .await?)
+}
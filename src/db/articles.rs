@@ -1,10 +1,21 @@
 use chrono::{DateTime, NaiveDateTime, Utc};
+use rocket::form::{self, FromFormField, ValueField};
 use serde::Serialize;
 use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
 use crate::Result;
 
+/// Articles named like this are content templates, which only admins can edit.
+pub const TEMPLATE_PREFIX: &str = "Template:";
+/// What's left of a revision after an admin redacted it.
+pub const REDACTED_CONTENT: &str = "*This revision's content was removed by an administrator.*";
+/// The prefix of the pages new users can get when they register.
+pub const USER_PREFIX: &str = "User:";
+/// The prefix of the pages for discussing another page, e.g. `Talk:User:Jo`.
+pub const TALK_PREFIX: &str = "Talk:";
+
 /// A revision id.
 /// This type wraps an article id and a revision number (both u32).
 /// It is used to store an article's revision so it's easier to query
@@ -33,28 +44,59 @@ pub struct RevisionMeta {
     pub date: DateTime<Utc>,
 }
 
+pub struct LatestRevision {
+    pub num: i64,
+    pub content: String,
+    pub created: NaiveDateTime,
+}
+
 pub struct ArticleWithRevision {
     pub id: Uuid,
     pub name: String,
     pub content: String,
     pub rev_created: NaiveDateTime,
+    pub tags: Vec<String>,
+}
+
+/// Normalizes an article name to NFC so visually identical names written
+/// with composed or decomposed characters are the same article.
+/// Every name is passed through this before being stored or looked up.
+pub fn normalize_name(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// How many hex digits of the content hash are shown.
+const SHORT_HASH_LEN: usize = 10;
+
+/// A short hash of a revision's content, for telling at a glance whether
+/// two revisions (even of different articles) are identical.
+pub fn short_hash(content: &str) -> String {
+    use sha1::{Digest, Sha1};
+
+    let mut hash = format!("{:x}", Sha1::digest(content.as_bytes()));
+    hash.truncate(SHORT_HASH_LEN);
+    hash
 }
 
 /// Get the id for the given article name if it exists.
 pub async fn id_by_name(conn: &mut PgConnection, name: &str) -> Result<Option<Uuid>> {
-    Ok(
-        sqlx::query_scalar!("SELECT id FROM article WHERE name = $1", name)
-            .fetch_optional(&mut *conn)
-            .await?,
+    Ok(sqlx::query_scalar!(
+        "SELECT id FROM article WHERE name = $1",
+        normalize_name(name)
     )
+    .fetch_optional(&mut *conn)
+    .await?)
 }
 /// Lists the articles from the database, returning the article name, id and
 /// the latest revision.
+/// The date is the one of the latest edit which isn't minor.
 pub async fn list_articles(pool: &PgPool) -> Result<Vec<ArticleWithRevision>> {
     Ok(sqlx::query_as!(
         ArticleWithRevision,
         r#"SELECT a.id AS "id!", a.name AS "name!", r.content AS "content!",
-        r.created AS "rev_created!"
+        (SELECT MAX(created) FROM revision WHERE article_id = a.id AND NOT minor)
+            AS "rev_created!",
+        ARRAY(SELECT tag FROM article_tag WHERE article_id = a.id ORDER BY tag) AS "tags!"
         FROM article a
         INNER JOIN revision r ON (a.id = r.article_id)
         WHERE r.num = (SELECT MAX(num) FROM revision WHERE article_id = a.id)"#
@@ -63,30 +105,315 @@ pub async fn list_articles(pool: &PgPool) -> Result<Vec<ArticleWithRevision>> {
     .await?)
 }
 
+/// Like `list_articles`, but only for the articles with the given names.
+pub async fn list_by_name(pool: &PgPool, names: &[String]) -> Result<Vec<ArticleWithRevision>> {
+    let names = names
+        .iter()
+        .map(|name| normalize_name(name))
+        .collect::<Vec<_>>();
+    Ok(sqlx::query_as!(
+        ArticleWithRevision,
+        r#"SELECT a.id AS "id!", a.name AS "name!", r.content AS "content!",
+        (SELECT MAX(created) FROM revision WHERE article_id = a.id AND NOT minor)
+            AS "rev_created!",
+        ARRAY(SELECT tag FROM article_tag WHERE article_id = a.id ORDER BY tag) AS "tags!"
+        FROM article a
+        INNER JOIN revision r ON (a.id = r.article_id)
+        WHERE r.num = (SELECT MAX(num) FROM revision WHERE article_id = a.id)
+        AND a.name = ANY($1)"#,
+        &names,
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
 #[derive(Serialize)]
 pub struct ListRevision {
     pub num: i64,
+    /// The revision before this one, if there is any.
+    pub prev_num: Option<i64>,
     pub author_name: String,
     pub date: NaiveDateTime,
 }
-/// Retrieves the list of revision ids for the given article id.
+/// Which way revisions are listed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionOrder {
+    /// Oldest first.
+    #[default]
+    Asc,
+    Desc,
+}
+// Not derived, since the derive can't cope with our `Result` being imported
+impl<'v> FromFormField<'v> for RevisionOrder {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        if field.value.eq_ignore_ascii_case("asc") {
+            Ok(Self::Asc)
+        } else if field.value.eq_ignore_ascii_case("desc") {
+            Ok(Self::Desc)
+        } else {
+            Err(form::Error::validation("unknown order").into())
+        }
+    }
+}
+
+/// Retrieves the list of revision ids for the given article id, optionally
+/// only those by the given author.
 /// Returns Ok(empty Vec) when the article doesn't exist.
 /// Returns RevisionMeta because loading the revision's content doesn't
 /// make sense for listing the revisions.
-pub async fn list_revisions(pool: &PgPool, article_name: &str) -> Result<Vec<ListRevision>> {
+pub async fn list_revisions(
+    pool: &PgPool,
+    article_name: &str,
+    author: Option<&str>,
+    order: RevisionOrder,
+) -> Result<Vec<ListRevision>> {
+    // The previous revision is looked up before filtering, so it's always
+    // the one right before, no matter who wrote it
     Ok(sqlx::query_as!(
         ListRevision,
-        r#"SELECT r.num, u.name AS author_name, r.created AS date
+        r#"SELECT num AS "num!", prev_num, author_name AS "author_name!", date AS "date!"
+        FROM (
+            SELECT r.num, LAG(r.num) OVER (ORDER BY r.num) AS prev_num,
+                u.name AS author_name, r.created AS date
+            FROM revision r
+            INNER JOIN "user" u ON u.id = r.author_id
+            WHERE article_id = (SELECT id FROM article WHERE name = $1)
+        ) revs
+        WHERE $2::TEXT IS NULL OR author_name = $2
+        ORDER BY CASE WHEN $3 THEN num END DESC, num ASC"#,
+        normalize_name(article_name),
+        author,
+        order == RevisionOrder::Desc,
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentChange {
+    pub article_name: String,
+    pub num: i64,
+    pub author_name: String,
+    pub date: NaiveDateTime,
+}
+/// Returns the newest revisions of all articles, newest first.
+/// Minor edits are left out.
+pub async fn recent_changes(pool: &PgPool, limit: i64) -> Result<Vec<RecentChange>> {
+    Ok(sqlx::query_as!(
+        RecentChange,
+        r#"SELECT a.name AS article_name, r.num, u.name AS author_name, r.created AS date
+        FROM revision r
+        INNER JOIN article a ON a.id = r.article_id
+        INNER JOIN "user" u ON u.id = r.author_id
+        WHERE NOT r.minor
+        ORDER BY r.created DESC
+        LIMIT $1"#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Editor {
+    pub name: String,
+    pub edits: i64,
+}
+/// Returns up to `limit` of the article's authors, with the most edits first.
+pub async fn top_editors(pool: &PgPool, article_name: &str, limit: i64) -> Result<Vec<Editor>> {
+    Ok(sqlx::query_as!(
+        Editor,
+        r#"SELECT u.name, COUNT(*) AS "edits!"
         FROM revision r
         INNER JOIN "user" u ON u.id = r.author_id
         WHERE article_id = (SELECT id FROM article WHERE name = $1)
-        ORDER BY r.num ASC"#,
-        article_name
+        GROUP BY u.name
+        ORDER BY COUNT(*) DESC, MAX(r.created) DESC
+        LIMIT $2"#,
+        normalize_name(article_name),
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArticleInfo {
+    pub created: DateTime<Utc>,
+    pub last_edited: DateTime<Utc>,
+    pub revision_count: i64,
+    /// Everyone who edited the article, with the most edits first.
+    pub authors: Vec<String>,
+    /// Both counted in the current revision.
+    pub word_count: usize,
+    pub byte_size: usize,
+    /// The current revision's `short_hash`.
+    pub content_hash: String,
+}
+/// Collects some statistics about the article, if it exists.
+pub async fn info(pool: &PgPool, article_name: &str) -> Result<Option<ArticleInfo>> {
+    let stats = sqlx::query!(
+        r#"SELECT MIN(r.created) AS "created!", MAX(r.created) AS "last_edited!",
+        COUNT(*) AS "revision_count!"
+        FROM revision r
+        INNER JOIN article a ON a.id = r.article_id
+        WHERE a.name = $1
+        GROUP BY a.id"#,
+        normalize_name(article_name)
+    )
+    .fetch_optional(pool)
+    .await?;
+    let (stats, current) = match (stats, get_current_rev(pool, article_name).await?) {
+        (Some(stats), Some(current)) => (stats, current),
+        _ => return Ok(None),
+    };
+    let authors = top_editors(pool, article_name, i64::MAX)
+        .await?
+        .into_iter()
+        .map(|editor| editor.name)
+        .collect();
+    Ok(Some(ArticleInfo {
+        created: DateTime::from_utc(stats.created, Utc),
+        last_edited: DateTime::from_utc(stats.last_edited, Utc),
+        revision_count: stats.revision_count,
+        authors,
+        word_count: current.content.split_whitespace().count(),
+        byte_size: current.content.len(),
+        content_hash: short_hash(&current.content),
+    }))
+}
+
+/// Returns which of the given names are articles or aliases.
+pub async fn existing_names(pool: &PgPool, names: &[String]) -> Result<Vec<String>> {
+    let names = names
+        .iter()
+        .map(|name| normalize_name(name))
+        .collect::<Vec<_>>();
+    Ok(sqlx::query_scalar!(
+        r#"SELECT name AS "name!" FROM article WHERE name = ANY($1)
+        UNION SELECT name FROM alias WHERE name = ANY($1)"#,
+        &names
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Returns the namespaces in use, i.e. the distinct non-empty parts of
+/// article names before their first colon.
+pub async fn namespaces(pool: &PgPool) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT DISTINCT split_part(name, ':', 1) AS "namespace!" FROM article
+        WHERE position(':' IN name) > 1
+        ORDER BY 1"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The names of the content templates new articles can be started from,
+/// without the prefix.
+pub async fn templates(pool: &PgPool) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT substr(name, length($1) + 1) AS "name!" FROM article
+        WHERE name LIKE $2 || '_%' ESCAPE '\'
+        ORDER BY 1"#,
+        TEMPLATE_PREFIX,
+        escape_like(TEMPLATE_PREFIX),
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// How many revisions the user wrote, including pruned ones.
+pub async fn edit_count(pool: &PgPool, user_id: Uuid) -> Result<i64> {
+    Ok(
+        sqlx::query_scalar!(r#"SELECT edit_count FROM "user" WHERE id = $1"#, user_id)
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(0),
+    )
+}
+
+/// Counts a new revision for its author.
+async fn count_edit(conn: &mut PgConnection, author_id: Uuid) -> Result<()> {
+    sqlx::query!(
+        r#"UPDATE "user" SET edit_count = edit_count + 1 WHERE id = $1"#,
+        author_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// The names of the articles the user created, i.e. wrote the first
+/// revision of; later edits don't count.
+pub async fn created_by(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT name FROM article WHERE creator_id = $1 ORDER BY name",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The names of the articles, most recently edited first; all of them
+/// without a limit. Minor edits don't count.
+pub async fn recently_edited(pool: &PgPool, limit: Option<i64>) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT a.name AS "name!" FROM article a
+        INNER JOIN revision r ON r.article_id = a.id
+        WHERE NOT r.minor
+        GROUP BY a.id
+        ORDER BY MAX(r.created) DESC
+        LIMIT $1"#,
+        limit
     )
     .fetch_all(pool)
     .await?)
 }
 
+/// Escapes the wildcards in a `LIKE` pattern, for use with `ESCAPE '\'`.
+fn escape_like(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Up to `limit` article names starting with the prefix, in order.
+/// Uses the `text_pattern_ops` index on the name, so this stays fast.
+pub async fn names_starting_with(pool: &PgPool, prefix: &str, limit: i64) -> Result<Vec<String>> {
+    let pattern = escape_like(prefix);
+    Ok(sqlx::query_scalar!(
+        r"SELECT name FROM article
+        WHERE name LIKE $1 || '%' ESCAPE '\'
+        ORDER BY name
+        LIMIT $2",
+        pattern,
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The newest revision of the article with the given id.
+pub async fn latest_revision(
+    conn: &mut PgConnection,
+    article_id: Uuid,
+) -> Result<Option<LatestRevision>> {
+    Ok(sqlx::query_as!(
+        LatestRevision,
+        "SELECT num, content, created FROM revision
+        WHERE article_id = $1
+        ORDER BY num DESC
+        LIMIT 1",
+        article_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?)
+}
+
 /// Get the current revision for the given article id if it exists.
 /// Will return None if the article doesn't exist.
 pub async fn get_current_rev(pool: &PgPool, article_name: &str) -> Result<Option<DisplayRevision>> {
@@ -98,7 +425,25 @@ pub async fn get_current_rev(pool: &PgPool, article_name: &str) -> Result<Option
         INNER JOIN "user" u ON (u.id = r.author_id)
         WHERE a.name = $1
         AND r.num = (SELECT MAX(num) FROM revision WHERE article_id = a.id)"#,
-        article_name,
+        normalize_name(article_name),
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+/// Like `get_current_rev`, but skipping minor edits.
+pub async fn get_last_major_rev(
+    pool: &PgPool,
+    article_name: &str,
+) -> Result<Option<DisplayRevision>> {
+    Ok(sqlx::query_as!(
+        DisplayRevision,
+        r#"SELECT r.num AS rev_id, u.name AS author_name, r.content, r.created
+        FROM article a
+        INNER JOIN revision r ON (a.id = r.article_id)
+        INNER JOIN "user" u ON (u.id = r.author_id)
+        WHERE a.name = $1
+        AND r.num = (SELECT MAX(num) FROM revision WHERE article_id = a.id AND NOT minor)"#,
+        normalize_name(article_name),
     )
     .fetch_optional(pool)
     .await?)
@@ -116,12 +461,59 @@ pub async fn get_revision(
         INNER JOIN "user" u ON u.id = r.author_id
         WHERE r.article_id = (SELECT id FROM article WHERE name = $1)
         AND r.num = $2"#,
-        article_name,
+        normalize_name(article_name),
+        num,
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+/// The newest revision before the given one. That's not necessarily
+/// `num - 1`, since old revisions can be pruned.
+pub async fn get_previous_revision(
+    pool: &PgPool,
+    article_name: &str,
+    num: i64,
+) -> Result<Option<DisplayRevision>> {
+    Ok(sqlx::query_as!(
+        DisplayRevision,
+        r#"SELECT r.num AS "rev_id!", r.content AS "content!",
+            u.name AS "author_name!", r.created AS "created!"
+        FROM revision r
+        INNER JOIN "user" u ON u.id = r.author_id
+        WHERE r.article_id = (SELECT id FROM article WHERE name = $1)
+        AND r.num = (
+            SELECT MAX(num) FROM revision
+            WHERE article_id = r.article_id AND num < $2
+        )"#,
+        normalize_name(article_name),
         num,
     )
     .fetch_optional(pool)
     .await?)
 }
+/// Replaces a revision's content with `REDACTED_CONTENT` for good.
+/// Returns false if there's no such revision.
+pub async fn redact(conn: &mut PgConnection, article_id: Uuid, num: i64) -> Result<bool> {
+    let res = sqlx::query!(
+        "UPDATE revision SET content = $3 WHERE article_id = $1 AND num = $2",
+        article_id,
+        num,
+        REDACTED_CONTENT,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+/// When the article was last edited, not counting minor edits.
+pub async fn last_major_edit(conn: &mut PgConnection, article_id: Uuid) -> Result<NaiveDateTime> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT MAX(created) AS "created!" FROM revision
+        WHERE article_id = $1 AND NOT minor"#,
+        article_id
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
 /// Create an empty article with no revisions.
 pub async fn create(
     txn: &mut Transaction<'_, Postgres>,
@@ -134,7 +526,7 @@ pub async fn create(
         "INSERT INTO article(id, name, creator_id)
         VALUES($1, $2, $3)",
         id,
-        name,
+        normalize_name(name),
         author_id,
     )
     .execute(&mut *txn)
@@ -151,6 +543,7 @@ pub async fn create(
     )
     .fetch_one(&mut *txn)
     .await?;
+    count_edit(&mut *txn, author_id).await?;
     Ok((
         RevId(id, rev_num),
         RevisionMeta {
@@ -165,7 +558,7 @@ pub async fn create(
 pub async fn change_name(conn: &mut PgConnection, article_id: Uuid, new_name: &str) -> Result<()> {
     sqlx::query!(
         "UPDATE article SET name = $1 WHERE id = $2",
-        new_name,
+        normalize_name(new_name),
         article_id,
     )
     .execute(&mut *conn)
@@ -174,23 +567,62 @@ pub async fn change_name(conn: &mut PgConnection, article_id: Uuid, new_name: &s
 }
 /// Add a new revision. Uses the current date and time as the date.
 /// The core part of this type as it touches *all* of its trees.
+///
+/// If `max_revisions` isn't 0, the oldest revisions beyond it are deleted,
+/// except for the first one.
+///
+/// Minor revisions are stored like any other, but leave the date the
+/// article is listed with alone.
+///
+/// Concurrent saves of the same article wait for each other, so each one
+/// gets the next number; this relies on `conn` being a transaction.
 pub async fn add_revision(
     conn: &mut PgConnection,
     article_id: Uuid,
     author_id: Uuid,
     content: &str,
+    minor: bool,
+    max_revisions: u32,
 ) -> Result<(RevId, RevisionMeta)> {
+    // Held until the transaction ends; the insert only starts once the
+    // other transaction's revision is visible to it
+    sqlx::query!(
+        "SELECT id FROM article WHERE id = $1 FOR UPDATE",
+        article_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
     let (rev_num, date) = sqlx::query!(
-        "INSERT INTO revision(article_id, num, content, author_id)
-        VALUES ($1, (SELECT MAX(num) + 1 FROM revision WHERE article_id = $1), $2, $3)
+        "INSERT INTO revision(article_id, num, content, author_id, minor)
+        VALUES ($1, (SELECT MAX(num) + 1 FROM revision WHERE article_id = $1), $2, $3, $4)
         RETURNING num, created",
         article_id,
         content,
         author_id,
+        minor,
     )
     .fetch_one(&mut *conn)
     .await
     .map(|r| (r.num, DateTime::from_utc(r.created, Utc)))?;
+    count_edit(&mut *conn, author_id).await?;
+
+    if max_revisions > 0 {
+        // The first and the new revision are always kept
+        let keep_after_first = i64::from(max_revisions.max(2)) - 1;
+        sqlx::query!(
+            "DELETE FROM revision WHERE article_id = $1 AND num IN (
+                SELECT num FROM revision
+                WHERE article_id = $1
+                AND num > (SELECT MIN(num) FROM revision WHERE article_id = $1)
+                ORDER BY num DESC
+                OFFSET $2
+            )",
+            article_id,
+            keep_after_first,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
 
     let id = RevId(article_id, rev_num);
     let revision = RevisionMeta { author_id, date };
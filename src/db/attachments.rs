@@ -0,0 +1,50 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::Result;
+
+pub struct Attachment {
+    pub id: Uuid,
+    pub content_type: String,
+}
+
+/// Records a new attachment; the file itself has to be stored separately.
+pub async fn create(
+    pool: &PgPool,
+    article_id: Uuid,
+    uploader_id: Uuid,
+    content_type: &str,
+    size: i64,
+) -> Result<Uuid> {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO attachment(id, article_id, uploader_id, content_type, size)
+        VALUES($1, $2, $3, $4, $5)",
+        id,
+        article_id,
+        uploader_id,
+        content_type,
+        size,
+    )
+    .execute(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Removes an attachment's record, e.g. when storing its file failed.
+pub async fn delete(pool: &PgPool, id: Uuid) -> Result<()> {
+    sqlx::query!("DELETE FROM attachment WHERE id = $1", id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get(pool: &PgPool, id: Uuid) -> Result<Option<Attachment>> {
+    Ok(sqlx::query_as!(
+        Attachment,
+        "SELECT id, content_type FROM attachment WHERE id = $1",
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
@@ -0,0 +1,178 @@
+//! The audit log of admin actions.
+use chrono::NaiveDateTime;
+use rocket::form::{self, FromFormField, ValueField};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::Result;
+
+/// The kinds of entries in the audit log.
+/// Filters can only ever use one of these as the action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    ChangeSettings,
+    ImportArticles,
+    MergeArticles,
+    CreateAlias,
+    DeleteAlias,
+    SetFeatured,
+    SetAnnouncement,
+    SetWelcome,
+    TagArticles,
+    RenameTag,
+    MergeTags,
+    RepairIntegrity,
+    RedactRevision,
+    SetSynonyms,
+    ApproveRevision,
+    RejectRevision,
+    Optimize,
+    CreateInvite,
+}
+impl AuditAction {
+    pub const ALL: &'static [AuditAction] = &[
+        Self::ChangeSettings,
+        Self::ImportArticles,
+        Self::MergeArticles,
+        Self::CreateAlias,
+        Self::DeleteAlias,
+        Self::SetFeatured,
+        Self::SetAnnouncement,
+        Self::SetWelcome,
+        Self::TagArticles,
+        Self::RenameTag,
+        Self::MergeTags,
+        Self::RepairIntegrity,
+        Self::RedactRevision,
+        Self::SetSynonyms,
+        Self::ApproveRevision,
+        Self::RejectRevision,
+        Self::Optimize,
+        Self::CreateInvite,
+    ];
+
+    /// How the action is stored, serialized and given in filters.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ChangeSettings => "change_settings",
+            Self::ImportArticles => "import_articles",
+            Self::MergeArticles => "merge_articles",
+            Self::CreateAlias => "create_alias",
+            Self::DeleteAlias => "delete_alias",
+            Self::SetFeatured => "set_featured",
+            Self::SetAnnouncement => "set_announcement",
+            Self::SetWelcome => "set_welcome",
+            Self::TagArticles => "tag_articles",
+            Self::RenameTag => "rename_tag",
+            Self::MergeTags => "merge_tags",
+            Self::RepairIntegrity => "repair_integrity",
+            Self::RedactRevision => "redact_revision",
+            Self::SetSynonyms => "set_synonyms",
+            Self::ApproveRevision => "approve_revision",
+            Self::RejectRevision => "reject_revision",
+            Self::Optimize => "optimize",
+            Self::CreateInvite => "create_invite",
+        }
+    }
+}
+impl Serialize for AuditAction {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+impl<'v> FromFormField<'v> for AuditAction {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|action| action.as_str() == field.value)
+            .ok_or_else(|| form::Error::validation("unknown audit action").into())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    /// What the action was done to, e.g. an article name.
+    pub target: String,
+    pub created: NaiveDateTime,
+}
+
+/// Restricts which entries are listed; every filter left out matches all.
+#[derive(Debug, Default)]
+pub struct AuditFilter<'a> {
+    pub actor: Option<&'a str>,
+    pub action: Option<AuditAction>,
+    pub from: Option<NaiveDateTime>,
+    /// Exclusive.
+    pub until: Option<NaiveDateTime>,
+}
+
+/// Logs the action; call this in the transaction doing it, so there's an
+/// entry exactly if the action happened.
+pub async fn record(
+    conn: &mut PgConnection,
+    actor_id: Uuid,
+    action: AuditAction,
+    target: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO audit_log(actor_id, action, target) VALUES ($1, $2, $3)",
+        actor_id,
+        action.as_str(),
+        target,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Lists the matching entries, newest first.
+pub async fn list(
+    pool: &PgPool,
+    filter: &AuditFilter<'_>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditEntry>> {
+    Ok(sqlx::query_as!(
+        AuditEntry,
+        r#"SELECT l.id, u.name AS actor, l.action, l.target, l.created
+        FROM audit_log l
+        INNER JOIN "user" u ON u.id = l.actor_id
+        WHERE ($1::TEXT IS NULL OR u.name = $1)
+        AND ($2::TEXT IS NULL OR l.action = $2)
+        AND ($3::TIMESTAMP IS NULL OR l.created >= $3)
+        AND ($4::TIMESTAMP IS NULL OR l.created < $4)
+        ORDER BY l.created DESC, l.id DESC
+        LIMIT $5 OFFSET $6"#,
+        filter.actor,
+        filter.action.map(AuditAction::as_str),
+        filter.from,
+        filter.until,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// How many entries match, for paginating them.
+pub async fn count(pool: &PgPool, filter: &AuditFilter<'_>) -> Result<i64> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM audit_log l
+        INNER JOIN "user" u ON u.id = l.actor_id
+        WHERE ($1::TEXT IS NULL OR u.name = $1)
+        AND ($2::TEXT IS NULL OR l.action = $2)
+        AND ($3::TIMESTAMP IS NULL OR l.created >= $3)
+        AND ($4::TIMESTAMP IS NULL OR l.created < $4)"#,
+        filter.actor,
+        filter.action.map(AuditAction::as_str),
+        filter.from,
+        filter.until,
+    )
+    .fetch_one(pool)
+    .await?)
+}
@@ -0,0 +1,50 @@
+use chrono::NaiveDateTime;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::Result;
+
+pub struct Draft {
+    pub content: String,
+    pub updated: NaiveDateTime,
+}
+
+/// Stores the given content as the user's draft for the given article,
+/// replacing any previous draft.
+pub async fn save(pool: &PgPool, user_id: Uuid, article_name: &str, content: &str) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO draft(user_id, article_name, content)
+        VALUES($1, $2, $3)
+        ON CONFLICT(user_id, article_name) DO UPDATE SET content = $3, updated = now()",
+        user_id,
+        article_name,
+        content,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Get the user's draft for the given article, if there is one.
+pub async fn get(pool: &PgPool, user_id: Uuid, article_name: &str) -> Result<Option<Draft>> {
+    Ok(sqlx::query_as!(
+        Draft,
+        "SELECT content, updated FROM draft WHERE user_id = $1 AND article_name = $2",
+        user_id,
+        article_name,
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Removes the user's draft for the given article, if there is one.
+pub async fn delete(conn: &mut PgConnection, user_id: Uuid, article_name: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM draft WHERE user_id = $1 AND article_name = $2",
+        user_id,
+        article_name,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Consistency checks for data the foreign keys don't (or didn't always)
+//! protect, e.g. after manual edits or a partially applied delete.
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::Result;
+
+#[derive(Debug, Serialize)]
+pub struct OrphanedRevision {
+    pub article_id: Uuid,
+    pub num: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmptyArticle {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrphanedSession {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub orphaned_revisions: Vec<OrphanedRevision>,
+    pub empty_articles: Vec<EmptyArticle>,
+    pub orphaned_sessions: Vec<OrphanedSession>,
+}
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_revisions.is_empty()
+            && self.empty_articles.is_empty()
+            && self.orphaned_sessions.is_empty()
+    }
+}
+
+/// Revisions whose article doesn't exist.
+pub async fn orphaned_revisions(pool: &PgPool) -> Result<Vec<OrphanedRevision>> {
+    Ok(sqlx::query_as!(
+        OrphanedRevision,
+        "SELECT r.article_id, r.num FROM revision r
+        LEFT JOIN article a ON a.id = r.article_id
+        WHERE a.id IS NULL
+        ORDER BY r.article_id, r.num"
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Articles without any revision, which can't be displayed.
+pub async fn empty_articles(pool: &PgPool) -> Result<Vec<EmptyArticle>> {
+    Ok(sqlx::query_as!(
+        EmptyArticle,
+        "SELECT a.id, a.name FROM article a
+        WHERE NOT EXISTS (SELECT 1 FROM revision r WHERE r.article_id = a.id)
+        ORDER BY a.name"
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Sessions whose user doesn't exist.
+pub async fn orphaned_sessions(pool: &PgPool) -> Result<Vec<OrphanedSession>> {
+    Ok(sqlx::query_as!(
+        OrphanedSession,
+        r#"SELECT s.session_id AS "session_id!", s.user_id FROM session s
+        LEFT JOIN "user" u ON u.id = s.user_id
+        WHERE u.id IS NULL"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn check(pool: &PgPool) -> Result<Report> {
+    Ok(Report {
+        orphaned_revisions: orphaned_revisions(pool).await?,
+        empty_articles: empty_articles(pool).await?,
+        orphaned_sessions: orphaned_sessions(pool).await?,
+    })
+}
+
+/// Deletes everything `check` would report, in the caller's transaction.
+/// Empty articles take their tags, aliases and attachments with them; the
+/// ids of those attachments are returned so their files can be removed too.
+pub async fn repair(conn: &mut PgConnection) -> Result<Vec<Uuid>> {
+    sqlx::query!(
+        "DELETE FROM revision r
+        WHERE NOT EXISTS (SELECT 1 FROM article a WHERE a.id = r.article_id)"
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM article_tag WHERE article_id IN (
+            SELECT a.id FROM article a
+            WHERE NOT EXISTS (SELECT 1 FROM revision r WHERE r.article_id = a.id)
+        )"
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM alias WHERE article_id IN (
+            SELECT a.id FROM article a
+            WHERE NOT EXISTS (SELECT 1 FROM revision r WHERE r.article_id = a.id)
+        )"
+    )
+    .execute(&mut *conn)
+    .await?;
+    let attachments = sqlx::query_scalar!(
+        "DELETE FROM attachment WHERE article_id IN (
+            SELECT a.id FROM article a
+            WHERE NOT EXISTS (SELECT 1 FROM revision r WHERE r.article_id = a.id)
+        ) RETURNING id"
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM article a
+        WHERE NOT EXISTS (SELECT 1 FROM revision r WHERE r.article_id = a.id)"
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM session s
+        WHERE NOT EXISTS (SELECT 1 FROM "user" u WHERE u.id = s.user_id)"#
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(attachments)
+}
+
+/// Reclaims the space of deleted rows and updates the query planner's
+/// statistics. This can't run inside a transaction.
+pub async fn vacuum(pool: &PgPool) -> Result<()> {
+    sqlx::query("VACUUM ANALYZE").execute(pool).await?;
+    Ok(())
+}
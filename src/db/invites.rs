@@ -0,0 +1,59 @@
+//! Invite codes, which registering needs while the wiki is invite-only.
+//! Every code can be used for registering once.
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::Result;
+
+#[derive(Debug, Serialize)]
+pub struct Invite {
+    pub code: Uuid,
+    pub created_by: String,
+    pub created: NaiveDateTime,
+}
+
+/// Creates a new invite code.
+pub async fn create(conn: &mut PgConnection, created_by: Uuid) -> Result<Uuid> {
+    let code = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO invite(code, created_by) VALUES($1, $2)",
+        code,
+        created_by
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(code)
+}
+
+/// The codes which weren't used yet, newest first.
+pub async fn list(pool: &PgPool) -> Result<Vec<Invite>> {
+    Ok(sqlx::query_as!(
+        Invite,
+        r#"SELECT i.code, u.name AS created_by, i.created
+        FROM invite i
+        INNER JOIN "user" u ON u.id = i.created_by
+        ORDER BY i.created DESC"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Whether the code can still be used.
+pub async fn exists(pool: &PgPool, code: Uuid) -> Result<bool> {
+    Ok(
+        sqlx::query_scalar!("SELECT code FROM invite WHERE code = $1", code)
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+    )
+}
+
+/// Uses up the code, returning whether it could still be used.
+pub async fn take(conn: &mut PgConnection, code: Uuid) -> Result<bool> {
+    let res = sqlx::query!("DELETE FROM invite WHERE code = $1", code)
+        .execute(&mut *conn)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
@@ -3,13 +3,22 @@ use rocket::{
     request::{FromRequest, Outcome},
     Request,
 };
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::Result;
 
+pub mod aliases;
 pub mod articles;
+pub mod attachments;
+pub mod audit;
 use articles::{ArticleWithRevision, DisplayRevision};
+pub mod drafts;
+pub mod integrity;
+pub mod invites;
+pub mod pending;
+pub mod synonyms;
+pub mod tags;
 pub mod users;
 use users::UserSession;
 
@@ -26,6 +35,10 @@ impl std::ops::Deref for Db {
 /// Settings keys
 mod flags {
     pub const REGISTRATION_ENABLED: &str = "global:registration_enabled";
+    pub const FEATURED_ARTICLE: &str = "global:featured_article";
+    pub const ANNOUNCEMENT: &str = "global:announcement";
+    pub const WELCOME_MESSAGE: &str = "global:welcome_message";
+    pub const WELCOME_USER_PAGE: &str = "global:welcome_user_page";
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -46,6 +59,40 @@ impl<'r> FromRequest<'r> for EnabledRegistration {
     }
 }
 
+/// A transaction spanning a request.
+/// Unless it's committed, it's rolled back when dropped, so a handler
+/// returning early or with an error leaves no partial writes behind.
+pub struct Txn(Transaction<'static, Postgres>);
+impl Txn {
+    pub async fn commit(self) -> Result<()> {
+        self.0.commit().await?;
+        Ok(())
+    }
+}
+impl std::ops::Deref for Txn {
+    type Target = Transaction<'static, Postgres>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl std::ops::DerefMut for Txn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Txn {
+    type Error = crate::Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        use crate::error::IntoOutcomeHack;
+        use rocket::outcome::IntoOutcome;
+        let db: &Db = try_outcome!(request.rocket().state().or_forward(()));
+        let txn = db.begin().await.map_err(crate::Error::from);
+        txn.map(Txn).into_outcome_hack()
+    }
+}
+
 impl Db {
     pub async fn try_connect(uri: &str) -> Result<Self> {
         Ok(Self {
@@ -54,7 +101,8 @@ impl Db {
     }
 
     pub async fn user_name_exists(&self, username: &str) -> Result<bool> {
-        users::name_exists(self, username).await
+        let mut conn = self.acquire().await?;
+        users::name_exists(&mut conn, username).await
     }
     pub async fn user_id_by_name(&self, username: &str) -> Result<Option<Uuid>> {
         Ok(
@@ -63,15 +111,22 @@ impl Db {
                 .await?,
         )
     }
-    pub async fn register_user(&self, username: &str, password: String) -> Result<()> {
-        users::register(self, username, password).await?;
-        Ok(())
+    /// Logs in without "remember me".
+    pub async fn try_login(
+        &self,
+        username: &str,
+        password: String,
+        pepper: Option<&str>,
+        max_sessions: u32,
+    ) -> Result<UserSession> {
+        users::try_login(self, username, password, pepper, max_sessions, None).await
     }
-    pub async fn try_login(&self, username: &str, password: String) -> Result<UserSession> {
-        users::try_login(self, username, password).await
-    }
-    pub async fn get_session_user(&self, session_id: Uuid) -> Result<Option<Uuid>> {
-        users::get_session_user(self, session_id).await
+    pub async fn get_session_user(
+        &self,
+        session_id: Uuid,
+        limits: users::SessionLimits,
+    ) -> Result<Option<Uuid>> {
+        users::get_session_user(self, session_id, limits).await
     }
     pub async fn destroy_session(&self, session_id: Uuid) -> Result<()> {
         users::destroy_session(self, session_id).await
@@ -91,30 +146,103 @@ impl Db {
         articles::get_current_rev(self, article_name).await
     }
 
-    async fn set_flag(&self, flag: &str, value: bool) -> Result<()> {
-        sqlx::query!(
-            "INSERT INTO flags(name, value)
-            VALUES($1, $2)
-            ON CONFLICT(name) DO UPDATE SET value = $2",
-            flag,
-            value
+    async fn flag(&self, flag: &str, default: bool) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar!("SELECT value FROM flags WHERE name = $1", flag)
+                .fetch_optional(&**self)
+                .await?
+                .unwrap_or(default),
         )
-        .execute(&self.pool)
-        .await?;
-        Ok(())
     }
     pub async fn registration_enabled(&self) -> Result<bool> {
-        Ok(sqlx::query_scalar!(
-            "SELECT value FROM flags WHERE name = $1",
-            flags::REGISTRATION_ENABLED
+        self.flag(flags::REGISTRATION_ENABLED, true).await
+    }
+    async fn text_flag(&self, flag: &str) -> Result<Option<String>> {
+        Ok(
+            sqlx::query_scalar!("SELECT text_value FROM flags WHERE name = $1", flag)
+                .fetch_optional(&**self)
+                .await?
+                .flatten(),
         )
-        .fetch_optional(&**self)
-        .await?
-        .unwrap_or(true))
     }
-    pub async fn set_registration_enabled(&self, value: bool) -> Result<()> {
-        self.set_flag(flags::REGISTRATION_ENABLED, value).await
+    /// The article admins pinned to the landing page, if any.
+    pub async fn featured_article(&self) -> Result<Option<String>> {
+        self.text_flag(flags::FEATURED_ARTICLE).await
     }
+    pub async fn announcement(&self) -> Result<Option<String>> {
+        self.text_flag(flags::ANNOUNCEMENT).await
+    }
+    /// The message shown to new users after they registered.
+    pub async fn welcome_message(&self) -> Result<Option<String>> {
+        self.text_flag(flags::WELCOME_MESSAGE).await
+    }
+    /// Whether new users get a `User:<name>` page with the welcome message.
+    pub async fn welcome_user_page(&self) -> Result<bool> {
+        self.flag(flags::WELCOME_USER_PAGE, false).await
+    }
+}
+
+// Settings are changed on a connection instead, so admins can do it in the
+// same transaction as logging it (see `audit::record`).
+async fn set_flag(conn: &mut PgConnection, flag: &str, value: bool) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO flags(name, value)
+        VALUES($1, $2)
+        ON CONFLICT(name) DO UPDATE SET value = $2",
+        flag,
+        value
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+/// Sets a text flag, or removes it if `value` is None.
+async fn set_text_flag(conn: &mut PgConnection, flag: &str, value: Option<&str>) -> Result<()> {
+    match value {
+        Some(value) => {
+            sqlx::query!(
+                "INSERT INTO flags(name, value, text_value)
+                VALUES($1, TRUE, $2)
+                ON CONFLICT(name) DO UPDATE SET text_value = $2",
+                flag,
+                value
+            )
+            .execute(&mut *conn)
+            .await?
+        }
+        None => {
+            sqlx::query!("DELETE FROM flags WHERE name = $1", flag)
+                .execute(&mut *conn)
+                .await?
+        }
+    };
+    Ok(())
+}
+pub async fn set_registration_enabled(conn: &mut PgConnection, value: bool) -> Result<()> {
+    set_flag(conn, flags::REGISTRATION_ENABLED, value).await
+}
+/// Pins an article to the landing page; it has to exist.
+pub async fn set_featured_article(conn: &mut PgConnection, name: Option<&str>) -> Result<()> {
+    let name = match name {
+        Some(name) => {
+            let name = articles::normalize_name(name);
+            if articles::id_by_name(conn, &name).await?.is_none() {
+                return Err(crate::Error::FeaturedArticleUnknown(name));
+            }
+            Some(name)
+        }
+        None => None,
+    };
+    set_text_flag(conn, flags::FEATURED_ARTICLE, name.as_deref()).await
+}
+pub async fn set_announcement(conn: &mut PgConnection, text: Option<&str>) -> Result<()> {
+    set_text_flag(conn, flags::ANNOUNCEMENT, text).await
+}
+pub async fn set_welcome_message(conn: &mut PgConnection, text: Option<&str>) -> Result<()> {
+    set_text_flag(conn, flags::WELCOME_MESSAGE, text).await
+}
+pub async fn set_welcome_user_page(conn: &mut PgConnection, value: bool) -> Result<()> {
+    set_flag(conn, flags::WELCOME_USER_PAGE, value).await
 }
 
 // #[cfg(test)]
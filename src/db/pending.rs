@@ -0,0 +1,99 @@
+//! The moderation queue: edits which only become revisions once an admin
+//! approves them.
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::Result;
+
+#[derive(Debug, Serialize)]
+pub struct PendingRevision {
+    pub id: i64,
+    pub article_name: String,
+    pub author_name: String,
+    pub content: String,
+    pub new_name: Option<String>,
+    pub tags: Option<String>,
+    pub leave_redirect: bool,
+    pub created: NaiveDateTime,
+    /// Whether the article was edited since, so it can't be approved.
+    pub outdated: bool,
+}
+
+/// An edit to be queued.
+pub struct Submission<'a> {
+    pub article_name: &'a str,
+    pub content: &'a str,
+    pub author_id: Uuid,
+    /// The newest revision when the edit was made; `None` for new articles.
+    pub base_rev: Option<i64>,
+    pub new_name: Option<&'a str>,
+    /// Comma-separated, like in the edit form.
+    pub tags: Option<&'a str>,
+    /// Whether renaming leaves a redirect at the old name.
+    pub leave_redirect: bool,
+}
+
+/// What's needed for publishing an approved edit.
+pub struct ApprovedEdit {
+    pub article_name: String,
+    pub author_id: Uuid,
+    pub content: String,
+    pub base_rev: Option<i64>,
+    pub new_name: Option<String>,
+    pub tags: Option<String>,
+    pub leave_redirect: bool,
+}
+
+/// Queues an edit, returning its id.
+pub async fn submit(conn: &mut PgConnection, edit: Submission<'_>) -> Result<i64> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO pending_revision(article_name, content, author_id, base_rev, new_name, tags,
+            leave_redirect)
+        VALUES($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id",
+        edit.article_name,
+        edit.content,
+        edit.author_id,
+        edit.base_rev,
+        edit.new_name,
+        edit.tags,
+        edit.leave_redirect,
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// All queued edits, oldest first.
+pub async fn list(pool: &PgPool) -> Result<Vec<PendingRevision>> {
+    Ok(sqlx::query_as!(
+        PendingRevision,
+        r#"SELECT p.id AS "id!", p.article_name AS "article_name!", u.name AS "author_name!",
+            p.content AS "content!", p.new_name, p.tags, p.leave_redirect AS "leave_redirect!",
+            p.created AS "created!",
+            p.base_rev IS DISTINCT FROM (
+                SELECT MAX(r.num) FROM revision r
+                INNER JOIN article a ON a.id = r.article_id
+                WHERE a.name = p.article_name
+            ) AS "outdated!"
+        FROM pending_revision p
+        INNER JOIN "user" u ON u.id = p.author_id
+        ORDER BY p.id"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Removes the edit from the queue, returning it if it was there.
+/// Both approving and rejecting an edit take it.
+pub async fn take(conn: &mut PgConnection, id: i64) -> Result<Option<ApprovedEdit>> {
+    Ok(sqlx::query_as!(
+        ApprovedEdit,
+        "DELETE FROM pending_revision WHERE id = $1
+        RETURNING article_name, author_id, content, base_rev, new_name, tags, leave_redirect",
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?)
+}
@@ -0,0 +1,26 @@
+//! The synonyms admins defined for searching; see `ArticleIndex::set_synonyms`.
+use sqlx::{PgConnection, PgPool};
+
+use crate::Result;
+
+/// All synonym groups, in the order they were defined.
+pub async fn list(pool: &PgPool) -> Result<Vec<Vec<String>>> {
+    Ok(
+        sqlx::query_scalar!("SELECT terms FROM synonym_group ORDER BY id")
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+/// Replaces all synonym groups with the given ones.
+pub async fn replace(conn: &mut PgConnection, groups: &[Vec<String>]) -> Result<()> {
+    sqlx::query!("DELETE FROM synonym_group")
+        .execute(&mut *conn)
+        .await?;
+    for terms in groups {
+        sqlx::query!("INSERT INTO synonym_group(terms) VALUES ($1)", terms)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
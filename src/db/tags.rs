@@ -0,0 +1,193 @@
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use super::articles::normalize_name;
+use crate::{Error, Result};
+
+#[derive(Debug, serde::Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub articles: i64,
+}
+
+/// Tags are compared case-insensitively and without surrounding whitespace.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Tags all of the given articles at once.
+/// Returns the names of the articles which don't exist; the others are
+/// still tagged.
+pub async fn add(
+    conn: &mut PgConnection,
+    tag: &str,
+    article_names: &[String],
+) -> Result<Vec<String>> {
+    let tag = normalize_tag(tag);
+    if tag.is_empty() {
+        return Err(Error::TagNameEmpty);
+    }
+    let names = article_names
+        .iter()
+        .map(|name| normalize_name(name))
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        "INSERT INTO article_tag(article_id, tag)
+        SELECT id, $1 FROM article WHERE name = ANY($2)
+        ON CONFLICT DO NOTHING",
+        tag,
+        &names,
+    )
+    .execute(&mut *conn)
+    .await?;
+    let found = sqlx::query_scalar!("SELECT name FROM article WHERE name = ANY($1)", &names)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(names
+        .into_iter()
+        .filter(|name| !found.contains(name))
+        .collect())
+}
+
+/// Parses a comma-separated list of tags, leaving out empty ones.
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(normalize_tag)
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Adds the given tags to one article; tags it already has are kept.
+/// Fails if the article would end up with more than `max_tags` tags,
+/// unless that's 0.
+pub async fn add_to_article(
+    conn: &mut PgConnection,
+    article_id: Uuid,
+    tags: &[String],
+    max_tags: usize,
+) -> Result<()> {
+    if max_tags > 0 {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM (
+                SELECT tag FROM article_tag WHERE article_id = $1
+                UNION SELECT UNNEST($2::TEXT[])
+            ) tags"#,
+            article_id,
+            tags,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        if count as usize > max_tags {
+            return Err(Error::TooManyTags(max_tags));
+        }
+    }
+    sqlx::query!(
+        "INSERT INTO article_tag(article_id, tag)
+        SELECT $1, tag FROM UNNEST($2::TEXT[]) AS tag
+        ON CONFLICT DO NOTHING",
+        article_id,
+        tags,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Returns an article's tags in alphabetical order.
+pub async fn for_article(pool: &PgPool, article_name: &str) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT t.tag FROM article_tag t
+        INNER JOIN article a ON a.id = t.article_id
+        WHERE a.name = $1
+        ORDER BY t.tag",
+        normalize_name(article_name)
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Returns the names of all articles with the given tag.
+pub async fn articles(pool: &PgPool, tag: &str) -> Result<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT a.name FROM article_tag t
+        INNER JOIN article a ON a.id = t.article_id
+        WHERE t.tag = $1
+        ORDER BY a.name",
+        normalize_tag(tag)
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Lists every tag with the number of articles it's on.
+pub async fn list(pool: &PgPool) -> Result<Vec<TagCount>> {
+    Ok(sqlx::query_as!(
+        TagCount,
+        r#"SELECT tag, COUNT(*) AS "articles!" FROM article_tag
+        GROUP BY tag
+        ORDER BY tag"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Like `list`, but the most used tags come first.
+pub async fn by_count(pool: &PgPool) -> Result<Vec<TagCount>> {
+    Ok(sqlx::query_as!(
+        TagCount,
+        r#"SELECT tag, COUNT(*) AS "articles!" FROM article_tag
+        GROUP BY tag
+        ORDER BY COUNT(*) DESC, tag"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Renames a tag on all articles.
+/// Fails if an article already has a tag with the new name; use `merge` for
+/// combining two tags.
+pub async fn rename(conn: &mut PgConnection, old: &str, new: &str) -> Result<()> {
+    let new = normalize_tag(new);
+    if new.is_empty() {
+        return Err(Error::TagNameEmpty);
+    }
+    let res = sqlx::query!(
+        "UPDATE article_tag SET tag = $2 WHERE tag = $1",
+        normalize_tag(old),
+        new,
+    )
+    .execute(&mut *conn)
+    .await;
+    if let Err(sqlx::Error::Database(err)) = &res {
+        if err.constraint() == Some("article_tag_pkey") {
+            return Err(Error::TagNameTaken(new));
+        }
+    }
+    res?;
+    Ok(())
+}
+
+/// Moves every article tagged with `from` over to `into`; articles which
+/// already have both keep `into` only once.
+pub async fn merge(conn: &mut PgConnection, from: &str, into: &str) -> Result<()> {
+    let (from, into) = (normalize_tag(from), normalize_tag(into));
+    if into.is_empty() {
+        return Err(Error::TagNameEmpty);
+    }
+    if from == into {
+        return Ok(());
+    }
+    sqlx::query!(
+        "INSERT INTO article_tag(article_id, tag)
+        SELECT article_id, $2 FROM article_tag WHERE tag = $1
+        ON CONFLICT DO NOTHING",
+        from,
+        into,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!("DELETE FROM article_tag WHERE tag = $1", from)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
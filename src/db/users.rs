@@ -1,16 +1,62 @@
 use std::{convert::TryFrom, result::Result as StdResult};
 
+use chrono::{Duration, NaiveDateTime, Utc};
 use rocket::{
+    http::Cookie,
     outcome::try_outcome,
     request::{FromRequest, Outcome},
     tokio::task::spawn_blocking,
     Request,
 };
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 use zeroize::Zeroize;
 
-use crate::{Db, Error, Result};
+use crate::{confusables::skeleton, error::GuardError, Config, Db, Error, Result};
+
+/// The user revisions are attributed to when their real author is unknown,
+/// e.g. because the account was deleted. Nobody can log in as this user.
+pub const GHOST_USER_ID: Uuid = Uuid::nil();
+/// The ghost user's name in the database; it's never shown as is.
+pub const GHOST_USER_NAME: &str = "[ghost]";
+
+/// Returns the name to show for an author; the ghost user is shown with the
+/// configured label instead.
+pub fn display_name<'a>(name: &'a str, ghost_label: &'a str) -> &'a str {
+    if name == GHOST_USER_NAME {
+        ghost_label
+    } else {
+        name
+    }
+}
+
+/// How long sessions stay valid.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimits {
+    /// Counted from logging in.
+    pub max_age: Duration,
+    /// Counted from the last request made with the session.
+    pub idle_timeout: Duration,
+}
+impl From<&Config> for SessionLimits {
+    fn from(cfg: &Config) -> Self {
+        Self {
+            max_age: Duration::seconds(cfg.session_max_age.into()),
+            idle_timeout: Duration::seconds(cfg.session_idle_timeout.into()),
+        }
+    }
+}
+
+/// The cookie logging in the browser with the given session.
+/// Without a lifetime, the cookie is gone once the browser closes; the
+/// session itself still expires on the server either way.
+pub fn session_cookie(session_id: Uuid, lifetime: Option<Duration>) -> Cookie<'static> {
+    let mut cookie = Cookie::new("session_id", base64::encode(session_id.as_bytes()));
+    if let Some(lifetime) = lifetime {
+        cookie.set_max_age(time::Duration::seconds(lifetime.num_seconds()));
+    }
+    cookie
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct UserSession {
@@ -34,8 +80,9 @@ impl<'r> FromRequest<'r> for &'r UserSession {
                     .map(Uuid::from_bytes)?;
                 // ...and also early return if we can't get a db handle...
                 let db: &Db = request.rocket().state()?;
+                let cfg: &Config = request.rocket().state()?;
                 // ...of course, also if querying the session returns an error...
-                let user_id = match db.get_session_user(session_id).await {
+                let user_id = match db.get_session_user(session_id, cfg.into()).await {
                     Err(e) => {
                         log::error!("Error getting session user: {}", e);
                         None
@@ -44,7 +91,17 @@ impl<'r> FromRequest<'r> for &'r UserSession {
                     Ok(user_id) => Some(user_id),
                 }?;
                 // ...and finally, if the session doesn't exist (returns None), also forward.
-                user_id.map(|user_id| UserSession {
+                let user_id = user_id?;
+                if cfg.refresh_session_cookies {
+                    match renew_session_cookie(db, session_id).await {
+                        Ok(Some(lifetime)) => request
+                            .cookies()
+                            .add(session_cookie(session_id, Some(lifetime))),
+                        Ok(None) => {}
+                        Err(e) => log::error!("Error renewing session cookie: {}", e),
+                    }
+                }
+                Some(UserSession {
                     session_id,
                     user_id,
                 })
@@ -62,6 +119,9 @@ pub struct LoggedUser {
     is_admin: bool,
 }
 impl LoggedUser {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
     pub fn is_admin(&self) -> bool {
         self.is_admin
     }
@@ -99,6 +159,11 @@ impl<'r> FromRequest<'r> for LoggedUser {
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct LoggedAdmin(LoggedUser);
+impl LoggedAdmin {
+    pub fn id(&self) -> Uuid {
+        self.0.id()
+    }
+}
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for LoggedAdmin {
     type Error = Error;
@@ -113,7 +178,49 @@ impl<'r> FromRequest<'r> for LoggedAdmin {
     }
 }
 
-fn hash_password(password: &str) -> StdResult<String, argon2::Error> {
+/// A user authenticated for the API, either through an API token in the
+/// `Authorization: Bearer <token>` header or through a regular session.
+#[derive(Debug, Clone, Copy)]
+pub struct ApiUser {
+    pub user_id: Uuid,
+}
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiUser {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        use rocket::outcome::IntoOutcome;
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        let token = match token {
+            Some(token) => token,
+            // Without a token, fall back to the session
+            None => {
+                return match request.guard::<&UserSession>().await.succeeded() {
+                    Some(session) => Outcome::Success(ApiUser {
+                        user_id: session.user_id,
+                    }),
+                    None => GuardError::record(request, Error::ApiUserMissing),
+                }
+            }
+        };
+        let token = match token.trim().parse::<Uuid>() {
+            Ok(token) => token,
+            Err(_) => return GuardError::record(request, Error::InvalidApiToken),
+        };
+        let db: &Db = try_outcome!(request.rocket().state().or_forward(()));
+        match get_token_user(db, token).await {
+            Ok(Some(user_id)) => Outcome::Success(ApiUser { user_id }),
+            Ok(None) => GuardError::record(request, Error::InvalidApiToken),
+            Err(e) => GuardError::record(request, e),
+        }
+    }
+}
+
+/// Hashes the password, mixing in the pepper (argon2's secret) if given.
+fn hash_password(password: &str, pepper: Option<&[u8]>) -> StdResult<String, argon2::Error> {
     fn gen_salt() -> Vec<u8> {
         use rand::Rng;
         rand::thread_rng()
@@ -123,74 +230,239 @@ fn hash_password(password: &str) -> StdResult<String, argon2::Error> {
     }
     let config = argon2::Config {
         variant: argon2::Variant::Argon2i,
+        secret: pepper.unwrap_or_default(),
         ..Default::default()
     };
     let salt = gen_salt();
     argon2::hash_encoded(password.as_bytes(), &salt, &config)
 }
 
-fn verify_password(hash: &str, password: &str) -> StdResult<bool, argon2::Error> {
-    argon2::verify_encoded(hash, password.as_bytes())
+fn verify_password(
+    hash: &str,
+    password: &str,
+    pepper: Option<&[u8]>,
+) -> StdResult<bool, argon2::Error> {
+    argon2::verify_encoded_ext(hash, password.as_bytes(), pepper.unwrap_or_default(), &[])
 }
 
 /// Simply checks if the given username is known to the database.
-pub async fn name_exists(pool: &PgPool, username: &str) -> Result<bool> {
+pub async fn name_exists(conn: &mut PgConnection, username: &str) -> Result<bool> {
     Ok(sqlx::query_scalar!(
         r#"SELECT EXISTS(SELECT 1 FROM "user" WHERE name = $1) AS "a!""#,
         username
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// The usernames which could be within `max_distance` edits of the given
+/// skeleton, judging by the length of theirs.
+pub async fn similar_name_candidates(
+    pool: &PgPool,
+    skeleton: &str,
+    max_distance: usize,
+) -> Result<Vec<String>> {
+    let len = skeleton.chars().count();
+    let min_len = len.saturating_sub(max_distance) as i32;
+    let max_len = len.saturating_add(max_distance).min(i32::MAX as usize) as i32;
+    Ok(sqlx::query_scalar!(
+        r#"SELECT name FROM "user"
+        WHERE id <> $1 AND char_length(name_skeleton) BETWEEN $2 AND $3"#,
+        GHOST_USER_ID,
+        min_len,
+        max_len,
+    )
+    .fetch_all(pool)
     .await?)
 }
 
+/// Computes the skeletons of users which don't have one yet, i.e. the ones
+/// registered before it was stored.
+pub async fn fill_name_skeletons(pool: &PgPool) -> Result<()> {
+    let names = sqlx::query_scalar!(r#"SELECT name FROM "user" WHERE name_skeleton IS NULL"#)
+        .fetch_all(pool)
+        .await?;
+    for name in names {
+        sqlx::query!(
+            r#"UPDATE "user" SET name_skeleton = $2 WHERE name = $1"#,
+            name,
+            skeleton(&name),
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
 // TODO Email
 /// Attempts to register a new user with the given password.
 /// This is a heavy operation due to the password being hashed,
 /// which will be done on a threadpool.
-pub async fn register(pool: &PgPool, username: &str, mut password: String) -> Result<Uuid> {
-    if name_exists(pool, username).await? {
+pub async fn register(
+    conn: &mut PgConnection,
+    username: &str,
+    email: Option<&str>,
+    mut password: String,
+    pepper: Option<&str>,
+) -> Result<Uuid> {
+    if name_exists(conn, username).await? {
         return Err(Error::UserAlreadyExists(username.to_string()));
     }
     let id = Uuid::new_v4();
+    let pw_peppered = pepper.is_some();
+    let pepper = pepper.map(|pepper| pepper.as_bytes().to_vec());
     let pw_hash = spawn_blocking(move || {
-        let res = hash_password(&password);
+        let res = hash_password(&password, pepper.as_deref());
         // Remove the password from RAM
         password.zeroize();
         res
     })
     .await??;
     sqlx::query!(
-        r#"INSERT INTO "user"(id, name, pw_hash, is_admin)
-        VALUES($1, $2, $3, (SELECT COUNT(*) FROM "user") = 0)"#,
+        r#"INSERT INTO "user"(id, name, name_skeleton, email, pw_hash, pw_peppered, is_admin)
+        VALUES($1, $2, $3, $4, $5, $6, (SELECT COUNT(*) FROM "user" WHERE id <> $7) = 0)"#,
         id,
         username,
-        pw_hash
+        skeleton(username),
+        email,
+        pw_hash,
+        pw_peppered,
+        GHOST_USER_ID,
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
     Ok(id)
 }
 
+/// Creates a token for verifying the user's email address with.
+pub async fn create_email_verification(pool: &PgPool, user_id: Uuid) -> Result<Uuid> {
+    let token = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO email_verification(token, user_id) VALUES($1, $2)",
+        token,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(token)
+}
+
+/// Marks the email address of the token's user as verified and uses up the
+/// token. Returns false if the token is unknown.
+pub async fn verify_email(pool: &PgPool, token: Uuid) -> Result<bool> {
+    let mut txn = pool.begin().await?;
+    let user_id = sqlx::query_scalar!(
+        "DELETE FROM email_verification WHERE token = $1 RETURNING user_id",
+        token
+    )
+    .fetch_optional(&mut txn)
+    .await?;
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => return Ok(false),
+    };
+    sqlx::query!(
+        r#"UPDATE "user" SET email_verified = TRUE WHERE id = $1"#,
+        user_id
+    )
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await?;
+    Ok(true)
+}
+
+/// Whether the user verified their email address, or has nothing to verify:
+/// users without an address, and those logging in through an identity
+/// provider, which already vouches for them.
+pub async fn email_verified(pool: &PgPool, user_id: Uuid) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT email_verified OR email IS NULL OR oidc_subject IS NOT NULL AS "verified!"
+        FROM "user" WHERE id = $1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(false))
+}
+
+/// When the user registered, if they exist.
+pub async fn registered_at(pool: &PgPool, user_id: Uuid) -> Result<Option<NaiveDateTime>> {
+    Ok(
+        sqlx::query_scalar!(r#"SELECT created FROM "user" WHERE id = $1"#, user_id)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+/// Records a visit of the given user, returning when the previous one was.
+pub async fn record_visit(pool: &PgPool, user_id: Uuid) -> Result<Option<NaiveDateTime>> {
+    Ok(sqlx::query_scalar!(
+        r#"UPDATE "user" u SET last_visit = NOW()
+        FROM "user" previous
+        WHERE u.id = $1 AND previous.id = u.id
+        RETURNING previous.last_visit"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RegisteredUser {
+    pub name: String,
+    pub email: Option<String>,
+    pub created: NaiveDateTime,
+}
+
+/// Lists the users who registered after the given date, newest first.
+pub async fn registered_since(pool: &PgPool, since: NaiveDateTime) -> Result<Vec<RegisteredUser>> {
+    Ok(sqlx::query_as!(
+        RegisteredUser,
+        r#"SELECT name, email, created FROM "user"
+        WHERE created > $1 AND id <> $2
+        ORDER BY created DESC"#,
+        since,
+        GHOST_USER_ID,
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
 /// Attempts to create a new session for the given user.
 /// Will return Ok(None) when password verification fails.
 /// This is a heavy operation due to the password hash being verified.
-pub async fn try_login(pool: &PgPool, username: &str, mut password: String) -> Result<UserSession> {
-    let (user_id, hash) = sqlx::query!(
-        r#"SELECT id, pw_hash FROM "user" WHERE name = $1"#,
+///
+/// Hashes made before a pepper was configured still verify without it, and
+/// are replaced by a peppered one on success.
+pub async fn try_login(
+    pool: &PgPool,
+    username: &str,
+    password: String,
+    pepper: Option<&str>,
+    max_sessions: u32,
+    lifetime: Option<Duration>,
+) -> Result<UserSession> {
+    let (user_id, hash, peppered) = sqlx::query!(
+        r#"SELECT id, pw_hash, pw_peppered FROM "user" WHERE name = $1"#,
         username
     )
     .fetch_optional(pool)
     .await?
-    .map(|r| (r.id, r.pw_hash))
+    .map(|r| (r.id, r.pw_hash, r.pw_peppered))
     .ok_or_else(|| Error::UserNotFound(username.to_string()))?;
-    let pw_valid = spawn_blocking(move || {
-        let res = verify_password(&hash, &password);
-        password.zeroize();
-        res
-    })
-    .await??;
+    let (pw_valid, new_hash) = check_password(hash, peppered, password, pepper).await?;
+    if let Some(new_hash) = new_hash {
+        sqlx::query!(
+            r#"UPDATE "user" SET pw_hash = $1, pw_peppered = TRUE WHERE id = $2"#,
+            new_hash,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+    }
     if pw_valid {
-        let session_id = create_session(pool, user_id).await?;
+        let session_id = create_session(pool, user_id, max_sessions, lifetime).await?;
         Ok(UserSession {
             session_id,
             user_id,
@@ -199,18 +471,186 @@ pub async fn try_login(pool: &PgPool, username: &str, mut password: String) -> R
         Err(Error::WrongPassword)
     }
 }
-async fn create_session(pool: &PgPool, user_id: Uuid) -> Result<Uuid> {
-    let session_id = Uuid::new_v4();
+/// Whether the given password is the user's current one, e.g. for
+/// confirming a password change.
+pub async fn password_matches(
+    pool: &PgPool,
+    user_id: Uuid,
+    password: String,
+    pepper: Option<&str>,
+) -> Result<bool> {
+    let row = sqlx::query!(
+        r#"SELECT pw_hash, pw_peppered FROM "user" WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    // A hash without the pepper isn't worth replacing just before the
+    // password itself is
+    let (matches, _) = check_password(row.pw_hash, row.pw_peppered, password, pepper).await?;
+    Ok(matches)
+}
+
+/// Verifies the password against the stored hash on a threadpool.
+/// Returns whether it matches, and a peppered hash to replace the stored one
+/// with if that was made before a pepper was configured.
+/// Users created through an external login have no hash, so no password
+/// matches theirs.
+async fn check_password(
+    hash: Option<String>,
+    peppered: bool,
+    mut password: String,
+    pepper: Option<&str>,
+) -> Result<(bool, Option<String>)> {
+    let hash = match hash {
+        Some(hash) => hash,
+        None => {
+            password.zeroize();
+            return Ok((false, None));
+        }
+    };
+    let rehash = pepper.is_some() && !peppered;
+    let pepper = pepper.map(|pepper| pepper.as_bytes().to_vec());
+    Ok(spawn_blocking(move || {
+        let pepper = pepper.as_deref();
+        let res =
+            verify_password(&hash, &password, pepper.filter(|_| peppered)).and_then(|valid| {
+                if valid && rehash {
+                    Ok((true, Some(hash_password(&password, pepper)?)))
+                } else {
+                    Ok((valid, None))
+                }
+            });
+        password.zeroize();
+        res
+    })
+    .await??)
+}
+
+/// Replaces the user's password; like registering, this hashes it on a
+/// threadpool.
+pub async fn set_password(
+    pool: &PgPool,
+    user_id: Uuid,
+    mut password: String,
+    pepper: Option<&str>,
+) -> Result<()> {
+    let pw_peppered = pepper.is_some();
+    let pepper = pepper.map(|pepper| pepper.as_bytes().to_vec());
+    let pw_hash = spawn_blocking(move || {
+        let res = hash_password(&password, pepper.as_deref());
+        password.zeroize();
+        res
+    })
+    .await??;
     sqlx::query!(
-        "INSERT INTO session(session_id, user_id) VALUES($1, $2)",
-        session_id,
+        r#"UPDATE "user" SET pw_hash = $1, pw_peppered = $2 WHERE id = $3"#,
+        pw_hash,
+        pw_peppered,
         user_id
     )
     .execute(pool)
     .await?;
+    Ok(())
+}
+
+/// Creates a new session for the given user without any verification.
+/// If `max_sessions` isn't 0, the user's oldest sessions beyond it are
+/// removed, logging them out there. A lifetime replaces the configured
+/// limits, as for "remember me".
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    max_sessions: u32,
+    lifetime: Option<Duration>,
+) -> Result<Uuid> {
+    let session_id = Uuid::new_v4();
+    let mut txn = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO session(session_id, user_id, lifetime) VALUES($1, $2, $3)",
+        session_id,
+        user_id,
+        lifetime.map(|lifetime| lifetime.num_seconds()),
+    )
+    .execute(&mut txn)
+    .await?;
+    if max_sessions > 0 {
+        sqlx::query!(
+            "DELETE FROM session WHERE user_id = $1 AND id NOT IN (
+                SELECT id FROM session
+                WHERE user_id = $1
+                ORDER BY created DESC, id DESC
+                LIMIT $2
+            )",
+            user_id,
+            i64::from(max_sessions),
+        )
+        .execute(&mut txn)
+        .await?;
+    }
+    txn.commit().await?;
     Ok(session_id)
 }
 
+/// The user who logs in through the given OpenID Connect subject, if any.
+pub async fn oidc_user(pool: &PgPool, subject: &str) -> Result<Option<Uuid>> {
+    Ok(
+        sqlx::query_scalar!(r#"SELECT id FROM "user" WHERE oidc_subject = $1"#, subject)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+/// Finds the user linked to the given OpenID Connect subject, creating a new
+/// one without a password if there is none yet.
+/// If the preferred name is already taken, a random suffix is appended to it.
+pub async fn provision_oidc(pool: &PgPool, subject: &str, preferred_name: &str) -> Result<Uuid> {
+    if let Some(id) = oidc_user(pool, subject).await? {
+        return Ok(id);
+    }
+    let name = if name_exists(&mut *pool.acquire().await?, preferred_name).await? {
+        format!(
+            "{}-{}",
+            preferred_name,
+            &Uuid::new_v4().to_simple().to_string()[..6]
+        )
+    } else {
+        preferred_name.to_string()
+    };
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO "user"(id, name, name_skeleton, oidc_subject, is_admin)
+        VALUES($1, $2, $3, $4, (SELECT COUNT(*) FROM "user" WHERE id <> $5) = 0)"#,
+        id,
+        name,
+        skeleton(&name),
+        subject,
+        GHOST_USER_ID,
+    )
+    .execute(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Links an existing user to the given OpenID Connect subject, so they can
+/// log in through the provider from now on.
+pub async fn link_oidc(pool: &PgPool, user_id: Uuid, subject: &str) -> Result<()> {
+    let res = sqlx::query!(
+        r#"UPDATE "user" SET oidc_subject = $1 WHERE id = $2"#,
+        subject,
+        user_id
+    )
+    .execute(pool)
+    .await;
+    if let Err(sqlx::Error::Database(err)) = &res {
+        if err.constraint() == Some("user_oidc_subject_unique") {
+            return Err(Error::OidcSubjectTaken);
+        }
+    }
+    res?;
+    Ok(())
+}
+
 /// Logs out a user by deleting the given session id.
 pub async fn destroy_session(pool: &PgPool, session_id: Uuid) -> Result<()> {
     sqlx::query!("DELETE FROM session WHERE session_id = $1", session_id)
@@ -219,14 +659,147 @@ pub async fn destroy_session(pool: &PgPool, session_id: Uuid) -> Result<()> {
     Ok(())
 }
 
-/// Returns the user logged in with the given session id, if any.
-pub async fn get_session_user(pool: &PgPool, session_id: Uuid) -> Result<Option<Uuid>> {
-    Ok(sqlx::query_scalar!(
-        "SELECT user_id FROM session WHERE session_id = $1",
-        session_id
+/// Logs out a user everywhere except in the given session.
+/// Returns how many sessions were ended.
+pub async fn destroy_all_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    except: Option<Uuid>,
+) -> Result<u64> {
+    Ok(sqlx::query!(
+        "DELETE FROM session WHERE user_id = $1 AND session_id IS DISTINCT FROM $2",
+        user_id,
+        except
+    )
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ActiveSession {
+    /// Not the session id itself, which must never be shown.
+    pub id: i64,
+    /// The start of the session id, for recognizing the current session.
+    pub prefix: String,
+    pub current: bool,
+    pub created: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+}
+
+/// Lists the user's sessions which are still valid, most recently used first.
+pub async fn list_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    current_session_id: Uuid,
+    limits: SessionLimits,
+) -> Result<Vec<ActiveSession>> {
+    let now = Utc::now().naive_utc();
+    let sessions = sqlx::query!(
+        r#"SELECT id, session_id AS "session_id!", created, last_seen FROM session
+        WHERE user_id = $1
+        AND created > $2::TIMESTAMP - COALESCE(lifetime, $3) * INTERVAL '1 second'
+        AND last_seen > $2::TIMESTAMP - COALESCE(lifetime, $4) * INTERVAL '1 second'
+        ORDER BY last_seen DESC"#,
+        user_id,
+        now,
+        limits.max_age.num_seconds(),
+        limits.idle_timeout.num_seconds(),
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| ActiveSession {
+        id: row.id,
+        prefix: row.session_id.to_simple().to_string()[..8].to_string(),
+        current: row.session_id == current_session_id,
+        created: row.created,
+        last_seen: row.last_seen,
+    })
+    .collect();
+    Ok(sessions)
+}
+
+/// Deletes one of the user's sessions by its listed id.
+/// Returns false if the user has no such session.
+pub async fn revoke_session(pool: &PgPool, user_id: Uuid, id: i64) -> Result<bool> {
+    let res = sqlx::query!(
+        "DELETE FROM session WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Marks the session's cookie as sent again if it has a lifetime, and more
+/// than half of it has passed since the cookie was last sent.
+/// Returns the lifetime the new cookie should have, if it's due.
+pub async fn renew_session_cookie(pool: &PgPool, session_id: Uuid) -> Result<Option<Duration>> {
+    let now = Utc::now().naive_utc();
+    let lifetime = sqlx::query_scalar!(
+        r#"UPDATE session SET cookie_issued = $2
+        WHERE session_id = $1
+        AND cookie_issued < $2::TIMESTAMP - lifetime * INTERVAL '1 second' / 2
+        RETURNING lifetime AS "lifetime!""#,
+        session_id,
+        now,
     )
     .fetch_optional(pool)
-    .await?)
+    .await?;
+    Ok(lifetime.map(Duration::seconds))
+}
+
+/// Returns the user logged in with the given session id, if any, and marks
+/// the session as just used.
+/// Sessions past either of the limits (or their own lifetime) are deleted
+/// instead.
+pub async fn get_session_user(
+    pool: &PgPool,
+    session_id: Uuid,
+    limits: SessionLimits,
+) -> Result<Option<Uuid>> {
+    let now = Utc::now().naive_utc();
+    let user_id = sqlx::query_scalar!(
+        "UPDATE session SET last_seen = $2
+        WHERE session_id = $1
+        AND created > $2::TIMESTAMP - COALESCE(lifetime, $3) * INTERVAL '1 second'
+        AND last_seen > $2::TIMESTAMP - COALESCE(lifetime, $4) * INTERVAL '1 second'
+        RETURNING user_id",
+        session_id,
+        now,
+        limits.max_age.num_seconds(),
+        limits.idle_timeout.num_seconds(),
+    )
+    .fetch_optional(pool)
+    .await?;
+    if user_id.is_none() {
+        destroy_session(pool, session_id).await?;
+    }
+    Ok(user_id)
+}
+
+/// Creates a new API token for the given user.
+pub async fn create_api_token(pool: &PgPool, user_id: Uuid) -> Result<Uuid> {
+    let token = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO api_token(token, user_id) VALUES($1, $2)",
+        token,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(token)
+}
+
+/// Returns the user the given API token belongs to, if any.
+pub async fn get_token_user(pool: &PgPool, token: Uuid) -> Result<Option<Uuid>> {
+    Ok(
+        sqlx::query_scalar!("SELECT user_id FROM api_token WHERE token = $1", token)
+            .fetch_optional(pool)
+            .await?,
+    )
 }
 
 /// Checks if the given user has admin privileges.
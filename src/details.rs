@@ -0,0 +1,159 @@
+//! Collapsible sections, written as a fenced block:
+//!
+//! ```text
+//! :::details Title
+//! Some *Markdown* which is hidden until the title is clicked
+//! :::
+//! ```
+//!
+//! Sections can be nested; one which isn't closed runs to the end.
+//! Markers inside code blocks are left alone.
+
+const OPENING: &str = ":::details";
+const CLOSING: &str = ":::";
+
+#[derive(Debug, PartialEq)]
+pub enum Block<'a> {
+    Markdown(&'a str),
+    Details {
+        summary: &'a str,
+        body: Vec<Block<'a>>,
+    },
+}
+
+/// Splits the content into plain Markdown and collapsible sections.
+pub fn parse(input: &str) -> Vec<Block<'_>> {
+    // The sections which are still open, with their blocks so far
+    let mut stack = vec![("", Vec::new())];
+    let mut fence = None;
+    let mut markdown_start = 0;
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"]
+            .iter()
+            .find(|marker| trimmed.starts_with(*marker))
+        {
+            fence = Some(*marker);
+            continue;
+        }
+        let summary = trimmed
+            .strip_prefix(OPENING)
+            .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace));
+        let closing = trimmed == CLOSING && stack.len() > 1;
+        if summary.is_none() && !closing {
+            continue;
+        }
+        let blocks = &mut stack.last_mut().unwrap().1;
+        push_markdown(blocks, &input[markdown_start..line_start]);
+        markdown_start = offset;
+        match summary {
+            Some(summary) => stack.push((summary.trim(), Vec::new())),
+            None => close(&mut stack),
+        }
+    }
+    push_markdown(&mut stack.last_mut().unwrap().1, &input[markdown_start..]);
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack.pop().unwrap().1
+}
+
+fn push_markdown<'a>(blocks: &mut Vec<Block<'a>>, text: &'a str) {
+    if !text.trim().is_empty() {
+        blocks.push(Block::Markdown(text));
+    }
+}
+
+fn close<'a>(stack: &mut Vec<(&'a str, Vec<Block<'a>>)>) {
+    let (summary, body) = stack.pop().unwrap();
+    stack
+        .last_mut()
+        .unwrap()
+        .1
+        .push(Block::Details { summary, body });
+}
+
+/// The content without the markers, with every section's title as a
+/// paragraph before its body. Used for extracting the text.
+pub fn flatten(input: &str) -> String {
+    fn push_blocks(output: &mut String, blocks: &[Block]) {
+        for block in blocks {
+            match block {
+                Block::Markdown(text) => output.push_str(text),
+                Block::Details { summary, body } => {
+                    output.push_str(summary);
+                    output.push_str("\n\n");
+                    push_blocks(output, body);
+                }
+            }
+            output.push_str("\n\n");
+        }
+    }
+    let mut output = String::with_capacity(input.len());
+    push_blocks(&mut output, &parse(input));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flatten, parse, Block};
+
+    #[test]
+    fn sections() {
+        assert_eq!(parse("Just text\n"), vec![Block::Markdown("Just text\n")]);
+        assert_eq!(
+            parse("Before\n:::details Title\nInside\n:::\nAfter"),
+            vec![
+                Block::Markdown("Before\n"),
+                Block::Details {
+                    summary: "Title",
+                    body: vec![Block::Markdown("Inside\n")],
+                },
+                Block::Markdown("After"),
+            ]
+        );
+        assert_eq!(
+            parse(":::details Outer\n:::details\nInner\n:::\n:::\n:::"),
+            vec![
+                Block::Details {
+                    summary: "Outer",
+                    body: vec![Block::Details {
+                        summary: "",
+                        body: vec![Block::Markdown("Inner\n")],
+                    }],
+                },
+                Block::Markdown(":::"),
+            ]
+        );
+        // Unclosed sections run to the end
+        assert_eq!(
+            parse(":::details Title\nInside"),
+            vec![Block::Details {
+                summary: "Title",
+                body: vec![Block::Markdown("Inside")],
+            }]
+        );
+        // Neither markers inside code blocks nor similar words count
+        let code = "```\n:::details Title\n```\n:::detailsTitle\n";
+        assert_eq!(parse(code), vec![Block::Markdown(code)]);
+    }
+
+    #[test]
+    fn flattened() {
+        let flat = flatten("Before\n:::details Title\nInside\n:::\nAfter");
+        assert!(!flat.contains(":::"));
+        assert_eq!(
+            flat.split_whitespace().collect::<Vec<_>>(),
+            vec!["Before", "Title", "Inside", "After"]
+        );
+    }
+}
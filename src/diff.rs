@@ -0,0 +1,159 @@
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+/// How many unchanged lines are kept around each change.
+const CONTEXT_LINES: usize = 3;
+/// How many unchanged lines around a change have to be the same for an undo
+/// to apply. Less than for displaying, so undoing isn't blocked by every
+/// later edit in the same paragraph.
+const UNDO_CONTEXT_LINES: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Equal,
+    Insert,
+    Delete,
+}
+impl From<ChangeTag> for Operation {
+    fn from(tag: ChangeTag) -> Self {
+        match tag {
+            ChangeTag::Equal => Operation::Equal,
+            ChangeTag::Insert => Operation::Insert,
+            ChangeTag::Delete => Operation::Delete,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Line {
+    pub operation: Operation,
+    pub content: String,
+}
+
+/// A group of changed lines with some surrounding context.
+/// Line numbers are 1-based, like in a unified diff.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub new_start: usize,
+    pub lines: Vec<Line>,
+}
+
+/// Computes a line-based diff between two article contents.
+/// This is the one diff engine used everywhere a diff is shown, so the
+/// different views always agree with each other.
+pub fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(old, new);
+    diff.grouped_ops(CONTEXT_LINES)
+        .iter()
+        .filter_map(|group| {
+            let first = group.first()?;
+            let lines = group
+                .iter()
+                .flat_map(|op| diff.iter_changes(op))
+                .map(|change| Line {
+                    operation: change.tag().into(),
+                    // Browsers submit text with \r\n, so strip both
+                    content: change.value().trim_end_matches(&['\r', '\n'][..]).into(),
+                })
+                .collect();
+            Some(Hunk {
+                old_start: first.old_range().start + 1,
+                new_start: first.new_range().start + 1,
+                lines,
+            })
+        })
+        .collect()
+}
+
+fn same_line(a: &str, b: &str) -> bool {
+    let strip = |s: &str| s.trim_end_matches(&['\r', '\n'][..]).to_string();
+    strip(a) == strip(b)
+}
+
+/// Finds `needle` in `lines` at or after `min`, preferring the match closest
+/// to `guess`.
+fn find_lines(lines: &[&str], needle: &[&str], min: usize, guess: usize) -> Option<usize> {
+    let max = lines.len().checked_sub(needle.len())?;
+    (min..=max)
+        .filter(|&i| {
+            lines[i..i + needle.len()]
+                .iter()
+                .zip(needle)
+                .all(|(a, b)| same_line(a, b))
+        })
+        .min_by_key(|&i| (i as isize - guess as isize).abs())
+}
+
+/// Reverts the change from `old` to `new` in `current`, keeping any changes
+/// made since.
+/// Returns None if that's not possible because the same lines were changed
+/// again in the meantime.
+pub fn undo(old: &str, new: &str, current: &str) -> Option<String> {
+    // The change we want to apply is the one from new back to old
+    let diff = TextDiff::from_lines(new, old);
+    let (from, to) = (diff.old_slices(), diff.new_slices());
+    let current = current.split_inclusive('\n').collect::<Vec<_>>();
+
+    let mut result = Vec::new();
+    let mut pos = 0;
+    // How far the lines in current have moved compared to new
+    let mut offset = 0;
+    for group in diff.grouped_ops(UNDO_CONTEXT_LINES) {
+        let (first, last) = (group.first()?, group.last()?);
+        let expected = &from[first.old_range().start..last.old_range().end];
+        let replacement = &to[first.new_range().start..last.new_range().end];
+        let guess = (first.old_range().start as isize + offset).max(pos as isize) as usize;
+        let found = find_lines(&current, expected, pos, guess)?;
+        result.extend_from_slice(&current[pos..found]);
+        result.extend_from_slice(replacement);
+        pos = found + expected.len();
+        offset = found as isize - first.old_range().start as isize;
+    }
+    result.extend_from_slice(&current[pos..]);
+
+    let mut output = String::new();
+    for (i, line) in result.iter().enumerate() {
+        output.push_str(line);
+        // A line which was the last one before might not be anymore
+        if !line.ends_with('\n') && i + 1 < result.len() {
+            output.push('\n');
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::undo;
+
+    #[test]
+    fn undo_latest() {
+        assert_eq!(
+            undo("a\nb\nc", "a\nB\nc", "a\nB\nc").as_deref(),
+            Some("a\nb\nc")
+        );
+    }
+
+    #[test]
+    fn undo_keeps_later_changes() {
+        let v1 = "Intro\nOne\nTwo\nThree\nFour\nOutro";
+        let v2 = "Intro\nUno\nTwo\nThree\nFour\nOutro";
+        let v3 = "New intro\nIntro\nUno\nTwo\nThree\nVier\nOutro";
+        assert_eq!(
+            undo(v1, v2, v3).as_deref(),
+            Some("New intro\nIntro\nOne\nTwo\nThree\nVier\nOutro")
+        );
+        // Added lines can be removed again just as well
+        assert_eq!(undo(v2, v3, v3).as_deref(), Some(v2));
+    }
+
+    #[test]
+    fn undo_conflict() {
+        let v1 = "Intro\nOne\nTwo";
+        let v2 = "Intro\nUno\nTwo";
+        let v3 = "Intro\nEins\nTwo";
+        assert_eq!(undo(v1, v2, v3), None);
+    }
+}
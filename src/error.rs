@@ -4,6 +4,7 @@ use rocket::{
     http::Status,
     outcome::Outcome,
     response::{self, Responder},
+    serde::json::Json,
     Request,
 };
 use rocket_dyn_templates::{tera, Template};
@@ -46,6 +47,10 @@ pub enum Error {
     CaptchaNotFound,
     #[error("An unexpected error occured when trying to generate a captcha")]
     CaptchaPngError,
+    #[error("An external captcha provider needs a `captcha` config section")]
+    CaptchaConfigMissing,
+    #[error("Too many people are registering right now; please try again in a moment")]
+    CaptchaBusy,
     #[error("Error trying to join a blocking task: {0}")]
     TokioJoinError(#[from] rocket::tokio::task::JoinError),
     #[error("Internal rocket error: failed to get database")]
@@ -54,6 +59,112 @@ pub enum Error {
     TantivyError(#[from] TantivyError),
     #[error("Error parsing search query: {0}")]
     QueryParserError(#[from] QueryParserError),
+    #[error("Error contacting external service: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("Invalid identity token: {0}")]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+    #[error("The identity provider's configuration is invalid")]
+    OidcProviderInvalid,
+    #[error("Unknown or expired login attempt; please retry!")]
+    OidcStateUnknown,
+    #[error("The identity token does not belong to this login attempt")]
+    OidcNonceMismatch,
+    #[error("The identity token was signed with an unknown key")]
+    OidcKeyUnknown,
+    #[error("This external account is already linked to a different user")]
+    OidcSubjectTaken,
+    #[error("New accounts need an invite code; register with one, then link single sign-on in your settings")]
+    OidcInviteRequired,
+    #[error("Your username {0} can't be used here, or looks too much like an existing one")]
+    OidcUsernameRejected(String),
+    #[error("Invalid API token")]
+    InvalidApiToken,
+    #[error("This needs an API token or a logged-in session")]
+    ApiUserMissing,
+    #[error("Import data is not a JSON object of page names and contents: {0}")]
+    InvalidImportData(#[from] serde_json::Error),
+    #[error("Error accessing a file: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Error creating zip file: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+    #[error("The file is too large; the limit is {0} bytes")]
+    AttachmentTooLarge(u64),
+    #[error("Files of type '{0}' can't be uploaded")]
+    AttachmentTypeNotAllowed(String),
+    #[error("Thumbnails can't be {0} pixels wide")]
+    ThumbnailWidthNotAllowed(u32),
+    #[error("Error processing image: {0}")]
+    ImageError(#[from] image::ImageError),
+    #[error("Can't create alias: {0} is already an article or alias")]
+    AliasNameTaken(String),
+    #[error("Can't create alias: article {0} doesn't exist")]
+    AliasTargetUnknown(String),
+    #[error("Invalid SMTP server URL: {0}")]
+    SmtpUrlInvalid(String),
+    #[error("Error sending mail: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+    #[error("Invalid email address: {0}")]
+    MailAddressError(#[from] lettre::address::AddressError),
+    #[error("Error building mail: {0}")]
+    MailBuildError(#[from] lettre::error::Error),
+    #[error("Article titles can be at most {0} characters long")]
+    ArticleTitleTooLong(usize),
+    #[error("The title {0} is reserved for the wiki itself")]
+    ArticleTitleReserved(String),
+    #[error("The title {0} contains a word which isn't allowed in titles")]
+    ArticleTitleBlocked(String),
+    #[error("Only users with a verified email address can edit")]
+    EmailNotVerified,
+    #[error("Unknown or already used verification link")]
+    VerificationTokenUnknown,
+    #[error("Can't feature article {0}: it doesn't exist")]
+    FeaturedArticleUnknown(String),
+    #[error("You don't have a draft of {0}")]
+    DraftNotFound(String),
+    #[error("Registration isn't possible from here")]
+    RegistrationBlocked,
+    #[error("This invite code is unknown or was used already")]
+    InviteInvalid,
+    #[error("Too many searches; please wait up to {0} seconds before searching again")]
+    SearchRateLimited(u32),
+    #[error("Comments can be at most {0} characters long")]
+    CommentTooLong(usize),
+    #[error("Too many comments; please wait up to {0} seconds before commenting again")]
+    CommentRateLimited(u32),
+    #[error("At most {0} queries can be searched at once")]
+    TooManySearchQueries(usize),
+    #[error("The search index is unavailable")]
+    SearchIndexUnavailable,
+    #[error("Revision {1} of {0} was redacted, so it can't be undone")]
+    RevisionRedacted(String, i64),
+    #[error("Can't merge articles: {0} doesn't exist")]
+    MergeArticleUnknown(String),
+    #[error("Can't merge an article into itself")]
+    MergeIntoItself,
+    #[error("{0} can't redirect to itself")]
+    RedirectToItself(String),
+    #[error("There is no template named {0}")]
+    TemplateNotFound(String),
+    #[error("Only admins can edit templates")]
+    TemplateEditRequiresAdmin,
+    #[error("Only admins can do this")]
+    AdminRequired,
+    #[error(
+        "New accounts can create articles in {0} more minutes; editing existing ones works already"
+    )]
+    ArticleCreationCooldown(u32),
+    #[error("Invalid date {0}; dates look like 2021-06-30")]
+    InvalidDate(String),
+    #[error("Tags can't be empty")]
+    TagNameEmpty,
+    #[error("Articles can have at most {0} tags")]
+    TooManyTags(usize),
+    #[error("Can't rename tag: some articles already have the tag {0}; merge the tags instead")]
+    TagNameTaken(String),
+    #[error("The wiki is already being optimized")]
+    MaintenanceRunning,
+    #[error("{0} was edited after this change was submitted; reject it instead")]
+    PendingRevisionOutdated(String),
 }
 
 impl Error {
@@ -63,6 +174,7 @@ impl Error {
             FigmentError(_)
             | SqlxError(_)
             | CaptchaPngError
+            | CaptchaConfigMissing
             | DatabaseRequestGuardFailed
             | Argon2Error(_)
             | BincodeError(_)
@@ -72,12 +184,63 @@ impl Error {
             | TemplateError(_)
             | TokioJoinError(_)
             | TantivyError(_)
-            | QueryParserError(_) => Status::InternalServerError,
+            | QueryParserError(_)
+            | IoError(_)
+            | ZipError(_)
+            | ImageError(_)
+            | MailBuildError(_)
+            | SmtpUrlInvalid(_)
+            | OidcProviderInvalid => Status::InternalServerError,
+            ReqwestError(_) | SmtpError(_) => Status::BadGateway,
+            InvalidApiToken | ApiUserMissing => Status::Unauthorized,
+            SearchRateLimited(_) | CommentRateLimited(_) => Status::TooManyRequests,
+            SearchIndexUnavailable | CaptchaBusy => Status::ServiceUnavailable,
+            EmailNotVerified
+            | TemplateEditRequiresAdmin
+            | AdminRequired
+            | ArticleCreationCooldown(_)
+            | InviteInvalid
+            | OidcInviteRequired
+            | OidcUsernameRejected(_)
+            | RegistrationBlocked => Status::Forbidden,
+            AttachmentTooLarge(_) => Status::PayloadTooLarge,
+            AttachmentTypeNotAllowed(_) => Status::UnsupportedMediaType,
+            DuplicateArticleName(_)
+            | AliasNameTaken(_)
+            | TagNameTaken(_)
+            | MaintenanceRunning
+            | PendingRevisionOutdated(_) => Status::Conflict,
             UserAlreadyExists(_)
             | IdenticalNewRevision
-            | DuplicateArticleName(_)
-            | WrongPassword => Status::BadRequest,
-            UserNotFound(_) | RevisionUnknown(_, _) | CaptchaNotFound => Status::NotFound,
+            | WrongPassword
+            | JwtError(_)
+            | OidcNonceMismatch
+            | OidcKeyUnknown
+            | OidcSubjectTaken
+            | InvalidImportData(_)
+            | TagNameEmpty
+            | TooManyTags(_)
+            | InvalidDate(_)
+            | MergeIntoItself
+            | RedirectToItself(_)
+            | ArticleTitleTooLong(_)
+            | ArticleTitleReserved(_)
+            | ArticleTitleBlocked(_)
+            | TooManySearchQueries(_)
+            | CommentTooLong(_)
+            | MailAddressError(_)
+            | ThumbnailWidthNotAllowed(_) => Status::BadRequest,
+            UserNotFound(_)
+            | RevisionUnknown(_, _)
+            | CaptchaNotFound
+            | OidcStateUnknown
+            | AliasTargetUnknown(_)
+            | VerificationTokenUnknown
+            | FeaturedArticleUnknown(_)
+            | DraftNotFound(_)
+            | TemplateNotFound(_)
+            | MergeArticleUnknown(_) => Status::NotFound,
+            RevisionRedacted(_, _) => Status::Gone,
         }
     }
 }
@@ -97,17 +260,45 @@ impl<S> IntoOutcomeHack<S> for crate::Result<S> {
     }
 }
 
+/// The message of an error a request guard failed with. Catchers only get
+/// the status, so guards leave it in the request's local cache for them.
+pub struct GuardError(pub Option<String>);
+
+impl GuardError {
+    pub fn record<S>(request: &Request<'_>, error: Error) -> Outcome<S, (Status, Error), ()> {
+        let status = error.status();
+        request.local_cache(|| GuardError(Some(error.to_string())));
+        Outcome::Failure((status, error))
+    }
+}
+
+/// API clients get errors as JSON, everyone else gets an error page.
+pub fn wants_json(request: &Request<'_>) -> bool {
+    request.uri().path().starts_with("/api")
+        || request
+            .accept()
+            .is_some_and(|accept| accept.preferred().media_type().is_json())
+}
+
 impl<'r> Responder<'r, 'static> for Error {
     fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
         // If this doesn't return Some, we're dead anyways because the whole
         // runtime was initialized in the wrong way
-        let cfg: &crate::Config = request.rocket().state().unwrap();
         let status = self.status();
+        if wants_json(request) {
+            let body = serde_json::json! {{
+                "status": status.code,
+                "error": self.to_string(),
+            }};
+            return response::status::Custom(status, Json(body)).respond_to(request);
+        }
+        let cfg: &crate::Config = request.rocket().state().unwrap();
         let context = serde_json::json! {{
             "site_name": &cfg.site_name,
             "default_path": &cfg.default_path,
             "status": status.to_string(),
             "error": self.to_string(),
+            "request_id": crate::logging::RequestId::of(request),
         }};
         response::status::Custom(status, Template::render("error", context)).respond_to(request)
     }
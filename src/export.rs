@@ -0,0 +1,178 @@
+//! Exporting the whole wiki as static HTML pages in a zip file, for reading
+//! it offline. Every article becomes one file next to an index of all of
+//! them, and links between articles point to those files.
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Seek, Write},
+};
+
+use pulldown_cmark::escape::{escape_href, escape_html};
+use rocket::http::RawStr;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{db::articles::normalize_name, Result};
+
+const INDEX_FILE: &str = "index.html";
+const STYLE_FILE: &str = "style.css";
+
+/// Shared by all pages; just enough to make them readable.
+const STYLE: &str = "body {
+  max-width: 50em;
+  margin: 2em auto;
+  padding: 0 1em;
+  font-family: sans-serif;
+  line-height: 1.5;
+}
+pre, code {
+  background: #f5f5f5;
+}
+pre {
+  padding: 1em;
+  overflow-x: auto;
+}
+img {
+  max-width: 100%;
+}
+nav {
+  border-bottom: 1px solid #ddd;
+  margin-bottom: 1em;
+}
+";
+
+/// Finds a file for every article, telling apart names which are only
+/// different in characters that can't be part of one.
+fn file_names<'a>(names: impl IntoIterator<Item = &'a str>) -> HashMap<String, String> {
+    let mut taken: HashSet<_> = [INDEX_FILE.to_string(), STYLE_FILE.to_string()].into();
+    let mut files = HashMap::new();
+    for name in names {
+        let stem = crate::articles::file_name(name);
+        let mut file = format!("{}.html", stem);
+        let mut suffix = 2;
+        while taken.contains(&file) {
+            file = format!("{}-{}.html", stem, suffix);
+            suffix += 1;
+        }
+        taken.insert(file.clone());
+        files.insert(name.to_string(), file);
+    }
+    files
+}
+
+/// Points links to exported articles to their files, keeping the section
+/// they link to. Every other link is left alone.
+fn rewrite_links(html: &str, files: &HashMap<String, String>) -> String {
+    const LINK_START: &str = "href=\"/";
+
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(LINK_START) {
+        // Up to the quote, keeping the slash with the link
+        let (before, after) = rest.split_at(start + LINK_START.len() - 1);
+        output.push_str(before);
+        let (href, after) = after.split_at(after.find('"').unwrap_or(after.len()));
+        let path_end = href.find(['#', '?']).unwrap_or(href.len());
+        let (path, suffix) = href.split_at(path_end);
+        let name = path[1..].replace("&amp;", "&");
+        let name = normalize_name(&RawStr::new(&name).percent_decode_lossy());
+        match files.get(&name) {
+            Some(file) => {
+                // Writing to a String can't fail
+                escape_href(&mut output, file).unwrap();
+                if suffix.starts_with('#') {
+                    output.push_str(suffix);
+                }
+            }
+            None => output.push_str(href),
+        }
+        rest = after;
+    }
+    output.push_str(rest);
+    output
+}
+
+/// A whole page around the given body, with a link back to the index.
+fn page(site_name: &str, title: &str, body: &str) -> String {
+    let mut html =
+        String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>");
+    escape_html(&mut html, title).unwrap();
+    html.push_str(" - ");
+    escape_html(&mut html, site_name).unwrap();
+    html.push_str("</title>\n<link rel=\"stylesheet\" href=\"");
+    html.push_str(STYLE_FILE);
+    html.push_str("\">\n</head>\n<body>\n<nav><a href=\"");
+    html.push_str(INDEX_FILE);
+    html.push_str("\">");
+    escape_html(&mut html, site_name).unwrap();
+    html.push_str("</a></nav>\n<h1>");
+    escape_html(&mut html, title).unwrap();
+    html.push_str("</h1>\n");
+    html.push_str(body);
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Zips up the articles with an index and the stylesheet into the writer.
+/// Every article is rendered to HTML with `render` right before it's
+/// written, so only one of them is held in memory at a time.
+/// This compresses everything, so better call it on a blocking task.
+pub fn bundle<W: Write + Seek>(
+    writer: W,
+    site_name: &str,
+    mut names: Vec<&str>,
+    mut render: impl FnMut(&str) -> String,
+) -> Result<W> {
+    names.sort_unstable();
+    let files = file_names(names.iter().copied());
+    let mut zip = ZipWriter::new(writer);
+    let options = FileOptions::default();
+
+    let mut index = String::from("<ul>\n");
+    for name in names {
+        let file = &files[name];
+        index.push_str("<li><a href=\"");
+        escape_href(&mut index, file).unwrap();
+        index.push_str("\">");
+        escape_html(&mut index, name).unwrap();
+        index.push_str("</a></li>\n");
+
+        zip.start_file(file.as_str(), options)?;
+        let html = rewrite_links(&render(name), &files);
+        zip.write_all(page(site_name, name, &html).as_bytes())?;
+    }
+    index.push_str("</ul>\n");
+    zip.start_file(INDEX_FILE, options)?;
+    zip.write_all(page(site_name, "All articles", &index).as_bytes())?;
+    zip.start_file(STYLE_FILE, options)?;
+    zip.write_all(STYLE.as_bytes())?;
+    Ok(zip.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_names, rewrite_links};
+
+    #[test]
+    fn unique_file_names() {
+        let files = file_names(vec!["A B", "A_B", "index", "Über"]);
+        assert_eq!(files["A B"], "A_B.html");
+        assert_eq!(files["A_B"], "A_B-2.html");
+        assert_eq!(files["index"], "index-2.html");
+        assert_eq!(files["Über"], "_ber.html");
+    }
+
+    #[test]
+    fn rewritten_links() {
+        let files = file_names(vec!["Some Page", "Other"]);
+        assert_eq!(
+            rewrite_links(
+                "<a href=\"/Some%20Page#usage\">x</a> <a href=\"/Other?a=b\">y</a>",
+                &files
+            ),
+            "<a href=\"Some_Page.html#usage\">x</a> <a href=\"Other.html\">y</a>"
+        );
+        // Missing articles and everything else stay as they are
+        let unchanged = "<a href=\"/Missing\">x</a> <a href=\"https://example.com/\">y</a> \
+            <img src=\"/attachments/1\">";
+        assert_eq!(rewrite_links(unchanged, &files), unchanged);
+    }
+}
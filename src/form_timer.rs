@@ -0,0 +1,89 @@
+//! Signed timestamps telling when a form was shown, since bots tend to
+//! submit forms right after loading them.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha2::Sha256;
+
+const KEY_SIZE: usize = 32;
+
+/// Signs timestamps with a key only this process knows, so a form shown
+/// before a restart has to be reloaded.
+pub struct FormTimer {
+    key: [u8; KEY_SIZE],
+}
+
+impl Default for FormTimer {
+    fn default() -> Self {
+        let mut key = [0; KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self { key }
+    }
+}
+
+impl FormTimer {
+    fn mac(&self, millis: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.key).expect("HMAC takes keys of any size");
+        mac.update(millis.to_string().as_bytes());
+        mac
+    }
+
+    /// HMAC-SHA256 of the timestamp, as `<millis>.<signature>`.
+    fn sign(&self, millis: u64) -> String {
+        let signature = self.mac(millis).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            millis,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// A token for a form shown at the given time.
+    pub fn issue_at(&self, time: SystemTime) -> String {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default();
+        self.sign(millis)
+    }
+
+    /// A token for a form shown right now.
+    pub fn issue(&self) -> String {
+        self.issue_at(SystemTime::now())
+    }
+
+    /// How long ago the token was issued, or None if it wasn't issued by us.
+    pub fn elapsed(&self, token: &str) -> Option<Duration> {
+        let (millis, signature) = token.split_once('.')?;
+        let millis = millis.parse().ok()?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        // Verified in constant time, so the signature can't be guessed bytewise
+        self.mac(millis).verify(&signature).ok()?;
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_millis(millis))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::FormTimer;
+
+    #[test]
+    fn signed_timestamps() {
+        let timer = FormTimer::default();
+        let token = timer.issue();
+        assert!(timer.elapsed(&token).unwrap() < Duration::from_secs(1));
+        let earlier = timer.issue_at(SystemTime::now() - Duration::from_secs(10));
+        assert!(timer.elapsed(&earlier).unwrap() >= Duration::from_secs(10));
+        // Changing the time invalidates the signature
+        let (_, signature) = earlier.split_once('.').unwrap();
+        assert_eq!(timer.elapsed(&format!("0.{}", signature)), None);
+        assert_eq!(timer.elapsed("garbage"), None);
+        // Another key doesn't accept it either
+        assert_eq!(FormTimer::default().elapsed(&token), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Structured JSON logging, for feeding logs into aggregation systems.
+//! This is switched on by the `json_logging` config option; otherwise,
+//! Rocket's human-readable logging is used.
+use std::{io::Write, time::Instant};
+
+use chrono::Utc;
+use log::{LevelFilter, Metadata, Record, SetLoggerError};
+use parking_lot::Mutex;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Data, Request, Response,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+/// A random id identifying a single request.
+pub struct RequestId(pub Uuid);
+impl RequestId {
+    /// Gets the id for the given request, generating it on first use.
+    pub fn of(request: &Request<'_>) -> Uuid {
+        request.local_cache(|| RequestId(Uuid::new_v4())).0
+    }
+}
+
+/// Adds the request id as an `X-Request-Id` header to every response, so
+/// it can be found in the logs when users report errors.
+pub struct RequestIdHeader;
+#[rocket::async_trait]
+impl Fairing for RequestIdHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request id header",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let id = RequestId::of(request).to_string();
+        response.set_header(Header::new("X-Request-Id", id));
+    }
+}
+
+/// Logs every record as a JSON object on its own line on stdout.
+struct JsonLogger;
+impl log::Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = json! {{
+            "timestamp": Utc::now(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        }};
+        println!("{}", line);
+    }
+    fn flush(&self) {}
+}
+
+/// Installs the JSON logger. This has to happen before Rocket launches,
+/// otherwise Rocket installs its own logger first.
+pub fn init_json_logger() -> std::result::Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(JsonLogger)).map(|()| log::set_max_level(LevelFilter::Info))
+}
+
+struct RequestStart(Instant);
+
+/// Writes a JSON line with the request id, client ip, method, path, status
+/// and latency for every handled request.
+pub struct RequestLogger {
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+impl RequestLogger {
+    pub fn new(sink: impl Write + Send + 'static) -> Self {
+        Self {
+            sink: Mutex::new(Box::new(sink)),
+        }
+    }
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "JSON request logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let line = json! {{
+            "timestamp": Utc::now(),
+            "request_id": RequestId::of(request),
+            "client_ip": crate::client_ip::of_request(request),
+            "method": request.method().as_str(),
+            "path": request.uri().path().to_string(),
+            "status": response.status().code,
+            "latency_ms": start.0.elapsed().as_secs_f64() * 1000.0,
+        }};
+        let mut sink = self.sink.lock();
+        if let Err(e) = writeln!(sink, "{}", line) {
+            // Not much else we can do here
+            eprintln!("Failed to write request log: {}", e);
+        }
+    }
+}
@@ -1,22 +1,499 @@
 #![recursion_limit = "512"]
 
-use rocket::{fairing::AdHoc, fs::FileServer, response::Redirect, Build, Rocket, State};
+use std::path::PathBuf;
+
+use rocket::{fairing::AdHoc, response::Redirect, shield::Shield, Build, Rocket, State};
 use rocket_dyn_templates::Template;
 use serde::Deserialize;
 
 mod cache;
 pub use cache::Cache;
+mod captcha;
+use captcha::{CaptchaKind, ExternalCaptchaConfig};
+mod client_ip;
+mod confusables;
 mod db;
 pub use db::Db;
+mod details;
+mod diff;
+mod export;
+mod form_timer;
+pub use form_timer::FormTimer;
+mod logging;
+mod mail;
+pub use mail::Mailer;
+mod oidc;
+use oidc::{OidcConfig, OidcProvider};
+mod pagination;
+mod private_mode;
+mod pwned;
 mod search;
 pub use search::ArticleIndex;
+use search::{EmptySearch, SearchSort, SnippetStrategy};
+mod security_headers;
+use security_headers::SecurityHeadersConfig;
+mod static_files;
+use static_files::StaticFiles;
+mod templating;
+mod trailing_slash;
+mod transclusion;
+
+/// What `/` shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Homepage {
+    /// Redirect to the default path.
+    #[default]
+    Redirect,
+    /// Render the landing template.
+    Landing,
+}
+
+/// What saving an article without changing its content does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdenticalSaves {
+    /// Don't add a revision and tell the user nothing changed.
+    #[default]
+    Ignore,
+    /// Fail with 400 Bad Request.
+    Reject,
+}
 
-#[derive(serde::Serialize, Deserialize)]
+/// What happens when the search index can't be built on startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchIndexFailure {
+    /// Refuse to start.
+    #[default]
+    Abort,
+    /// Start without search and keep retrying in the background.
+    Degrade,
+}
+
+#[derive(Clone, serde::Serialize, Deserialize)]
 pub struct Config {
     pub site_name: String,
     pub main_page: String,
     #[serde(default)]
     pub default_path: String,
+    #[serde(default)]
+    pub homepage: Homepage,
+    /// The template `/` renders in landing mode.
+    #[serde(default = "default_landing_template")]
+    pub landing_template: String,
+    /// Feature a different article every day on the landing page, unless
+    /// an admin pinned one.
+    #[serde(default)]
+    pub article_of_the_day: bool,
+    /// Article name patterns which are never the article of the day; `*` is
+    /// a wildcard.
+    #[serde(default)]
+    pub article_of_the_day_exclude: Vec<String>,
+    /// Enables logging in through an OpenID Connect provider if set.
+    #[serde(default, skip_serializing)]
+    pub oidc: Option<OidcConfig>,
+    /// The captcha shown when registering.
+    #[serde(default)]
+    pub captcha_provider: CaptchaKind,
+    /// Keys for an external captcha provider.
+    #[serde(default, skip_serializing)]
+    pub captcha: Option<ExternalCaptchaConfig>,
+    /// How many image captchas can be generated at the same time; more
+    /// registration attempts are asked to try again.
+    #[serde(default = "default_captcha_concurrency")]
+    pub captcha_concurrency: usize,
+    /// Mixed into password hashes, so a leaked database alone isn't enough
+    /// for cracking them. Best set through `ROCKET_PASSWORD_PEPPER`.
+    #[serde(default, skip_serializing)]
+    pub password_pepper: Option<String>,
+    /// How long a login lasts at most, in seconds.
+    #[serde(default = "default_session_max_age")]
+    pub session_max_age: u32,
+    /// How long a login lasts without being used, in seconds.
+    #[serde(default = "default_session_idle_timeout")]
+    pub session_idle_timeout: u32,
+    /// How long a login with "remember me" lasts, in seconds; both limits
+    /// above are replaced by this.
+    #[serde(default = "default_remember_me_max_age")]
+    pub remember_me_max_age: u32,
+    /// Whether remembered logins get a new cookie once half of the old
+    /// one's lifetime has passed.
+    #[serde(default = "default_refresh_session_cookies")]
+    pub refresh_session_cookies: bool,
+    /// How many sessions one user may have at once; logging in once more
+    /// ends the oldest one. 0 is unlimited.
+    #[serde(default)]
+    pub max_sessions: u32,
+    /// Whether changing the password logs the user out everywhere else.
+    #[serde(default = "default_logout_on_password_change")]
+    pub logout_on_password_change: bool,
+    /// Where the wiki is reachable, for links in emails.
+    #[serde(default = "default_site_url")]
+    pub site_url: String,
+    /// Sends mails through this server if set; otherwise they're only logged.
+    #[serde(default, skip_serializing)]
+    pub smtp_url: Option<String>,
+    #[serde(default = "default_mail_from")]
+    pub mail_from: String,
+    /// Create a `Talk:User:<name>` page for every new user, so others can
+    /// leave messages for them.
+    #[serde(default)]
+    pub create_talk_pages: bool,
+    /// The content template (without `Template:`) new talk pages start as,
+    /// with `{user}` replaced by the user's name. Without one (or if it
+    /// doesn't exist), they get a short default text.
+    #[serde(default)]
+    pub talk_page_template: Option<String>,
+    /// Edits to talk pages count as comments; each can add at most this
+    /// many characters. 0 means unlimited.
+    #[serde(default)]
+    pub max_comment_length: usize,
+    /// How many comments one user may make per `comment_rate_window`;
+    /// 0 means unlimited.
+    #[serde(default)]
+    pub comment_rate_limit: u32,
+    /// In seconds.
+    #[serde(default = "default_comment_rate_window")]
+    pub comment_rate_window: u32,
+    /// Only logged in users can see anything but the login and registration.
+    #[serde(default)]
+    pub private: bool,
+    /// Only users who verified their email address can edit; users without
+    /// one (see `require_email`) and external logins aren't affected.
+    #[serde(default)]
+    pub edit_requires_verified_email: bool,
+    /// Edits by untrusted users wait for an admin's approval at
+    /// `/settings/moderation` instead of being published right away.
+    #[serde(default)]
+    pub moderate_edits: bool,
+    /// How many published edits make a user trusted; admins always are.
+    #[serde(default = "default_trusted_edit_count")]
+    pub trusted_edit_count: u32,
+    /// How long new accounts have to wait before creating articles, in
+    /// seconds; they can edit existing ones right away. Admins never wait.
+    #[serde(default)]
+    pub article_creation_cooldown: u32,
+    /// Ask for an email address when registering.
+    #[serde(default)]
+    pub require_email: bool,
+    /// While registration is enabled, it needs an invite code created by an
+    /// admin at `/settings/invites`.
+    #[serde(default)]
+    pub invite_only: bool,
+    /// Reject new usernames which look like an existing one, e.g. `Adm1n`
+    /// for `Admin`, if they're at most this many edits apart after
+    /// ignoring case and look-alike characters. Unset allows any name.
+    #[serde(default)]
+    pub similar_username_distance: Option<usize>,
+    /// Registrations submitted sooner than this after loading the form are
+    /// rejected as likely bots. 0 disables the check.
+    #[serde(default)]
+    pub min_register_seconds: u64,
+    /// If not empty, only email addresses from these domains can register.
+    #[serde(default)]
+    pub allowed_email_domains: Vec<String>,
+    /// How far back the admin's list of recent registrations goes, in days.
+    #[serde(default = "default_recent_registrations_days")]
+    pub recent_registrations_days: u32,
+    /// Reject new passwords which are known from data breaches.
+    #[serde(default)]
+    pub check_breached_passwords: bool,
+    #[serde(default = "default_pwned_passwords_url")]
+    pub pwned_passwords_url: String,
+    /// How many entries each in-memory cache may hold.
+    #[serde(default = "default_cache_capacity")]
+    pub cache_capacity: usize,
+    /// Log structured JSON lines instead of Rocket's human-readable output.
+    #[serde(default)]
+    pub json_logging: bool,
+    /// How many searches one address may make per `search_rate_window`;
+    /// 0 means unlimited.
+    #[serde(default)]
+    pub search_rate_limit: u32,
+    /// The same for logged in users, who are exempt by default.
+    #[serde(default)]
+    pub search_rate_limit_logged_in: u32,
+    /// In seconds.
+    #[serde(default = "default_search_rate_window")]
+    pub search_rate_window: u32,
+    /// How long search results are reused for the same query, in seconds;
+    /// 0 disables caching them. Edits always make for new results.
+    #[serde(default)]
+    pub search_cache_ttl: u32,
+    /// How search results are ordered unless the search asks otherwise.
+    #[serde(default)]
+    pub search_default_sort: SearchSort,
+    /// Whether search results show one snippet or several matching ones.
+    #[serde(default)]
+    pub search_snippets: SnippetStrategy,
+    /// The element matches in snippets are wrapped in, e.g. `mark`.
+    #[serde(default = "default_search_highlight_tag")]
+    pub search_highlight_tag: String,
+    /// Queries shorter than this, in characters and without stop words,
+    /// only show a hint instead of searching; 0 searches for anything.
+    #[serde(default)]
+    pub search_min_query_length: usize,
+    /// Words left out of queries, like `the`.
+    #[serde(default)]
+    pub search_stop_words: Vec<String>,
+    /// Which articles a search without a query lists.
+    #[serde(default)]
+    pub empty_search: EmptySearch,
+    /// Article name patterns which won't show up in search; `*` is a wildcard.
+    #[serde(default)]
+    pub exclude_from_search: Vec<String>,
+    /// Whether the wiki starts without search if the index can't be built.
+    #[serde(default)]
+    pub search_index_failure: SearchIndexFailure,
+    /// How long to wait before trying to build the index again in degraded
+    /// mode, in seconds. The wait doubles after every failed attempt, up to
+    /// an hour.
+    #[serde(default = "default_search_index_retry")]
+    pub search_index_retry: u32,
+    /// Edits are committed to the search index in batches of this many,
+    /// instead of one commit each. Up to `search_commit_interval_ms` can
+    /// pass before an edit becomes searchable.
+    #[serde(default = "default_search_commit_batch")]
+    pub search_commit_batch: usize,
+    #[serde(default = "default_search_commit_interval_ms")]
+    pub search_commit_interval_ms: u64,
+    /// Headers sent with every HTML page.
+    #[serde(default)]
+    pub security_headers: SecurityHeadersConfig,
+    /// How long browsers may cache static assets, in seconds.
+    #[serde(default = "default_static_max_age")]
+    pub static_max_age: u32,
+    /// Reverse proxies whose `X-Forwarded-For` header we believe.
+    #[serde(default)]
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+    /// Networks nobody can register from, matched against the client's
+    /// address as found through the trusted proxies.
+    #[serde(default)]
+    pub registration_blocklist: Vec<ipnet::IpNet>,
+    /// Text (or HTML) shown at the bottom of every page.
+    #[serde(default)]
+    pub footer: String,
+    /// The longest allowed article title, in characters.
+    #[serde(default = "default_max_title_length")]
+    pub max_title_length: usize,
+    /// Titles starting with these can't be used for new articles.
+    /// A leading `/` only matches whole path segments, e.g. `/u` reserves
+    /// `u` and `u/...` but not `Unicorn`.
+    #[serde(default = "default_reserved_title_prefixes")]
+    pub reserved_title_prefixes: Vec<String>,
+    /// Titles containing any of these, ignoring case, can't be used either.
+    #[serde(default)]
+    pub blocked_title_terms: Vec<String>,
+    /// How many tags one article can have when tagging it while editing;
+    /// 0 is unlimited.
+    #[serde(default = "default_max_tags_per_article")]
+    pub max_tags_per_article: usize,
+    /// Articles rendering to more bytes than this get a warning and a list
+    /// of their sections at the top; 0 never shows it.
+    #[serde(default)]
+    pub long_article_size: usize,
+    /// Added as `rel` to links leaving the wiki; empty leaves it out.
+    #[serde(default = "default_external_link_rel")]
+    pub external_link_rel: String,
+    /// Open links leaving the wiki in a new tab.
+    #[serde(default)]
+    pub external_links_new_tab: bool,
+    /// The only url schemes links and images may use, besides `file:` for
+    /// attachments; others like `javascript:` are neutralized.
+    #[serde(default = "default_allowed_link_schemes")]
+    pub allowed_link_schemes: Vec<String>,
+    /// Whether articles are rendered as Markdown or shown as plain text.
+    #[serde(default)]
+    pub article_format: articles::ArticleFormat,
+    /// Whether admins can download all articles as static HTML pages at
+    /// `/settings/export/html`.
+    #[serde(default = "default_html_export")]
+    pub html_export: bool,
+    /// Whether HTML in articles is rendered; otherwise it's shown as text.
+    #[serde(default = "default_allow_html")]
+    pub allow_html: bool,
+    /// How many revisions are kept per article; 0 keeps all of them.
+    /// The first revision is kept either way, so this is at least 2.
+    #[serde(default)]
+    pub max_revisions: u32,
+    /// Whether saving unchanged content is silently ignored or an error.
+    /// Renaming an article without changing its content works either way.
+    #[serde(default)]
+    pub identical_saves: IdenticalSaves,
+    /// Whether edits can be marked as minor, which leaves them out of
+    /// recent changes and keeps the date articles are listed with.
+    #[serde(default)]
+    pub minor_edits: bool,
+    /// How many redirect pages are followed in a row before giving up.
+    /// Redirects going in a circle are detected either way.
+    #[serde(default = "default_max_redirect_hops")]
+    pub max_redirect_hops: u32,
+    /// Whether saving a redirect page pointing to itself is rejected, and
+    /// one pointing to a missing article shows a warning.
+    #[serde(default = "default_check_redirects")]
+    pub check_redirects: bool,
+    /// Permanently redirect URLs ending in a slash, like `/Main/`, to the
+    /// same URL without it.
+    #[serde(default = "default_redirect_trailing_slashes")]
+    pub redirect_trailing_slashes: bool,
+    /// How many of an article's most active authors are shown on it.
+    #[serde(default = "default_top_editors")]
+    pub top_editors: u32,
+    /// Shown instead of the author's name when it's unknown.
+    #[serde(default = "default_ghost_author")]
+    pub ghost_author: String,
+    /// How dates are shown, as a strftime-style format string.
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// Where uploaded attachments are stored.
+    #[serde(default = "default_upload_dir")]
+    pub upload_dir: PathBuf,
+    /// The largest accepted upload, in bytes. Rocket's `limits.file` has
+    /// to be at least as large.
+    #[serde(default = "default_upload_max_size")]
+    pub upload_max_size: u64,
+    /// Content types which may be uploaded.
+    #[serde(default = "default_upload_allowed_types")]
+    pub upload_allowed_types: Vec<String>,
+    /// The widths thumbnails of uploaded images can be requested in.
+    #[serde(default = "default_thumbnail_widths")]
+    pub thumbnail_widths: Vec<u32>,
+}
+fn default_landing_template() -> String {
+    "landing".into()
+}
+fn default_pwned_passwords_url() -> String {
+    "https://api.pwnedpasswords.com/range/".into()
+}
+fn default_max_title_length() -> usize {
+    200
+}
+fn default_max_tags_per_article() -> usize {
+    20
+}
+fn default_reserved_title_prefixes() -> Vec<String> {
+    [
+        "Special:",
+        "/api",
+        "/u",
+        "/settings",
+        "/files",
+        "/res",
+        "/search",
+        "/create",
+        "/wanted",
+    ]
+    .iter()
+    .map(|p| p.to_string())
+    .collect()
+}
+fn default_allow_html() -> bool {
+    true
+}
+fn default_html_export() -> bool {
+    true
+}
+fn default_allowed_link_schemes() -> Vec<String> {
+    ["http", "https", "mailto"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+fn default_external_link_rel() -> String {
+    "nofollow noopener".into()
+}
+fn default_redirect_trailing_slashes() -> bool {
+    true
+}
+fn default_max_redirect_hops() -> u32 {
+    5
+}
+fn default_check_redirects() -> bool {
+    true
+}
+fn default_top_editors() -> u32 {
+    3
+}
+fn default_ghost_author() -> String {
+    "Anonymous".into()
+}
+fn default_date_format() -> String {
+    "%Y-%m-%d %H:%M".into()
+}
+fn default_site_url() -> String {
+    "http://localhost:8480".into()
+}
+fn default_mail_from() -> String {
+    "Simple Wiki <wiki@localhost>".into()
+}
+fn default_session_max_age() -> u32 {
+    // 30 days
+    30 * 86400
+}
+fn default_session_idle_timeout() -> u32 {
+    // 7 days
+    7 * 86400
+}
+fn default_remember_me_max_age() -> u32 {
+    // 90 days
+    90 * 86400
+}
+fn default_refresh_session_cookies() -> bool {
+    true
+}
+fn default_logout_on_password_change() -> bool {
+    true
+}
+fn default_recent_registrations_days() -> u32 {
+    7
+}
+fn default_captcha_concurrency() -> usize {
+    4
+}
+fn default_cache_capacity() -> usize {
+    cache::DEFAULT_CAPACITY
+}
+fn default_search_highlight_tag() -> String {
+    "b".into()
+}
+fn default_search_commit_batch() -> usize {
+    1
+}
+fn default_search_commit_interval_ms() -> u64 {
+    1000
+}
+fn default_search_index_retry() -> u32 {
+    60
+}
+fn default_search_rate_window() -> u32 {
+    60
+}
+fn default_comment_rate_window() -> u32 {
+    60
+}
+fn default_trusted_edit_count() -> u32 {
+    10
+}
+fn default_static_max_age() -> u32 {
+    // One day
+    86400
+}
+fn default_upload_dir() -> PathBuf {
+    "uploads".into()
+}
+fn default_upload_max_size() -> u64 {
+    // The same as Rocket's default file limit, 1 MiB
+    1024 * 1024
+}
+fn default_thumbnail_widths() -> Vec<u32> {
+    vec![200, 400, 800]
+}
+fn default_upload_allowed_types() -> Vec<String> {
+    ["image/png", "image/jpeg", "image/gif", "image/webp"]
+        .iter()
+        .map(|t| t.to_string())
+        .collect()
 }
 
 mod error;
@@ -24,23 +501,50 @@ pub use error::Error;
 type Result<T> = std::result::Result<T, Error>;
 
 // Route modules
+mod api;
 mod articles;
+mod attachments;
 mod settings;
 mod users;
 
+#[derive(rocket::Responder)]
+enum IndexResponse {
+    Redirect(Redirect),
+    Landing(Template),
+}
+
 #[rocket::get("/")]
-fn index(cfg: &State<Config>) -> Redirect {
-    Redirect::to(cfg.default_path.clone())
+async fn index(
+    cfg: &State<Config>,
+    db: &State<Db>,
+    user: Option<db::users::LoggedUser>,
+) -> Result<IndexResponse> {
+    match cfg.homepage {
+        Homepage::Redirect => Ok(IndexResponse::Redirect(Redirect::to(
+            cfg.default_path.clone(),
+        ))),
+        Homepage::Landing => Ok(IndexResponse::Landing(
+            articles::landing(db, cfg, user).await?,
+        )),
+    }
 }
 
 fn rocket() -> Rocket<Build> {
     rocket::build()
         .mount("/", rocket::routes![index])
         .mount("/", articles::routes())
+        .mount("/", attachments::routes())
         .mount("/u", users::routes())
+        .mount("/api", api::routes())
+        .register("/api", api::catchers())
         .mount("/settings", settings::routes())
-        .mount("/res", FileServer::from("static"))
-        .manage(Cache::default())
+        .attach(logging::RequestIdHeader)
+        .attach(security_headers::SecurityHeaders)
+        // Replaces Rocket's default one, which would send some of the same
+        // headers no matter what's configured
+        .attach(Shield::new())
+        .attach(trailing_slash::TrailingSlash)
+        .attach(private_mode::PrivateMode)
         .attach(AdHoc::try_on_ignite("Read config", |rocket| async {
             let mut config: Config = match rocket.figment().extract() {
                 Ok(c) => c,
@@ -49,10 +553,66 @@ fn rocket() -> Rocket<Build> {
                     return Err(rocket);
                 }
             };
+            if !templating::valid_date_format(&config.date_format) {
+                log::warn!(
+                    "Invalid date_format {:?}, using the default",
+                    config.date_format
+                );
+                config.date_format = default_date_format();
+            }
+            if !search::valid_highlight_tag(&config.search_highlight_tag) {
+                log::error!(
+                    "Invalid search_highlight_tag {:?}: only letters and digits are allowed",
+                    config.search_highlight_tag
+                );
+                return Err(rocket);
+            }
             if config.default_path.is_empty() {
                 config.default_path = "/".to_string() + &config.main_page;
             }
-            Ok(rocket.manage(config))
+            // This is inside the upload directory, so it creates that as well
+            if let Err(e) = std::fs::create_dir_all(attachments::thumbnail_dir(&config)) {
+                log::error!("Failed to create upload directory: {}", e);
+                return Err(rocket);
+            }
+            let captcha = match captcha::from_config(
+                config.captcha_provider,
+                config.captcha.as_ref(),
+                config.captcha_concurrency,
+            ) {
+                Ok(captcha) => captcha,
+                Err(e) => {
+                    log::error!("Failed to set up captcha: {}", e);
+                    return Err(rocket);
+                }
+            };
+            let mailer = match Mailer::new(config.smtp_url.as_deref(), &config.mail_from) {
+                Ok(mailer) => mailer,
+                Err(e) => {
+                    log::error!("Failed to set up mail: {}", e);
+                    return Err(rocket);
+                }
+            };
+            // These are the same on every page, so the templates get them directly
+            let announcement = templating::Announcement::default();
+            let globals = templating::Globals {
+                footer: config.footer.clone(),
+                ghost_author: config.ghost_author.clone(),
+                date_format: config.date_format.clone(),
+                announcement: announcement.clone(),
+            };
+            Ok(rocket
+                .attach(Template::custom(move |engines| {
+                    templating::register(&mut engines.tera, globals.clone())
+                }))
+                .mount("/res", StaticFiles::new("static", config.static_max_age))
+                .manage(Cache::new(config.cache_capacity))
+                .manage(FormTimer::default())
+                .manage(settings::MaintenanceLock::default())
+                .manage(captcha)
+                .manage(mailer)
+                .manage(announcement)
+                .manage(config))
         }))
         .attach(AdHoc::try_on_ignite("Connect to db", |rocket| async {
             #[derive(Deserialize)]
@@ -73,30 +633,226 @@ fn rocket() -> Rocket<Build> {
                     return Err(rocket);
                 }
             };
+            if let Err(e) = db::users::fill_name_skeletons(&db).await {
+                log::error!("Failed to compute username skeletons: {}", e);
+                return Err(rocket);
+            }
             Ok(rocket.manage(db))
         }))
         .attach(AdHoc::try_on_ignite(
             "Create search index",
             |rocket| async {
-                // I think I can unwrap this because this fairing will only run if the first one succeeds.
-                let db = rocket.state::<Db>().unwrap();
-                let index = match ArticleIndex::new(db).await {
+                // The other fairings still run after one fails, which already
+                // said what went wrong
+                let (db, config) = match (rocket.state::<Db>(), rocket.state::<Config>()) {
+                    (Some(db), Some(config)) => (db, config),
+                    _ => return Err(rocket),
+                };
+                let index = match ArticleIndex::empty(
+                    config.exclude_from_search.clone(),
+                    config.search_commit_batch,
+                ) {
                     Ok(index) => index,
                     Err(e) => {
                         log::error!("Failed to create article index: {}", e);
                         return Err(rocket);
                     }
                 };
+                match db::synonyms::list(db).await {
+                    Ok(groups) => index.set_synonyms(groups),
+                    Err(e) => {
+                        log::error!("Failed to load search synonyms: {}", e);
+                        return Err(rocket);
+                    }
+                }
+                if let Err(e) = fill_index(&index, db, search_index_broken(&rocket)).await {
+                    if config.search_index_failure == SearchIndexFailure::Abort {
+                        log::error!("Failed to create article index: {}", e);
+                        return Err(rocket);
+                    }
+                    // The retries are started once the wiki is up
+                    log::error!(
+                        "Failed to create article index, starting without search: {}",
+                        e
+                    );
+                }
+                if config.search_commit_batch > 1 {
+                    let interval =
+                        std::time::Duration::from_millis(config.search_commit_interval_ms);
+                    let index = index.clone();
+                    rocket::tokio::spawn(async move {
+                        loop {
+                            rocket::tokio::time::sleep(interval).await;
+                            if let Err(e) = index.flush() {
+                                log::warn!("Failed to commit the search index: {}", e);
+                            }
+                        }
+                    });
+                }
                 Ok(rocket.manage(index))
             },
         ))
-        .attach(Template::fairing())
+        .attach(AdHoc::on_liftoff("Retry search index", |rocket| {
+            Box::pin(async move {
+                // Only a degraded index isn't ready after ignition
+                let (db, config, index) = match (
+                    rocket.state::<Db>(),
+                    rocket.state::<Config>(),
+                    rocket.state::<ArticleIndex>(),
+                ) {
+                    (Some(db), Some(config), Some(index)) if !index.is_ready() => {
+                        (db, config, index)
+                    }
+                    _ => return,
+                };
+                let mut retry = std::time::Duration::from_secs(config.search_index_retry.into());
+                let force_failure = search_index_broken(rocket);
+                let mut shutdown = rocket.shutdown();
+                let index = index.clone();
+                let db = Db {
+                    pool: db.pool.clone(),
+                };
+                rocket::tokio::spawn(async move {
+                    loop {
+                        rocket::tokio::select! {
+                            _ = &mut shutdown => break,
+                            _ = rocket::tokio::time::sleep(retry) => {}
+                        }
+                        match fill_index(&index, &db, force_failure).await {
+                            Ok(()) => {
+                                log::info!("Created article index");
+                                break;
+                            }
+                            Err(e) => log::warn!("Failed to create article index: {}", e),
+                        }
+                        retry = (retry * 2).min(MAX_SEARCH_INDEX_RETRY.max(retry));
+                    }
+                });
+            })
+        }))
+        .attach(AdHoc::try_on_ignite("Load announcement", |rocket| async {
+            // Both are there if the fairings above succeeded.
+            let (db, announcement) = match (
+                rocket.state::<Db>(),
+                rocket.state::<templating::Announcement>(),
+            ) {
+                (Some(db), Some(announcement)) => (db, announcement),
+                _ => return Err(rocket),
+            };
+            match db.announcement().await {
+                Ok(text) => {
+                    announcement.set(text.as_deref().unwrap_or_default());
+                    Ok(rocket)
+                }
+                Err(e) => {
+                    log::error!("Failed to load announcement: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::try_on_ignite(
+            "Discover OIDC provider",
+            |rocket| async {
+                // Same as above, the config is only there if reading it succeeded.
+                let config = match rocket.state::<Config>().map(|config| &config.oidc) {
+                    Some(Some(config)) => config.clone(),
+                    Some(None) => return Ok(rocket),
+                    None => return Err(rocket),
+                };
+                let provider = match OidcProvider::discover(config).await {
+                    Ok(provider) => provider,
+                    Err(e) => {
+                        log::error!("Failed to discover OIDC provider: {}", e);
+                        return Err(rocket);
+                    }
+                };
+                Ok(rocket.manage(provider).mount("/u/oidc", oidc::routes()))
+            },
+        ))
+        .attach(AdHoc::on_ignite("JSON request logging", |rocket| async {
+            if rocket
+                .state::<Config>()
+                .is_some_and(|config| config.json_logging)
+            {
+                rocket.attach(logging::RequestLogger::stdout())
+            } else {
+                rocket
+            }
+        }))
+}
+
+/// The longest wait between attempts at building a degraded search index.
+const MAX_SEARCH_INDEX_RETRY: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Makes building the search index fail when it's managed.
+#[cfg(test)]
+struct BreakSearchIndex;
+
+/// The wiki with a search index that can't be built, for testing degraded
+/// mode.
+#[cfg(test)]
+fn rocket_with_broken_search() -> Rocket<Build> {
+    rocket().manage(BreakSearchIndex)
+}
+
+/// Whether a test asked for a broken search index.
+#[cfg(test)]
+fn search_index_broken<P: rocket::Phase>(rocket: &Rocket<P>) -> bool {
+    rocket.state::<BreakSearchIndex>().is_some()
+}
+#[cfg(not(test))]
+fn search_index_broken<P: rocket::Phase>(_: &Rocket<P>) -> bool {
+    false
+}
+
+/// Adds the articles to the search index, unless told to fail.
+async fn fill_index(index: &ArticleIndex, db: &Db, force_failure: bool) -> Result<()> {
+    if force_failure {
+        return Err(Error::SearchIndexUnavailable);
+    }
+    index.fill(db).await
+}
+
+/// Cleans up after Rocket stopped serving requests (e.g. after a Ctrl-C).
+/// Rocket waits for in-flight requests first, so anything they wrote to the
+/// search index is committed here.
+async fn shutdown(index: Option<search::IndexShutdownHandle>, pool: Option<sqlx::PgPool>) {
+    if let Some(index) = index {
+        if let Err(e) = index.commit() {
+            log::error!("Failed to commit the search index: {}", e);
+        }
+    }
+    if let Some(pool) = pool {
+        pool.close().await;
+    }
 }
 
 #[rocket::main]
 async fn main() -> Result<()> {
+    let json_logging = rocket::Config::figment()
+        .extract_inner("json_logging")
+        .unwrap_or(false);
+    if json_logging {
+        if let Err(e) = logging::init_json_logger() {
+            eprintln!("Failed to set up JSON logging: {}", e);
+        }
+    }
     loop {
-        if let Err(e) = rocket().launch().await {
+        let rocket = match rocket().ignite().await {
+            Ok(rocket) => rocket,
+            Err(e) => {
+                println!("Rocket crashed: {:?}", e);
+                continue;
+            }
+        };
+        // Launching consumes rocket, so keep what we need for shutting down
+        let index = rocket
+            .state::<ArticleIndex>()
+            .map(ArticleIndex::shutdown_handle);
+        let pool = rocket.state::<Db>().map(|db| db.pool.clone());
+        let result = rocket.launch().await;
+        shutdown(index, pool).await;
+        if let Err(e) = result {
             println!("Rocket crashed: {:?}", e);
             continue;
         }
@@ -0,0 +1,374 @@
+//! Optional login through an external OpenID Connect provider.
+//! This is only mounted when an `oidc` section is present in the config;
+//! password logins keep working alongside it.
+use std::str::FromStr;
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rocket::{
+    get,
+    http::{Cookie, CookieJar, SameSite},
+    post,
+    response::Redirect,
+    Route, State,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    client_ip::ClientIp,
+    db::{
+        self,
+        users::{LoggedUser, UserSession},
+    },
+    users, Config, Db, Error, Result,
+};
+
+pub fn routes() -> Vec<Route> {
+    rocket::routes![login, link, callback]
+}
+
+const ATTEMPT_COOKIE: &str = "oidc_attempt";
+/// How long users have for logging in at the provider.
+const ATTEMPT_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    /// The provider's issuer url, used for discovery.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where the provider sends the user back to; this should point to
+    /// `/u/oidc/callback` on this wiki.
+    pub redirect_url: String,
+}
+
+/// The claims we care about from an id token.
+/// exp, iss and aud are checked by jsonwebtoken directly.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub preferred_username: Option<String>,
+}
+
+struct SigningKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey<'static>,
+}
+
+/// A discovered OpenID Connect provider.
+pub struct OidcProvider {
+    config: OidcConfig,
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    keys: Vec<SigningKey>,
+    client: reqwest::Client,
+}
+
+impl OidcProvider {
+    /// Fetches the provider's metadata and signing keys.
+    pub async fn discover(config: OidcConfig) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Metadata {
+            issuer: String,
+            authorization_endpoint: String,
+            token_endpoint: String,
+            jwks_uri: String,
+        }
+        #[derive(Deserialize)]
+        struct Jwk {
+            kid: Option<String>,
+            kty: String,
+            alg: Option<String>,
+            n: Option<String>,
+            e: Option<String>,
+        }
+        #[derive(Deserialize)]
+        struct JwkSet {
+            keys: Vec<Jwk>,
+        }
+        let client = reqwest::Client::new();
+        let metadata_url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let metadata: Metadata = client
+            .get(&metadata_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwks: JwkSet = client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // Only RSA keys are supported, since that's what everyone uses
+        let keys = jwks
+            .keys
+            .into_iter()
+            .filter(|jwk| jwk.kty == "RSA")
+            .filter_map(|jwk| {
+                let algorithm = match jwk.alg.as_deref() {
+                    Some(alg) => Algorithm::from_str(alg).ok()?,
+                    None => Algorithm::RS256,
+                };
+                let key = DecodingKey::from_rsa_components(jwk.n.as_deref()?, jwk.e.as_deref()?)
+                    .into_static();
+                Some(SigningKey {
+                    kid: jwk.kid,
+                    algorithm,
+                    key,
+                })
+            })
+            .collect();
+        Ok(Self {
+            config,
+            issuer: metadata.issuer,
+            authorization_endpoint: metadata.authorization_endpoint,
+            token_endpoint: metadata.token_endpoint,
+            keys,
+            client,
+        })
+    }
+
+    /// Creates a provider verifying tokens with a shared HS256 secret.
+    #[cfg(test)]
+    pub fn with_secret(config: OidcConfig, secret: &[u8]) -> Self {
+        Self {
+            issuer: config.issuer.clone(),
+            authorization_endpoint: format!("{}/authorize", config.issuer),
+            token_endpoint: format!("{}/token", config.issuer),
+            config,
+            keys: vec![SigningKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret).into_static(),
+            }],
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Builds the url the user has to be sent to for logging in.
+    pub fn authorization_url(&self, state: Uuid, nonce: &str) -> Result<String> {
+        let state = state.to_string();
+        let url = reqwest::Url::parse_with_params(
+            &self.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("scope", "openid profile"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_url),
+                ("state", &state),
+                ("nonce", nonce),
+            ],
+        )
+        .map_err(|_| Error::OidcProviderInvalid)?;
+        Ok(url.into())
+    }
+
+    /// Exchanges an authorization code for the user's id token.
+    pub async fn exchange_code(&self, code: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            id_token: String,
+        }
+        let response: TokenResponse = self
+            .client
+            .post(&self.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_url),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.id_token)
+    }
+
+    /// Verifies the id token's signature and claims, including the nonce we
+    /// generated for this login attempt.
+    pub fn verify_id_token(&self, token: &str, nonce: &str) -> Result<IdTokenClaims> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                key.algorithm == header.alg && (header.kid.is_none() || key.kid == header.kid)
+            })
+            .ok_or(Error::OidcKeyUnknown)?;
+        let mut validation = Validation::new(key.algorithm);
+        validation.iss = Some(self.issuer.clone());
+        validation.set_audience(&[&self.config.client_id]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(token, &key.key, &validation)?.claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(Error::OidcNonceMismatch);
+        }
+        Ok(claims)
+    }
+}
+
+/// Finds the user logging in, creating an account on their first login.
+/// New accounts go through the same checks as registering, except that
+/// taken names get a suffix. They also need an invite while the wiki is
+/// invite-only, but there's no code to take from an external login.
+pub(crate) async fn login_user(
+    cfg: &Config,
+    db: &Db,
+    client_ip: &ClientIp,
+    claims: &IdTokenClaims,
+) -> Result<Uuid> {
+    if let Some(user_id) = db::users::oidc_user(db, &claims.sub).await? {
+        return Ok(user_id);
+    }
+    if !db.registration_enabled().await? || users::registration_blocked(cfg, client_ip) {
+        return Err(Error::RegistrationBlocked);
+    }
+    if cfg.invite_only {
+        return Err(Error::OidcInviteRequired);
+    }
+    let name = claims.preferred_username.as_deref().unwrap_or(&claims.sub);
+    if users::reserved_username(name)
+        || (!db.user_name_exists(name).await? && users::similar_username(cfg, db, name).await?)
+    {
+        return Err(Error::OidcUsernameRejected(name.to_owned()));
+    }
+    db::users::provision_oidc(db, &claims.sub, name).await
+}
+
+/// A login attempt started in this browser, kept in a cookie until the
+/// provider sends the user back. The callback only accepts the state from
+/// this cookie, so nobody can make someone else's browser finish a login
+/// they started. Nothing in it is secret from the user, the nonce is in the
+/// authorization url anyway.
+#[derive(Debug, PartialEq)]
+pub(crate) struct LoginAttempt {
+    pub state: Uuid,
+    pub nonce: Uuid,
+    /// The user who asked to link their account, if this isn't a login.
+    pub link_user: Option<Uuid>,
+}
+
+impl LoginAttempt {
+    fn new(link_user: Option<Uuid>) -> Self {
+        Self {
+            state: Uuid::new_v4(),
+            nonce: Uuid::new_v4(),
+            link_user,
+        }
+    }
+
+    /// The attempt as `<state>.<nonce>[.<user id>]`.
+    fn encode(&self) -> String {
+        let mut value = format!("{}.{}", self.state, self.nonce);
+        if let Some(user_id) = self.link_user {
+            value.push('.');
+            value.push_str(&user_id.to_string());
+        }
+        value
+    }
+
+    pub(crate) fn decode(value: &str) -> Option<Self> {
+        let mut parts = value.split('.');
+        let state = parts.next()?.parse().ok()?;
+        let nonce = parts.next()?.parse().ok()?;
+        let link_user = parts.next().map(str::parse).transpose().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            state,
+            nonce,
+            link_user,
+        })
+    }
+
+    fn cookie(&self) -> Cookie<'static> {
+        Cookie::build(ATTEMPT_COOKIE, self.encode())
+            .path("/u/oidc")
+            .http_only(true)
+            // The provider sends the user back with a top-level GET
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::minutes(ATTEMPT_MINUTES))
+            .finish()
+    }
+
+    /// Takes the attempt out of the cookies; every attempt is only good for
+    /// one callback.
+    fn take(cookies: &CookieJar<'_>) -> Option<Self> {
+        let attempt = cookies
+            .get(ATTEMPT_COOKIE)
+            .and_then(|cookie| Self::decode(cookie.value()));
+        cookies.remove(Cookie::build(ATTEMPT_COOKIE, "").path("/u/oidc").finish());
+        attempt
+    }
+
+    /// Remembers the attempt and sends the user to the provider.
+    fn start(self, provider: &OidcProvider, cookies: &CookieJar<'_>) -> Result<Redirect> {
+        let url = provider.authorization_url(self.state, &self.nonce.to_string())?;
+        cookies.add(self.cookie());
+        Ok(Redirect::to(url))
+    }
+}
+
+#[get("/login")]
+fn login(provider: &State<OidcProvider>, cookies: &CookieJar<'_>) -> Result<Redirect> {
+    LoginAttempt::new(None).start(provider, cookies)
+}
+
+/// Links the logged in user's account to the one they log in with at the
+/// provider. This is only ever started from the settings page.
+#[post("/link")]
+fn link(
+    provider: &State<OidcProvider>,
+    cookies: &CookieJar<'_>,
+    user: LoggedUser,
+) -> Result<Redirect> {
+    LoginAttempt::new(Some(user.id())).start(provider, cookies)
+}
+
+#[get("/callback?<code>&<state>")]
+#[allow(clippy::too_many_arguments)]
+async fn callback(
+    cfg: &State<Config>,
+    db: &State<Db>,
+    provider: &State<OidcProvider>,
+    cookies: &CookieJar<'_>,
+    session: Option<&UserSession>,
+    client_ip: ClientIp,
+    code: String,
+    state: Uuid,
+) -> Result<Redirect> {
+    let attempt = LoginAttempt::take(cookies)
+        .filter(|attempt| attempt.state == state)
+        .ok_or(Error::OidcStateUnknown)?;
+    // Linking has to finish in the session that asked for it
+    if let Some(user_id) = attempt.link_user {
+        if session.map(|session| session.user_id) != Some(user_id) {
+            return Err(Error::OidcStateUnknown);
+        }
+    }
+    let id_token = provider.exchange_code(&code).await?;
+    let claims = provider.verify_id_token(&id_token, &attempt.nonce.to_string())?;
+    match attempt.link_user {
+        Some(user_id) => {
+            db::users::link_oidc(db, user_id, &claims.sub).await?;
+            Ok(Redirect::to("/settings"))
+        }
+        None => {
+            let user_id = login_user(cfg, db, &client_ip, &claims).await?;
+            let session_id = db::users::create_session(db, user_id, cfg.max_sessions, None).await?;
+            cookies.add(db::users::session_cookie(session_id, None));
+            Ok(Redirect::to(cfg.default_path.clone()))
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Splitting long lists into pages.
+use serde::Serialize;
+
+/// One page of a longer list, with what's needed to link to the others.
+/// Templates render the links with the `pagination` partial.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The current page, starting at 1.
+    pub page: usize,
+    pub total_pages: usize,
+    pub total_count: usize,
+}
+
+impl<T> Page<T> {
+    /// Takes the given page out of the complete list.
+    /// Pages past the end are empty; page 0 is treated as the first one.
+    pub fn from_items(items: Vec<T>, page: usize, per_page: usize) -> Self {
+        let page = page.max(1);
+        let total_count = items.len();
+        let items = items
+            .into_iter()
+            .skip((page - 1) * per_page)
+            .take(per_page)
+            .collect();
+        Self {
+            items,
+            page,
+            total_pages: total_pages(total_count, per_page),
+            total_count,
+        }
+    }
+}
+
+/// How many pages are needed for the given amount of items.
+/// There's always at least one, even if it's empty.
+pub fn total_pages(total_count: usize, per_page: usize) -> usize {
+    let pages = total_count / per_page;
+    if total_count.is_multiple_of(per_page) {
+        pages.max(1)
+    } else {
+        pages + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{total_pages, Page};
+
+    #[test]
+    fn counts_pages() {
+        assert_eq!(total_pages(0, 10), 1);
+        assert_eq!(total_pages(1, 10), 1);
+        assert_eq!(total_pages(10, 10), 1);
+        assert_eq!(total_pages(11, 10), 2);
+        assert_eq!(total_pages(25, 10), 3);
+        assert_eq!(total_pages(30, 10), 3);
+    }
+
+    #[test]
+    fn slices_pages() {
+        let items = (1..=25).collect::<Vec<_>>();
+        let page = Page::from_items(items.clone(), 3, 10);
+        assert_eq!(page.items, vec![21, 22, 23, 24, 25]);
+        assert_eq!((page.page, page.total_pages, page.total_count), (3, 3, 25));
+        // Out of range pages are empty, but still know about the others
+        let page = Page::from_items(items.clone(), 4, 10);
+        assert!(page.items.is_empty());
+        assert_eq!(page.total_pages, 3);
+        assert_eq!(Page::from_items(items, 0, 10).page, 1);
+    }
+}
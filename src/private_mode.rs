@@ -0,0 +1,84 @@
+//! Optionally hiding the whole wiki from anyone who isn't logged in.
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, Method, RawStr},
+    Data, Request,
+};
+
+use crate::{db::users::ApiUser, Config};
+
+/// Paths anonymous visitors can still reach, so they can log in, along with
+/// everything below them.
+const PUBLIC_PATHS: &[&str] = &[
+    "/u/login",
+    "/u/login-required",
+    "/u/register",
+    "/u/verify",
+    "/u/oidc",
+    "/res",
+];
+
+/// Compares whole segments, so e.g. a user page like `/u/loginbob` isn't
+/// public just because it starts like the login.
+fn is_public(path: &str) -> bool {
+    PUBLIC_PATHS
+        .iter()
+        .any(|public| match path.strip_prefix(public) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        })
+}
+
+/// With `private` set in the config, sends every request without a session
+/// (or API token) to `/u/login-required`, which redirects to the login.
+pub struct PrivateMode;
+#[rocket::async_trait]
+impl Fairing for PrivateMode {
+    fn info(&self) -> Info {
+        Info {
+            name: "Private mode",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let private = request
+            .rocket()
+            .state::<Config>()
+            .is_some_and(|cfg| cfg.private);
+        if !private || is_public(request.uri().path().as_str()) {
+            return;
+        }
+        if request.guard::<ApiUser>().await.succeeded().is_some() {
+            return;
+        }
+        let next = RawStr::new(&request.uri().to_string())
+            .percent_encode()
+            .to_string();
+        if let Ok(uri) = Origin::parse_owned(format!("/u/login-required?next={}", next)) {
+            request.set_method(Method::Get);
+            request.set_uri(uri);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public;
+
+    #[test]
+    fn public_paths() {
+        assert!(is_public("/u/login"));
+        assert!(is_public("/u/register"));
+        assert!(is_public("/res/css/style.css"));
+        assert!(!is_public("/"));
+        assert!(!is_public("/Main"));
+        assert!(!is_public("/search"));
+        assert!(is_public("/u/verify/some-token"));
+        assert!(!is_public("/u/someone"));
+        assert!(!is_public("/u/loginbob"));
+        assert!(!is_public("/u/registered"));
+        assert!(!is_public("/resources"));
+        assert!(!is_public("/api/tags"));
+    }
+}
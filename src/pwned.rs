@@ -0,0 +1,55 @@
+//! Checks passwords against the Pwned Passwords range API.
+//! Using the k-anonymity model, only the first five characters of the
+//! password's SHA-1 hash ever leave the server.
+use sha1::{Digest, Sha1};
+
+/// Returns true if the password is known from a data breach.
+/// If the API can't be reached, this fails open and returns false, so an
+/// outage doesn't block registrations.
+pub async fn is_breached(api_url: &str, password: &str) -> bool {
+    let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+    match fetch_range(api_url, prefix).await {
+        Ok(range) => range_contains(&range, suffix),
+        Err(e) => {
+            log::warn!("Failed to check password against breaches: {}", e);
+            false
+        }
+    }
+}
+
+async fn fetch_range(api_url: &str, prefix: &str) -> reqwest::Result<String> {
+    reqwest::get(format!("{}{}", api_url, prefix))
+        .await?
+        .error_for_status()?
+        .text()
+        .await
+}
+
+/// The API returns one `SUFFIX:COUNT` pair per line.
+fn range_contains(range: &str, suffix: &str) -> bool {
+    range
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .any(|line_suffix| line_suffix.trim().eq_ignore_ascii_case(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::range_contains;
+
+    #[test]
+    fn range_lookup() {
+        let range = "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n\
+            00D4F6E8FA6EECAD2A3AA415EEC418D38EC:2\r\n\
+            011053FD0102E94D6AE2F8B83D76FAF94F6:1";
+        assert!(range_contains(range, "00D4F6E8FA6EECAD2A3AA415EEC418D38EC"));
+        // The hash case shouldn't matter
+        assert!(range_contains(range, "011053fd0102e94d6ae2f8b83d76faf94f6"));
+        assert!(!range_contains(
+            range,
+            "011053FD0102E94D6AE2F8B83D76FAF94F7"
+        ));
+        assert!(!range_contains("", "011053FD0102E94D6AE2F8B83D76FAF94F6"));
+    }
+}
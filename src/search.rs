@@ -1,25 +1,116 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
 use chrono::{DateTime, Utc};
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use pulldown_cmark::{html, BrokenLink, CowStr, Event, Options, Parser, Tag};
+use rocket::FromFormField;
+use serde::{Deserialize, Serialize};
 use tantivy::{
     collector::TopDocs,
     doc,
-    query::QueryParser,
-    schema::{Field, Schema, STORED, STRING, TEXT},
-    IndexReader, IndexWriter, Snippet, SnippetGenerator, Term,
+    query::{AllQuery, BooleanQuery, MoreLikeThisQuery, Occur, Query, QueryParser, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, FAST, STORED, STRING, TEXT},
+    DocAddress, Document, IndexReader, IndexWriter, Snippet, SnippetGenerator, Term,
 };
 use uuid::Uuid;
 
-use crate::{db::articles::ArticleWithRevision, Result};
+use crate::db::articles::ArticleWithRevision;
+
+/// Search results beyond this aren't useful anymore.
+const MAX_SEARCH_RESULTS: usize = 100;
+
+/// What the namespace field holds for articles without a namespace.
+/// This is also what the search page sends for scoping to them.
+pub const MAIN_NAMESPACE: &str = "(main)";
+
+/// How many matching snippets are shown per article when grouping them.
+const MAX_GROUPED_SNIPPETS: usize = 3;
+/// Roughly the length of tantivy's snippets.
+const SNIPPET_CHUNK_LEN: usize = 150;
+
+/// How search results are ordered.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, FromFormField,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchSort {
+    #[default]
+    Relevance,
+    /// Most recently edited first.
+    Date,
+}
+
+/// What's shown below each search result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetStrategy {
+    /// The best matching part of the article.
+    #[default]
+    Best,
+    /// Up to a few matching parts, so multiple matches in one article are
+    /// shown together.
+    Grouped,
+}
+
+/// What a search without a query shows instead of results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptySearch {
+    /// The most recently edited articles.
+    #[default]
+    Recent,
+    /// Every article, most recently edited first.
+    All,
+}
+
+/// The namespace of an article is the part of its name before the first
+/// colon, e.g. `Help` for `Help:Editing`.
+pub fn namespace(article_name: &str) -> &str {
+    match article_name.split_once(':') {
+        Some((namespace, _)) if !namespace.is_empty() => namespace,
+        _ => MAIN_NAMESPACE,
+    }
+}
 
+/// Clones share the same index, e.g. for filling it in the background.
+#[derive(Clone)]
 pub struct ArticleIndex {
+    /// Patterns of article names which shouldn't be searchable.
+    excluded: Vec<String>,
     id_field: Field,
     name_field: Field,
+    namespace_field: Field,
     content_field: Field,
+    tags_field: Field,
     date_field: Field,
     inner: tantivy::Index,
     pub(crate) reader: IndexReader,
-    writer: Mutex<IndexWriter>,
+    writer: Arc<Mutex<IndexWriter>>,
+    /// Counts the commits, so cached search results can tell they're stale.
+    generation: Arc<AtomicU64>,
+    /// Whether the articles were added yet; see `fill`.
+    ready: Arc<AtomicBool>,
+    /// Groups of words which find each other; see `synonym_variants`.
+    synonyms: Arc<RwLock<Vec<Vec<String>>>>,
+    /// How many changes were written since the last commit.
+    pending: Arc<AtomicUsize>,
+    /// How many changes are collected before committing them together;
+    /// whatever is left over is committed by `flush`.
+    commit_batch: usize,
+}
+
+/// Keeps the index writer reachable after Rocket took ownership of the
+/// index, so pending changes can still be committed on shutdown.
+pub struct IndexShutdownHandle(Arc<Mutex<IndexWriter>>);
+impl IndexShutdownHandle {
+    /// Waits for any running write to finish and commits what's pending.
+    pub fn commit(&self) -> crate::Result<()> {
+        self.0.lock().commit()?;
+        Ok(())
+    }
 }
 
 fn serialize_snippet<S: serde::Serializer>(
@@ -27,15 +118,166 @@ fn serialize_snippet<S: serde::Serializer>(
     s: S,
 ) -> std::result::Result<S::Ok, S::Error> {
     match snippet {
-        SnippetOrFirstSentence::Snippet(snippet) => s.serialize_str(&snippet.to_html()),
-        SnippetOrFirstSentence::FirstSentence(string) => s.serialize_str(string),
+        SnippetOrFirstSentence::Snippet(html) | SnippetOrFirstSentence::FirstSentence(html) => {
+            s.serialize_str(html)
+        }
+        SnippetOrFirstSentence::Grouped(snippets) => s.serialize_str(&snippets.join(" … ")),
     }
 }
 
+/// Snippets are kept as HTML, with their matches highlighted.
 #[derive(Debug)]
 pub enum SnippetOrFirstSentence {
-    Snippet(Snippet),
+    Snippet(String),
     FirstSentence(String),
+    Grouped(Vec<String>),
+}
+
+/// How many versions of a query synonyms can lead to at most.
+const MAX_SYNONYM_VARIANTS: usize = 16;
+
+/// The byte ranges of the words in the text, leaving out quoted phrases.
+fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        match (start, c.is_alphanumeric() && !quoted) {
+            (None, true) => start = Some(i),
+            (Some(from), false) => {
+                words.push((from, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        words.push((from, text.len()));
+    }
+    words
+}
+
+/// The query itself, followed by its versions with words replaced by their
+/// synonyms (quoted, as they can consist of several words). Words in quoted
+/// phrases stay as they are.
+fn synonym_variants(text: &str, groups: &[Vec<String>]) -> Vec<String> {
+    let mut variants = vec![text.to_string()];
+    // Going backwards, the earlier words' offsets stay the same in every
+    // variant
+    for (start, end) in words(text).into_iter().rev() {
+        let word = text[start..end].to_lowercase();
+        let alternatives = groups
+            .iter()
+            .filter(|group| group.iter().any(|term| term.to_lowercase() == word))
+            .flatten()
+            .filter(|term| term.to_lowercase() != word)
+            .collect::<Vec<_>>();
+        let mut more = Vec::new();
+        for variant in &variants {
+            for alternative in &alternatives {
+                if variants.len() + more.len() >= MAX_SYNONYM_VARIANTS {
+                    break;
+                }
+                more.push(format!(
+                    "{}\"{}\"{}",
+                    &variant[..start],
+                    alternative,
+                    &variant[end..]
+                ));
+            }
+        }
+        variants.extend(more);
+    }
+    variants
+}
+
+/// The whitespace-separated parts of the query, keeping quoted phrases in
+/// one piece.
+fn query_parts(query: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in query.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        match (start, c.is_whitespace() && !quoted) {
+            (None, false) => start = Some(i),
+            (Some(from), true) => {
+                parts.push(&query[from..i]);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        parts.push(&query[from..]);
+    }
+    parts
+}
+
+/// The query without the given words, compared case-insensitively.
+/// Only whole words count, so quoted phrases are left alone.
+pub fn without_stop_words(query: &str, stop_words: &[String]) -> String {
+    query_parts(query)
+        .into_iter()
+        .filter(|part| {
+            part.contains('"')
+                || !stop_words
+                    .iter()
+                    .any(|stop_word| stop_word.to_lowercase() == part.to_lowercase())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether the tag can be used for highlighting matches, i.e. is a plain
+/// element name which can't break out of the markup.
+pub fn valid_highlight_tag(tag: &str) -> bool {
+    tag.starts_with(|c: char| c.is_ascii_alphabetic())
+        && tag.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Like `Snippet::to_html`, but with the given tag around the matches.
+fn snippet_html(snippet: &Snippet, tag: &str) -> String {
+    use pulldown_cmark::escape::escape_html;
+
+    let fragment = snippet.fragments();
+    // Writing to a String can't fail
+    let mut html = String::with_capacity(fragment.len());
+    let mut start = 0;
+    for range in snippet.highlighted() {
+        escape_html(&mut html, &fragment[start..range.start]).unwrap();
+        html.push_str(&format!("<{}>", tag));
+        escape_html(&mut html, &fragment[range.clone()]).unwrap();
+        html.push_str(&format!("</{}>", tag));
+        start = range.end;
+    }
+    escape_html(&mut html, &fragment[start..]).unwrap();
+    html
+}
+
+/// An article which should be in the index, but isn't.
+#[derive(Debug, Serialize)]
+pub struct UnindexedArticle {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// How the index differs from the database; see `ArticleIndex::compare`.
+#[derive(Debug, Serialize)]
+pub struct IndexDrift {
+    pub missing: Vec<UnindexedArticle>,
+    /// Ids of documents without an article.
+    pub orphaned: Vec<String>,
+}
+impl IndexDrift {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty()
+    }
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -55,7 +297,8 @@ fn markdown_to_text(input: &str) -> String {
             broken_link.reference.to_owned().into(),
         ))
     };
-    let parser = Parser::new_with_broken_link_callback(input, Options::all(), Some(callback))
+    let input = crate::details::flatten(input);
+    let parser = Parser::new_with_broken_link_callback(&input, Options::all(), Some(callback))
         .filter_map(|event| match event {
             Event::Text(_) => Some(event),
             Event::Start(Tag::Link(_, _, _)) | Event::End(Tag::Link(_, _, _)) => None,
@@ -67,123 +310,544 @@ fn markdown_to_text(input: &str) -> String {
     output.trim().into()
 }
 
+/// Splits the text at whitespace into pieces of about `len` bytes.
+fn chunks(text: &str, len: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while rest.len() > len {
+        // Don't split inside a character, and prefer splitting after a word
+        let mut end = len;
+        while !rest.is_char_boundary(end) {
+            end += 1;
+        }
+        let end = rest[end..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |index| end + index);
+        chunks.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+/// Checks if the name matches the pattern, where `*` matches any amount of
+/// characters, so e.g. `Sandbox*` excludes all sandbox pages.
+pub(crate) fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // No wildcard at all, so this has to be an exact match
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
 impl ArticleIndex {
-    pub async fn new(db: &crate::Db) -> Result<ArticleIndex> {
+    pub async fn new(
+        db: &crate::Db,
+        excluded: Vec<String>,
+        commit_batch: usize,
+    ) -> crate::Result<ArticleIndex> {
+        let index = Self::empty(excluded, commit_batch)?;
+        index.fill(db).await?;
+        Ok(index)
+    }
+
+    /// Creates an index without any articles, which `fill` adds later.
+    /// Changes are only committed (i.e. searchable) once `commit_batch` of
+    /// them are pending, or on `flush`.
+    pub fn empty(excluded: Vec<String>, commit_batch: usize) -> crate::Result<ArticleIndex> {
         let mut schema_builder = Schema::builder();
-        let id_field = schema_builder.add_text_field("id", STRING);
+        // Stored so `compare` can list the indexed articles
+        let id_field = schema_builder.add_text_field("id", STRING | STORED);
         let name_field = schema_builder.add_text_field("name", TEXT | STORED);
+        let namespace_field = schema_builder.add_text_field("namespace", STRING);
         let content_field = schema_builder.add_text_field("content", TEXT | STORED);
-        let date_field = schema_builder.add_date_field("last_edited", STORED);
+        let tags_field = schema_builder.add_text_field("tags", TEXT);
+        // Fast for sorting by it
+        let date_field = schema_builder.add_date_field("last_edited", STORED | FAST);
         let schema = schema_builder.build();
         let inner = tantivy::Index::create_in_ram(schema);
-
-        let mut writer = inner.writer(50_000_000)?;
-        for article in db.list_articles().await? {
-            let ArticleWithRevision {
-                id,
-                name,
-                content,
-                rev_created,
-            } = article;
-            let date = DateTime::from_utc(rev_created, Utc);
-            writer.add_document(doc! {
-                id_field => id.to_string(),
-                name_field => name,
-                content_field => markdown_to_text(&content),
-                date_field => date,
-            });
-        }
-        writer.commit()?;
-
+        let writer = inner.writer(50_000_000)?;
         let reader = inner
             .reader_builder()
             .reload_policy(tantivy::ReloadPolicy::OnCommit)
             .try_into()?;
 
         Ok(ArticleIndex {
+            excluded,
             id_field,
             name_field,
+            namespace_field,
             content_field,
+            tags_field,
             date_field,
             inner,
             reader,
-            writer: Mutex::new(writer),
+            writer: Arc::new(Mutex::new(writer)),
+            generation: Arc::new(AtomicU64::new(0)),
+            ready: Arc::new(AtomicBool::new(false)),
+            synonyms: Arc::default(),
+            pending: Arc::new(AtomicUsize::new(0)),
+            commit_batch: commit_batch.max(1),
         })
     }
 
-    pub fn search_by_text(&self, text: &str) -> Result<Vec<SearchResult>> {
+    /// Replaces everything in the index with the current articles.
+    /// Edits made while the articles are loaded might be missing until the
+    /// next edit of the same article.
+    pub async fn fill(&self, db: &crate::Db) -> crate::Result<()> {
+        let articles = db.list_articles().await?;
+        let mut writer = self.writer.lock();
+        writer.delete_all_documents()?;
+        for article in articles {
+            let ArticleWithRevision {
+                id,
+                name,
+                content,
+                rev_created,
+                tags,
+            } = article;
+            if self.is_excluded(&name) {
+                continue;
+            }
+            let date = DateTime::from_utc(rev_created, Utc);
+            writer.add_document(self.document(id, &name, &content, &tags, date));
+        }
+        self.commit(&mut writer)?;
+        drop(writer);
+        self.ready.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Indexes the given articles again, e.g. after their tags changed.
+    pub async fn reindex(&self, db: &crate::Db, article_names: &[String]) -> crate::Result<()> {
+        for article in crate::db::articles::list_by_name(db, article_names).await? {
+            let date = DateTime::from_utc(article.rev_created, Utc);
+            self.add_or_update_article(
+                article.id,
+                &article.name,
+                &article.content,
+                &article.tags,
+                date,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn document(
+        &self,
+        id: Uuid,
+        article_name: &str,
+        content: &str,
+        tags: &[String],
+        date: DateTime<Utc>,
+    ) -> Document {
+        let mut doc = doc! {
+            self.id_field => id.to_string(),
+            self.namespace_field => namespace(article_name),
+            self.name_field => article_name,
+            self.content_field => markdown_to_text(content),
+            self.date_field => date,
+        };
+        for tag in tags {
+            doc.add_text(self.tags_field, tag);
+        }
+        doc
+    }
+
+    fn commit(&self, writer: &mut IndexWriter) -> crate::Result<()> {
+        writer.commit()?;
+        self.pending.store(0, Ordering::Release);
+        self.generation.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Commits the changes which are still waiting for their batch to fill.
+    pub fn flush(&self) -> crate::Result<()> {
+        if self.pending.load(Ordering::Acquire) > 0 {
+            self.commit(&mut self.writer.lock())?;
+        }
+        Ok(())
+    }
+
+    /// Commits what's pending and merges all segments into one, which keeps
+    /// searches fast after many edits. Returns how many segments there were.
+    pub async fn optimize(&self) -> crate::Result<usize> {
+        let index = self.clone();
+        rocket::tokio::task::spawn_blocking(move || -> crate::Result<usize> {
+            let mut writer = index.writer.lock();
+            index.commit(&mut writer)?;
+            let segments = index.inner.searchable_segment_ids()?;
+            if segments.len() > 1 {
+                let merge = writer.merge(&segments);
+                // Edits can go on while the merge runs in the background
+                drop(writer);
+                rocket::tokio::runtime::Handle::current().block_on(merge)?;
+            }
+            Ok(segments.len())
+        })
+        .await?
+    }
+
+    /// Whether the articles were added, i.e. searching finds anything.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn shutdown_handle(&self) -> IndexShutdownHandle {
+        IndexShutdownHandle(self.writer.clone())
+    }
+
+    /// Replaces the synonyms used by every search from now on.
+    pub fn set_synonyms(&self, groups: Vec<Vec<String>>) {
+        *self.synonyms.write() = groups;
+        // Cached results might have been found without them
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Compares the indexed documents with the given articles; excluded
+    /// articles are expected to be missing.
+    pub fn compare(&self, articles: Vec<ArticleWithRevision>) -> crate::Result<IndexDrift> {
+        use std::collections::HashSet;
+
+        let searcher = self.reader.searcher();
+        let limit = (searcher.num_docs() as usize).max(1);
+        let mut indexed = HashSet::new();
+        for (_, doc_address) in searcher.search(&AllQuery, &TopDocs::with_limit(limit))? {
+            let doc = searcher.doc(doc_address)?;
+            if let Some(id) = doc.get_first(self.id_field).and_then(|id| id.text()) {
+                indexed.insert(id.to_string());
+            }
+        }
+        let mut missing = Vec::new();
+        for article in articles {
+            let id = article.id.to_string();
+            if !indexed.remove(&id) && !self.is_excluded(&article.name) {
+                missing.push(UnindexedArticle {
+                    id: article.id,
+                    name: article.name,
+                });
+            }
+        }
+        missing.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut orphaned = indexed.into_iter().collect::<Vec<_>>();
+        orphaned.sort();
+        Ok(IndexDrift { missing, orphaned })
+    }
+
+    /// Changes whenever the indexed articles change.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn is_excluded(&self, article_name: &str) -> bool {
+        self.excluded
+            .iter()
+            .any(|pattern| matches_pattern(pattern, article_name))
+    }
+
+    fn content<'a>(&self, doc: &'a Document) -> &'a str {
+        doc.field_values()
+            .iter()
+            .find(|field| field.field() == self.content_field)
+            .and_then(|field| field.value().text())
+            .unwrap()
+    }
+
+    fn first_sentence(&self, doc: &Document) -> SnippetOrFirstSentence {
+        let content = self.content(doc);
+        let sentence = content
+            .find(|c: char| c.is_ascii_punctuation() && c != ',')
+            .map(|index| usize::min(index + 1, content.len()))
+            .map(|index| &content[..index])
+            .unwrap_or(content);
+        SnippetOrFirstSentence::FirstSentence(sentence.to_string())
+    }
+
+    fn search_result(&self, doc: &Document, snippet: SnippetOrFirstSentence) -> SearchResult {
+        let mut article = SearchResult {
+            title: String::default(),
+            snippet,
+            last_edited: chrono::MIN_DATETIME,
+        };
+        for field in doc.field_values() {
+            if field.field() == self.name_field {
+                if let Some(value) = field.value().text() {
+                    article.title = value.to_string();
+                }
+            } else if field.field() == self.date_field {
+                if let Some(value) = field.value().date_value() {
+                    article.last_edited = *value;
+                }
+            }
+        }
+        article
+    }
+
+    /// Searches names and contents; if `namespace` is given, only articles
+    /// in that namespace are returned.
+    pub fn search_by_text(
+        &self,
+        text: &str,
+        namespace: Option<&str>,
+        sort: SearchSort,
+        snippets: SnippetStrategy,
+        highlight_tag: &str,
+    ) -> crate::Result<Vec<SearchResult>> {
         let searcher = self.reader.searcher();
-        let query_parser =
-            QueryParser::for_index(&self.inner, vec![self.name_field, self.content_field]);
-        let query = query_parser.parse_query(text)?;
+        let query_parser = QueryParser::for_index(
+            &self.inner,
+            vec![self.name_field, self.content_field, self.tags_field],
+        );
+        let variants = synonym_variants(text, &self.synonyms.read());
+        let mut query = if variants.len() == 1 {
+            query_parser.parse_query(text)?
+        } else {
+            let variants = variants
+                .iter()
+                .map(|variant| Ok((Occur::Should, query_parser.parse_query(variant)?)))
+                .collect::<crate::Result<Vec<_>>>()?;
+            Box::new(BooleanQuery::new(variants))
+        };
+        if let Some(namespace) = namespace {
+            let namespace_term = Term::from_field_text(self.namespace_field, namespace);
+            let in_namespace: Box<dyn Query> =
+                Box::new(TermQuery::new(namespace_term, IndexRecordOption::Basic));
+            query = Box::new(BooleanQuery::new(vec![
+                (Occur::Must, query),
+                (Occur::Must, in_namespace),
+            ]));
+        }
         let snippet_generator = SnippetGenerator::create(&searcher, &*query, self.content_field)?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+        let top_docs = TopDocs::with_limit(MAX_SEARCH_RESULTS);
+        let doc_addresses: Vec<DocAddress> = match sort {
+            SearchSort::Relevance => searcher
+                .search(&query, &top_docs)?
+                .into_iter()
+                .map(|(_, doc_address)| doc_address)
+                .collect(),
+            SearchSort::Date => searcher
+                .search(
+                    &query,
+                    &top_docs.order_by_fast_field::<DateTime<Utc>>(self.date_field),
+                )?
+                .into_iter()
+                .map(|(_, doc_address)| doc_address)
+                .collect(),
+        };
 
-        let mut result = Vec::with_capacity(top_docs.len());
-        for (_, doc_address) in top_docs {
+        let mut result = Vec::with_capacity(doc_addresses.len());
+        for doc_address in doc_addresses {
             let doc = searcher.doc(doc_address)?;
-            let snippet = snippet_generator.snippet_from_doc(&doc);
-            let snippet = if snippet.fragments().is_empty() {
-                doc.field_values()
-                    .iter()
-                    .find(|field| field.field() == self.content_field)
-                    .and_then(|field| field.value().text())
-                    .map(|content| {
-                        content
-                            .find(|c: char| c.is_ascii_punctuation() && c != ',')
-                            .map(|index| usize::min(index + 1, content.len()))
-                            .map(|index| &content[..index])
-                            .unwrap_or(content)
-                            .to_string()
-                    })
-                    .map(SnippetOrFirstSentence::FirstSentence)
-                    .unwrap()
-            } else {
-                SnippetOrFirstSentence::Snippet(snippet)
-            };
-            let mut article = SearchResult {
-                title: String::default(),
-                snippet,
-                last_edited: chrono::MIN_DATETIME,
-            };
-            for field in doc.field_values() {
-                if field.field() == self.name_field {
-                    if let Some(value) = field.value().text() {
-                        article.title = value.to_string();
+            let snippet = match snippets {
+                SnippetStrategy::Best => {
+                    let snippet = snippet_generator.snippet_from_doc(&doc);
+                    if snippet.fragments().is_empty() {
+                        self.first_sentence(&doc)
+                    } else {
+                        SnippetOrFirstSentence::Snippet(snippet_html(&snippet, highlight_tag))
                     }
-                } else if field.field() == self.date_field {
-                    if let Some(value) = field.value().date_value() {
-                        article.last_edited = *value;
+                }
+                SnippetStrategy::Grouped => {
+                    let snippets = chunks(self.content(&doc), SNIPPET_CHUNK_LEN)
+                        .into_iter()
+                        .map(|chunk| snippet_generator.snippet(chunk))
+                        .filter(|snippet| !snippet.fragments().is_empty())
+                        .take(MAX_GROUPED_SNIPPETS)
+                        .map(|snippet| snippet_html(&snippet, highlight_tag))
+                        .collect::<Vec<_>>();
+                    if snippets.is_empty() {
+                        self.first_sentence(&doc)
+                    } else {
+                        SnippetOrFirstSentence::Grouped(snippets)
                     }
                 }
+            };
+            let article = self.search_result(&doc, snippet);
+            // Just in case the article was renamed into an excluded name
+            if !self.is_excluded(&article.title) {
+                result.push(article);
             }
-            result.push(article);
         }
         Ok(result)
     }
 
+    /// Finds up to `limit` articles using the same distinctive words as the
+    /// article with the given id, not including the article itself.
+    pub fn similar(&self, id: Uuid, limit: usize) -> crate::Result<Vec<SearchResult>> {
+        let searcher = self.reader.searcher();
+        let id_term = Term::from_field_text(self.id_field, &id.to_string());
+        let own_doc = TermQuery::new(id_term, IndexRecordOption::Basic);
+        let own_address = match searcher.search(&own_doc, &TopDocs::with_limit(1))?.first() {
+            Some((_, doc_address)) => *doc_address,
+            // Excluded or not indexed yet
+            None => return Ok(Vec::new()),
+        };
+        // The defaults are meant for big indexes; a wiki can be very small
+        let query = MoreLikeThisQuery::builder()
+            .with_min_doc_frequency(1)
+            .with_min_term_frequency(1)
+            .with_document(own_address);
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit + 1))?;
+
+        let mut result = Vec::with_capacity(limit);
+        for (_, doc_address) in top_docs {
+            if doc_address == own_address {
+                continue;
+            }
+            let doc = searcher.doc(doc_address)?;
+            let article = self.search_result(&doc, self.first_sentence(&doc));
+            if !self.is_excluded(&article.title) {
+                result.push(article);
+            }
+        }
+        result.truncate(limit);
+        Ok(result)
+    }
+
     /// Unconditionally tries to remove the article with the given id and
     /// recreates it with the given parameters.
     ///
     /// Passing in a different name than the article had before will also
     /// rename it, making the old rename_article method redundant.
+    /// Articles matching one of the excluded patterns are only removed.
     pub fn add_or_update_article(
         &self,
         id: Uuid,
         article_name: &str,
         content: &str,
+        tags: &[String],
         date: DateTime<Utc>,
-    ) -> Result<()> {
-        let id = id.to_string();
+    ) -> crate::Result<()> {
         let mut writer = self.writer.lock();
-        writer.delete_term(Term::from_field_text(self.id_field, &id));
-        writer.add_document(doc! {
-            self.id_field => id,
-            self.name_field => article_name,
-            self.content_field => markdown_to_text(content),
-            self.date_field => date,
-        });
-        writer.commit()?;
+        writer.delete_term(Term::from_field_text(self.id_field, &id.to_string()));
+        // Excluded articles are removed, but not added back
+        if !self.is_excluded(article_name) {
+            writer.add_document(self.document(id, article_name, content, tags, date));
+        }
+        if self.pending.fetch_add(1, Ordering::AcqRel) + 1 >= self.commit_batch {
+            self.commit(&mut writer)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        chunks, matches_pattern, namespace, synonym_variants, valid_highlight_tag,
+        without_stop_words, MAIN_NAMESPACE,
+    };
+
+    #[test]
+    fn exclusion_patterns() {
+        assert!(matches_pattern("Sandbox", "Sandbox"));
+        assert!(!matches_pattern("Sandbox", "Sandbox2"));
+        assert!(matches_pattern("Sandbox*", "Sandbox2"));
+        assert!(matches_pattern("Sandbox*", "Sandbox"));
+        assert!(!matches_pattern("Sandbox*", "MySandbox"));
+        assert!(matches_pattern("*Template", "InfoboxTemplate"));
+        assert!(matches_pattern("Template:*:Old", "Template:Infobox:Old"));
+        assert!(!matches_pattern("Template:*:Old", "Template:Infobox"));
+        assert!(matches_pattern("*", "Anything"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+
+    #[test]
+    fn namespaces() {
+        assert_eq!(namespace("Help:Editing"), "Help");
+        assert_eq!(namespace("Template:Infobox:Old"), "Template");
+        assert_eq!(namespace("Baguette"), MAIN_NAMESPACE);
+        assert_eq!(namespace(":Baguette"), MAIN_NAMESPACE);
+    }
+
+    #[test]
+    fn snippet_chunks() {
+        assert_eq!(chunks("  ", 4), Vec::<&str>::new());
+        assert_eq!(chunks("one two three", 4), vec!["one two", "three"]);
+        assert_eq!(chunks("one two three", 20), vec!["one two three"]);
+        assert_eq!(chunks("äöü äöü", 1), vec!["äöü", "äöü"]);
+    }
+
+    #[test]
+    fn highlight_tags() {
+        assert!(valid_highlight_tag("b"));
+        assert!(valid_highlight_tag("mark"));
+        assert!(valid_highlight_tag("h1"));
+        assert!(!valid_highlight_tag(""));
+        assert!(!valid_highlight_tag("1b"));
+        assert!(!valid_highlight_tag("b onmouseover=alert(1)"));
+        assert!(!valid_highlight_tag("b><script"));
+    }
+
+    #[test]
+    fn synonyms() {
+        let groups = vec![
+            vec!["k8s".to_string(), "kubernetes".to_string()],
+            vec![
+                "db".to_string(),
+                "database".to_string(),
+                "data store".to_string(),
+            ],
+        ];
+        assert_eq!(synonym_variants("trains", &groups), vec!["trains"]);
+        assert_eq!(
+            synonym_variants("K8s setup", &groups),
+            vec!["K8s setup", "\"kubernetes\" setup"]
+        );
+        assert_eq!(
+            synonym_variants("k8s db", &groups),
+            vec![
+                "k8s db",
+                "k8s \"database\"",
+                "k8s \"data store\"",
+                "\"kubernetes\" db",
+                "\"kubernetes\" \"database\"",
+                "\"kubernetes\" \"data store\"",
+            ]
+        );
+        // Only whole words count
+        assert_eq!(synonym_variants("k8sdb", &groups), vec!["k8sdb"]);
+        // Phrases are searched as written
+        assert_eq!(
+            synonym_variants("\"k8s setup\" db", &groups),
+            vec![
+                "\"k8s setup\" db",
+                "\"k8s setup\" \"database\"",
+                "\"k8s setup\" \"data store\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_words() {
+        let stop_words = vec!["the".to_string(), "of".to_string()];
+        assert_eq!(
+            without_stop_words("The lord of  the rings", &stop_words),
+            "lord rings"
+        );
+        assert_eq!(without_stop_words("the of", &stop_words), "");
+        assert_eq!(without_stop_words("theory", &stop_words), "theory");
+        assert_eq!(without_stop_words("the rings", &[]), "the rings");
+        assert_eq!(
+            without_stop_words("the \"lord of the rings\" of", &stop_words),
+            "\"lord of the rings\""
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! Browser security headers, sent with every response.
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Request, Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Config;
+
+/// The values of the headers; an empty one isn't sent at all.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub content_type_options: String,
+    pub frame_options: String,
+    pub referrer_policy: String,
+    pub permissions_policy: String,
+}
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            content_type_options: "nosniff".into(),
+            frame_options: "DENY".into(),
+            referrer_policy: "strict-origin-when-cross-origin".into(),
+            permissions_policy: "camera=(), microphone=(), geolocation=()".into(),
+        }
+    }
+}
+
+/// Adds the configured security headers to every response. Attachments, API
+/// responses and static files need them just as much as pages do, and
+/// Rocket's own shield is replaced by this.
+pub struct SecurityHeaders;
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let cfg = match request.rocket().state::<Config>() {
+            Some(cfg) => &cfg.security_headers,
+            None => return,
+        };
+        let headers = [
+            ("X-Content-Type-Options", &cfg.content_type_options),
+            ("X-Frame-Options", &cfg.frame_options),
+            ("Referrer-Policy", &cfg.referrer_policy),
+            ("Permissions-Policy", &cfg.permissions_policy),
+        ];
+        for (name, value) in headers.iter() {
+            if !value.is_empty() {
+                response.set_header(Header::new(*name, value.to_string()));
+            }
+        }
+    }
+}
@@ -1,29 +1,105 @@
-use rocket::{form::Form, get, post, response::Redirect, FromForm, State};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    fs::OpenOptions,
+    io::{Seek, SeekFrom},
+    time::Instant,
+};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rocket::{
+    form::Form,
+    get,
+    http::{ContentType, Header, Status},
+    post,
+    response::{status, Redirect},
+    tokio::{fs::File, sync::Mutex, task::spawn_blocking},
+    FromForm, Responder, State,
+};
 use rocket_dyn_templates::Template;
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::{
-    db::users::{LoggedAdmin, LoggedUser},
-    Config, Db, Result,
+    articles, attachments,
+    db::{
+        self,
+        articles::RevId,
+        audit::{AuditAction, AuditFilter},
+        users::{LoggedAdmin, LoggedUser, UserSession},
+    },
+    export,
+    pagination::{self, Page},
+    templating::Announcement,
+    transclusion, ArticleIndex, Config, Db, Error, Result,
 };
 
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![panel_page, panel_redirect, admin_settings, admin_redirect]
+    rocket::routes![
+        panel_page,
+        panel_redirect,
+        create_token,
+        admin_settings,
+        admin_redirect,
+        import_articles,
+        create_alias,
+        delete_alias,
+        recent_users,
+        integrity_report,
+        integrity_repair,
+        index_check,
+        optimize,
+        export_html,
+        moderation,
+        approve_revision,
+        reject_revision,
+        invites,
+        create_invite,
+        sessions,
+        revoke_session,
+        password_page,
+        change_password,
+        set_featured_article,
+        set_announcement,
+        set_welcome,
+        set_synonyms,
+        merge_articles,
+        tag_articles,
+        rename_tag,
+        merge_tags,
+        audit_log
+    ]
 }
 
 #[get("/")]
 async fn panel_page(db: &State<Db>, cfg: &State<Config>, user: LoggedUser) -> Result<Template> {
+    let created_pages = db::articles::created_by(db, user.id()).await?;
     let mut context = json! {{
         "site_name": &cfg.site_name,
         "default_path": &cfg.default_path,
         "user": user,
+        "created_pages": created_pages,
+        "oidc_enabled": cfg.oidc.is_some(),
     }};
     if user.is_admin() {
         let registration_enabled = db.registration_enabled().await?;
-        context.as_object_mut().unwrap().extend(vec![(
-            "registration_enabled".into(),
-            registration_enabled.into(),
-        )]);
+        let aliases = db::aliases::list(db).await?;
+        let tags = db::tags::list(db).await?;
+        let featured_article = db.featured_article().await?;
+        let announcement = db.announcement().await?;
+        let welcome_message = db.welcome_message().await?;
+        let welcome_user_page = db.welcome_user_page().await?;
+        let synonyms = db::synonyms::list(db).await?;
+        context.as_object_mut().unwrap().extend(vec![
+            ("registration_enabled".into(), registration_enabled.into()),
+            ("featured_article".into(), json!(featured_article)),
+            ("announcement".into(), json!(announcement)),
+            ("welcome_message".into(), json!(welcome_message)),
+            ("welcome_user_page".into(), welcome_user_page.into()),
+            ("synonyms".into(), format_synonyms(&synonyms).into()),
+            ("aliases".into(), json!(aliases)),
+            ("tags".into(), json!(tags)),
+            ("html_export".into(), cfg.html_export.into()),
+        ]);
     }
     Ok(Template::render("settings_panel", dbg!(context)))
 }
@@ -33,6 +109,120 @@ fn panel_redirect() -> Redirect {
     Redirect::to("/u/login")
 }
 
+#[post("/tokens")]
+async fn create_token(db: &State<Db>, cfg: &State<Config>, user: LoggedUser) -> Result<Template> {
+    let token = db::users::create_api_token(db, user.id()).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": user,
+        "token": token,
+    }};
+    Ok(Template::render("settings_token", context))
+}
+
+#[get("/sessions")]
+async fn sessions(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    session: &UserSession,
+    user: LoggedUser,
+) -> Result<Template> {
+    let sessions =
+        db::users::list_sessions(db, user.id(), session.session_id, (&**cfg).into()).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": user,
+        "sessions": sessions,
+    }};
+    Ok(Template::render("settings_sessions", context))
+}
+
+/// Logs out one of the user's sessions, e.g. on a lost device.
+#[post("/sessions/<id>/revoke")]
+async fn revoke_session(db: &State<Db>, id: i64, user: LoggedUser) -> Result<Option<Redirect>> {
+    if db::users::revoke_session(db, user.id(), id).await? {
+        Ok(Some(Redirect::to("/settings/sessions")))
+    } else {
+        Ok(None)
+    }
+}
+
+#[get("/password")]
+fn password_page(cfg: &State<Config>, user: LoggedUser) -> Template {
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": user,
+    }};
+    Template::render("settings_password", context)
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct PasswordRequest {
+    pub current_password: String,
+    pub password: String,
+    pub pwd_confirm: String,
+}
+
+/// Changes the user's password; unless configured otherwise, this also
+/// logs them out everywhere but here.
+#[post("/password", data = "<form>")]
+async fn change_password(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    form: Form<PasswordRequest>,
+    session: &UserSession,
+    user: LoggedUser,
+) -> Result<status::Custom<Template>> {
+    let PasswordRequest {
+        current_password,
+        password,
+        pwd_confirm,
+    } = form.into_inner();
+    let pepper = cfg.password_pepper.as_deref();
+    let wrong_password =
+        !db::users::password_matches(db, user.id(), current_password, pepper).await?;
+    let pwds_dont_match = password != pwd_confirm || password.is_empty();
+    let breached_password = !wrong_password
+        && !pwds_dont_match
+        && cfg.check_breached_passwords
+        && crate::pwned::is_breached(&cfg.pwned_passwords_url, &password).await;
+    if wrong_password || pwds_dont_match || breached_password {
+        let context = json! {{
+            "site_name": &cfg.site_name,
+            "default_path": &cfg.default_path,
+            "user": user,
+            "wrong_password": wrong_password,
+            "pwds_dont_match": pwds_dont_match,
+            "breached_password": breached_password,
+        }};
+        return Ok(status::Custom(
+            Status::BadRequest,
+            Template::render("settings_password", context),
+        ));
+    }
+    db::users::set_password(db, user.id(), password, pepper).await?;
+    let sessions_ended = if cfg.logout_on_password_change {
+        db::users::destroy_all_sessions(db, user.id(), Some(session.session_id)).await?
+    } else {
+        0
+    };
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": user,
+        "changed": true,
+        "sessions_ended": sessions_ended,
+    }};
+    Ok(status::Custom(
+        Status::Ok,
+        Template::render("settings_password", context),
+    ))
+}
+
 #[derive(FromForm)]
 #[cfg_attr(test, derive(serde::Serialize))]
 pub struct AdminSettings {
@@ -45,14 +235,17 @@ async fn admin_settings(
     cfg: &State<Config>,
     form: Form<AdminSettings>,
     // Only admins can call this
-    // TODO: Mark down the admin's userid somewhere
     admin: LoggedAdmin,
 ) -> Result<Template> {
     let AdminSettings {
         registration_enabled,
     } = form.into_inner();
     if db.registration_enabled().await? != registration_enabled {
-        db.set_registration_enabled(registration_enabled).await?;
+        let mut txn = db.begin().await?;
+        db::set_registration_enabled(&mut txn, registration_enabled).await?;
+        let target = format!("registration_enabled = {}", registration_enabled);
+        db::audit::record(&mut txn, admin.id(), AuditAction::ChangeSettings, &target).await?;
+        txn.commit().await?;
         let context = json! {{
             "site_name": &cfg.site_name,
             "default_path": &cfg.default_path,
@@ -75,3 +268,868 @@ async fn admin_settings(
 fn admin_redirect() -> Redirect {
     Redirect::to("/settings")
 }
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct ImportRequest {
+    /// A JSON object mapping article names to their content.
+    pub pages: String,
+}
+
+/// Creates many articles at once, e.g. for seeding a new wiki.
+/// Articles which already exist are left alone, as are names given twice
+/// and ones which couldn't be chosen in the editor either.
+#[post("/admin/import", data = "<form>")]
+async fn import_articles(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    form: Form<ImportRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let pages: BTreeMap<String, String> = serde_json::from_str(&form.pages)?;
+    let mut created = Vec::new();
+    let mut skipped = Vec::new();
+    let mut invalid = Vec::new();
+
+    // Different spellings of the same name end up as the same article
+    let mut normalized = BTreeMap::new();
+    for (name, content) in pages {
+        let name = db::articles::normalize_name(&name);
+        match normalized.entry(name) {
+            Entry::Occupied(entry) => skipped.push(entry.key().clone()),
+            Entry::Vacant(entry) => {
+                entry.insert(content);
+            }
+        }
+    }
+
+    let mut txn = db.begin().await?;
+    for (name, content) in normalized {
+        if let Some(reason) = articles::check_title(cfg, &name) {
+            invalid.push(json! {{ "name": name, "reason": reason }});
+            continue;
+        }
+        if db::articles::id_by_name(&mut txn, &name).await?.is_some() {
+            skipped.push(name);
+            continue;
+        }
+        let (db::articles::RevId(article_id, _), rev) =
+            db::articles::create(&mut txn, &name, &content, admin.id()).await?;
+        created.push((article_id, name, content, rev.date));
+    }
+    if !created.is_empty() {
+        let names = created.iter().map(|(_, name, _, _)| name.as_str());
+        let target = names.collect::<Vec<_>>().join(", ");
+        db::audit::record(&mut txn, admin.id(), AuditAction::ImportArticles, &target).await?;
+    }
+    txn.commit().await?;
+
+    for (article_id, name, content, date) in &created {
+        search_index.add_or_update_article(*article_id, name, content, &[], *date)?;
+    }
+
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "created": created.into_iter().map(|(_, name, _, _)| name).collect::<Vec<_>>(),
+        "skipped": skipped,
+        "invalid": invalid,
+        "max_title_length": cfg.max_title_length,
+    }};
+    Ok(Template::render("settings_import", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct MergeArticlesRequest {
+    pub source: String,
+    pub target: String,
+}
+
+/// Merges a duplicate article into another one: the source's content is
+/// appended to the target, and the source becomes a redirect to it.
+/// Both keep their history.
+#[post("/merge", data = "<form>")]
+async fn merge_articles(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    form: Form<MergeArticlesRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let source_name = db::articles::normalize_name(&form.source);
+    let target_name = db::articles::normalize_name(&form.target);
+    if source_name == target_name {
+        return Err(Error::MergeIntoItself);
+    }
+    let mut txn = db.begin().await?;
+    let source_id = match db::articles::id_by_name(&mut txn, &source_name).await? {
+        Some(id) => id,
+        None => return Err(Error::MergeArticleUnknown(source_name)),
+    };
+    let target_id = match db::articles::id_by_name(&mut txn, &target_name).await? {
+        Some(id) => id,
+        None => return Err(Error::MergeArticleUnknown(target_name)),
+    };
+    let source = db::articles::latest_revision(&mut txn, source_id)
+        .await?
+        .ok_or(Error::ArticleDataInconsistent(source_id))?;
+    let target = db::articles::latest_revision(&mut txn, target_id)
+        .await?
+        .ok_or(Error::ArticleDataInconsistent(target_id))?;
+
+    let merged_content = format!("{}\n\n{}", target.content.trim_end(), source.content);
+    let (_, target_rev) = db::articles::add_revision(
+        &mut txn,
+        target_id,
+        admin.id(),
+        &merged_content,
+        false,
+        cfg.max_revisions,
+    )
+    .await?;
+    let redirect = articles::redirect_content(&target_name);
+    let (_, source_rev) = db::articles::add_revision(
+        &mut txn,
+        source_id,
+        admin.id(),
+        &redirect,
+        false,
+        cfg.max_revisions,
+    )
+    .await?;
+    let target = format!("{} -> {}", source_name, target_name);
+    db::audit::record(&mut txn, admin.id(), AuditAction::MergeArticles, &target).await?;
+    txn.commit().await?;
+
+    search_index.add_or_update_article(
+        target_id,
+        &target_name,
+        &merged_content,
+        &db::tags::for_article(db, &target_name).await?,
+        target_rev.date,
+    )?;
+    search_index.add_or_update_article(
+        source_id,
+        &source_name,
+        &redirect,
+        &db::tags::for_article(db, &source_name).await?,
+        source_rev.date,
+    )?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct AliasRequest {
+    pub name: String,
+    pub target: String,
+}
+
+#[post("/admin/aliases", data = "<form>")]
+async fn create_alias(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    form: Form<AliasRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let mut txn = db.begin().await?;
+    db::aliases::create(&mut txn, &form.name, &form.target).await?;
+    let target = format!("{} -> {}", form.name, form.target);
+    db::audit::record(&mut txn, admin.id(), AuditAction::CreateAlias, &target).await?;
+    txn.commit().await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct DeleteAliasRequest {
+    pub name: String,
+}
+
+#[post("/admin/aliases/delete", data = "<form>")]
+async fn delete_alias(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    form: Form<DeleteAliasRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let mut txn = db.begin().await?;
+    db::aliases::delete(&mut txn, &form.name).await?;
+    db::audit::record(&mut txn, admin.id(), AuditAction::DeleteAlias, &form.name).await?;
+    txn.commit().await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct FeaturedArticleRequest {
+    /// Empty to unpin the current article.
+    pub name: String,
+}
+
+#[post("/admin/featured", data = "<form>")]
+async fn set_featured_article(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    form: Form<FeaturedArticleRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let name = Some(form.name.trim()).filter(|name| !name.is_empty());
+    let mut txn = db.begin().await?;
+    db::set_featured_article(&mut txn, name).await?;
+    let target = name.unwrap_or_default();
+    db::audit::record(&mut txn, admin.id(), AuditAction::SetFeatured, target).await?;
+    txn.commit().await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct AnnouncementRequest {
+    pub text: String,
+}
+
+/// Sets the notice shown on every page; empty text removes it.
+#[post("/admin/announcement", data = "<form>")]
+async fn set_announcement(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    announcement: &State<Announcement>,
+    form: Form<AnnouncementRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let text = form.text.trim();
+    let mut txn = db.begin().await?;
+    db::set_announcement(&mut txn, Some(text).filter(|text| !text.is_empty())).await?;
+    db::audit::record(&mut txn, admin.id(), AuditAction::SetAnnouncement, text).await?;
+    txn.commit().await?;
+    announcement.set(text);
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct SynonymsRequest {
+    /// One group per line, the words separated by commas.
+    pub groups: String,
+}
+
+/// Parses the synonyms form; groups need at least two distinct words.
+/// Quotes are dropped, since the words are quoted in the search query.
+fn parse_synonyms(text: &str) -> Vec<Vec<String>> {
+    text.lines()
+        .map(|line| {
+            let mut terms = Vec::<String>::new();
+            for term in line.split(',') {
+                let term = term.replace('"', "");
+                let term = term.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !term.is_empty() && !terms.iter().any(|t| t.eq_ignore_ascii_case(&term)) {
+                    terms.push(term);
+                }
+            }
+            terms
+        })
+        .filter(|terms| terms.len() > 1)
+        .collect()
+}
+
+fn format_synonyms(groups: &[Vec<String>]) -> String {
+    groups
+        .iter()
+        .map(|terms| terms.join(", "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Replaces the search synonyms; they're used right away.
+#[post("/admin/synonyms", data = "<form>")]
+async fn set_synonyms(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    form: Form<SynonymsRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let groups = parse_synonyms(&form.groups);
+    let mut txn = db.begin().await?;
+    db::synonyms::replace(&mut txn, &groups).await?;
+    let target = format_synonyms(&groups);
+    db::audit::record(&mut txn, admin.id(), AuditAction::SetSynonyms, &target).await?;
+    txn.commit().await?;
+    search_index.set_synonyms(groups);
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct WelcomeRequest {
+    pub text: String,
+    /// Also create a `User:<name>` page containing the message.
+    pub user_page: bool,
+}
+
+/// Sets the message shown after registering; empty text removes it.
+#[post("/admin/welcome", data = "<form>")]
+async fn set_welcome(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    form: Form<WelcomeRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let text = form.text.trim();
+    let mut txn = db.begin().await?;
+    db::set_welcome_message(&mut txn, Some(text).filter(|text| !text.is_empty())).await?;
+    db::set_welcome_user_page(&mut txn, form.user_page).await?;
+    db::audit::record(&mut txn, admin.id(), AuditAction::SetWelcome, text).await?;
+    txn.commit().await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct TagArticlesRequest {
+    pub tag: String,
+    /// One article name per line.
+    pub articles: String,
+}
+
+/// Adds a tag to many articles at once.
+#[post("/tags/add", data = "<form>")]
+async fn tag_articles(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    form: Form<TagArticlesRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let names = form
+        .articles
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut txn = db.begin().await?;
+    let unknown = db::tags::add(&mut txn, &form.tag, &names).await?;
+    db::audit::record(&mut txn, admin.id(), AuditAction::TagArticles, &form.tag).await?;
+    txn.commit().await?;
+    search_index.reindex(db, &names).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+        "unknown_articles": unknown,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct RenameTagRequest {
+    pub old: String,
+    pub new: String,
+}
+
+#[post("/tags/rename", data = "<form>")]
+async fn rename_tag(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    form: Form<RenameTagRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let tagged = db::tags::articles(db, &form.old).await?;
+    let mut txn = db.begin().await?;
+    db::tags::rename(&mut txn, &form.old, &form.new).await?;
+    let target = format!("{} -> {}", form.old, form.new);
+    db::audit::record(&mut txn, admin.id(), AuditAction::RenameTag, &target).await?;
+    txn.commit().await?;
+    search_index.reindex(db, &tagged).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct MergeTagsRequest {
+    pub from: String,
+    pub into: String,
+}
+
+#[post("/tags/merge", data = "<form>")]
+async fn merge_tags(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    form: Form<MergeTagsRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let tagged = db::tags::articles(db, &form.from).await?;
+    let mut txn = db.begin().await?;
+    db::tags::merge(&mut txn, &form.from, &form.into).await?;
+    let target = format!("{} -> {}", form.from, form.into);
+    db::audit::record(&mut txn, admin.id(), AuditAction::MergeTags, &target).await?;
+    txn.commit().await?;
+    search_index.reindex(db, &tagged).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "changed": true,
+    }};
+    Ok(Template::render("settings_success", context))
+}
+
+#[get("/users/recent")]
+async fn recent_users(db: &State<Db>, cfg: &State<Config>, admin: LoggedAdmin) -> Result<Template> {
+    let since = Utc::now().naive_utc() - Duration::days(cfg.recent_registrations_days.into());
+    let users = db::users::registered_since(db, since).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "days": cfg.recent_registrations_days,
+        "users": users,
+    }};
+    Ok(Template::render("settings_recent_users", context))
+}
+
+/// Lists data which is inconsistent (see `db::integrity`).
+#[get("/integrity")]
+async fn integrity_report(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let report = db::integrity::check(db).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "clean": report.is_clean(),
+        "report": report,
+        "repaired": false,
+    }};
+    Ok(Template::render("settings_integrity", context))
+}
+
+/// Deletes everything the integrity report lists.
+#[post("/integrity/repair")]
+async fn integrity_repair(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let mut txn = db.begin().await?;
+    let removed_attachments = db::integrity::repair(&mut txn).await?;
+    db::audit::record(&mut txn, admin.id(), AuditAction::RepairIntegrity, "").await?;
+    txn.commit().await?;
+    for id in removed_attachments {
+        attachments::remove_files(cfg, id).await?;
+    }
+    let report = db::integrity::check(db).await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "clean": report.is_clean(),
+        "report": report,
+        "repaired": true,
+    }};
+    Ok(Template::render("settings_integrity", context))
+}
+
+/// Lists how the search index differs from the articles, e.g. before
+/// deciding to rebuild it.
+#[get("/index-check")]
+async fn index_check(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let drift = search_index.compare(db.list_articles().await?)?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "clean": drift.is_clean(),
+        "drift": drift,
+    }};
+    Ok(Template::render("settings_index_check", context))
+}
+
+/// Held while the wiki is optimized, so there's only one run at a time.
+#[derive(Default)]
+pub struct MaintenanceLock(pub(crate) Mutex<()>);
+
+#[derive(FromForm)]
+#[cfg_attr(test, derive(serde::Serialize))]
+pub struct OptimizeRequest {
+    /// Also vacuum the database, which can take a while on big wikis.
+    pub vacuum: bool,
+}
+
+/// Merges the search index's segments and optionally vacuums the database,
+/// listing what was done and how long it took.
+#[post("/maintenance/optimize", data = "<form>")]
+async fn optimize(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    lock: &State<MaintenanceLock>,
+    form: Form<OptimizeRequest>,
+    admin: LoggedAdmin,
+) -> Result<Template> {
+    let _running = lock.0.try_lock().map_err(|_| Error::MaintenanceRunning)?;
+    let started = Instant::now();
+    let segments = search_index.optimize().await?;
+    let mut steps = vec![format!(
+        "Merged {} search index segments in {} ms",
+        segments,
+        started.elapsed().as_millis()
+    )];
+    if form.vacuum {
+        let started = Instant::now();
+        db::integrity::vacuum(db).await?;
+        steps.push(format!(
+            "Vacuumed the database in {} ms",
+            started.elapsed().as_millis()
+        ));
+    }
+    // Neither step can be rolled back (and VACUUM can't run in a
+    // transaction), so this is only logged once they're done
+    let mut conn = db.acquire().await?;
+    db::audit::record(&mut conn, admin.id(), AuditAction::Optimize, "").await?;
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "steps": steps,
+    }};
+    Ok(Template::render("settings_maintenance", context))
+}
+
+#[derive(Responder)]
+struct HtmlExport {
+    zip: (ContentType, File),
+    disposition: Header<'static>,
+}
+
+/// Every current article as a static HTML page, zipped up with an index,
+/// for reading the wiki offline.
+/// The zip is written to a temporary file, which is then streamed to the
+/// client, so only the articles' sources have to fit into memory, not
+/// the rendered pages or the export.
+#[get("/export/html")]
+async fn export_html(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    _admin: LoggedAdmin,
+) -> Result<Option<HtmlExport>> {
+    if !cfg.html_export {
+        return Ok(None);
+    }
+    // Everything that could be transcluded is in here already
+    let pages: HashMap<String, Option<String>> = db
+        .list_articles()
+        .await?
+        .into_iter()
+        .map(|article| (article.name, Some(article.content)))
+        .collect();
+    let path = std::env::temp_dir().join(format!("swiki-export-{}.zip", Uuid::new_v4()));
+    let render_cfg = cfg.inner().clone();
+    let zip = spawn_blocking(move || -> Result<std::fs::File> {
+        let cfg = render_cfg;
+        let names = pages.keys().map(String::as_str).collect();
+        let render = |name: &str| {
+            let content = pages[name].as_deref().unwrap_or_default();
+            let content = transclusion::expand_loaded(name, content, &pages, cfg.article_format);
+            articles::render_content(&content, &cfg)
+        };
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // The open file stays readable and goes away once it's closed
+        std::fs::remove_file(&path)?;
+        let mut file = export::bundle(file, &cfg.site_name, names, render)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    })
+    .await??;
+    let disposition = format!(
+        "attachment; filename=\"{}.zip\"",
+        articles::file_name(&cfg.site_name)
+    );
+    Ok(Some(HtmlExport {
+        zip: (ContentType::ZIP, File::from_std(zip)),
+        disposition: Header::new("Content-Disposition", disposition),
+    }))
+}
+
+/// Lists the edits waiting for approval (see `Config::moderate_edits`).
+#[get("/moderation")]
+async fn moderation(db: &State<Db>, cfg: &State<Config>, admin: LoggedAdmin) -> Result<Template> {
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "pending": db::pending::list(db).await?,
+    }};
+    Ok(Template::render("settings_moderation", context))
+}
+
+/// Publishes the queued edit as a new revision by its author, creating the
+/// article if it doesn't exist yet. Fails if the article was edited after
+/// the edit was queued, which would otherwise be overwritten.
+#[post("/moderation/<id>/approve")]
+async fn approve_revision(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    search_index: &State<ArticleIndex>,
+    id: i64,
+    admin: LoggedAdmin,
+) -> Result<Option<Redirect>> {
+    let mut txn = db.begin().await?;
+    let edit = match db::pending::take(&mut txn, id).await? {
+        Some(edit) => edit,
+        None => return Ok(None),
+    };
+    let article_id = db::articles::id_by_name(&mut txn, &edit.article_name).await?;
+    let latest = match article_id {
+        Some(article_id) => db::articles::latest_revision(&mut txn, article_id).await?,
+        None => None,
+    };
+    // Dropping the transaction puts the edit back in the queue
+    if latest.as_ref().map(|rev| rev.num) != edit.base_rev {
+        return Err(Error::PendingRevisionOutdated(edit.article_name));
+    }
+    let article_name =
+        db::articles::normalize_name(edit.new_name.as_deref().unwrap_or(&edit.article_name));
+    let mut redirect = None;
+    let (article_id, rev_id, date) = match (article_id, latest) {
+        (Some(article_id), latest) => {
+            if article_name != edit.article_name {
+                let res = db::articles::change_name(&mut txn, article_id, &article_name).await;
+                if let Err(Error::SqlxError(sqlx::Error::Database(err))) = &res {
+                    if err.constraint() == Some("article_name_unique") {
+                        return Err(Error::DuplicateArticleName(article_name));
+                    }
+                }
+                res?;
+                if edit.leave_redirect {
+                    let content = articles::redirect_content(&article_name);
+                    let (RevId(redirect_id, _), rev) = db::articles::create(
+                        &mut txn,
+                        &edit.article_name,
+                        &content,
+                        edit.author_id,
+                    )
+                    .await?;
+                    redirect = Some((redirect_id, content, rev.date));
+                }
+            }
+            match latest.filter(|rev| rev.content == edit.content) {
+                // Only renamed or tagged
+                Some(rev) => (article_id, rev.num, DateTime::from_utc(rev.created, Utc)),
+                None => {
+                    let (RevId(article_id, rev_id), rev) = db::articles::add_revision(
+                        &mut txn,
+                        article_id,
+                        edit.author_id,
+                        &edit.content,
+                        false,
+                        cfg.max_revisions,
+                    )
+                    .await?;
+                    (article_id, rev_id, rev.date)
+                }
+            }
+        }
+        (None, _) => {
+            let (RevId(article_id, rev_id), rev) =
+                db::articles::create(&mut txn, &article_name, &edit.content, edit.author_id)
+                    .await?;
+            (article_id, rev_id, rev.date)
+        }
+    };
+    if let Some(tags) = &edit.tags {
+        let tags = db::tags::parse_tags(tags);
+        db::tags::add_to_article(&mut txn, article_id, &tags, cfg.max_tags_per_article).await?;
+    }
+    let target = format!("{}#{}", article_name, rev_id);
+    db::audit::record(&mut txn, admin.id(), AuditAction::ApproveRevision, &target).await?;
+    txn.commit().await?;
+
+    let tags = db::tags::for_article(db, &article_name).await?;
+    search_index.add_or_update_article(article_id, &article_name, &edit.content, &tags, date)?;
+    if let Some((redirect_id, content, date)) = redirect {
+        search_index.add_or_update_article(redirect_id, &edit.article_name, &content, &[], date)?;
+    }
+    Ok(Some(Redirect::to("/settings/moderation")))
+}
+
+/// Discards the queued edit.
+#[post("/moderation/<id>/reject")]
+async fn reject_revision(db: &State<Db>, id: i64, admin: LoggedAdmin) -> Result<Option<Redirect>> {
+    let mut txn = db.begin().await?;
+    let edit = match db::pending::take(&mut txn, id).await? {
+        Some(edit) => edit,
+        None => return Ok(None),
+    };
+    db::audit::record(
+        &mut txn,
+        admin.id(),
+        AuditAction::RejectRevision,
+        &edit.article_name,
+    )
+    .await?;
+    txn.commit().await?;
+    Ok(Some(Redirect::to("/settings/moderation")))
+}
+
+/// Lists the unused invite codes (see `Config::invite_only`).
+#[get("/invites")]
+async fn invites(db: &State<Db>, cfg: &State<Config>, admin: LoggedAdmin) -> Result<Template> {
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "invite_only": cfg.invite_only,
+        "invites": db::invites::list(db).await?,
+    }};
+    Ok(Template::render("settings_invites", context))
+}
+
+#[post("/invites")]
+async fn create_invite(db: &State<Db>, admin: LoggedAdmin) -> Result<Redirect> {
+    let mut txn = db.begin().await?;
+    let code = db::invites::create(&mut txn, admin.id()).await?;
+    // Only the beginning, so the log doesn't give away usable codes
+    let target = &code.to_simple().to_string()[..8];
+    db::audit::record(&mut txn, admin.id(), AuditAction::CreateInvite, target).await?;
+    txn.commit().await?;
+    Ok(Redirect::to("/settings/invites"))
+}
+
+const AUDIT_ENTRIES_PER_PAGE: usize = 50;
+
+#[derive(Debug, FromForm)]
+struct AuditQuery {
+    actor: Option<String>,
+    /// Unknown actions are ignored like a missing one.
+    action: Option<AuditAction>,
+    /// Dates like `2021-06-30`; both ends are inclusive.
+    from: Option<String>,
+    until: Option<String>,
+}
+
+fn parse_date(date: Option<&str>) -> Result<Option<NaiveDate>> {
+    match date.filter(|date| !date.is_empty()) {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| Error::InvalidDate(date.into())),
+        None => Ok(None),
+    }
+}
+
+/// Lists what admins did, newest first.
+#[get("/audit?<page>&<query..>")]
+async fn audit_log(
+    db: &State<Db>,
+    cfg: &State<Config>,
+    admin: LoggedAdmin,
+    page: Option<usize>,
+    query: AuditQuery,
+) -> Result<Template> {
+    let from = parse_date(query.from.as_deref())?;
+    let until = parse_date(query.until.as_deref())?;
+    let filter = AuditFilter {
+        actor: query.actor.as_deref().filter(|actor| !actor.is_empty()),
+        action: query.action,
+        from: from.map(|date| date.and_hms(0, 0, 0)),
+        until: until.map(|date| date.succ().and_hms(0, 0, 0)),
+    };
+    let page = page.unwrap_or(1).max(1);
+    let total_count = db::audit::count(db, &filter).await? as usize;
+    let items = db::audit::list(
+        db,
+        &filter,
+        AUDIT_ENTRIES_PER_PAGE as i64,
+        ((page - 1) * AUDIT_ENTRIES_PER_PAGE) as i64,
+    )
+    .await?;
+    let entries = Page {
+        items,
+        page,
+        total_pages: pagination::total_pages(total_count, AUDIT_ENTRIES_PER_PAGE),
+        total_count,
+    };
+    let context = json! {{
+        "site_name": &cfg.site_name,
+        "default_path": &cfg.default_path,
+        "user": admin,
+        "entries": entries,
+        "actions": AuditAction::ALL,
+        "filter": {
+            "actor": query.actor.unwrap_or_default(),
+            "action": query.action.map(AuditAction::as_str).unwrap_or_default(),
+            "from": from.map(|date| date.to_string()).unwrap_or_default(),
+            "until": until.map(|date| date.to_string()).unwrap_or_default(),
+        },
+    }};
+    Ok(Template::render("settings_audit", context))
+}
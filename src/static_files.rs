@@ -0,0 +1,84 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use rocket::{
+    fs::FileServer,
+    http::{Method, Status},
+    route::{Handler, Outcome, Route},
+    Data, Request, Response,
+};
+
+/// Serves the static assets like `FileServer`, but adds `Cache-Control` and
+/// `ETag` headers so browsers don't refetch them constantly.
+#[derive(Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    server: FileServer,
+    max_age: u32,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl AsRef<Path>, max_age: u32) -> Self {
+        let root = root.as_ref().to_path_buf();
+        Self {
+            server: FileServer::from(&root),
+            root,
+            max_age,
+        }
+    }
+}
+
+/// Derives an ETag from the file's size and modification date, which is
+/// a lot cheaper than hashing its content.
+async fn etag(path: &Path) -> Option<String> {
+    let metadata = rocket::tokio::fs::metadata(path).await.ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("\"{:x}-{:x}\"", metadata.len(), modified.as_secs()))
+}
+
+#[rocket::async_trait]
+impl Handler for StaticFiles {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let cache_control = format!("public, max-age={}", self.max_age);
+        let etag = match request.segments::<PathBuf>(0..) {
+            Ok(path) => etag(&self.root.join(path)).await,
+            Err(_) => None,
+        };
+        if let Some(etag) = &etag {
+            if request
+                .headers()
+                .get("If-None-Match")
+                .any(|tag| tag == etag)
+            {
+                return Outcome::Success(
+                    Response::build()
+                        .status(Status::NotModified)
+                        .raw_header("Cache-Control", cache_control)
+                        .raw_header("ETag", etag.clone())
+                        .finalize(),
+                );
+            }
+        }
+        match self.server.handle(request, data).await {
+            Outcome::Success(mut response) => {
+                response.set_raw_header("Cache-Control", cache_control);
+                if let Some(etag) = etag {
+                    response.set_raw_header("ETag", etag);
+                }
+                Outcome::Success(response)
+            }
+            other => other,
+        }
+    }
+}
+
+impl From<StaticFiles> for Vec<Route> {
+    fn from(files: StaticFiles) -> Self {
+        // Same rank as FileServer uses, so routes can still override files
+        let mut route = Route::ranked(10, Method::Get, "/<path..>", files);
+        route.name = Some("StaticFiles".into());
+        vec![route]
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDateTime,
+};
+use parking_lot::RwLock;
+use rocket_dyn_templates::tera::{self, try_get_value, Tera, Value};
+
+use crate::db::users::{display_name, GHOST_USER_NAME};
+
+/// Config values which templates use directly, instead of every route
+/// passing them in.
+#[derive(Clone)]
+pub struct Globals {
+    /// Returned by `site_footer()`, for use in the base template.
+    pub footer: String,
+    /// Used by the `author` filter.
+    pub ghost_author: String,
+    /// Used by the `fmt_date` filter.
+    pub date_format: String,
+    /// Returned by `announcement()`.
+    pub announcement: Announcement,
+}
+
+/// The site-wide notice set by an admin, shown on every page.
+/// This is shared between the templates and the route changing it; the
+/// database has the persistent copy.
+#[derive(Clone, Default)]
+pub struct Announcement(Arc<RwLock<Option<Value>>>);
+impl Announcement {
+    /// Replaces the announcement; empty text removes it.
+    pub fn set(&self, text: &str) {
+        let value = if text.is_empty() {
+            None
+        } else {
+            // Lets browsers remember which announcement was dismissed
+            let mut hasher = DefaultHasher::new();
+            text.hash(&mut hasher);
+            Some(serde_json::json! {{
+                "text": text,
+                "id": format!("{:x}", hasher.finish()),
+            }})
+        };
+        *self.0.write() = value;
+    }
+}
+
+/// Registers all of our custom filters and functions with Tera.
+pub fn register(tera: &mut Tera, globals: Globals) {
+    tera.register_filter("truncate_chars", truncate_chars);
+    tera.register_filter("titlecase", titlecase);
+    let Globals {
+        footer,
+        ghost_author,
+        date_format,
+        announcement,
+    } = globals;
+    tera.register_function("site_footer", move |_: &HashMap<String, Value>| {
+        Ok(Value::String(footer.clone()))
+    });
+    tera.register_function("announcement", move |_: &HashMap<String, Value>| {
+        Ok(announcement.0.read().clone().unwrap_or(Value::Null))
+    });
+    tera.register_filter(
+        "author",
+        move |value: &Value, _: &HashMap<String, Value>| {
+            let name = try_get_value!("author", "value", String, value);
+            Ok(Value::from(display_name(&name, &ghost_author)))
+        },
+    );
+    // The ghost has no profile to link to
+    tera.register_tester("ghost", |value: Option<&Value>, _: &[Value]| {
+        Ok(value.and_then(Value::as_str) == Some(GHOST_USER_NAME))
+    });
+    tera.register_filter(
+        "fmt_date",
+        move |value: &Value, _: &HashMap<String, Value>| Ok(format_date(value, &date_format)),
+    );
+}
+
+/// Whether chrono understands the format string; formatting with one it
+/// doesn't would fail.
+pub fn valid_date_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+}
+
+/// Formats a date as serialized by chrono, with or without a timezone.
+/// Anything else is left as it is.
+///
+/// Usage: `{{ rev.date | fmt_date }}`
+fn format_date(value: &Value, format: &str) -> Value {
+    let date = match value.as_str() {
+        Some(date) => date,
+        None => return value.clone(),
+    };
+    let naive = match DateTime::parse_from_rfc3339(date) {
+        Ok(date) => date.naive_utc(),
+        Err(_) => match date.parse::<NaiveDateTime>() {
+            Ok(date) => date,
+            Err(_) => return value.clone(),
+        },
+    };
+    Value::from(naive.format(format).to_string())
+}
+
+/// Truncates a string to the given amount of characters (not bytes), adding
+/// `end` (defaulting to an ellipsis) if anything was cut off.
+///
+/// Usage: `{{ title | truncate_chars(length=20) }}`
+pub fn truncate_chars(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("truncate_chars", "value", String, value);
+    let length = match args.get("length") {
+        Some(length) => try_get_value!("truncate_chars", "length", usize, length),
+        None => {
+            return Err(tera::Error::msg(
+                "Filter `truncate_chars` expected an arg called `length`",
+            ))
+        }
+    };
+    let end = match args.get("end") {
+        Some(end) => try_get_value!("truncate_chars", "end", String, end),
+        None => "…".to_string(),
+    };
+    if s.chars().count() <= length {
+        return Ok(Value::String(s));
+    }
+    let mut truncated: String = s.chars().take(length).collect();
+    truncated.push_str(&end);
+    Ok(Value::String(truncated))
+}
+
+/// Uppercases the first character of every word, leaving the rest as-is so
+/// already-cased names like "McDonald" stay intact.
+///
+/// Usage: `{{ page_name | titlecase }}`
+pub fn titlecase(value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("titlecase", "value", String, value);
+    let mut result = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if word_start {
+            result.extend(c.to_uppercase());
+        } else {
+            result.push(c);
+        }
+        word_start = c.is_whitespace();
+    }
+    Ok(Value::String(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rocket_dyn_templates::tera::Value;
+
+    use super::{format_date, titlecase, truncate_chars, valid_date_format};
+
+    fn truncate(input: &str, length: usize) -> String {
+        let mut args = HashMap::new();
+        args.insert("length".to_string(), Value::from(length));
+        truncate_chars(&Value::from(input), &args)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+    fn title(input: &str) -> String {
+        titlecase(&Value::from(input), &HashMap::new())
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn truncate_counts_chars() {
+        // Short enough strings stay the same
+        assert_eq!(truncate("abc", 3), "abc");
+        assert_eq!(truncate("", 3), "");
+        // Longer ones get cut off with an ellipsis
+        assert_eq!(truncate("abcdef", 3), "abc…");
+        // Multibyte characters count as one and are never split
+        assert_eq!(truncate("äöüß", 4), "äöüß");
+        assert_eq!(truncate("äöüß", 2), "äö…");
+        assert_eq!(truncate("日本語のテキスト", 3), "日本語…");
+    }
+
+    #[test]
+    fn truncate_args() {
+        // The length is required
+        assert!(truncate_chars(&Value::from("abc"), &HashMap::new()).is_err());
+        // The ending can be customized
+        let mut args = HashMap::new();
+        args.insert("length".to_string(), Value::from(2));
+        args.insert("end".to_string(), Value::from("..."));
+        assert_eq!(
+            truncate_chars(&Value::from("abcdef"), &args).unwrap(),
+            Value::from("ab...")
+        );
+    }
+
+    #[test]
+    fn titlecase_words() {
+        assert_eq!(title("hello world"), "Hello World");
+        assert_eq!(title(""), "");
+        // Already-cased input is left alone
+        assert_eq!(title("Hello World"), "Hello World");
+        assert_eq!(title("McDonald and HTML"), "McDonald And HTML");
+        // Whitespace is preserved
+        assert_eq!(title("  two  spaces\tand tab"), "  Two  Spaces\tAnd Tab");
+        // Non-ASCII letters work too
+        assert_eq!(title("école ärger"), "École Ärger");
+    }
+
+    #[test]
+    fn date_formats() {
+        let format = |date: &str| format_date(&Value::from(date), "%d.%m.%Y %H:%M");
+        assert_eq!(format("2021-06-30T12:34:56.789Z"), "30.06.2021 12:34");
+        assert_eq!(format("2021-06-30T12:34:56+02:00"), "30.06.2021 10:34");
+        assert_eq!(format("2021-06-30T12:34:56.123456"), "30.06.2021 12:34");
+        // Whatever isn't a date stays the same
+        assert_eq!(format("yesterday"), "yesterday");
+        assert_eq!(format_date(&Value::Null, "%Y"), Value::Null);
+
+        assert!(valid_date_format("%Y-%m-%d %H:%M"));
+        assert!(!valid_date_format("%Y-%"));
+        assert!(!valid_date_format("%Q"));
+    }
+}
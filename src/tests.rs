@@ -1,15 +1,19 @@
 use rocket::{
+    error::ErrorKind,
+    figment::Figment,
     http::{ContentType, Status},
     local::blocking::{Client, LocalResponse},
+    Build, Rocket,
 };
 use scraper::Selector;
 use serial_test::serial;
 use uuid::Uuid;
 
-use super::rocket;
+use super::{rocket, rocket_with_broken_search};
 use crate::{
-    articles::AddRevRequest,
-    settings::AdminSettings,
+    articles::{AddRevRequest, DraftRequest},
+    db::articles::RevisionOrder,
+    settings::{AdminSettings, AliasRequest, DeleteAliasRequest, ImportRequest},
     users::{LoginRequest, RegisterRequest},
     ArticleIndex, Cache, Db,
 };
@@ -19,6 +23,25 @@ const PASSWORD: &str = "abc123";
 fn client() -> Client {
     Client::tracked(rocket()).expect("failed to create rocket client")
 }
+/// The default configuration, to be changed with `.merge((key, value))`.
+fn config() -> Figment {
+    rocket::Config::figment()
+}
+/// Creates a client with a customized configuration.
+fn client_with(figment: Figment) -> Client {
+    Client::tracked(rocket().configure(figment)).expect("failed to create rocket client")
+}
+/// Asserts that the configuration keeps the wiki from starting.
+fn assert_ignite_fails(figment: Figment) {
+    assert_fails_to_start(rocket().configure(figment))
+}
+fn assert_fails_to_start(rocket: Rocket<Build>) {
+    match Client::tracked(rocket) {
+        Ok(_) => panic!("the wiki started anyway"),
+        // Errors which nobody looked at panic when they're dropped
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::FailedFairings(_))),
+    }
+}
 fn block_on<F, R>(fut: F) -> R
 where
     F: std::future::Future<Output = R>,
@@ -33,17 +56,44 @@ fn content_type_form() -> ContentType {
 
 fn post_form<'a>(
     client: &'a Client,
-    uri: &'static str,
+    uri: impl AsRef<str>,
     data: impl serde::Serialize,
 ) -> LocalResponse<'a> {
     let request_body = serde_urlencoded::to_string(data).unwrap();
     client
-        .post(uri)
+        .post(uri.as_ref().to_string())
         .header(content_type_form())
         .body(request_body)
         .dispatch()
 }
 
+/// Posts a multipart form containing a single file field called `file`.
+fn post_file<'a>(
+    client: &'a Client,
+    uri: impl AsRef<str>,
+    content_type: &str,
+    data: &[u8],
+) -> LocalResponse<'a> {
+    let boundary = "swiki-test-boundary";
+    let mut body = format!(
+        "--{}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\n\
+        Content-Type: {}\r\n\r\n",
+        boundary, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    client
+        .post(uri.as_ref().to_string())
+        .header(rocket::http::Header::new(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        ))
+        .body(body)
+        .dispatch()
+}
+
 /// Helper method that returns a captcha id and its solution from a new challenge.
 /// Will panic if getting any of these fails.
 fn register_challenge(client: &Client) -> (Uuid, String) {
@@ -80,6 +130,7 @@ fn register_account(client: &Client, username: &str, password: &str) {
             pwd_confirm: password.into(),
             captcha_id,
             captcha_solution,
+            ..Default::default()
         },
     );
     // If it succeeds, we're registered
@@ -99,6 +150,7 @@ fn login(client: &Client, username: &str, password: &str) {
         LoginRequest {
             username: username.into(),
             password: password.into(),
+            ..Default::default()
         },
     );
     // If this request succeeds, we're logged in
@@ -118,6 +170,49 @@ fn register_and_login(client: &Client, username: &str) {
     // Then we log in, which should give us the appropriate cookies
     login(client, username, PASSWORD);
 }
+/// Returns the titles of all search results for the given query.
+/// This reloads the search index first, so all edits are searchable.
+fn search_titles(client: &Client, query: &str) -> Vec<String> {
+    search_titles_with(client, query, &[])
+}
+/// Like `search_titles`, with more parameters for the search page.
+fn search_titles_with(client: &Client, query: &str, params: &[(&str, &str)]) -> Vec<String> {
+    let mut uri = format!("/search?q={}", query);
+    for (name, value) in params {
+        uri.push_str(&format!("&{}={}", name, value));
+    }
+    client
+        .rocket()
+        .state::<ArticleIndex>()
+        .unwrap()
+        .reader
+        .reload()
+        .unwrap();
+    let body = client.get(uri).dispatch().into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let selector = Selector::parse(".box h2 a").unwrap();
+    document
+        .select(&selector)
+        .map(|elem| elem.inner_html().trim().to_string())
+        .collect()
+}
+/// Returns the name of the admin account, registering it if there's none yet.
+fn admin_account(client: &Client) -> String {
+    async fn load_admin(db: &Db) -> Option<String> {
+        sqlx::query_scalar!(r#"SELECT name FROM "user" WHERE is_admin = TRUE"#)
+            .fetch_optional(&db.pool)
+            .await
+            .unwrap()
+    }
+    let db = client.rocket().state::<Db>().unwrap();
+    match block_on(load_admin(db)) {
+        Some(name) => name,
+        None => {
+            register_account(client, "Admin", PASSWORD);
+            "Admin".into()
+        }
+    }
+}
 fn logout(client: &Client) {
     let response = client.get("/u/logout").dispatch();
     assert_eq!(response.status(), Status::Ok);
@@ -149,7 +244,7 @@ fn redirects() {
     // Always redirect / to main
     assert_redirect("/", "/Main");
     // When not logged in, don't allow any edits
-    assert_redirect("/Main/edit", "/u/login");
+    assert_redirect("/Main/edit", "/u/login?next=%2FMain%2Fedit");
     // And you don't allow access to settings
     assert_redirect("/settings", "/u/login");
     // Also trying to "log out" while not logged in should redirect
@@ -233,6 +328,7 @@ fn creating_and_editing_articles() {
         AddRevRequest {
             title: None,
             content: "Some content blah blah blah".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -247,6 +343,7 @@ fn creating_and_editing_articles() {
         AddRevRequest {
             title: Some("ANewArticle".into()),
             content: "Some content blah blah blah".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -278,6 +375,7 @@ fn creating_and_editing_articles() {
         AddRevRequest {
             title: Some("ANewArticle".into()),
             content: "Some *new*, **shiney** content! blah blah blah!".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -296,6 +394,7 @@ fn creating_and_editing_articles() {
         AddRevRequest {
             title: Some("New_Article".into()),
             content: "The same old content again blah blah blah".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -327,6 +426,7 @@ fn creating_and_editing_articles() {
         AddRevRequest {
             title: Some("New_Article".into()),
             content: "The same old content again blah blah blah".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -361,6 +461,7 @@ fn search() {
         AddRevRequest {
             title: None,
             content: "Some content blah blah blah Baguette".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -370,6 +471,7 @@ fn search() {
         AddRevRequest {
             title: None,
             content: "Baguette some content blah blah blah blub".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -379,6 +481,7 @@ fn search() {
         AddRevRequest {
             title: None,
             content: "Some content blah blah blah".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -399,6 +502,7 @@ fn search() {
         AddRevRequest {
             title: None,
             content: "Some lame content blah blah blub".into(),
+            ..Default::default()
         },
     );
     assert_eq!(response.status(), Status::Ok);
@@ -456,6 +560,7 @@ fn failed_register() {
         pwd_confirm: "password123".into(),
         captcha_id,
         captcha_solution,
+        ..Default::default()
     };
     let html = get_html(&request);
     assert_help_text(&html, "You need a username!");
@@ -468,6 +573,7 @@ fn failed_register() {
         pwd_confirm: "".into(),
         captcha_id,
         captcha_solution,
+        ..Default::default()
     };
     let html = get_html(&request);
     assert_help_text(&html, "The given passwords were empty or did not match!");
@@ -492,6 +598,7 @@ fn failed_register() {
         pwd_confirm: "password123".into(),
         captcha_id,
         captcha_solution,
+        ..Default::default()
     };
     let html = get_html(&request);
     assert_help_text(&html, "This username is invalid or already taken!");
@@ -511,6 +618,7 @@ fn failed_register() {
         pwd_confirm: "password123".into(),
         captcha_id,
         captcha_solution,
+        ..Default::default()
     };
     let html = get_html(&request);
     assert_help_text(&html, "This username is invalid or already taken!");
@@ -524,6 +632,7 @@ fn failed_register() {
         captcha_id,
         // This is a definitly invalid captcha
         captcha_solution: "aAaAaA".into(),
+        ..Default::default()
     };
     let html = get_html(&request);
     assert_help_text(&html, "Error, please try again!");
@@ -535,6 +644,7 @@ fn failed_register() {
         //          v ok Rocket, wtf
         captcha_id: uuid::Uuid::new_v4().to_string().parse().unwrap(),
         captcha_solution: "WXZTMWEMOUTRIXWFaaaaAAaaAAAAhaudhwkjsd".into(),
+        ..Default::default()
     };
     let html = get_html(&request);
     assert_help_text(&html, "Error, please try again!");
@@ -623,3 +733,5726 @@ fn admin_permissions_and_settings() {
     );
     assert_eq!(response.status(), Status::Ok);
 }
+
+#[test]
+#[serial]
+fn drafts() {
+    let client = client();
+    register_and_login(&client, "drafts");
+    // Helper to get the editor's content
+    let editor_content = || {
+        let body = client
+            .get("/DraftArticle/edit")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let selector = Selector::parse("textarea#content").unwrap();
+        document.select(&selector).next().unwrap().inner_html()
+    };
+    let response = post_form(
+        &client,
+        "/DraftArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Saved content".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(editor_content(), "Saved content");
+    // Store a draft, which is newer than the current revision
+    let response = post_form(
+        &client,
+        "/DraftArticle/draft",
+        DraftRequest {
+            content: "Unsaved draft content".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::NoContent);
+    // Reopening the editor should restore it
+    assert_eq!(editor_content(), "Unsaved draft content");
+    // Saving the article clears the draft
+    let response = post_form(
+        &client,
+        "/DraftArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Saved draft content".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(editor_content(), "Saved draft content");
+    // Discarding a draft falls back to the current revision
+    let response = post_form(
+        &client,
+        "/DraftArticle/draft",
+        DraftRequest {
+            content: "Another draft".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(editor_content(), "Another draft");
+    let response = client.post("/DraftArticle/draft/discard").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(editor_content(), "Saved draft content");
+}
+
+#[test]
+#[serial]
+fn json_diff() {
+    let client = client();
+    register_and_login(&client, "json diff");
+    let response = post_form(
+        &client,
+        "/DiffArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "First line\nSecond line\nThird line".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = post_form(
+        &client,
+        "/DiffArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "First line\nChanged line\nThird line".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/api/articles/DiffArticle/diff?from=1&to=2")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let diff: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(diff["from"], 1);
+    assert_eq!(diff["to"], 2);
+    // A single changed line is a single hunk...
+    let hunks = diff["hunks"].as_array().unwrap();
+    assert_eq!(hunks.len(), 1);
+    // ...with one line removed and one added, surrounded by the unchanged ones
+    let lines = hunks[0]["lines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|line| {
+            (
+                line["operation"].as_str().unwrap(),
+                line["content"].as_str().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            ("equal", "First line"),
+            ("delete", "Second line"),
+            ("insert", "Changed line"),
+            ("equal", "Third line"),
+        ]
+    );
+
+    // Unknown revisions or articles are a 404
+    let response = client
+        .get("/api/articles/DiffArticle/diff?from=1&to=3")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client
+        .get("/api/articles/UnknownDiffArticle/diff?from=1&to=2")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn normalized_article_names() {
+    let client = client();
+    register_and_login(&client, "normalized names");
+    let db = client.rocket().state::<Db>().unwrap();
+    // "Café" with a composed é (U+00E9)
+    let composed = "Caf\u{e9}";
+    // "Café" with an e followed by a combining acute accent (U+0301)
+    let decomposed = "Cafe\u{301}";
+    assert_ne!(composed, decomposed);
+
+    let response = post_form(
+        &client,
+        "/Caf%C3%A9/edit",
+        AddRevRequest {
+            title: None,
+            content: "Coffee and cake".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    // Editing the decomposed variant edits the same article
+    let response = post_form(
+        &client,
+        "/Cafe%CC%81/edit",
+        AddRevRequest {
+            title: None,
+            content: "Coffee and more cake".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    let id = block_on(db.article_id_by_name(composed))
+        .unwrap()
+        .expect("Inserted article's id not found");
+    assert_eq!(
+        block_on(db.article_id_by_name(decomposed)).unwrap(),
+        Some(id)
+    );
+    // Both forms show the latest content
+    for name in &[composed, decomposed] {
+        assert_eq!(
+            block_on(db.get_current_rev(name))
+                .unwrap()
+                .map(|r| (r.rev_id, r.content))
+                .unwrap(),
+            (2, "Coffee and more cake".to_string())
+        );
+    }
+}
+
+#[test]
+#[serial]
+fn oidc_token_and_provisioning() {
+    use crate::oidc::{OidcConfig, OidcProvider};
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let client = client();
+    let db = client.rocket().state::<Db>().unwrap();
+    let secret = b"oidc test secret";
+    let provider = OidcProvider::with_secret(
+        OidcConfig {
+            issuer: "https://sso.example.com".into(),
+            client_id: "swiki".into(),
+            client_secret: "client secret".into(),
+            redirect_url: "http://localhost/u/oidc/callback".into(),
+        },
+        secret,
+    );
+    let token = |iss: &str, aud: &str, nonce: &str| {
+        let claims = serde_json::json! {{
+            "iss": iss,
+            "aud": aud,
+            "sub": "oidc-subject-1",
+            "nonce": nonce,
+            "preferred_username": "oidc user",
+            "exp": chrono::Utc::now().timestamp() + 300,
+        }};
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    };
+
+    // A valid token with the right nonce is accepted
+    let claims = provider
+        .verify_id_token(&token("https://sso.example.com", "swiki", "n1"), "n1")
+        .expect("valid token was rejected");
+    assert_eq!(claims.sub, "oidc-subject-1");
+    assert_eq!(claims.preferred_username.as_deref(), Some("oidc user"));
+    // The nonce has to match the login attempt
+    assert!(matches!(
+        provider.verify_id_token(&token("https://sso.example.com", "swiki", "n1"), "n2"),
+        Err(crate::Error::OidcNonceMismatch)
+    ));
+    // Tokens for someone else are rejected
+    assert!(provider
+        .verify_id_token(&token("https://evil.example.com", "swiki", "n1"), "n1")
+        .is_err());
+    assert!(provider
+        .verify_id_token(&token("https://sso.example.com", "other", "n1"), "n1")
+        .is_err());
+
+    // The first login creates a user...
+    let user_id = block_on(crate::db::users::provision_oidc(
+        db,
+        &claims.sub,
+        "oidc user",
+    ))
+    .unwrap();
+    assert_eq!(
+        block_on(db.user_id_by_name("oidc user")).unwrap(),
+        Some(user_id)
+    );
+    // ...and later ones find it again
+    assert_eq!(
+        block_on(crate::db::users::provision_oidc(
+            db,
+            &claims.sub,
+            "oidc user"
+        ))
+        .unwrap(),
+        user_id
+    );
+    // A different subject with the same name gets its own account
+    let other_id = block_on(crate::db::users::provision_oidc(
+        db,
+        "oidc-subject-2",
+        "oidc user",
+    ))
+    .unwrap();
+    assert_ne!(user_id, other_id);
+    // Provisioned users don't have a password
+    assert!(matches!(
+        block_on(db.try_login("oidc user", "".into(), None, 0)),
+        Err(crate::Error::WrongPassword)
+    ));
+}
+
+#[test]
+#[serial]
+fn oidc_login_attempts() {
+    use crate::oidc::{LoginAttempt, OidcConfig, OidcProvider};
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let secret = b"oidc test secret";
+    let wiki = || {
+        let provider = OidcProvider::with_secret(
+            OidcConfig {
+                issuer: mockito::server_url(),
+                client_id: "swiki".into(),
+                client_secret: "client secret".into(),
+                redirect_url: "http://localhost/u/oidc/callback".into(),
+            },
+            secret,
+        );
+        let rocket = rocket()
+            .manage(provider)
+            .mount("/u/oidc", crate::oidc::routes());
+        Client::tracked(rocket).expect("failed to create rocket client")
+    };
+    // The provider hands out a token for the given subject and nonce
+    let token_for = |sub: &str, attempt: &LoginAttempt| {
+        let claims = serde_json::json! {{
+            "iss": mockito::server_url(),
+            "aud": "swiki",
+            "sub": sub,
+            "nonce": attempt.nonce.to_string(),
+            "preferred_username": "sso user",
+            "exp": chrono::Utc::now().timestamp() + 300,
+        }};
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap();
+        mockito::mock("POST", "/token")
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::json!({ "id_token": token }).to_string())
+            .create()
+    };
+    let start = |client: &Client, response: LocalResponse<'_>| {
+        assert_eq!(response.status(), Status::SeeOther);
+        let attempt = client
+            .cookies()
+            .get("oidc_attempt")
+            .and_then(|cookie| LoginAttempt::decode(cookie.value()))
+            .expect("the login attempt wasn't remembered");
+        assert!(response
+            .headers()
+            .get_one("Location")
+            .unwrap()
+            .contains(&format!("state={}", attempt.state)));
+        attempt
+    };
+    let callback = |client: &Client, state: Uuid| {
+        client
+            .get(format!("/u/oidc/callback?code=abc&state={}", state))
+            .dispatch()
+            .status()
+    };
+
+    let attacker = wiki();
+    let db = attacker.rocket().state::<Db>().unwrap();
+    // Only the state of this browser's attempt is accepted
+    let attempt = start(&attacker, attacker.get("/u/oidc/login").dispatch());
+    assert_eq!(callback(&attacker, Uuid::new_v4()), Status::NotFound);
+    // ...and only once
+    assert_eq!(callback(&attacker, attempt.state), Status::NotFound);
+    let attempt = start(&attacker, attacker.get("/u/oidc/login").dispatch());
+    let mock = token_for("sso-attacker", &attempt);
+    assert_eq!(callback(&attacker, attempt.state), Status::SeeOther);
+    drop(mock);
+    assert!(attacker.cookies().get("session_id").is_some());
+    assert!(block_on(db.user_name_exists("sso user")).unwrap());
+
+    // Someone else opening the attacker's callback url gets nothing linked
+    let victim = wiki();
+    register_and_login(&victim, "sso victim");
+    let victim_id = block_on(db.user_id_by_name("sso victim")).unwrap().unwrap();
+    let attempt = start(&attacker, attacker.get("/u/oidc/login").dispatch());
+    let mock = token_for("sso-attacker-2", &attempt);
+    assert_eq!(callback(&victim, attempt.state), Status::NotFound);
+    drop(mock);
+    assert_eq!(
+        block_on(crate::db::users::oidc_user(db, "sso-attacker-2")).unwrap(),
+        None
+    );
+
+    // Linking only works when asked for from the settings
+    let attempt = start(&victim, victim.post("/u/oidc/link").dispatch());
+    assert_eq!(attempt.link_user, Some(victim_id));
+    let mock = token_for("sso-victim", &attempt);
+    assert_eq!(callback(&victim, attempt.state), Status::SeeOther);
+    drop(mock);
+    assert_eq!(
+        block_on(crate::db::users::oidc_user(db, "sso-victim")).unwrap(),
+        Some(victim_id)
+    );
+}
+
+#[test]
+#[serial]
+fn breached_passwords() {
+    use sha1::{Digest, Sha1};
+
+    let breached = "correct horse battery staple";
+    let hash = format!("{:X}", Sha1::digest(breached.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+    // Only the hash prefix is part of the request
+    let _mock = mockito::mock("GET", format!("/range/{}", prefix).as_str())
+        .with_body(format!(
+            "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n{}:1337",
+            suffix
+        ))
+        .create();
+    let client = client_with(config().merge(("check_breached_passwords", true)).merge((
+        "pwned_passwords_url",
+        format!("{}/range/", mockito::server_url()),
+    )));
+
+    let (captcha_id, captcha_solution) = register_challenge(&client);
+    let response = post_form(
+        &client,
+        "/u/register",
+        RegisterRequest {
+            username: "breached".into(),
+            password: breached.into(),
+            pwd_confirm: breached.into(),
+            captcha_id,
+            captcha_solution,
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("This password is known from a data breach!"));
+    // Other passwords are fine; the mock doesn't know their prefix and
+    // returns an error, which also shows that the check fails open.
+    register_account(&client, "breached", PASSWORD);
+}
+
+#[test]
+#[serial]
+fn json_request_log() {
+    use crate::logging::RequestLogger;
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    // A log sink we can look into afterwards
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let buffer = SharedBuffer::default();
+    let client = Client::tracked(rocket().attach(RequestLogger::new(buffer.clone())))
+        .expect("failed to create rocket client");
+
+    let response = client.get("/Main").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let mut lines = output.lines();
+    let line: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+    assert_eq!(lines.next(), None);
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["path"], "/Main");
+    assert_eq!(line["status"], 200);
+    assert!(line["latency_ms"].as_f64().unwrap() >= 0.0);
+    assert!(line["request_id"].as_str().unwrap().parse::<Uuid>().is_ok());
+}
+
+#[test]
+#[serial]
+fn request_id_on_error_page() {
+    let client = client();
+    // Searching an unknown field fails to parse the query
+    let response = client.get("/search?q=unknownfield:abc").dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+    let header_id = response
+        .headers()
+        .get_one("X-Request-Id")
+        .expect("no request id header")
+        .to_string();
+    assert!(header_id.parse::<Uuid>().is_ok());
+    let body = response.into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let selector = Selector::parse("code#request-id").unwrap();
+    let page_id = document.select(&selector).next().unwrap().inner_html();
+    assert_eq!(page_id, header_id);
+    // Every request gets its own id
+    let response = client.get("/Main").dispatch();
+    let other_id = response.headers().get_one("X-Request-Id").unwrap();
+    assert_ne!(other_id, header_id);
+}
+
+#[test]
+fn static_asset_caching() {
+    let client = client();
+    let response = client.get("/res/css/index.css").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Cache-Control"),
+        Some("public, max-age=86400")
+    );
+    let etag = response
+        .headers()
+        .get_one("ETag")
+        .expect("no etag")
+        .to_string();
+    // Asking again with the etag doesn't send the file again
+    let response = client
+        .get("/res/css/index.css")
+        .header(rocket::http::Header::new("If-None-Match", etag))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotModified);
+    // Unknown files are still a 404
+    let response = client.get("/res/css/unknown.css").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn excluded_from_search() {
+    let client = client_with(config().merge(("exclude_from_search", vec!["Sandbox*"])));
+    register_and_login(&client, "excluded from search");
+    for name in &["SandboxZanzibar", "ZanzibarGuide"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: "All about Zanzibar".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    // Both pages exist...
+    assert_eq!(
+        client.get("/SandboxZanzibar").dispatch().status(),
+        Status::Ok
+    );
+    // ...but only the one which isn't excluded is found
+    let titles = search_titles(&client, "Zanzibar");
+    assert!(titles.contains(&"ZanzibarGuide".to_string()));
+    assert!(!titles.contains(&"SandboxZanzibar".to_string()));
+}
+
+#[test]
+#[serial]
+fn move_article_api() {
+    use crate::api::MoveRequest;
+    use rocket::http::Header;
+
+    let client = client();
+    register_and_login(&client, "article mover");
+    for name in &["MoveSource", "MoveTarget"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: format!("This is {}", name),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let db = client.rocket().state::<Db>().unwrap();
+    let user_id = block_on(db.user_id_by_name("article mover"))
+        .unwrap()
+        .unwrap();
+    let token = block_on(crate::db::users::create_api_token(db, user_id)).unwrap();
+    // Only the token should be used from here on
+    logout(&client);
+    let move_article = |name: &str, request: &MoveRequest, token: Option<Uuid>| {
+        let mut req = client
+            .post(format!("/api/articles/{}/move", name))
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(request).unwrap());
+        if let Some(token) = token {
+            req = req.header(Header::new("Authorization", format!("Bearer {}", token)));
+        }
+        req.dispatch().status()
+    };
+
+    let request = MoveRequest {
+        new_name: "MovedArticle".into(),
+        leave_redirect: true,
+    };
+    // Anonymous and made-up tokens aren't accepted
+    assert_eq!(
+        move_article("MoveSource", &request, None),
+        Status::Unauthorized
+    );
+    assert_eq!(
+        move_article("MoveSource", &request, Some(Uuid::new_v4())),
+        Status::Unauthorized
+    );
+    assert_eq!(
+        move_article("MoveSource", &request, Some(token)),
+        Status::Ok
+    );
+    let response = client.get("/MovedArticle").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("This is MoveSource"));
+    // The old name now redirects to the new one
+    let response = client.get("/MoveSource").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("/MovedArticle")
+    );
+    // Both are in the search index under their names
+    let mut found = search_titles(&client, "MoveSource");
+    found.sort();
+    assert_eq!(found, vec!["MoveSource", "MovedArticle"]);
+
+    // The same titles as in the editor are refused
+    let request = MoveRequest {
+        new_name: "Long".repeat(60),
+        leave_redirect: false,
+    };
+    assert_eq!(
+        move_article("MovedArticle", &request, Some(token)),
+        Status::BadRequest
+    );
+
+    // Existing names can't be overwritten, and unknown articles are a 404
+    let request = MoveRequest {
+        new_name: "MoveTarget".into(),
+        leave_redirect: false,
+    };
+    assert_eq!(
+        move_article("MovedArticle", &request, Some(token)),
+        Status::Conflict
+    );
+    assert_eq!(
+        move_article("UnknownMoveSource", &request, Some(token)),
+        Status::NotFound
+    );
+}
+
+#[test]
+#[serial]
+fn import_articles() {
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    // One of the pages already exists and should be skipped
+    let response = post_form(
+        &client,
+        "/ImportedQuokkaOne/edit",
+        AddRevRequest {
+            title: None,
+            content: "Quokka one, written by hand".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let pages = serde_json::json! {{
+        "ImportedQuokkaOne": "Quokka one, imported",
+        "ImportedQuokkaTwo": "Quokka two",
+        "ImportedQuokkaThree": "Quokka three",
+        "ImportedQuokkaFour": "Quokka four",
+        // The same name twice, composed and decomposed
+        "ImportedQuokka\u{e9}": "Quokka \u{e9}",
+        "ImportedQuokkae\u{301}": "Quokka e\u{301}",
+        "Special:ImportedQuokka": "Reserved quokka",
+        "ImportedQuokka".repeat(20): "Long quokka",
+    }};
+    let response = post_form(
+        &client,
+        "/settings/admin/import",
+        ImportRequest {
+            pages: pages.to_string(),
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let document = scraper::Html::parse_document(&response.into_string().unwrap());
+    let count = |selector: &str| document.select(&Selector::parse(selector).unwrap()).count();
+    assert_eq!(count("#import-created li"), 4);
+    assert_eq!(count("#import-skipped li"), 2);
+    assert_eq!(count("#import-invalid li"), 2);
+    // The existing page wasn't touched
+    let db = client.rocket().state::<Db>().unwrap();
+    let rev = block_on(db.get_current_rev("ImportedQuokkaOne"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(rev.content, "Quokka one, written by hand");
+    // Exactly the new pages are searchable
+    let mut titles = search_titles(&client, "Quokka");
+    titles.sort();
+    assert_eq!(
+        titles,
+        vec![
+            "ImportedQuokkaFour",
+            "ImportedQuokkaOne",
+            "ImportedQuokkaThree",
+            "ImportedQuokkaTwo",
+            "ImportedQuokka\u{e9}"
+        ]
+    );
+
+    // Broken data is rejected
+    let response = post_form(
+        &client,
+        "/settings/admin/import",
+        ImportRequest {
+            pages: "[1, 2, 3]".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    logout(&client);
+}
+
+#[test]
+#[serial]
+fn compare_with_current_revision() {
+    let client = client();
+    register_and_login(&client, "revision comparer");
+    for content in &["First version", "Second version", "Third version"] {
+        let response = post_form(
+            &client,
+            "/ComparedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let selector = Selector::parse("a#compare-current").unwrap();
+    let compare_link = |uri: &str| {
+        let body = client
+            .get(uri.to_string())
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        document
+            .select(&selector)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+            .map(str::to_string)
+    };
+    // Old revisions link to a comparison with the current one...
+    let link = compare_link("/ComparedArticle/rev/1");
+    assert_eq!(
+        link.as_deref(),
+        Some("/ComparedArticle/compare?from=1&to=3")
+    );
+    // ...while the current one doesn't need to
+    assert_eq!(compare_link("/ComparedArticle/rev/3"), None);
+    assert_eq!(compare_link("/ComparedArticle"), None);
+
+    let response = client.get(link.unwrap()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert!(body.contains("First version"));
+    assert!(body.contains("Third version"));
+    let response = client
+        .get("/ComparedArticle/compare?from=1&to=4")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn footer_and_last_edited() {
+    let client = client_with(config().merge(("footer", "All rights <em>reversed</em>")));
+    register_and_login(&client, "footer editor");
+    let response = post_form(
+        &client,
+        "/FooterArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Look down!".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let text_of = |body: &str, selector: &str| {
+        let document = scraper::Html::parse_document(body);
+        document
+            .select(&Selector::parse(selector).unwrap())
+            .next()
+            .map(|elem| elem.inner_html())
+    };
+    let body = client
+        .get("/FooterArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let last_edited = text_of(&body, "#last-edited").expect("no last edited line");
+    assert!(last_edited.contains("Last edited by"));
+    assert!(last_edited.contains("footer editor"));
+    assert_eq!(
+        text_of(&body, "#site-footer").as_deref(),
+        Some("All rights <em>reversed</em>")
+    );
+    // The site footer is on every other page too
+    let body = client
+        .get("/search?q=abc")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(text_of(&body, "#site-footer").is_some());
+    assert!(text_of(&body, "#last-edited").is_none());
+}
+
+#[test]
+#[serial]
+fn compare_with_previous_revision() {
+    let client = client();
+    register_and_login(&client, "previous comparer");
+    for content in &["One", "Two", "Three"] {
+        let response = post_form(
+            &client,
+            "/PreviousCompared/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let body = client
+        .get("/PreviousCompared/revs")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let links = document
+        .select(&Selector::parse("a.compare-prev").unwrap())
+        .filter_map(|link| link.value().attr("href"))
+        .collect::<Vec<_>>();
+    // The first revision has nothing to compare against
+    assert_eq!(
+        links,
+        vec![
+            "/PreviousCompared/compare?from=1&to=2",
+            "/PreviousCompared/compare?from=2&to=3"
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn attachments() {
+    let upload_dir = std::env::temp_dir().join("swiki-test-uploads");
+    let client = client_with(
+        config()
+            .merge(("upload_dir", &upload_dir))
+            .merge(("upload_max_size", 1000)),
+    );
+    register_and_login(&client, "attachment uploader");
+    let response = post_form(
+        &client,
+        "/PictureArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "No pictures yet".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    let image = b"\x89PNG\r\n\x1a\nnot really a png, but close enough";
+    let response = post_file(&client, "/PictureArticle/upload", "image/png", image);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let snippet = document
+        .select(&Selector::parse("#attachment-link").unwrap())
+        .next()
+        .expect("no attachment link")
+        .inner_html();
+    let id = snippet
+        .split("(file:")
+        .nth(1)
+        .and_then(|rest| rest.strip_suffix(')'))
+        .expect("unexpected attachment link")
+        .to_string();
+
+    // The file is served with its content type...
+    let response = client.get(format!("/files/{}", id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("inline")
+    );
+    assert_eq!(response.into_bytes().unwrap(), image.to_vec());
+    // ...and file: links point to it
+    let response = post_form(
+        &client,
+        "/PictureArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: format!("![A picture](file:{})", id),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = client
+        .get("/PictureArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains(&format!(r#"<img src="/files/{}" alt="A picture""#, id)));
+
+    // Files which are too large or of the wrong type are rejected
+    let response = post_file(&client, "/PictureArticle/upload", "image/png", &[0; 2000]);
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let response = post_file(&client, "/PictureArticle/upload", "text/html", b"<p>hi</p>");
+    assert_eq!(response.status(), Status::UnsupportedMediaType);
+    // The content type is told by the content, not by what the browser says
+    let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\"><script>alert(1)</script></svg>";
+    let response = post_file(&client, "/PictureArticle/upload", "image/png", svg);
+    assert_eq!(response.status(), Status::UnsupportedMediaType);
+    let response = post_file(&client, "/PictureArticle/upload", "image/gif", image);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let relabeled = body
+        .split("(file:")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .expect("no attachment link")
+        .to_string();
+    let response = client.get(format!("/files/{}", relabeled)).dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    // Anything else which made it in is only downloaded
+    let db = client.rocket().state::<Db>().unwrap();
+    let article_id = block_on(db.article_id_by_name("PictureArticle"))
+        .unwrap()
+        .unwrap();
+    let user_id = block_on(db.user_id_by_name("attachment uploader"))
+        .unwrap()
+        .unwrap();
+    let html_id = block_on(crate::db::attachments::create(
+        db,
+        article_id,
+        user_id,
+        "text/html",
+        9,
+    ))
+    .unwrap();
+    std::fs::write(
+        upload_dir.join(html_id.to_simple().to_string()),
+        "<p>hi</p>",
+    )
+    .unwrap();
+    let response = client.get(format!("/files/{}", html_id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::Binary));
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("attachment")
+    );
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+    // Unknown files and articles are a 404
+    let response = client.get(format!("/files/{}", Uuid::new_v4())).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = post_file(&client, "/UnknownPictureArticle/upload", "image/png", image);
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn thumbnails() {
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbImage};
+
+    let upload_dir = std::env::temp_dir().join("swiki-test-uploads");
+    let client = client_with(
+        config()
+            .merge(("upload_dir", &upload_dir))
+            .merge(("thumbnail_widths", vec![100, 1000])),
+    );
+    register_and_login(&client, "thumbnail uploader");
+    let response = post_form(
+        &client,
+        "/ThumbnailArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Small pictures".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::new(500, 250))
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .unwrap();
+    let response = post_file(&client, "/ThumbnailArticle/upload", "image/png", &png);
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let id = body
+        .split("(file:")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .expect("no attachment link")
+        .to_string();
+
+    let thumbnail_size = |width: u32| {
+        let response = client
+            .get(format!("/files/{}/thumb?w={}", id, width))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        let thumbnail = image::load_from_memory(&response.into_bytes().unwrap()).unwrap();
+        (thumbnail.width(), thumbnail.height())
+    };
+    // The aspect ratio is kept
+    assert_eq!(thumbnail_size(100), (100, 50));
+    // The second time, it comes from the cache
+    assert_eq!(thumbnail_size(100), (100, 50));
+    // The image isn't scaled up
+    assert_eq!(thumbnail_size(1000), (500, 250));
+    // Only the configured widths are allowed
+    let response = client.get(format!("/files/{}/thumb?w=123", id)).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+#[serial]
+fn undo_revision() {
+    let client = client();
+    register_and_login(&client, "revision undoer");
+    let versions = [
+        "Intro\nOne\nTwo\nThree\nFour\nOutro",
+        "Intro\nUno\nTwo\nThree\nFour\nOutro",
+        "Intro\nUno\nTwo\nThree\nVier\nOutro",
+    ];
+    for content in &versions {
+        let response = post_form(
+            &client,
+            "/UndoneArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let db = client.rocket().state::<Db>().unwrap();
+    let current_content = || {
+        block_on(db.get_current_rev("UndoneArticle"))
+            .unwrap()
+            .unwrap()
+            .content
+    };
+
+    // Undoing the middle edit keeps the last one
+    let response = client.post("/UndoneArticle/rev/2/undo").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("/UndoneArticle")
+    );
+    assert_eq!(current_content(), "Intro\nOne\nTwo\nThree\nVier\nOutro");
+
+    // Undoing something which has been changed again is a conflict
+    let response = post_form(
+        &client,
+        "/UndoneArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Intro\nOne\nTwo\nThree\nFünf\nOutro".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.post("/UndoneArticle/rev/3/undo").dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(current_content(), "Intro\nOne\nTwo\nThree\nFünf\nOutro");
+    // The first revision can't be undone
+    let response = client.post("/UndoneArticle/rev/1/undo").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn article_aliases() {
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for name in &["AliasTarget", "AliasCollision"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: format!("The content of {}", name),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let add_alias = |name: &str, target: &str| {
+        post_form(
+            &client,
+            "/settings/admin/aliases",
+            AliasRequest {
+                name: name.into(),
+                target: target.into(),
+            },
+        )
+        .status()
+    };
+    assert_eq!(add_alias("AliasName", "AliasTarget"), Status::Ok);
+    // Aliases can't replace articles or other aliases, or point nowhere
+    assert_eq!(add_alias("AliasCollision", "AliasTarget"), Status::Conflict);
+    assert_eq!(add_alias("AliasName", "AliasCollision"), Status::Conflict);
+    assert_eq!(
+        add_alias("AliasNowhere", "UnknownAliasTarget"),
+        Status::NotFound
+    );
+
+    // The alias serves the article directly
+    let response = client.get("/AliasName").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("The content of AliasTarget"));
+    let response = client.get("/AliasCollision").dispatch();
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("The content of AliasCollision"));
+
+    let response = post_form(
+        &client,
+        "/settings/admin/aliases/delete",
+        DeleteAliasRequest {
+            name: "AliasName".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/AliasName").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    logout(&client);
+}
+
+#[test]
+#[serial]
+fn similar_pages() {
+    let client = client();
+    register_and_login(&client, "similar pages");
+    let pages = [
+        (
+            "SimilarAxolotl",
+            "Axolotls are amphibians with external gills, living in lakes near Xochimilco.",
+        ),
+        (
+            "SimilarAxolotlCare",
+            "Keeping axolotls: their gills need cold water, just like in Xochimilco.",
+        ),
+        ("SimilarTrains", "Steam locomotives run on coal."),
+    ];
+    for (name, content) in &pages {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    client
+        .rocket()
+        .state::<ArticleIndex>()
+        .unwrap()
+        .reader
+        .reload()
+        .unwrap();
+    let similar = |name: &str| {
+        let body = client
+            .get(format!("/{}", name))
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        document
+            .select(&Selector::parse("#similar-pages a").unwrap())
+            .map(|elem| elem.inner_html())
+            .collect::<Vec<_>>()
+    };
+    let axolotl = similar("SimilarAxolotl");
+    assert!(axolotl.contains(&"SimilarAxolotlCare".to_string()));
+    assert!(!axolotl.contains(&"SimilarAxolotl".to_string()));
+    assert!(!axolotl.contains(&"SimilarTrains".to_string()));
+    assert!(similar("SimilarAxolotlCare").contains(&"SimilarAxolotl".to_string()));
+}
+
+#[test]
+#[serial]
+fn shutdown_commits_index() {
+    let client = client();
+    register_and_login(&client, "shutdown editor");
+    let response = post_form(
+        &client,
+        "/ShutdownArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Written right before a shutdown".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let rocket = client.rocket();
+    let index = rocket.state::<ArticleIndex>().unwrap();
+    let pool = rocket.state::<Db>().unwrap().pool.clone();
+    block_on(crate::shutdown(
+        Some(index.shutdown_handle()),
+        Some(pool.clone()),
+    ));
+    assert!(pool.is_closed());
+    // Everything written before the shutdown is searchable
+    index.reader.reload().unwrap();
+    let titles = index
+        .search_by_text(
+            "shutdown",
+            None,
+            Default::default(),
+            Default::default(),
+            "b",
+        )
+        .unwrap()
+        .into_iter()
+        .map(|result| result.title)
+        .collect::<Vec<_>>();
+    assert!(titles.contains(&"ShutdownArticle".to_string()));
+}
+
+#[test]
+#[serial]
+fn email_domain_allowlist() {
+    let client = client_with(
+        config()
+            .merge(("require_email", true))
+            .merge(("allowed_email_domains", vec!["example.org"])),
+    );
+    let request = |username: &str, email: Option<&str>| {
+        let (captcha_id, captcha_solution) = register_challenge(&client);
+        RegisterRequest {
+            username: username.into(),
+            email: email.map(Into::into),
+            password: PASSWORD.into(),
+            pwd_confirm: PASSWORD.into(),
+            captcha_id,
+            captcha_solution,
+            ..Default::default()
+        }
+    };
+    let help_texts = |body: &str| {
+        let document = scraper::Html::parse_document(body);
+        document
+            .select(&Selector::parse("p.help.is-danger").unwrap())
+            .map(|elem| elem.inner_html())
+            .collect::<Vec<_>>()
+    };
+
+    let response = post_form(
+        &client,
+        "/u/register",
+        request("outside domain", Some("someone@example.com")),
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        help_texts(&response.into_string().unwrap()),
+        vec!["Email addresses from this domain can't register here."]
+    );
+    let response = post_form(&client, "/u/register", request("no email", None));
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        help_texts(&response.into_string().unwrap()),
+        vec!["You need an email address!"]
+    );
+    let response = post_form(
+        &client,
+        "/u/register",
+        request("inside domain", Some("someone@Example.org")),
+    );
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn recent_registrations() {
+    let client = client();
+    let admin = admin_account(&client);
+    let newcomer = format!("newcomer {}", Uuid::new_v4().to_simple());
+    register_account(&client, &newcomer, PASSWORD);
+    login(&client, &admin, PASSWORD);
+    let response = client.get("/settings/users/recent").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let document = scraper::Html::parse_document(&response.into_string().unwrap());
+    let names = document
+        .select(&Selector::parse("#recent-users td a").unwrap())
+        .map(|elem| elem.inner_html())
+        .collect::<Vec<_>>();
+    assert!(names.contains(&newcomer));
+    logout(&client);
+    // Normal users don't get to see this
+    login(&client, &newcomer, PASSWORD);
+    let response = client.get("/settings/users/recent").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn ghost_author_label() {
+    use crate::db::users::GHOST_USER_ID;
+
+    let client = client_with(config().merge(("ghost_author", "Anonymous Coward")));
+    register_and_login(&client, "ghost haunter");
+    let response = post_form(
+        &client,
+        "/HauntedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Nothing to see here".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let db = client.rocket().state::<Db>().unwrap();
+    let article_id = block_on(db.article_id_by_name("HauntedArticle"))
+        .unwrap()
+        .unwrap();
+    // Revisions of deleted or imported authors belong to the ghost
+    block_on(async {
+        let mut conn = db.acquire().await.unwrap();
+        crate::db::articles::add_revision(&mut conn, article_id, GHOST_USER_ID, "Boo!", false, 0)
+            .await
+            .unwrap();
+    });
+    let links = Selector::parse("a").unwrap();
+    for uri in &[
+        "/HauntedArticle",
+        "/HauntedArticle/revs",
+        "/HauntedArticle/rev/2",
+    ] {
+        let body = client.get(*uri).dispatch().into_string().unwrap();
+        assert!(body.contains("Anonymous Coward"), "no label on {}", uri);
+        assert!(!body.contains(">[ghost]<"), "raw ghost name on {}", uri);
+        // There's no profile to link to
+        let document = scraper::Html::parse_document(&body);
+        let ghost_links = document
+            .select(&links)
+            .filter(|link| {
+                link.text().collect::<String>().contains("Anonymous Coward")
+                    || link.value().attr("href").unwrap_or("").contains("[ghost]")
+            })
+            .count();
+        assert_eq!(ghost_links, 0, "ghost linked on {}", uri);
+    }
+    // Real authors still link to their profile
+    let body = client
+        .get("/HauntedArticle/revs")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("href=\"/u/ghost haunter\""));
+}
+
+#[test]
+#[serial]
+fn revisions_by_author() {
+    let client = client();
+    register_account(&client, "first reviser", PASSWORD);
+    register_account(&client, "second reviser", PASSWORD);
+    for (i, author) in ["first reviser", "second reviser", "first reviser"]
+        .iter()
+        .enumerate()
+    {
+        login(&client, author, PASSWORD);
+        let response = post_form(
+            &client,
+            "/FilteredRevs/edit",
+            AddRevRequest {
+                title: None,
+                content: format!("Revision {}", i + 1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+        logout(&client);
+    }
+    let revs = |query: &str| {
+        let response = client
+            .get(format!("/FilteredRevs/revs?{}", query))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let document = scraper::Html::parse_document(&response.into_string().unwrap());
+        document
+            .select(&Selector::parse("tr.rev").unwrap())
+            .map(|row| {
+                let text = row.text().collect::<String>();
+                let num = text.split_whitespace().next().unwrap().to_string();
+                let author = row
+                    .select(&Selector::parse(".rev-author").unwrap())
+                    .next()
+                    .unwrap()
+                    .text()
+                    .collect::<String>();
+                (num, author)
+            })
+            .collect::<Vec<_>>()
+    };
+    let first = |num: &str| (num.to_string(), "first reviser".to_string());
+    assert_eq!(revs("").len(), 3);
+    assert_eq!(
+        revs("author=first%20reviser"),
+        vec![first("#1"), first("#3")]
+    );
+    assert_eq!(
+        revs("author=first%20reviser&order=desc"),
+        vec![first("#3"), first("#1")]
+    );
+    // Unknown authors just have no revisions
+    assert!(revs("author=nobody").is_empty());
+}
+
+#[test]
+#[serial]
+fn concurrent_revisions() {
+    use crate::db::users::GHOST_USER_ID;
+    use std::time::Duration;
+
+    let client = client();
+    register_and_login(&client, "racing editor");
+    let response = post_form(
+        &client,
+        "/RacyArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "The starting line".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let db = client.rocket().state::<Db>().unwrap();
+    let article_id = block_on(db.article_id_by_name("RacyArticle"))
+        .unwrap()
+        .unwrap();
+    // Both transactions are open at the same time, the first one committing
+    // only after the second one has tried to add its revision
+    let save = |content: &'static str, delay: u64| async move {
+        let mut txn = db.begin().await.unwrap();
+        let (rev_id, _) = crate::db::articles::add_revision(
+            &mut txn,
+            article_id,
+            GHOST_USER_ID,
+            content,
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+        rocket::tokio::time::sleep(Duration::from_millis(delay)).await;
+        txn.commit().await.unwrap();
+        rev_id.1
+    };
+    let (first, second) = block_on(async {
+        rocket::tokio::join!(save("First", 200), async {
+            rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+            save("Second", 0).await
+        })
+    });
+    assert_eq!(first, 2);
+    assert_eq!(second, 3);
+    let rev = block_on(db.get_current_rev("RacyArticle"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(rev.content, "Second");
+}
+
+#[test]
+#[serial]
+fn api_json_errors() {
+    let client = client();
+    let json_error = |response: LocalResponse<'_>| {
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+    };
+    // Missing articles...
+    let response = client
+        .get("/api/articles/UnknownJsonArticle/diff?from=1&to=2")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let error = json_error(response);
+    assert_eq!(error["status"], 404);
+    assert!(error["error"].is_string());
+    // ...and our own errors are both JSON
+    let response = client
+        .post("/api/articles/UnknownJsonArticle/move")
+        .header(ContentType::JSON)
+        .header(rocket::http::Header::new(
+            "Authorization",
+            format!("Bearer {}", Uuid::new_v4()),
+        ))
+        .body(r#"{"new_name": "Whatever"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let error = json_error(response);
+    assert_eq!(error["status"], 401);
+    assert_eq!(error["error"], "Invalid API token");
+    // Browsers still get a page
+    let response = client.get("/search?q=unknownfield:abc").dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+}
+
+#[test]
+#[serial]
+fn top_editors() {
+    let client = client();
+    let edit = |content: &str| {
+        let response = post_form(
+            &client,
+            "/MaintainedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    register_and_login(&client, "main maintainer");
+    edit("First draft");
+    edit("Second draft");
+    logout(&client);
+    register_and_login(&client, "drive-by editor");
+    edit("Typo fix");
+
+    let db = client.rocket().state::<Db>().unwrap();
+    let editors = block_on(crate::db::articles::top_editors(db, "MaintainedArticle", 3)).unwrap();
+    let editors: Vec<_> = editors
+        .iter()
+        .map(|editor| (editor.name.as_str(), editor.edits))
+        .collect();
+    assert_eq!(editors, [("main maintainer", 2), ("drive-by editor", 1)]);
+
+    let body = client
+        .get("/MaintainedArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("id=\"top-editors\""));
+    assert!(body.contains("main maintainer</a>"));
+    assert!(body.contains("drive-by editor</a>"));
+}
+
+#[test]
+#[serial]
+fn external_captcha() {
+    use mockito::Matcher;
+
+    let secret_and = |token: &str| {
+        Matcher::AllOf(vec![
+            Matcher::UrlEncoded("secret".into(), "turnstile secret".into()),
+            Matcher::UrlEncoded("response".into(), token.into()),
+        ])
+    };
+    let _solved = mockito::mock("POST", "/siteverify")
+        .match_body(secret_and("solved-token"))
+        .with_body(r#"{"success": true}"#)
+        .create();
+    let _unsolved = mockito::mock("POST", "/siteverify")
+        .match_body(secret_and("unsolved-token"))
+        .with_body(r#"{"success": false, "error-codes": ["invalid-input-response"]}"#)
+        .create();
+    let client = client_with(config().merge(("captcha_provider", "turnstile")).merge((
+        "captcha",
+        serde_json::json!({
+            "site_key": "turnstile site key",
+            "secret": "turnstile secret",
+            "verify_url": format!("{}/siteverify", mockito::server_url()),
+        }),
+    )));
+    let body = client.get("/u/register").dispatch().into_string().unwrap();
+    assert!(body.contains("class=\"cf-turnstile\" data-sitekey=\"turnstile site key\""));
+    assert!(!body.contains("data:image/png"));
+
+    let request = |captcha_id, token: &str| RegisterRequest {
+        username: "turnstile user".into(),
+        password: PASSWORD.into(),
+        pwd_confirm: PASSWORD.into(),
+        captcha_id,
+        captcha_solution: token.into(),
+        ..Default::default()
+    };
+    let (captcha_id, _) = register_challenge(&client);
+    let response = post_form(
+        &client,
+        "/u/register",
+        request(captcha_id, "unsolved-token"),
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("Error, please try again!"));
+
+    let (captcha_id, _) = register_challenge(&client);
+    let response = post_form(&client, "/u/register", request(captcha_id, "solved-token"));
+    assert_eq!(response.status(), Status::Ok);
+    login(&client, "turnstile user", PASSWORD);
+}
+
+#[test]
+#[serial]
+fn integrity_check() {
+    use crate::db::users::GHOST_USER_ID;
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    // Articles are always created with their first revision, so this has
+    // to be done by hand
+    let db = client.rocket().state::<Db>().unwrap();
+    block_on(
+        sqlx::query!(
+            "INSERT INTO article(id, name, creator_id) VALUES($1, $2, $3)",
+            Uuid::new_v4(),
+            "EmptyArticle",
+            GHOST_USER_ID,
+        )
+        .execute(&db.pool),
+    )
+    .unwrap();
+
+    let article_names = |body: &str| {
+        let document = scraper::Html::parse_document(body);
+        document
+            .select(&Selector::parse("#empty-articles li").unwrap())
+            .map(|elem| elem.inner_html())
+            .collect::<Vec<_>>()
+    };
+    let response = client.get("/settings/integrity").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let names = article_names(&response.into_string().unwrap());
+    assert!(names.iter().any(|name| name.starts_with("EmptyArticle ")));
+
+    let response = client.post("/settings/integrity/repair").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert!(body.contains("id=\"integrity-repaired\""));
+    assert!(article_names(&body).is_empty());
+    assert_eq!(
+        block_on(db.article_id_by_name("EmptyArticle")).unwrap(),
+        None
+    );
+}
+
+#[test]
+#[serial]
+fn index_check() {
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let response = post_form(
+        &client,
+        "/IndexedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "This one is in the index".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    // Creating the article directly doesn't index it
+    let db = client.rocket().state::<Db>().unwrap();
+    let author_id = block_on(db.user_id_by_name(&admin)).unwrap().unwrap();
+    block_on(async {
+        let mut txn = db.begin().await.unwrap();
+        crate::db::articles::create(&mut txn, "UnindexedArticle", "Not in the index", author_id)
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+    });
+
+    let response = client.get("/settings/index-check").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert!(!body.contains("id=\"index-clean\""));
+    let document = scraper::Html::parse_document(&body);
+    let missing = document
+        .select(&Selector::parse("#index-missing li").unwrap())
+        .map(|elem| elem.text().collect::<String>())
+        .collect::<Vec<_>>();
+    assert!(missing
+        .iter()
+        .any(|name| name.starts_with("UnindexedArticle ")));
+    assert!(!missing
+        .iter()
+        .any(|name| name.starts_with("IndexedArticle ")));
+}
+
+#[test]
+#[serial]
+fn title_rules() {
+    let client = client_with(config().merge(("max_title_length", 20)));
+    register_and_login(&client, "title checker");
+    let edit = |uri: &str, title: Option<&str>| {
+        post_form(
+            &client,
+            uri,
+            AddRevRequest {
+                title: title.map(Into::into),
+                content: "Hello".into(),
+                ..Default::default()
+            },
+        )
+    };
+
+    let response = edit("/TitleRules/edit", Some("A title which is way too long"));
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"title-too-long\""));
+    let response = edit("/Special:Titles/edit", None);
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"title-reserved\""));
+    let db = client.rocket().state::<Db>().unwrap();
+    assert_eq!(
+        block_on(db.article_id_by_name("Special:Titles")).unwrap(),
+        None
+    );
+
+    // Path prefixes only reserve whole segments
+    assert_eq!(edit("/Unicorns/edit", None).status(), Status::Ok);
+    // Renames are checked as well
+    let response = edit("/Unicorns/edit", Some("u"));
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"title-reserved\""));
+}
+
+#[test]
+#[serial]
+fn blocked_title_terms() {
+    let client = client_with(config().merge(("blocked_title_terms", vec!["darn"])));
+    register_and_login(&client, "title filter");
+    let edit = |uri: &str| {
+        post_form(
+            &client,
+            uri,
+            AddRevRequest {
+                title: None,
+                content: "Hello".into(),
+                ..Default::default()
+            },
+        )
+    };
+
+    // Matched anywhere in the title, ignoring case
+    let response = edit("/ThisDarnArticle/edit");
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"title-blocked\""));
+    let db = client.rocket().state::<Db>().unwrap();
+    assert_eq!(
+        block_on(db.article_id_by_name("ThisDarnArticle")).unwrap(),
+        None
+    );
+
+    assert_eq!(edit("/ThisNiceArticle/edit").status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn article_creation_cooldown() {
+    let client = client_with(config().merge(("article_creation_cooldown", 600)));
+    let edit = |uri: &str| {
+        post_form(
+            &client,
+            uri,
+            AddRevRequest {
+                title: None,
+                content: "Fresh content".into(),
+                ..Default::default()
+            },
+        )
+        .status()
+    };
+    // Admins don't have to wait, even with a new account
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    assert_eq!(edit("/CooldownExisting/edit"), Status::Ok);
+    logout(&client);
+
+    register_and_login(&client, "impatient newcomer");
+    assert_eq!(edit("/CooldownNew/edit"), Status::Forbidden);
+    let create = || {
+        post_form(
+            &client,
+            "/CooldownNew/edit",
+            AddRevRequest {
+                title: None,
+                content: "Fresh content".into(),
+                ..Default::default()
+            },
+        )
+        .into_string()
+        .unwrap()
+    };
+    assert!(create().contains("in 10 more minutes"));
+    let db = client.rocket().state::<Db>().unwrap();
+    assert_eq!(
+        block_on(db.article_id_by_name("CooldownNew")).unwrap(),
+        None
+    );
+    assert_eq!(edit("/CooldownExisting/edit"), Status::Ok);
+
+    let age = |minutes: f64| {
+        block_on(
+            sqlx::query!(
+                r#"UPDATE "user" SET created = now() - $2 * INTERVAL '1 minute' WHERE name = $1"#,
+                "impatient newcomer",
+                minutes,
+            )
+            .execute(&db.pool),
+        )
+        .unwrap();
+    };
+    // Only the time that's left is shown
+    age(7.0);
+    assert!(create().contains("in 3 more minutes"));
+    age(11.0);
+    assert_eq!(edit("/CooldownNew/edit"), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn wanted_pages() {
+    let client = client();
+    register_and_login(&client, "gap finder");
+    let response = post_form(
+        &client,
+        "/WantedSource/edit",
+        AddRevRequest {
+            title: None,
+            content: "See [WantedTarget], [WantedSource], [Wanted & Found] and [Special:Unwanted]"
+                .into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    let wanted = || {
+        let response = client.get("/wanted").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        scraper::Html::parse_document(&response.into_string().unwrap())
+    };
+    let create_links = |document: &scraper::Html| {
+        document
+            .select(&Selector::parse("a.wanted-create").unwrap())
+            .map(|elem| elem.value().attr("href").unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    let document = wanted();
+    let edit_links = create_links(&document);
+    // Existing articles aren't wanted, and reserved names can't be created
+    assert!(edit_links.contains(&"/WantedTarget/edit".to_string()));
+    assert!(edit_links.contains(&"/Wanted%20%26%20Found/edit".to_string()));
+    assert!(!edit_links.iter().any(|href| href.contains("WantedSource")));
+    assert!(!edit_links.iter().any(|href| href.contains("Special:")));
+    let entries = document
+        .select(&Selector::parse(".wanted-page").unwrap())
+        .map(|elem| elem.text().collect::<String>())
+        .collect::<Vec<_>>();
+    assert!(entries
+        .iter()
+        .any(|entry| entry.contains("Special:Unwanted")));
+
+    // Creating a wanted article takes it off the list
+    let response = post_form(
+        &client,
+        "/WantedTarget/edit",
+        AddRevRequest {
+            title: None,
+            content: "Not wanted anymore".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert!(!create_links(&wanted()).contains(&"/WantedTarget/edit".to_string()));
+}
+
+#[test]
+#[serial]
+fn preview_diff() {
+    let client = client();
+    register_and_login(&client, "diff previewer");
+    let response = post_form(
+        &client,
+        "/PreviewedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Unchanged line\nOld line".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = post_form(
+        &client,
+        "/PreviewedArticle/preview-diff",
+        DraftRequest {
+            content: "Unchanged line\nNew line".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    // Just the fragment, not a whole page
+    assert!(!body.contains("<html"));
+    let document = scraper::Html::parse_fragment(&body);
+    let lines = |operation: &str| {
+        document
+            .select(&Selector::parse(&format!(".line.{}", operation)).unwrap())
+            .map(|line| line.text().collect::<String>())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lines("delete"), ["- Old line"]);
+    assert_eq!(lines("insert"), ["+ New line"]);
+    // Nothing was saved
+    let body = client
+        .get("/PreviewedArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("Old line"));
+}
+
+#[test]
+#[serial]
+fn remember_me() {
+    // Every login starts without a session cookie
+    let client =
+        Client::untracked(rocket().configure(config().merge(("session_idle_timeout", 3600))))
+            .expect("failed to create rocket client");
+    register_account(&client, "remembered user", PASSWORD);
+    let login = |remember: bool| {
+        let response = post_form(
+            &client,
+            "/u/login",
+            LoginRequest {
+                username: "remembered user".into(),
+                password: PASSWORD.into(),
+                remember,
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+        let cookie = response.cookies().get("session_id").unwrap();
+        let session_id = base64::decode(cookie.value()).unwrap();
+        let max_days = cookie.max_age().map(|max_age| max_age.whole_days());
+        (Uuid::from_slice(&session_id).unwrap(), max_days)
+    };
+
+    // Normal logins end with the browser session
+    let (_, max_days) = login(false);
+    assert_eq!(max_days, None);
+    let (session_id, max_days) = login(true);
+    assert_eq!(max_days, Some(90));
+
+    // Remembered sessions also outlast the idle timeout
+    let db = client.rocket().state::<Db>().unwrap();
+    let (normal_id, _) = login(false);
+    block_on(
+        sqlx::query!(
+            "UPDATE session SET last_seen = now() - INTERVAL '2 hours'
+            WHERE session_id = ANY($1)",
+            &[session_id, normal_id][..]
+        )
+        .execute(&db.pool),
+    )
+    .unwrap();
+    let settings_with = |session_id: Uuid| {
+        client
+            .get("/settings")
+            .cookie(rocket::http::Cookie::new(
+                "session_id",
+                base64::encode(session_id.as_bytes()),
+            ))
+            .dispatch()
+            .status()
+    };
+    assert_eq!(settings_with(session_id), Status::Ok);
+    assert_eq!(settings_with(normal_id), Status::SeeOther);
+}
+
+#[test]
+#[serial]
+fn session_cookie_renewal() {
+    let client =
+        Client::untracked(rocket().configure(config())).expect("failed to create rocket client");
+    register_account(&client, "renewed user", PASSWORD);
+    let response = post_form(
+        &client,
+        "/u/login",
+        LoginRequest {
+            username: "renewed user".into(),
+            password: PASSWORD.into(),
+            remember: true,
+        },
+    );
+    let cookie = response.cookies().get("session_id").unwrap().clone();
+    let settings = || {
+        let response = client.get("/settings").cookie(cookie.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response
+            .cookies()
+            .get("session_id")
+            .map(|cookie| cookie.max_age().unwrap().whole_days())
+    };
+    // A fresh cookie is left alone
+    assert_eq!(settings(), None);
+
+    let db = client.rocket().state::<Db>().unwrap();
+    let session_id = Uuid::from_slice(&base64::decode(cookie.value()).unwrap()).unwrap();
+    block_on(
+        sqlx::query!(
+            "UPDATE session SET cookie_issued = now() - INTERVAL '46 days' WHERE session_id = $1",
+            session_id
+        )
+        .execute(&db.pool),
+    )
+    .unwrap();
+    assert_eq!(settings(), Some(90));
+    // Only once, since it's fresh again afterwards
+    assert_eq!(settings(), None);
+
+    // Being used doesn't keep it alive past its lifetime since logging in
+    block_on(
+        sqlx::query!(
+            "UPDATE session SET created = now() - INTERVAL '91 days' WHERE session_id = $1",
+            session_id
+        )
+        .execute(&db.pool),
+    )
+    .unwrap();
+    let response = client.get("/settings").cookie(cookie.clone()).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+}
+
+#[test]
+#[serial]
+fn session_idle_timeout() {
+    use rocket::http::Cookie;
+
+    let client = client_with(config().merge(("session_idle_timeout", 3600)));
+    register_account(&client, "idle user", PASSWORD);
+    let db = client.rocket().state::<Db>().unwrap();
+    let new_session = || {
+        block_on(db.try_login("idle user", PASSWORD.into(), None, 0))
+            .unwrap()
+            .session_id
+    };
+    let (active, idle) = (new_session(), new_session());
+    block_on(
+        sqlx::query!(
+            "UPDATE session SET last_seen = now() - INTERVAL '2 hours' WHERE session_id = $1",
+            idle
+        )
+        .execute(&db.pool),
+    )
+    .unwrap();
+
+    let settings_with = |session_id: Uuid| {
+        client
+            .get("/settings")
+            .cookie(Cookie::new(
+                "session_id",
+                base64::encode(session_id.as_bytes()),
+            ))
+            .dispatch()
+            .status()
+    };
+    assert_eq!(settings_with(active), Status::Ok);
+    assert_eq!(settings_with(idle), Status::SeeOther);
+    // Using the active session keeps it alive
+    assert_eq!(settings_with(active), Status::Ok);
+    // The idle one is gone for good
+    let found: Option<Uuid> = block_on(
+        sqlx::query_scalar!("SELECT user_id FROM session WHERE session_id = $1", idle)
+            .fetch_optional(&db.pool),
+    )
+    .unwrap();
+    assert_eq!(found, None);
+}
+
+#[test]
+#[serial]
+fn password_change_logs_out_elsewhere() {
+    use rocket::http::Cookie;
+
+    use crate::settings::PasswordRequest;
+
+    let client = client();
+    register_account(&client, "careful user", PASSWORD);
+    let db = client.rocket().state::<Db>().unwrap();
+    let new_session = || {
+        block_on(db.try_login("careful user", PASSWORD.into(), None, 0))
+            .unwrap()
+            .session_id
+    };
+    let (current, other) = (new_session(), new_session());
+    let cookie =
+        |session_id: Uuid| Cookie::new("session_id", base64::encode(session_id.as_bytes()));
+    let change = |current_password: &str, password: &str| {
+        client
+            .post("/settings/password")
+            .cookie(cookie(current))
+            .header(content_type_form())
+            .body(
+                serde_urlencoded::to_string(PasswordRequest {
+                    current_password: current_password.into(),
+                    password: password.into(),
+                    pwd_confirm: password.into(),
+                })
+                .unwrap(),
+            )
+            .dispatch()
+    };
+    let settings_with = |session_id: Uuid| {
+        client
+            .get("/settings")
+            .cookie(cookie(session_id))
+            .dispatch()
+            .status()
+    };
+
+    let response = change("not my password", "NewPassword123");
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"wrong-password\""));
+    assert_eq!(settings_with(other), Status::Ok);
+
+    let response = change(PASSWORD, "NewPassword123");
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("<span id=\"sessions-ended\">1</span>"));
+    // Only the session the password was changed in is left
+    assert_eq!(settings_with(current), Status::Ok);
+    assert_eq!(settings_with(other), Status::SeeOther);
+    assert!(block_on(db.try_login("careful user", PASSWORD.into(), None, 0)).is_err());
+    assert!(block_on(db.try_login("careful user", "NewPassword123".into(), None, 0)).is_ok());
+}
+
+#[test]
+#[serial]
+fn revoke_session() {
+    use rocket::http::Cookie;
+
+    let client = client();
+    register_account(&client, "many devices", PASSWORD);
+    register_account(&client, "session thief", PASSWORD);
+    login(&client, "many devices", PASSWORD);
+    // Another device logging in
+    let db = client.rocket().state::<Db>().unwrap();
+    let other = block_on(db.try_login("many devices", PASSWORD.into(), None, 0))
+        .unwrap()
+        .session_id;
+    let other_cookie = || Cookie::new("session_id", base64::encode(other.as_bytes()));
+
+    let body = client
+        .get("/settings/sessions")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let rows = document
+        .select(&Selector::parse("#sessions tbody tr").unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), 2);
+    // The full session id must not show up anywhere
+    assert!(!body.contains(&other.to_string()));
+    assert!(!body.contains(&other.to_simple().to_string()));
+    // Revoke the other device's session: it's the one not marked as current
+    let form_selector = Selector::parse("form").unwrap();
+    let revoke_url = rows
+        .iter()
+        .find(|row| !row.inner_html().contains("(this one)"))
+        .and_then(|row| row.select(&form_selector).next())
+        .and_then(|form| form.value().attr("action"))
+        .unwrap()
+        .to_string();
+    let response = client.post(revoke_url.as_str()).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+
+    // Nobody can revoke other users' sessions
+    let current_id: i64 = rows
+        .iter()
+        .find(|row| row.inner_html().contains("(this one)"))
+        .and_then(|row| row.select(&form_selector).next())
+        .and_then(|form| form.value().attr("action"))
+        .and_then(|url| url.strip_prefix("/settings/sessions/"))
+        .and_then(|url| url.strip_suffix("/revoke"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let thief = block_on(db.try_login("session thief", PASSWORD.into(), None, 0))
+        .unwrap()
+        .user_id;
+    assert!(!block_on(crate::db::users::revoke_session(db, thief, current_id)).unwrap());
+
+    // This session still works, the other one doesn't
+    assert_eq!(client.get("/settings").dispatch().status(), Status::Ok);
+    logout(&client);
+    let response = client.get("/settings").cookie(other_cookie()).dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+}
+
+#[test]
+#[serial]
+fn tags_from_edit_form() {
+    let client = client();
+    register_and_login(&client, "tagging editor");
+    let edit = |content: &str, tags: Option<&str>| {
+        let response = post_form(
+            &client,
+            "/EditTaggedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                tags: tags.map(Into::into),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    let db = client.rocket().state::<Db>().unwrap();
+    let tags = || block_on(crate::db::tags::for_article(db, "EditTaggedArticle")).unwrap();
+
+    edit("Tagged while creating", Some("Recipes, french ,,"));
+    assert_eq!(tags(), ["french", "recipes"]);
+    // Later edits add to the tags, even without changing the content
+    edit("Tagged while creating", Some("french, baking"));
+    assert_eq!(tags(), ["baking", "french", "recipes"]);
+    edit("Changed without tags", None);
+    assert_eq!(tags(), ["baking", "french", "recipes"]);
+    // The tags are searchable, too
+    assert_eq!(search_titles(&client, "baking"), ["EditTaggedArticle"]);
+}
+
+#[test]
+#[serial]
+fn max_tags_per_article() {
+    let client = client_with(config().merge(("max_tags_per_article", 3)));
+    register_and_login(&client, "tag spammer");
+    let edit = |content: &str, tags: &str| {
+        post_form(
+            &client,
+            "/CappedTags/edit",
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                tags: Some(tags.into()),
+                ..Default::default()
+            },
+        )
+        .status()
+    };
+    let db = client.rocket().state::<Db>().unwrap();
+    let tags = || block_on(crate::db::tags::for_article(db, "CappedTags")).unwrap();
+
+    assert_eq!(edit("First", "one, two"), Status::Ok);
+    // Tags the article already has don't count twice
+    assert_eq!(edit("Second", "two, three"), Status::Ok);
+    assert_eq!(tags(), ["one", "three", "two"]);
+    // Neither the tags nor the content are saved if there are too many
+    assert_eq!(edit("Third", "four"), Status::BadRequest);
+    assert_eq!(tags(), ["one", "three", "two"]);
+    assert_eq!(
+        block_on(db.get_current_rev("CappedTags"))
+            .unwrap()
+            .unwrap()
+            .content,
+        "Second"
+    );
+}
+
+#[test]
+#[serial]
+fn long_article_sections() {
+    let client = client_with(config().merge(("long_article_size", 200)));
+    register_and_login(&client, "long writer");
+    let mut long = String::from("# Introduction\n\nStart\n\n## Some `code` here\n\n");
+    long.push_str(&"Lots of words. ".repeat(30));
+    long.push_str("\n\n## Usage\n\nOne way\n\n## Usage\n\nAnother way\n");
+    for (name, content) in &[("LongArticle", long.as_str()), ("ShortArticle", "# Tiny")] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let body = client.get("/LongArticle").dispatch().into_string().unwrap();
+    assert!(body.contains("id=\"long-article\""));
+    let document = scraper::Html::parse_document(&body);
+    let links = Selector::parse("#article-sections a").unwrap();
+    let sections = document
+        .select(&links)
+        .map(|link| {
+            (
+                link.value().attr("href").unwrap().to_string(),
+                link.text().collect::<String>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sections,
+        [
+            ("#introduction".to_string(), "Introduction".to_string()),
+            ("#some-code-here".to_string(), "Some code here".to_string()),
+            ("#usage".to_string(), "Usage".to_string()),
+            ("#usage-2".to_string(), "Usage".to_string()),
+        ]
+    );
+    // The links lead to the headings
+    assert!(body.contains("<h1 id=\"introduction\">Introduction</h1>"));
+    assert!(body.contains("<h2 id=\"some-code-here\">"));
+    assert!(body.contains("<h2 id=\"usage\">Usage</h2>"));
+    assert!(body.contains("<h2 id=\"usage-2\">Usage</h2>"));
+
+    let body = client
+        .get("/ShortArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(!body.contains("id=\"long-article\""));
+    assert!(body.contains("<h1 id=\"tiny\">Tiny</h1>"));
+}
+
+#[test]
+#[serial]
+fn plain_article_format() {
+    let client = client_with(config().merge(("article_format", "plain")));
+    register_and_login(&client, "plain writer");
+    let response = post_form(
+        &client,
+        "/PlainArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Some **bold** <b>claims</b>\nUnformatted".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = client
+        .get("/PlainArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("Some **bold** &lt;b&gt;claims&lt;/b&gt;\nUnformatted"));
+    assert!(!body.contains("<strong>bold</strong>"));
+    // It's still found by its text
+    assert_eq!(search_titles(&client, "Unformatted"), vec!["PlainArticle"]);
+}
+
+#[test]
+#[serial]
+fn redirect_validation() {
+    use crate::api::SaveRequest;
+
+    {
+        let client = client();
+        register_and_login(&client, "redirect checker");
+        let edit = |name: &str, content: &str| {
+            post_form(
+                &client,
+                format!("/{}/edit", name),
+                AddRevRequest {
+                    title: None,
+                    content: content.into(),
+                    ..Default::default()
+                },
+            )
+        };
+
+        let response = edit("SelfRedirect", "#REDIRECT [SelfRedirect]");
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(block_on(
+            client
+                .rocket()
+                .state::<Db>()
+                .unwrap()
+                .get_current_rev("SelfRedirect")
+        )
+        .unwrap()
+        .is_none());
+
+        // Missing targets are saved, but with a warning
+        let response = edit("EarlyRedirect", "#REDIRECT [NotWrittenYet]");
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().unwrap();
+        assert!(body.contains("id=\"broken-redirect\""));
+        assert!(body.contains("href=\"/NotWrittenYet\""));
+        let response = edit("NotWrittenYet", "Now it exists");
+        assert_eq!(response.status(), Status::Ok);
+        let response = edit("LateRedirect", "#REDIRECT [NotWrittenYet]");
+        assert_eq!(response.status(), Status::Ok);
+        assert!(!response
+            .into_string()
+            .unwrap()
+            .contains("id=\"broken-redirect\""));
+
+        // The API checks it as well
+        let response = client
+            .put("/api/articles/SelfRedirect")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::to_string(&SaveRequest {
+                    content: "#REDIRECT [SelfRedirect]".into(),
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    let client = client_with(config().merge(("check_redirects", false)));
+    login(&client, "redirect checker", PASSWORD);
+    let response = post_form(
+        &client,
+        "/SelfRedirect/edit",
+        AddRevRequest {
+            title: None,
+            content: "#REDIRECT [SelfRedirect]".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn minor_edits_keep_listed_date() {
+    {
+        let client = client_with(config().merge(("minor_edits", true)));
+        register_and_login(&client, "major editor");
+        let edit = |content: &str, minor: bool| {
+            let response = post_form(
+                &client,
+                "/MinorlyEdited/edit",
+                AddRevRequest {
+                    title: None,
+                    content: content.into(),
+                    minor,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(response.status(), Status::Ok);
+        };
+        let db = client.rocket().state::<Db>().unwrap();
+        let listed_date = || {
+            block_on(db.list_articles())
+                .unwrap()
+                .into_iter()
+                .find(|article| article.name == "MinorlyEdited")
+                .unwrap()
+                .rev_created
+        };
+        let recent = || {
+            block_on(crate::db::articles::recent_changes(db, 100))
+                .unwrap()
+                .into_iter()
+                .filter(|change| change.article_name == "MinorlyEdited")
+                .map(|change| change.num)
+                .collect::<Vec<_>>()
+        };
+
+        edit("Frist version", false);
+        edit("Second version", false);
+        logout(&client);
+        register_and_login(&client, "typo fixer");
+        let date = listed_date();
+        assert_eq!(recent(), [2, 1]);
+        assert!(client
+            .get("/MinorlyEdited/edit")
+            .dispatch()
+            .into_string()
+            .unwrap()
+            .contains("id=\"minor\""));
+
+        edit("Second version, fixed", true);
+        // The revision is there, but the listings don't know about it
+        let current = block_on(db.get_current_rev("MinorlyEdited"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.rev_id, 3);
+        assert_eq!(current.content, "Second version, fixed");
+        assert_eq!(listed_date(), date);
+        assert_eq!(recent(), [2, 1]);
+        // Neither does the footer
+        let body = client
+            .get("/MinorlyEdited")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let footer = &body[body.find("id=\"last-edited\"").unwrap()..];
+        assert!(footer.contains("major editor"));
+        assert!(!footer.contains("typo fixer"));
+
+        edit("Third version", false);
+        assert!(listed_date() > date);
+        assert_eq!(recent(), [4, 2, 1]);
+    }
+
+    // Without the option, the flag is ignored
+    let client = client();
+    login(&client, "typo fixer", PASSWORD);
+    let response = post_form(
+        &client,
+        "/MinorlyEdited/edit",
+        AddRevRequest {
+            title: None,
+            content: "Fourth version".into(),
+            minor: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let db = client.rocket().state::<Db>().unwrap();
+    let changes = block_on(crate::db::articles::recent_changes(db, 100)).unwrap();
+    assert!(changes
+        .iter()
+        .any(|change| change.article_name == "MinorlyEdited" && change.num == 5));
+}
+
+#[test]
+#[serial]
+fn html_export() {
+    use std::io::{Cursor, Read};
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for (name, content) in &[
+        (
+            "Exported Start",
+            "See [Exported Other] and [Exported Missing]",
+        ),
+        (
+            "Exported Other",
+            "# Details\n\nBack to [the start](/Exported%20Start#top)",
+        ),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name.replace(' ', "%20")),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let response = client.get("/settings/export/html").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::ZIP));
+    let mut zip = zip::ZipArchive::new(Cursor::new(response.into_bytes().unwrap())).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        zip.by_name(file)
+            .unwrap_or_else(|_| panic!("{} is missing", file))
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    // One file per article, which the index links to
+    let articles = block_on(client.rocket().state::<Db>().unwrap().list_articles()).unwrap();
+    let index = read("index.html");
+    let document = scraper::Html::parse_document(&index);
+    let links = Selector::parse("li a").unwrap();
+    let files = document
+        .select(&links)
+        .map(|link| link.value().attr("href").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), articles.len());
+    for file in &files {
+        read(file);
+    }
+    assert!(files.contains(&"Exported_Start.html".to_string()));
+    assert!(index.contains("style.css"));
+    read("style.css");
+
+    // Links between articles point to their files
+    let start = read("Exported_Start.html");
+    assert!(start.contains("href=\"Exported_Other.html\""));
+    assert!(start.contains("href=\"/Exported%20Missing\""));
+    let other = read("Exported_Other.html");
+    assert!(other.contains("<h1 id=\"details\">Details</h1>"));
+    assert!(other.contains("href=\"Exported_Start.html#top\""));
+
+    logout(&client);
+    register_and_login(&client, "curious exporter");
+    let response = client.get("/settings/export/html").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn tag_maintenance() {
+    use crate::settings::{MergeTagsRequest, RenameTagRequest, TagArticlesRequest};
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for name in &["TaggedOne", "TaggedTwo", "TaggedThree"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: "Tag me".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let tag = |tag: &str, articles: &[&str]| {
+        let response = post_form(
+            &client,
+            "/settings/tags/add",
+            TagArticlesRequest {
+                tag: tag.into(),
+                articles: articles.join("\n"),
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+        response.into_string().unwrap()
+    };
+    tag("Recipes", &["TaggedOne", "TaggedTwo"]);
+    let body = tag("cooking", &["TaggedTwo", "TaggedThree", "NotAnArticle"]);
+    assert!(body.contains("NotAnArticle"));
+
+    let db = client.rocket().state::<Db>().unwrap();
+    let tagged = |tag: &str| block_on(crate::db::tags::articles(db, tag)).unwrap();
+    assert_eq!(tagged("recipes"), ["TaggedOne", "TaggedTwo"]);
+
+    // Renaming keeps the articles
+    let response = post_form(
+        &client,
+        "/settings/tags/rename",
+        RenameTagRequest {
+            old: "recipes".into(),
+            new: "food".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert!(tagged("recipes").is_empty());
+    assert_eq!(tagged("food"), ["TaggedOne", "TaggedTwo"]);
+    assert!(search_titles(&client, "recipes").is_empty());
+    let mut found = search_titles(&client, "food");
+    found.sort();
+    assert_eq!(found, ["TaggedOne", "TaggedTwo"]);
+    // Renaming onto a tag some of the articles already have doesn't work
+    let response = post_form(
+        &client,
+        "/settings/tags/rename",
+        RenameTagRequest {
+            old: "food".into(),
+            new: "cooking".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::Conflict);
+
+    // But merging does, and TaggedTwo only has the tag once
+    let response = post_form(
+        &client,
+        "/settings/tags/merge",
+        MergeTagsRequest {
+            from: "food".into(),
+            into: "cooking".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert!(tagged("food").is_empty());
+    assert_eq!(tagged("cooking"), ["TaggedOne", "TaggedThree", "TaggedTwo"]);
+    assert!(search_titles(&client, "food").is_empty());
+    assert_eq!(search_titles(&client, "cooking").len(), 3);
+    assert_eq!(
+        block_on(crate::db::tags::for_article(db, "TaggedTwo")).unwrap(),
+        ["cooking"]
+    );
+    let body = client.get("/TaggedTwo").dispatch().into_string().unwrap();
+    assert!(body.contains("<span class=\"tag\">cooking</span>"));
+}
+
+#[test]
+#[serial]
+fn edit_requires_verified_email() {
+    let client = client_with(config().merge(("edit_requires_verified_email", true)));
+    // Something to undo later on
+    register_and_login(&client, "undo setup");
+    for content in &["Before", "After"] {
+        let response = post_form(
+            &client,
+            "/VerifiedUndo/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    logout(&client);
+    let (captcha_id, captcha_solution) = register_challenge(&client);
+    let response = post_form(
+        &client,
+        "/u/register",
+        RegisterRequest {
+            username: "unverified".into(),
+            email: Some("unverified@example.com".into()),
+            password: PASSWORD.into(),
+            pwd_confirm: PASSWORD.into(),
+            captcha_id,
+            captcha_solution,
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    login(&client, "unverified", PASSWORD);
+    let edit = || {
+        post_form(
+            &client,
+            "/VerifiedOnly/edit",
+            AddRevRequest {
+                title: None,
+                content: "Am I allowed?".into(),
+                ..Default::default()
+            },
+        )
+    };
+    let response = edit();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"verify-email-required\""));
+    let db = client.rocket().state::<Db>().unwrap();
+    assert_eq!(
+        block_on(db.article_id_by_name("VerifiedOnly")).unwrap(),
+        None
+    );
+    // Undoing is editing too
+    let response = client.post("/VerifiedUndo/rev/2/undo").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert!(article_content(&client, "VerifiedUndo").contains("After"));
+
+    // Follow the link from the (logged) mail
+    let token: Uuid = block_on(
+        sqlx::query_scalar!(
+            r#"SELECT v.token FROM email_verification v
+            INNER JOIN "user" u ON u.id = v.user_id
+            WHERE u.name = $1"#,
+            "unverified"
+        )
+        .fetch_one(&db.pool),
+    )
+    .unwrap();
+    let response = client.get(format!("/u/verify/{}", token)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    // Links only work once
+    let response = client.get(format!("/u/verify/{}", token)).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    assert_eq!(edit().status(), Status::Ok);
+
+    // There's nothing to verify without an address, so those can still edit
+    logout(&client);
+    register_and_login(&client, "no address");
+    assert_eq!(edit().status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn api_broken_link_warnings() {
+    use crate::api::SaveRequest;
+
+    let client = client();
+    register_and_login(&client, "link checker");
+    let save = |name: &str, content: &str, check_links: bool| {
+        let response = client
+            .put(format!(
+                "/api/articles/{}?check_links={}",
+                name, check_links
+            ))
+            .header(ContentType::JSON)
+            .body(
+                serde_json::to_string(&SaveRequest {
+                    content: content.into(),
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+    };
+    save("LinkTarget", "I exist", false);
+    let content = "See [LinkTarget], [the missing one](/MissingLinkTarget#top), \
+        [its history](/LinkTarget/revs) and [elsewhere](https://example.com).";
+    let response = save("LinkSource", content, true);
+    assert_eq!(
+        response["warnings"],
+        serde_json::json!([{"kind": "broken_link", "target": "MissingLinkTarget"}])
+    );
+    // The article was saved anyway
+    let body = client.get("/LinkSource").dispatch().into_string().unwrap();
+    assert!(body.contains("the missing one"));
+    // Without asking, there are no warnings
+    let response = save("LinkSource", "[MissingLinkTarget]", false);
+    assert_eq!(response["rev_id"], 2);
+    assert_eq!(response["warnings"], serde_json::json!([]));
+}
+
+#[test]
+fn article_of_the_day_pick() {
+    use chrono::NaiveDate;
+
+    use crate::articles::article_of_the_day;
+
+    let candidates = (0..10).map(|i| format!("Daily{}", i)).collect::<Vec<_>>();
+    let day = NaiveDate::from_ymd(2021, 7, 1);
+    let pick = article_of_the_day(&candidates, day).unwrap();
+    assert_eq!(article_of_the_day(&candidates, day), Some(pick));
+    // The order of the candidates doesn't matter
+    let mut reversed = candidates.clone();
+    reversed.reverse();
+    assert_eq!(article_of_the_day(&reversed, day), Some(pick));
+    // Other days pick other articles, at least some of the time
+    let picks = (1..=30)
+        .filter_map(|d| article_of_the_day(&candidates, NaiveDate::from_ymd(2021, 7, d)))
+        .collect::<std::collections::HashSet<_>>();
+    assert!(picks.len() > 1);
+    assert_eq!(article_of_the_day(&[], day), None);
+}
+
+#[test]
+#[serial]
+fn article_of_the_day_landing() {
+    let landing = || config().merge(("homepage", "landing"));
+    let client = client_with(landing().merge(("article_of_the_day", true)));
+    register_and_login(&client, "daily editor");
+    let response = post_form(
+        &client,
+        "/DailyArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Read me today".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = client.get("/").dispatch().into_string().unwrap();
+    assert!(body.contains("id=\"article-of-the-day\""));
+
+    // Without candidates, it's the main page again
+    let client = client_with(
+        landing()
+            .merge(("article_of_the_day", true))
+            .merge(("article_of_the_day_exclude", vec!["*"])),
+    );
+    let body = client.get("/").dispatch().into_string().unwrap();
+    assert!(!body.contains("id=\"article-of-the-day\""));
+}
+
+#[test]
+#[serial]
+fn landing_page() {
+    // Redirecting stays the default
+    let client = client();
+    let response = client.get("/").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    drop(response);
+    drop(client);
+
+    let client = client_with(config().merge(("homepage", "landing")));
+    register_and_login(&client, "landing editor");
+    for (name, content) in &[
+        ("Main", "Welcome to the main page"),
+        ("LandingNews", "News"),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let response = client.get("/").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let document = scraper::Html::parse_document(&response.into_string().unwrap());
+    assert!(document
+        .select(&Selector::parse("#landing-search input[name='q']").unwrap())
+        .next()
+        .is_some());
+    let featured = document
+        .select(&Selector::parse("#featured-article").unwrap())
+        .next()
+        .unwrap()
+        .inner_html();
+    assert!(featured.contains("Welcome to the main page"));
+    let changes = document
+        .select(&Selector::parse("#recent-changes a").unwrap())
+        .map(|link| link.inner_html())
+        .collect::<Vec<_>>();
+    assert_eq!(changes[0], "LandingNews");
+}
+
+#[test]
+#[serial]
+fn featured_article() {
+    use crate::settings::FeaturedArticleRequest;
+
+    let client = client_with(config().merge(("homepage", "landing")));
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let response = post_form(
+        &client,
+        "/FeaturedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Look at me".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let feature = |name: &str| {
+        post_form(
+            &client,
+            "/settings/admin/featured",
+            FeaturedArticleRequest { name: name.into() },
+        )
+        .status()
+    };
+    assert_eq!(feature("NoSuchFeaturedArticle"), Status::NotFound);
+    assert_eq!(feature("FeaturedArticle"), Status::Ok);
+
+    let featured = || {
+        let body = client.get("/").dispatch().into_string().unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let titles = document
+            .select(&Selector::parse("#featured-article h2 a").unwrap())
+            .map(|link| link.inner_html())
+            .collect::<Vec<_>>();
+        titles.into_iter().next()
+    };
+    assert_eq!(featured().as_deref(), Some("FeaturedArticle"));
+    let body = client.get("/settings").dispatch().into_string().unwrap();
+    assert!(body.contains("value=\"FeaturedArticle\""));
+    // Unpinning goes back to the main page
+    assert_eq!(feature(""), Status::Ok);
+    assert_ne!(featured().as_deref(), Some("FeaturedArticle"));
+}
+
+#[test]
+#[serial]
+fn search_namespaces() {
+    let client = client();
+    register_and_login(&client, "namespace_searcher");
+    for name in &["Cassoulet", "Help:Cassoulet", "Recipe:Cassoulet"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: "Beans, sausages and Cassoulet".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let mut titles = search_titles(&client, "Cassoulet");
+    titles.sort();
+    assert_eq!(
+        titles,
+        vec!["Cassoulet", "Help:Cassoulet", "Recipe:Cassoulet"]
+    );
+    assert_eq!(
+        search_titles(&client, "Cassoulet&ns=Help"),
+        vec!["Help:Cassoulet"]
+    );
+    assert_eq!(
+        search_titles(&client, "Cassoulet&ns=%28main%29"),
+        vec!["Cassoulet"]
+    );
+    // An empty selection means all namespaces
+    assert_eq!(search_titles(&client, "Cassoulet&ns=").len(), 3);
+    let body = client
+        .get("/search?q=Cassoulet")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("<option value=\"Recipe\">"));
+}
+
+#[test]
+#[serial]
+fn password_pepper() {
+    const PEPPER: &str = "correct horse battery staple";
+    // An account from before the pepper was configured
+    register_account(&client(), "unpeppered user", PASSWORD);
+
+    let client = client_with(config().merge(("password_pepper", PEPPER)));
+    register_account(&client, "peppered user", PASSWORD);
+    let db = client.rocket().state::<Db>().unwrap();
+    let wrong_password = |name: &str, pepper: Option<&str>| {
+        matches!(
+            block_on(db.try_login(name, PASSWORD.into(), pepper, 0)),
+            Err(crate::Error::WrongPassword)
+        )
+    };
+    assert!(block_on(db.try_login("peppered user", PASSWORD.into(), Some(PEPPER), 0)).is_ok());
+    assert!(wrong_password("peppered user", None));
+    assert!(wrong_password("peppered user", Some("wrong pepper")));
+    // The old hash still verifies, and is replaced by a peppered one
+    assert!(block_on(db.try_login("unpeppered user", PASSWORD.into(), Some(PEPPER), 0)).is_ok());
+    assert!(wrong_password("unpeppered user", None));
+    assert!(block_on(db.try_login("unpeppered user", PASSWORD.into(), Some(PEPPER), 0)).is_ok());
+}
+
+#[test]
+#[serial]
+fn draft_diff() {
+    let client = client();
+    register_and_login(&client, "draft differ");
+    let response = post_form(
+        &client,
+        "/DraftDiffArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Unchanged line\nCurrent line".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/DraftDiffArticle/draft/diff").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = post_form(
+        &client,
+        "/DraftDiffArticle/draft",
+        DraftRequest {
+            content: "Unchanged line\nDraft line".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::NoContent);
+    let response = client.get("/DraftDiffArticle/draft/diff").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let lines = |operation: &str| {
+        document
+            .select(&Selector::parse(&format!(".line.{}", operation)).unwrap())
+            .map(|line| line.text().collect::<String>())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lines("delete"), ["- Current line"]);
+    assert_eq!(lines("insert"), ["+ Draft line"]);
+    // Drafts belong to logged in users
+    logout(&client);
+    let response = client.get("/DraftDiffArticle/draft/diff").dispatch();
+    assert_ne!(response.status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn search_rate_limit() {
+    let client = client_with(
+        config()
+            .merge(("search_rate_limit", 3))
+            .merge(("search_rate_window", 90)),
+    );
+    let remote: std::net::SocketAddr = "203.0.113.7:4242".parse().unwrap();
+    let search = || {
+        client
+            .get("/search?q=Baguette")
+            .remote(remote)
+            .dispatch()
+            .status()
+    };
+    for _ in 0..3 {
+        assert_eq!(search(), Status::Ok);
+    }
+    let response = client.get("/search?q=Baguette").remote(remote).dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.into_string().unwrap().contains("up to 90 seconds"));
+    // Other addresses aren't affected
+    let response = client
+        .get("/search?q=Baguette")
+        .remote("203.0.113.8:4242".parse().unwrap())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    // Logged in users are exempt by default
+    register_and_login(&client, "rate limited searcher");
+    assert_eq!(search(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn api_article_info() {
+    let client = client();
+    register_and_login(&client, "info author");
+    for content in &["First", "Second version", "Third and last version"] {
+        let response = post_form(
+            &client,
+            "/InfoArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let response = client.get("/api/articles/InfoArticle/info").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let info: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(info["revision_count"], 3);
+    assert_eq!(info["authors"], serde_json::json!(["info author"]));
+    assert_eq!(info["word_count"], 4);
+    assert_eq!(info["byte_size"], "Third and last version".len());
+    let response = client
+        .get("/api/articles/UnknownInfoArticle/info")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn search_result_cache() {
+    let client = client_with(config().merge(("search_cache_ttl", 60)));
+    register_and_login(&client, "cached searcher");
+    let edit = |content: &str| {
+        let response = post_form(
+            &client,
+            "/CachedSearchArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    let hits = || {
+        client
+            .rocket()
+            .state::<Cache>()
+            .unwrap()
+            .search_result_hits()
+    };
+    edit("Pretzel");
+    let first = search_titles(&client, "Pretzel");
+    assert_eq!(first, vec!["CachedSearchArticle"]);
+    assert_eq!(hits(), 0);
+    // Extra whitespace doesn't make for a different query
+    assert_eq!(search_titles(&client, "%20Pretzel%20"), first);
+    assert_eq!(hits(), 1);
+    // Edits aren't hidden by the cache
+    edit("No more pastries");
+    assert!(search_titles(&client, "Pretzel").is_empty());
+    assert_eq!(hits(), 1);
+}
+
+#[test]
+#[serial]
+fn announcement() {
+    use crate::settings::AnnouncementRequest;
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let announce = |text: &str| {
+        let response = post_form(
+            &client,
+            "/settings/admin/announcement",
+            AnnouncementRequest { text: text.into() },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    let banner = |uri: &str| {
+        let body = client
+            .get(uri.to_string())
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let banners = document
+            .select(&Selector::parse("#announcement").unwrap())
+            .map(|banner| banner.text().collect::<String>().trim().to_string())
+            .collect::<Vec<_>>();
+        banners.into_iter().next()
+    };
+    announce("Maintenance tonight");
+    for uri in &["/settings", "/create", "/search?q=Maintenance", "/u/logout"] {
+        assert_eq!(banner(uri).as_deref(), Some("Maintenance tonight"));
+    }
+    login(&client, &admin, PASSWORD);
+    announce("  ");
+    assert_eq!(banner("/settings"), None);
+    assert_eq!(banner("/create"), None);
+}
+
+#[test]
+#[serial]
+fn changes_since_last_visit() {
+    let client = client_with(config().merge(("homepage", "landing")));
+    register_and_login(&client, "returning visitor");
+    let new_changes = || {
+        let body = client.get("/").dispatch().into_string().unwrap();
+        let document = scraper::Html::parse_document(&body);
+        document
+            .select(&Selector::parse("#recent-changes li").unwrap())
+            .filter(|change| {
+                change
+                    .select(&Selector::parse(".tag").unwrap())
+                    .next()
+                    .is_some()
+            })
+            .map(|change| {
+                change
+                    .select(&Selector::parse("a").unwrap())
+                    .next()
+                    .unwrap()
+                    .inner_html()
+            })
+            .collect::<Vec<_>>()
+    };
+    // Nothing is new on the first visit
+    assert!(new_changes().is_empty());
+    let response = post_form(
+        &client,
+        "/ChangedSinceVisit/edit",
+        AddRevRequest {
+            title: None,
+            content: "Something new".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(new_changes(), vec!["ChangedSinceVisit"]);
+    // Having seen it, it's not new anymore
+    assert!(new_changes().is_empty());
+}
+
+#[test]
+#[serial]
+fn security_headers() {
+    let client = client();
+    let response = client.get("/Main").dispatch();
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+    assert_eq!(response.headers().get_one("X-Frame-Options"), Some("DENY"));
+    // Not only pages get them
+    let response = client.get("/api/articles/Main/info").dispatch();
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+    assert_eq!(response.headers().get_one("X-Frame-Options"), Some("DENY"));
+    let response = client.get("/res/css/index.css").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+
+    let client = client_with(
+        config()
+            .merge(("security_headers.frame_options", ""))
+            .merge(("security_headers.referrer_policy", "no-referrer")),
+    );
+    let response = client.get("/Main").dispatch();
+    assert_eq!(response.headers().get_one("X-Frame-Options"), None);
+    assert_eq!(
+        response.headers().get_one("Referrer-Policy"),
+        Some("no-referrer")
+    );
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+}
+
+#[test]
+#[serial]
+fn max_revisions() {
+    let client = client_with(config().merge(("max_revisions", 3)));
+    register_and_login(&client, "prolific pruner");
+    for content in &["First", "Second", "Third", "Fourth"] {
+        let response = post_form(
+            &client,
+            "/PrunedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let db = client.rocket().state::<Db>().unwrap();
+    let nums = block_on(crate::db::articles::list_revisions(
+        db,
+        "PrunedArticle",
+        None,
+        RevisionOrder::Asc,
+    ))
+    .unwrap()
+    .into_iter()
+    .map(|rev| rev.num)
+    .collect::<Vec<_>>();
+    // The first revision is kept for provenance
+    assert_eq!(nums, vec![1, 3, 4]);
+    // The pruned edit still counts for its author
+    let user_id = block_on(db.user_id_by_name("prolific pruner"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        block_on(crate::db::articles::edit_count(db, user_id)).unwrap(),
+        4
+    );
+    let current = block_on(db.get_current_rev("PrunedArticle"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(current.content, "Fourth");
+
+    // Undoing goes back to the revision before the gap, which conflicts
+    // with the current content here
+    let response = client.post("/PrunedArticle/rev/3/undo").dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+    let undo_buttons = |rev_id: i64| {
+        let body = client
+            .get(format!("/PrunedArticle/rev/{}", rev_id))
+            .dispatch()
+            .into_string()
+            .unwrap();
+        scraper::Html::parse_document(&body)
+            .select(&Selector::parse("#undo-form").unwrap())
+            .count()
+    };
+    assert_eq!(undo_buttons(3), 1);
+    assert_eq!(undo_buttons(1), 0);
+}
+
+#[test]
+#[serial]
+fn merge_articles() {
+    use crate::settings::MergeArticlesRequest;
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for (name, content) in &[
+        ("MergeSource", "Duplicated knowledge"),
+        ("MergeTarget", "Original knowledge"),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let merge = |source: &str, target: &str| {
+        post_form(
+            &client,
+            "/settings/merge",
+            MergeArticlesRequest {
+                source: source.into(),
+                target: target.into(),
+            },
+        )
+        .status()
+    };
+    assert_eq!(merge("MergeSource", "MergeSource"), Status::BadRequest);
+    assert_eq!(merge("MergeSource", "NoSuchMergeTarget"), Status::NotFound);
+    assert_eq!(merge("MergeSource", "MergeTarget"), Status::Ok);
+
+    let response = client.get("/MergeSource").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/MergeTarget"));
+    let db = client.rocket().state::<Db>().unwrap();
+    let target = block_on(db.get_current_rev("MergeTarget"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(target.content, "Original knowledge\n\nDuplicated knowledge");
+    // The source's history is still there
+    let source = block_on(crate::db::articles::get_revision(db, "MergeSource", 1))
+        .unwrap()
+        .unwrap();
+    assert_eq!(source.content, "Duplicated knowledge");
+    assert_eq!(search_titles(&client, "Duplicated"), vec!["MergeTarget"]);
+}
+
+#[test]
+#[serial]
+fn external_links() {
+    let client = client();
+    register_and_login(&client, "link writer");
+    let response = post_form(
+        &client,
+        "/ExternalLinkArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "[Out](https://example.com/?a=1&b=2 \"Example\"), \
+                [protocol relative](//example.org) and [in](/Main), [Main]"
+                .into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let links = |client: &Client| {
+        let body = client
+            .get("/ExternalLinkArticle")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        document
+            .select(&Selector::parse("section.content a").unwrap())
+            .map(|link| {
+                let attr = |name| link.value().attr(name).map(String::from);
+                (attr("href").unwrap(), attr("rel"), attr("target"))
+            })
+            .collect::<Vec<_>>()
+    };
+    let rel = Some("nofollow noopener".to_string());
+    assert_eq!(
+        links(&client),
+        vec![
+            ("https://example.com/?a=1&b=2".into(), rel.clone(), None),
+            ("//example.org".into(), rel, None),
+            ("/Main".into(), None, None),
+            ("/Main".into(), None, None),
+        ]
+    );
+
+    let client = client_with(
+        config()
+            .merge(("external_link_rel", ""))
+            .merge(("external_links_new_tab", true)),
+    );
+    assert_eq!(
+        links(&client)[0],
+        (
+            "https://example.com/?a=1&b=2".into(),
+            None,
+            Some("_blank".into())
+        )
+    );
+}
+
+#[test]
+#[serial]
+fn create_from_template() {
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let template = "# Person\n\nBorn:\n\nKnown for:";
+    let response = post_form(
+        &client,
+        "/Template:Person/edit",
+        AddRevRequest {
+            title: None,
+            content: template.into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = post_form(
+        &client,
+        "/Template:Person/edit",
+        AddRevRequest {
+            title: None,
+            content: format!("{}\n\nWebsite:", template),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    logout(&client);
+
+    register_and_login(&client, "template user");
+    // Only admins can define templates, not even by undoing changes to them
+    let response = client.post("/Template:Person/rev/2/undo").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = post_form(
+        &client,
+        "/Template:Other/edit",
+        AddRevRequest {
+            title: None,
+            content: "Not a template".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // The editor for a new article offers the templates
+    let body = client
+        .get("/TemplatedArticle/edit")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let templates = document
+        .select(&Selector::parse("#article-templates a").unwrap())
+        .map(|link| link.inner_html())
+        .collect::<Vec<_>>();
+    assert!(templates.contains(&"Person".to_string()));
+    assert!(!templates.contains(&"Other".to_string()));
+
+    let body = client
+        .get("/TemplatedArticle/edit?template=Person")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let content = document
+        .select(&Selector::parse("textarea#content").unwrap())
+        .map(|textarea| textarea.text().collect::<String>())
+        .collect::<Vec<_>>();
+    assert_eq!(content, vec![format!("{}\n\nWebsite:", template)]);
+
+    let response = client
+        .get("/TemplatedArticle/edit?template=Nope")
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+/// Returns the rendered content of the given article.
+fn article_content(client: &Client, article_name: &str) -> String {
+    let body = client
+        .get(format!("/{}", article_name))
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    document
+        .select(&Selector::parse("section.content").unwrap())
+        .map(|content| content.inner_html())
+        .collect()
+}
+
+#[test]
+#[serial]
+fn transclusion() {
+    let client = client();
+    register_and_login(&client, "transcluder");
+    for (name, content) in [
+        ("TranscludedInfobox", "**Sharedinfobox** text"),
+        (
+            "TranscludingArticle",
+            "Before\n\n{{TranscludedInfobox}}\n\nAfter",
+        ),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let content = article_content(&client, "TranscludingArticle");
+    assert!(
+        content.contains("<p>Before</p>\n<p><strong>Sharedinfobox</strong> text</p>\n<p>After</p>")
+    );
+    assert!(!content.contains("{{"));
+    // The search index keeps the marker instead of the transcluded content
+    assert_eq!(
+        search_titles(&client, "Sharedinfobox"),
+        vec!["TranscludedInfobox".to_string()]
+    );
+}
+
+#[test]
+#[serial]
+fn transclusion_missing() {
+    let client = client();
+    register_and_login(&client, "missing transcluder");
+    let response = post_form(
+        &client,
+        "/TranscludingNothing/edit",
+        AddRevRequest {
+            title: None,
+            content: "See {{DoesNotExistAnywhere}}".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let content = article_content(&client, "TranscludingNothing");
+    let document = scraper::Html::parse_fragment(&content);
+    let missing = document
+        .select(&Selector::parse("a.missing-link").unwrap())
+        .map(|link| link.value().attr("href").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(missing, vec!["/DoesNotExistAnywhere"]);
+}
+
+#[test]
+#[serial]
+fn transclusion_cycle() {
+    let client = client();
+    register_and_login(&client, "cyclic transcluder");
+    let response = post_form(
+        &client,
+        "/TranscludingItself/edit",
+        AddRevRequest {
+            title: None,
+            content: "Me: {{TranscludingItself}}".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let content = article_content(&client, "TranscludingItself");
+    let document = scraper::Html::parse_fragment(&content);
+    let skipped = document
+        .select(&Selector::parse("a.transclusion-skipped").unwrap())
+        .map(|link| link.value().attr("href").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(skipped, vec!["/TranscludingItself"]);
+    assert!(content.contains("Me: "));
+}
+
+#[test]
+#[serial]
+fn transclusion_without_html() {
+    let client = client_with(config().merge(("allow_html", false)));
+    register_and_login(&client, "htmlless transcluder");
+    let response = post_form(
+        &client,
+        "/TranscludingWithoutHtml/edit",
+        AddRevRequest {
+            title: None,
+            content: "See {{NotThereWithoutHtml}} and {{TranscludingWithoutHtml}}".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let content = article_content(&client, "TranscludingWithoutHtml");
+    assert!(!content.contains("&lt;a"));
+    let document = scraper::Html::parse_fragment(&content);
+    let links = |selector| {
+        document
+            .select(&Selector::parse(selector).unwrap())
+            .map(|link| link.value().attr("href").unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(links("a.missing-link"), vec!["/NotThereWithoutHtml"]);
+    assert_eq!(
+        links("a.transclusion-skipped"),
+        vec!["/TranscludingWithoutHtml"]
+    );
+}
+
+#[test]
+#[serial]
+fn search_default_sort() {
+    let client = client();
+    register_and_login(&client, "sorted searcher");
+    // The older article matches better, the newer one only in passing
+    for (name, content) in [
+        ("Zorbleword", "Zorbleword zorbleword zorbleword"),
+        (
+            "SortNewerArticle",
+            "A long text which mentions a zorbleword only once, among lots of other words",
+        ),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+        // The index only keeps the dates to the second
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+    let by_relevance = vec!["Zorbleword".to_string(), "SortNewerArticle".to_string()];
+    let by_date = vec!["SortNewerArticle".to_string(), "Zorbleword".to_string()];
+    assert_eq!(search_titles(&client, "zorbleword"), by_relevance);
+    assert_eq!(
+        search_titles_with(&client, "zorbleword", &[("sort", "date")]),
+        by_date
+    );
+
+    let client = client_with(config().merge(("search_default_sort", "date")));
+    assert_eq!(search_titles(&client, "zorbleword"), by_date);
+    assert_eq!(
+        search_titles_with(&client, "zorbleword", &[("sort", "relevance")]),
+        by_relevance
+    );
+
+    // Grouping shows every matching part of an article
+    let client = client_with(config().merge(("search_snippets", "grouped")));
+    let body = client
+        .get("/search?q=zorbleword")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("<b>Zorbleword</b> <b>zorbleword</b> <b>zorbleword</b>"));
+}
+
+#[test]
+#[serial]
+fn search_highlight_tag() {
+    let client = client_with(config().merge(("search_highlight_tag", "mark")));
+    register_and_login(&client, "highlighting editor");
+    let response = post_form(
+        &client,
+        "/HighlightArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Some <blinkenword> next to a quuxhighlight".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = client
+        .get("/search?q=quuxhighlight")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("<mark>quuxhighlight</mark>"));
+    assert!(!body.contains("<b>quuxhighlight</b>"));
+    // The rest of the snippet is still escaped
+    assert!(!body.contains("<blinkenword>"));
+
+    // Anything but a plain element name is refused at startup
+    assert_ignite_fails(config().merge(("search_highlight_tag", "b onclick=x")));
+}
+
+#[test]
+#[serial]
+fn edit_rolls_back_on_error() {
+    let client = client();
+    register_and_login(&client, "rolled back editor");
+    let response = post_form(
+        &client,
+        "/TxnArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Fine content".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    // The rename goes through, but postgres refuses to store the nul byte
+    let response = post_form(
+        &client,
+        "/TxnArticle/edit",
+        AddRevRequest {
+            title: Some("TxnArticleRenamed".into()),
+            content: "Broken \0 content".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::InternalServerError);
+    // So the rename has to be rolled back
+    let response = client.get("/TxnArticleRenamed").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let db = client.rocket().state::<Db>().unwrap();
+    let rev = block_on(db.get_current_rev("TxnArticle")).unwrap().unwrap();
+    assert_eq!(rev.rev_id, 1);
+    assert_eq!(rev.content, "Fine content");
+}
+
+#[test]
+#[serial]
+fn login_returns_to_edit_page() {
+    let client = client();
+    register_account(&client, "bounced editor", PASSWORD);
+    let response = client.get("/BouncedArticle/edit").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("Location").unwrap().to_string();
+    let next = location.strip_prefix("/u/login?next=").unwrap();
+    assert_eq!(
+        rocket::http::RawStr::new(next).percent_decode_lossy(),
+        "/BouncedArticle/edit"
+    );
+    // The login form keeps the target
+    let body = client.get(location).dispatch().into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let action = document
+        .select(&Selector::parse("form[method='POST']").unwrap())
+        .filter_map(|form| form.value().attr("action").map(String::from))
+        .collect::<Vec<_>>();
+    let action = &action[0];
+    let response = post_form(
+        &client,
+        action,
+        LoginRequest {
+            username: "bounced editor".into(),
+            password: PASSWORD.into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("/BouncedArticle/edit")
+    );
+    logout(&client);
+
+    // Other sites aren't allowed as a target
+    for next in ["//example.com", "https://example.com", "%2F%5Cexample.com"] {
+        let response = post_form(
+            &client,
+            format!("/u/login?next={}", next),
+            LoginRequest {
+                username: "bounced editor".into(),
+                password: PASSWORD.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+        logout(&client);
+    }
+}
+
+#[test]
+#[serial]
+fn download_revision() {
+    let client = client();
+    register_and_login(&client, "downloader");
+    for content in ["First *version*", "Second version\nwith two lines"] {
+        let response = post_form(
+            &client,
+            "/Download%20Me/edit",
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let response = client.get("/Download%20Me/rev/1/download").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("attachment; filename=\"Download_Me-rev1.md\"")
+    );
+    assert!(response.headers().get_one("Last-Modified").is_some());
+    assert_eq!(response.into_string().unwrap(), "First *version*");
+    let response = client.get("/Download%20Me/rev/2/download").dispatch();
+    assert_eq!(
+        response.into_string().unwrap(),
+        "Second version\nwith two lines"
+    );
+
+    let response = client.get("/Download%20Me/rev/3/download").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client.get("/NotDownloadable/rev/1/download").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn identical_saves_ignored() {
+    let client = client();
+    register_and_login(&client, "idle editor");
+    let db = client.rocket().state::<Db>().unwrap();
+    for _ in 0..2 {
+        let response = post_form(
+            &client,
+            "/UnchangedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: "Nothing new".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    // The second save didn't do anything
+    let revisions = block_on(crate::db::articles::list_revisions(
+        db,
+        "UnchangedArticle",
+        None,
+        RevisionOrder::Asc,
+    ))
+    .unwrap();
+    assert_eq!(revisions.len(), 1);
+    // The same goes for the API
+    let response = client
+        .put("/api/articles/UnchangedArticle")
+        .header(ContentType::JSON)
+        .body(r#"{"content": "Nothing new"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(response["rev_id"], 1);
+}
+
+#[test]
+#[serial]
+fn identical_saves_rejected() {
+    let client = client_with(config().merge(("identical_saves", "reject")));
+    register_and_login(&client, "rejected editor");
+    let db = client.rocket().state::<Db>().unwrap();
+    let save = |title: Option<&str>| {
+        post_form(
+            &client,
+            "/RejectedUnchanged/edit",
+            AddRevRequest {
+                title: title.map(String::from),
+                content: "Same old".into(),
+                ..Default::default()
+            },
+        )
+        .status()
+    };
+    assert_eq!(save(None), Status::Ok);
+    assert_eq!(save(None), Status::BadRequest);
+    assert_eq!(save(Some("RejectedUnchanged")), Status::BadRequest);
+    let response = client
+        .put("/api/articles/RejectedUnchanged")
+        .header(ContentType::JSON)
+        .body(r#"{"content": "Same old"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    // Renaming without changing the content still works
+    assert_eq!(save(Some("RenamedUnchanged")), Status::Ok);
+    let response = client.get("/RenamedUnchanged").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let revisions = block_on(crate::db::articles::list_revisions(
+        db,
+        "RenamedUnchanged",
+        None,
+        RevisionOrder::Asc,
+    ))
+    .unwrap();
+    assert_eq!(revisions.len(), 1);
+}
+
+#[test]
+#[serial]
+fn link_scheme_allowlist() {
+    let client = client();
+    register_and_login(&client, "scheme checker");
+    let response = post_form(
+        &client,
+        "/SchemeArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "[bad](javascript:alert(1)), [sneaky](JavaScript&#58;alert(1)), \
+                [good](https://example.com), [mail](mailto:someone@example.com), \
+                [internal](/Main) and ![image](data:image/png;base64,AAAA)"
+                .into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = client
+        .get("/SchemeArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let hrefs = document
+        .select(&Selector::parse("section.content a").unwrap())
+        .map(|link| link.value().attr("href").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        hrefs,
+        vec![
+            "#",
+            "#",
+            "https://example.com",
+            "mailto:someone@example.com",
+            "/Main"
+        ]
+    );
+    let sources = document
+        .select(&Selector::parse(".content img").unwrap())
+        .map(|image| image.value().attr("src").unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(sources, vec![""]);
+    assert!(!body.to_lowercase().contains("javascript:"));
+
+    // Other schemes can be allowed
+    let client = client_with(config().merge((
+        "allowed_link_schemes",
+        vec!["https", "mailto", "javascript"],
+    )));
+    let body = client
+        .get("/SchemeArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains(r#"href="javascript:alert(1)""#));
+}
+
+#[test]
+#[serial]
+fn html_toggle() {
+    let client = client();
+    register_and_login(&client, "html writer");
+    let response = post_form(
+        &client,
+        "/HtmlArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "<div class=\"raw\">Block</div>\n\nSome <span class=\"raw\">inline</span> \
+                HTML and [a link](https://example.com)"
+                .into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let raw_elements = |client: &Client| {
+        let body = client.get("/HtmlArticle").dispatch().into_string().unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let count = document
+            .select(&Selector::parse(".content .raw").unwrap())
+            .count();
+        (body, count)
+    };
+    let (_, count) = raw_elements(&client);
+    assert_eq!(count, 2);
+
+    let client = client_with(config().merge(("allow_html", false)));
+    let (body, count) = raw_elements(&client);
+    assert_eq!(count, 0);
+    assert!(body.contains("&lt;div class=&quot;raw&quot;&gt;"));
+    assert!(body.contains("&lt;span class=&quot;raw&quot;&gt;"));
+    // The wiki's own HTML still works
+    assert!(body.contains(r#"href="https://example.com""#));
+}
+
+#[test]
+#[serial]
+fn audit_log_filter() {
+    use crate::settings::{AnnouncementRequest, FeaturedArticleRequest};
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let response = post_form(
+        &client,
+        "/AuditedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Audit me".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = post_form(
+        &client,
+        "/settings/admin/featured",
+        FeaturedArticleRequest {
+            name: "AuditedArticle".into(),
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    for text in &["Audited announcement", ""] {
+        let response = post_form(
+            &client,
+            "/settings/admin/announcement",
+            AnnouncementRequest {
+                text: text.to_string(),
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let actions = |uri: &str| {
+        let body = client
+            .get(uri.to_string())
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let actions = document
+            .select(&Selector::parse("#audit-log td.audit-action").unwrap())
+            .map(|cell| cell.inner_html().trim().to_string())
+            .collect::<Vec<_>>();
+        actions
+    };
+    let featured = actions("/settings/audit?action=set_featured");
+    assert!(!featured.is_empty());
+    assert!(featured.iter().all(|action| action == "set_featured"));
+    let announcements = actions("/settings/audit?action=set_announcement");
+    assert!(announcements.len() >= 2);
+    assert!(announcements
+        .iter()
+        .all(|action| action == "set_announcement"));
+    // Without a filter, both show up
+    let all = actions("/settings/audit");
+    assert!(all.iter().any(|action| action == "set_featured"));
+    assert!(all.iter().any(|action| action == "set_announcement"));
+
+    let response = client.get("/settings/audit?from=yesterday").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+#[serial]
+fn welcome_message() {
+    use crate::settings::WelcomeRequest;
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let set_welcome = |text: &str, user_page| {
+        let response = post_form(
+            &client,
+            "/settings/admin/welcome",
+            WelcomeRequest {
+                text: text.into(),
+                user_page,
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    set_welcome("Welcome aboard, have a look around!", true);
+    logout(&client);
+
+    let (captcha_id, captcha_solution) = register_challenge(&client);
+    let response = post_form(
+        &client,
+        "/u/register",
+        RegisterRequest {
+            username: "WelcomedUser".into(),
+            password: PASSWORD.into(),
+            pwd_confirm: PASSWORD.into(),
+            captcha_id,
+            captcha_solution,
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let messages = document
+        .select(&Selector::parse("#welcome-message").unwrap())
+        .map(|message| message.inner_html())
+        .collect::<Vec<_>>();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("Welcome aboard, have a look around!"));
+    assert!(article_content(&client, "User:WelcomedUser").contains("Welcome aboard"));
+
+    // Without a message, there's nothing to show
+    login(&client, &admin, PASSWORD);
+    set_welcome("", false);
+    logout(&client);
+    let (captcha_id, captcha_solution) = register_challenge(&client);
+    let response = post_form(
+        &client,
+        "/u/register",
+        RegisterRequest {
+            username: "UnwelcomedUser".into(),
+            password: PASSWORD.into(),
+            pwd_confirm: PASSWORD.into(),
+            captcha_id,
+            captcha_solution,
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert!(!response.into_string().unwrap().contains("welcome-message"));
+}
+
+#[test]
+#[serial]
+fn collapsible_sections() {
+    let client = client();
+    register_and_login(&client, "details user");
+    let response = post_form(
+        &client,
+        "/DetailsArticle/edit",
+        AddRevRequest {
+            title: None,
+            content:
+                "Intro\n\n:::details Spoilers <here>\nThe *collapsiblewords* are hidden\n:::\n"
+                    .into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = client
+        .get("/DetailsArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let summaries = document
+        .select(&Selector::parse(".content details > summary").unwrap())
+        .map(|summary| summary.inner_html())
+        .collect::<Vec<_>>();
+    assert_eq!(summaries, vec!["Spoilers &lt;here&gt;"]);
+    let bodies = document
+        .select(&Selector::parse(".content details > p").unwrap())
+        .map(|paragraph| paragraph.inner_html())
+        .collect::<Vec<_>>();
+    assert_eq!(bodies, vec!["The <em>collapsiblewords</em> are hidden"]);
+    assert!(!body.contains(":::"));
+
+    // The search index only gets the text
+    assert!(search_titles(&client, "collapsiblewords").contains(&"DetailsArticle".to_string()));
+    let body = client
+        .get("/search?q=collapsiblewords")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(!body.contains(":::"));
+}
+
+#[test]
+#[serial]
+fn redirect_loops() {
+    let client = client();
+    register_and_login(&client, "redirect user");
+    let edit = |name: &str, content: &str| {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    edit("LoopA", "#REDIRECT [LoopB]");
+    edit("LoopB", "#REDIRECT [LoopA]");
+    edit("ChainA", "#REDIRECT [ChainB]");
+    edit("ChainB", "#REDIRECT [ChainC]");
+    edit("ChainC", "The end of the chain");
+
+    let chain = |client: &Client, uri: &str, status: Status| {
+        let response = client.get(uri.to_string()).dispatch();
+        assert_eq!(response.status(), status);
+        let body = response.into_string().unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let error = document
+            .select(&Selector::parse("#redirect-error").unwrap())
+            .map(|error| error.inner_html())
+            .collect::<String>();
+        let names = document
+            .select(&Selector::parse("#redirect-chain a").unwrap())
+            .map(|link| link.inner_html())
+            .collect::<Vec<_>>();
+        (error, names)
+    };
+    let (error, names) = chain(&client, "/LoopA", Status::LoopDetected);
+    assert_eq!(error, "Redirect loop detected.");
+    assert_eq!(names, vec!["LoopA", "LoopB", "LoopA"]);
+
+    // Chains are followed on the server, so there's only one redirect
+    let response = client.get("/ChainA").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/ChainC"));
+
+    let client = client_with(config().merge(("max_redirect_hops", 1)));
+    let (error, names) = chain(&client, "/ChainA", Status::LoopDetected);
+    assert_eq!(error, "Too many redirects.");
+    assert_eq!(names, vec!["ChainA", "ChainB", "ChainC"]);
+}
+
+#[test]
+#[serial]
+fn api_tag_counts() {
+    use crate::settings::TagArticlesRequest;
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for name in &["CloudOne", "CloudTwo", "CloudThree"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: "Tag cloud material".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let tag = |tag: &str, articles: &[&str]| {
+        let response = post_form(
+            &client,
+            "/settings/tags/add",
+            TagArticlesRequest {
+                tag: tag.into(),
+                articles: articles.join("\n"),
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    tag("cloud-all", &["CloudOne", "CloudTwo", "CloudThree"]);
+    tag("cloud-some", &["CloudOne", "CloudTwo"]);
+    tag("cloud-one", &["CloudOne"]);
+
+    let response = client.get("/api/tags").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let tags: Vec<serde_json::Value> =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    let counts = tags
+        .iter()
+        .map(|tag| tag["article_count"].as_i64().unwrap())
+        .collect::<Vec<_>>();
+    assert!(counts.windows(2).all(|pair| pair[0] >= pair[1]));
+    let cloud = tags
+        .iter()
+        .filter(|tag| tag["tag"].as_str().unwrap().starts_with("cloud-"))
+        .map(|tag| {
+            (
+                tag["tag"].as_str().unwrap(),
+                tag["article_count"].as_i64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        cloud,
+        vec![("cloud-all", 3), ("cloud-some", 2), ("cloud-one", 1)]
+    );
+}
+
+#[test]
+#[serial]
+fn degraded_search() {
+    // By default, a broken index keeps the wiki from starting
+    assert_fails_to_start(rocket_with_broken_search().configure(config()));
+
+    let client = Client::tracked(
+        rocket_with_broken_search().configure(config().merge(("search_index_failure", "degrade"))),
+    )
+    .expect("failed to create rocket client");
+    register_and_login(&client, "degraded user");
+    let response = post_form(
+        &client,
+        "/DegradedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Still readable without search".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert!(article_content(&client, "DegradedArticle").contains("Still readable"));
+
+    let response = client.get("/search?q=readable").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    assert_eq!(
+        document
+            .select(&Selector::parse("#search-unavailable").unwrap())
+            .count(),
+        1
+    );
+    assert_eq!(
+        document
+            .select(&Selector::parse(".box h2 a").unwrap())
+            .count(),
+        0
+    );
+}
+
+#[test]
+#[serial]
+fn max_sessions() {
+    use rocket::http::Cookie;
+
+    let client = client_with(config().merge(("max_sessions", 2)));
+    register_account(&client, "limited sessions", PASSWORD);
+    let db = client.rocket().state::<Db>().unwrap();
+    let mut sessions = Vec::new();
+    for _ in 0..2 {
+        let session = block_on(db.try_login("limited sessions", PASSWORD.into(), None, 2));
+        sessions.push(session.unwrap().session_id);
+    }
+    let status = |session: Uuid| {
+        let cookie = Cookie::new("session_id", base64::encode(session.as_bytes()));
+        client.get("/settings").cookie(cookie).dispatch().status()
+    };
+    assert_eq!(status(sessions[0]), Status::Ok);
+    assert_eq!(status(sessions[1]), Status::Ok);
+
+    // Logging in once more ends the oldest session
+    login(&client, "limited sessions", PASSWORD);
+    assert_eq!(client.get("/settings").dispatch().status(), Status::Ok);
+    logout(&client);
+    assert_eq!(status(sessions[0]), Status::SeeOther);
+    assert_eq!(status(sessions[1]), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn revision_content_hash() {
+    let client = client();
+    register_and_login(&client, "hash user");
+    for (name, content) in &[
+        ("HashTwinA", "Exactly the same"),
+        ("HashTwinB", "Exactly the same"),
+        ("HashOther", "Something else"),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let hash = |uri: &str| {
+        let body = client
+            .get(uri.to_string())
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        let hashes = document
+            .select(&Selector::parse("#content-hash").unwrap())
+            .map(|hash| hash.inner_html())
+            .collect::<Vec<_>>();
+        assert_eq!(hashes.len(), 1);
+        hashes.into_iter().next().unwrap()
+    };
+    let twin = hash("/HashTwinA");
+    assert_eq!(twin.len(), 10);
+    assert_eq!(hash("/HashTwinB"), twin);
+    assert_eq!(hash("/HashTwinB/rev/1"), twin);
+    assert_ne!(hash("/HashOther"), twin);
+
+    let response = client.get("/api/articles/HashTwinB/info").dispatch();
+    let info: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(info["content_hash"], twin.as_str());
+}
+
+#[test]
+#[serial]
+fn similar_usernames() {
+    let client = client_with(config().merge(("similar_username_distance", 0)));
+    let admin = admin_account(&client);
+    // Swap in look-alikes: a Cyrillic А, о and е, and digits
+    let impostor = admin
+        .chars()
+        .map(|c| match c {
+            'a' | 'A' => 'А',
+            'o' | 'O' => 'о',
+            'e' | 'E' => 'е',
+            'i' | 'l' => '1',
+            c => c,
+        })
+        .collect::<String>();
+    assert_ne!(impostor, admin);
+    // Accounts from before skeletons were stored get theirs on startup
+    let db = client.rocket().state::<Db>().unwrap();
+    block_on(sqlx::query!(r#"UPDATE "user" SET name_skeleton = NULL"#).execute(&db.pool)).unwrap();
+    block_on(crate::db::users::fill_name_skeletons(db)).unwrap();
+
+    let (captcha_id, captcha_solution) = register_challenge(&client);
+    let response = post_form(
+        &client,
+        "/u/register",
+        RegisterRequest {
+            username: impostor,
+            password: PASSWORD.into(),
+            pwd_confirm: PASSWORD.into(),
+            captcha_id,
+            captcha_solution,
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    let body = response.into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let errors = document
+        .select(&Selector::parse("#username-too-similar").unwrap())
+        .map(|error| error.inner_html())
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 1);
+    // Which one isn't told
+    assert!(!errors[0].contains(&admin));
+
+    // Names which don't look alike still work
+    register_account(&client, "Clearly Distinct Newcomer", PASSWORD);
+}
+
+#[test]
+#[serial]
+fn redact_revision() {
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for content in &["My secret address is 1 Hidden Lane", "Nothing to see here"] {
+        let response = post_form(
+            &client,
+            "/RedactedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let response = client.post("/RedactedArticle/rev/1/redact").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("/RedactedArticle/rev/1")
+    );
+    let body = client
+        .get("/RedactedArticle/rev/1")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(!body.contains("Hidden Lane"));
+    assert!(body.contains("removed by an administrator"));
+    // Later revisions are left alone, and the gap can't be undone
+    assert!(article_content(&client, "RedactedArticle").contains("Nothing to see here"));
+    let response = client.post("/RedactedArticle/rev/2/undo").dispatch();
+    assert_eq!(response.status(), Status::Gone);
+    let response = client.post("/RedactedArticle/rev/7/redact").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let body = client
+        .get("/settings/audit?action=redact_revision")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let targets = document
+        .select(&Selector::parse("#audit-log td.audit-target").unwrap())
+        .map(|cell| cell.inner_html())
+        .collect::<Vec<_>>();
+    assert!(targets.contains(&"RedactedArticle#1".to_string()));
+
+    // Only admins can redact
+    logout(&client);
+    register_and_login(&client, "would be redactor");
+    let response = client.post("/RedactedArticle/rev/2/redact").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn api_complete_article_name() {
+    let client = client();
+    register_and_login(&client, "completing editor");
+    for name in &["Completion", "CompletionTwo", "Complete_Word", "Incomplete"] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: "Something to link to".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let complete = |q: &str| {
+        let response = client
+            .get(format!("/api/articles/complete?q={}", q))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str::<Vec<String>>(&response.into_string().unwrap()).unwrap()
+    };
+    assert_eq!(complete("Completion"), vec!["Completion", "CompletionTwo"]);
+    let names = complete("Compl");
+    assert!(names.iter().all(|name| name.starts_with("Compl")));
+    assert!(names.contains(&"Complete_Word".to_string()));
+    assert!(!names.contains(&"Incomplete".to_string()));
+    // Wildcards are taken literally
+    assert_eq!(complete("Complete_"), vec!["Complete_Word"]);
+    assert!(complete("Compl%25").is_empty());
+    assert!(complete("").is_empty());
+}
+
+#[test]
+#[serial]
+fn api_link_graph() {
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    for (name, content) in &[
+        ("GraphFrom", "Links to [GraphTo] and [GraphMissing]"),
+        ("GraphTo", "Links back to [GraphFrom](/GraphFrom)"),
+        ("GraphRedirect", "#REDIRECT [GraphTo]"),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let graph = |query: &str| {
+        let response = client.get(format!("/api/linkgraph{}", query)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+    };
+    let has_edge = |graph: &serde_json::Value, from: &str, to: &str| {
+        graph["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|edge| edge["from"] == from && edge["to"] == to)
+    };
+    let node = |graph: &serde_json::Value, name: &str| {
+        graph["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|node| node["name"] == name)
+            .cloned()
+    };
+    let full = graph("");
+    assert!(has_edge(&full, "GraphFrom", "GraphTo"));
+    assert!(has_edge(&full, "GraphTo", "GraphFrom"));
+    assert!(has_edge(&full, "GraphRedirect", "GraphTo"));
+    // Only articles which exist are nodes
+    assert!(!has_edge(&full, "GraphFrom", "GraphMissing"));
+    assert_eq!(node(&full, "GraphMissing"), None);
+    let to = node(&full, "GraphTo").unwrap();
+    assert_eq!(to["links_in"], 2);
+    assert_eq!(to["links_out"], 1);
+    assert_eq!(node(&full, "GraphRedirect").unwrap()["is_redirect"], true);
+    assert_eq!(
+        full["edge_count"].as_u64().unwrap() as usize,
+        full["edges"].as_array().unwrap().len()
+    );
+
+    let without_redirects = graph("?exclude_redirects=true");
+    assert_eq!(node(&without_redirects, "GraphRedirect"), None);
+    assert!(!has_edge(&without_redirects, "GraphRedirect", "GraphTo"));
+    assert_eq!(node(&without_redirects, "GraphTo").unwrap()["links_in"], 1);
+
+    logout(&client);
+    register_and_login(&client, "graph viewer");
+    let response = client.get("/api/linkgraph").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[test]
+#[serial]
+fn api_bulk_search() {
+    use crate::api::BulkSearchRequest;
+
+    let client = client();
+    register_and_login(&client, "bulk searcher");
+    for (name, content) in &[
+        ("BulkApples", "Apples are crunchy"),
+        ("BulkPears", "Pears are juicy"),
+    ] {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    // Makes sure the edits are searchable
+    search_titles(&client, "crunchy");
+
+    let search = |queries: Vec<String>| {
+        client
+            .post("/api/search")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::to_string(&BulkSearchRequest {
+                    queries,
+                    limit: Some(5),
+                })
+                .unwrap(),
+            )
+            .dispatch()
+    };
+    let response = search(vec!["juicy".into(), "crunchy".into()]);
+    assert_eq!(response.status(), Status::Ok);
+    let body = serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap();
+    let sets = body.as_array().unwrap();
+    assert_eq!(sets.len(), 2);
+    assert_eq!(sets[0]["query"], "juicy");
+    assert_eq!(sets[0]["results"][0]["title"], "BulkPears");
+    assert_eq!(sets[1]["query"], "crunchy");
+    assert_eq!(sets[1]["results"][0]["title"], "BulkApples");
+
+    let response = search(vec!["juicy".into(); 21]);
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+#[serial]
+fn trailing_slash_redirect() {
+    let client = client();
+    let location = |uri: &str| {
+        let response = client.get(uri.to_string()).dispatch();
+        let location = response.headers().get_one("Location").map(Into::into);
+        (response.status(), location)
+    };
+    assert_eq!(
+        location("/Main/"),
+        (Status::MovedPermanently, Some("/Main".to_string()))
+    );
+    assert_eq!(
+        location("/Main/revs/?page=2"),
+        (
+            Status::MovedPermanently,
+            Some("/Main/revs?page=2".to_string())
+        )
+    );
+    // The front page stays where it is
+    assert_eq!(location("/").0, Status::SeeOther);
+    // Nothing outside the wiki is redirected to
+    assert_eq!(
+        location("//example.com/"),
+        (Status::MovedPermanently, Some("/".to_string()))
+    );
+    // Pages without a trailing slash are served as they are
+    assert_eq!(location("/u/login"), (Status::Ok, None));
+
+    let client = client_with(config().merge(("redirect_trailing_slashes", false)));
+    assert_ne!(
+        client.get("/Main/").dispatch().status(),
+        Status::MovedPermanently
+    );
+}
+
+#[test]
+#[serial]
+fn invite_only_registration() {
+    // The admin has to exist before registering needs an invite
+    let admin = admin_account(&client());
+    let client = client_with(config().merge(("invite_only", true)));
+    let register = |username: &str, invite: Option<&str>| {
+        let (captcha_id, captcha_solution) = register_challenge(&client);
+        let response = post_form(
+            &client,
+            "/u/register",
+            RegisterRequest {
+                username: username.into(),
+                password: PASSWORD.into(),
+                pwd_confirm: PASSWORD.into(),
+                captcha_id,
+                captcha_solution,
+                invite: invite.map(Into::into),
+                ..Default::default()
+            },
+        );
+        let status = response.status();
+        let invalid = response
+            .into_string()
+            .unwrap()
+            .contains("id=\"invalid-invite\"");
+        (status, invalid)
+    };
+    let db = client.rocket().state::<Db>().unwrap();
+
+    // Without a code (or with a made up one), nobody can register
+    assert_eq!(
+        register("uninvited guest", None),
+        (Status::BadRequest, true)
+    );
+    let made_up = Uuid::new_v4().to_string();
+    assert_eq!(
+        register("uninvited guest", Some(&made_up)),
+        (Status::BadRequest, true)
+    );
+    assert_eq!(
+        register("uninvited guest", Some("not a code")),
+        (Status::BadRequest, true)
+    );
+    assert!(!block_on(db.user_name_exists("uninvited guest")).unwrap());
+
+    login(&client, &admin, PASSWORD);
+    let response = client.post("/settings/invites").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    let body = client
+        .get("/settings/invites")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let code = document
+        .select(&Selector::parse("code.invite-code").unwrap())
+        .next()
+        .unwrap()
+        .text()
+        .collect::<String>();
+    logout(&client);
+
+    // A failed registration leaves the code usable
+    assert_eq!(register(&admin, Some(&code)), (Status::BadRequest, false));
+    assert_eq!(register("invited guest", Some(&code)), (Status::Ok, false));
+    assert!(block_on(db.user_name_exists("invited guest")).unwrap());
+    // Every code only works once
+    assert_eq!(
+        register("second guest", Some(&code)),
+        (Status::BadRequest, true)
+    );
+    assert!(!block_on(db.user_name_exists("second guest")).unwrap());
+
+    // External logins have no code to give, so they can't create accounts
+    let cfg = client.rocket().state::<crate::Config>().unwrap();
+    let claims = crate::oidc::IdTokenClaims {
+        sub: "uninvited-subject".into(),
+        nonce: None,
+        preferred_username: Some("uninvited oidc user".into()),
+    };
+    assert!(matches!(
+        block_on(crate::oidc::login_user(
+            cfg,
+            db,
+            &crate::client_ip::ClientIp(None),
+            &claims
+        )),
+        Err(crate::Error::OidcInviteRequired)
+    ));
+    assert!(!block_on(db.user_name_exists("uninvited oidc user")).unwrap());
+}
+
+#[test]
+#[serial]
+fn register_too_fast() {
+    use std::time::{Duration, SystemTime};
+
+    let client = client_with(config().merge(("min_register_seconds", 2)));
+    let timer = client.rocket().state::<crate::FormTimer>().unwrap();
+    let register = |username: &str, form_time: Option<String>| {
+        let (captcha_id, captcha_solution) = register_challenge(&client);
+        post_form(
+            &client,
+            "/u/register",
+            RegisterRequest {
+                username: username.into(),
+                password: PASSWORD.into(),
+                pwd_confirm: PASSWORD.into(),
+                captcha_id,
+                captcha_solution,
+                form_time,
+                ..Default::default()
+            },
+        )
+    };
+    let hasty = |response: LocalResponse<'_>| {
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = response.into_string().unwrap();
+        assert!(body.contains("id=\"register-error\""));
+    };
+    // Right after loading the form, without a token or with a forged one
+    hasty(register("hasty bot", Some(timer.issue())));
+    hasty(register("hasty bot", None));
+    let earlier = timer.issue_at(SystemTime::now() - Duration::from_secs(5));
+    let (_, signature) = earlier.split_once('.').unwrap();
+    hasty(register("hasty bot", Some(format!("0.{}", signature))));
+    assert!(!block_on(
+        client
+            .rocket()
+            .state::<Db>()
+            .unwrap()
+            .user_name_exists("hasty bot")
+    )
+    .unwrap());
+
+    // Human speed is fine
+    let response = register("patient human", Some(earlier));
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn article_revs_feed() {
+    let client = client();
+    register_and_login(&client, "feed editor");
+    for content in &["First", "Second", "Third"] {
+        let response = post_form(
+            &client,
+            "/FeedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let response = client.get("/FeedArticle/revs.atom").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("application", "atom+xml"))
+    );
+    let body = response.into_string().unwrap();
+    assert_eq!(body.matches("<entry>").count(), 3);
+    // Newest first, each linking to its revision
+    let third = body.find("/FeedArticle/rev/3\"").unwrap();
+    let first = body.find("/FeedArticle/rev/1\"").unwrap();
+    assert!(third < first);
+    assert!(body.contains("<name>feed editor</name>"));
+
+    let response = client.get("/NoFeedArticle/revs.atom").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn search_commit_batches() {
+    // The timer never fires during the test, only full batches commit
+    let client = client_with(
+        config()
+            .merge(("search_commit_batch", 3))
+            .merge(("search_commit_interval_ms", 3_600_000)),
+    );
+    register_and_login(&client, "batching editor");
+    let index = client.rocket().state::<ArticleIndex>().unwrap();
+    let edit = |name: &str| {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: "Plenty of batchedword".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    let generation = index.generation();
+    for name in &["BatchOne", "BatchTwo", "BatchThree"] {
+        edit(name);
+    }
+    assert_eq!(index.generation(), generation + 1);
+    let mut titles = search_titles(&client, "batchedword");
+    titles.sort();
+    assert_eq!(titles, vec!["BatchOne", "BatchThree", "BatchTwo"]);
+
+    // Leftovers wait for the flush
+    edit("BatchFour");
+    assert_eq!(index.generation(), generation + 1);
+    index.flush().unwrap();
+    assert_eq!(index.generation(), generation + 2);
+    assert!(search_titles(&client, "batchedword").contains(&"BatchFour".to_string()));
+}
+
+#[test]
+#[serial]
+fn private_mode() {
+    let client = client_with(config().merge(("private", true)));
+    // Registering still works, which also logs in
+    register_and_login(&client, "private editor");
+    let response = post_form(
+        &client,
+        "/PrivateArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "For members only".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/PrivateArticle").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.into_string().unwrap().contains("For members only"));
+
+    logout(&client);
+    for uri in &[
+        "/PrivateArticle",
+        "/search?q=members",
+        "/PrivateArticle/revs",
+    ] {
+        let response = client.get(*uri).dispatch();
+        assert_eq!(response.status(), Status::SeeOther, "{} is public", uri);
+        let location = response.headers().get_one("Location").unwrap();
+        assert!(location.starts_with("/u/login?next="), "{}", location);
+    }
+    // And come back after logging in
+    let response = client.get("/PrivateArticle").dispatch();
+    let location = response.headers().get_one("Location").unwrap();
+    let next = location.strip_prefix("/u/login?next=").unwrap();
+    assert_eq!(
+        rocket::http::RawStr::new(next).percent_decode_lossy(),
+        "/PrivateArticle"
+    );
+    // Edits are refused just the same
+    let response = post_form(
+        &client,
+        "/PrivateArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Vandalized".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(client.get("/u/login").dispatch().status(), Status::Ok);
+    assert_eq!(client.get("/u/register").dispatch().status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn search_synonyms() {
+    use crate::settings::SynonymsRequest;
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let response = post_form(
+        &client,
+        "/SynonymArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "All about the quuxinator".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert!(search_titles(&client, "zorbtron").is_empty());
+
+    let set_synonyms = |groups: &str| {
+        let response = post_form(
+            &client,
+            "/settings/admin/synonyms",
+            SynonymsRequest {
+                groups: groups.into(),
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    set_synonyms("Zorbtron, quuxinator\nlonely\n");
+    assert_eq!(search_titles(&client, "zorbtron"), vec!["SynonymArticle"]);
+    // The panel shows the groups which make sense
+    let body = client.get("/settings").dispatch().into_string().unwrap();
+    assert!(body.contains(">Zorbtron, quuxinator</textarea>"));
+
+    set_synonyms("");
+    assert!(search_titles(&client, "zorbtron").is_empty());
+}
+
+#[test]
+#[serial]
+fn configured_date_format() {
+    let client = client_with(config().merge(("date_format", "%d.%m.%Y (%A)")));
+    register_and_login(&client, "dating editor");
+    let response = post_form(
+        &client,
+        "/DatedArticle/edit",
+        AddRevRequest {
+            title: None,
+            content: "Written on some datedword day".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let today = chrono::Utc::now().format("%d.%m.%Y (%A)").to_string();
+    for uri in &[
+        "/DatedArticle",
+        "/DatedArticle/revs",
+        "/search?q=datedword",
+        "/settings/sessions",
+    ] {
+        let body = client.get(*uri).dispatch().into_string().unwrap();
+        assert!(body.contains(&today), "no formatted date on {}", uri);
+    }
+    // The admin pages as well
+    logout(&client);
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let response = client.post("/settings/invites").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    for uri in &["/settings/users/recent", "/settings/audit"] {
+        let body = client.get(*uri).dispatch().into_string().unwrap();
+        assert!(body.contains(&today), "no formatted date on {}", uri);
+    }
+
+    // A broken format doesn't break the pages
+    let client = client_with(config().merge(("date_format", "%Q")));
+    let response = client.get("/DatedArticle").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    assert!(response.into_string().unwrap().contains(&today));
+}
+
+#[test]
+#[serial]
+fn created_pages_list() {
+    let client = client();
+    register_and_login(&client, "page founder");
+    let edit = |name: &str| {
+        let response = post_form(
+            &client,
+            format!("/{}/edit", name),
+            AddRevRequest {
+                title: None,
+                content: format!("Something about {}", name),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    };
+    edit("FoundedArticle");
+    logout(&client);
+    register_and_login(&client, "page visitor");
+    edit("VisitedArticle");
+    logout(&client);
+    login(&client, "page founder", PASSWORD);
+    edit("VisitedArticle");
+
+    let body = client.get("/settings").dispatch().into_string().unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let pages = document
+        .select(&Selector::parse("#created-pages a").unwrap())
+        .map(|link| link.text().collect::<String>())
+        .collect::<Vec<_>>();
+    assert!(pages.contains(&"FoundedArticle".to_string()));
+    assert!(!pages.contains(&"VisitedArticle".to_string()));
+}
+
+#[test]
+#[serial]
+fn registration_blocklist() {
+    let client = client_with(
+        config()
+            .merge(("registration_blocklist", vec!["198.51.100.0/24"]))
+            .merge(("trusted_proxies", vec!["192.0.2.1/32"])),
+    );
+    let register = |username: &str, remote: &str, forwarded_for: Option<&str>| {
+        let (captcha_id, captcha_solution) = register_challenge(&client);
+        let mut request = client
+            .post("/u/register")
+            .header(content_type_form())
+            .remote(remote.parse().unwrap())
+            .body(
+                serde_urlencoded::to_string(RegisterRequest {
+                    username: username.into(),
+                    password: PASSWORD.into(),
+                    pwd_confirm: PASSWORD.into(),
+                    captcha_id,
+                    captcha_solution,
+                    ..Default::default()
+                })
+                .unwrap(),
+            );
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header(rocket::http::Header::new(
+                "X-Forwarded-For",
+                forwarded_for.to_string(),
+            ));
+        }
+        request.dispatch().status()
+    };
+    let db = client.rocket().state::<Db>().unwrap();
+    assert_eq!(
+        register("blocked spammer", "198.51.100.23:4242", None),
+        Status::Forbidden
+    );
+    // Behind a trusted proxy, the forwarded address counts
+    assert_eq!(
+        register("proxied spammer", "192.0.2.1:4242", Some("198.51.100.24")),
+        Status::Forbidden
+    );
+    assert!(!block_on(db.user_name_exists("blocked spammer")).unwrap());
+    assert!(!block_on(db.user_name_exists("proxied spammer")).unwrap());
+    assert_eq!(
+        register("allowed newcomer", "203.0.113.9:4242", None),
+        Status::Ok
+    );
+}
+
+#[test]
+#[serial]
+fn oidc_registration_checks() {
+    use crate::{client_ip::ClientIp, oidc::IdTokenClaims};
+
+    let client = client_with(
+        config()
+            .merge(("registration_blocklist", vec!["198.51.100.0/24"]))
+            .merge(("similar_username_distance", 0)),
+    );
+    register_account(&client, "sso regular", PASSWORD);
+    let cfg = client.rocket().state::<crate::Config>().unwrap();
+    let db = client.rocket().state::<Db>().unwrap();
+    let login_user = |name: &str, ip: &str| {
+        let claims = IdTokenClaims {
+            sub: format!("subject of {}", name),
+            nonce: None,
+            preferred_username: Some(name.into()),
+        };
+        block_on(crate::oidc::login_user(
+            cfg,
+            db,
+            &ClientIp(Some(ip.parse().unwrap())),
+            &claims,
+        ))
+    };
+    let allowed = "203.0.113.9";
+    assert!(matches!(
+        login_user("blocked sso user", "198.51.100.23"),
+        Err(crate::Error::RegistrationBlocked)
+    ));
+    assert!(matches!(
+        login_user("login", allowed),
+        Err(crate::Error::OidcUsernameRejected(_))
+    ));
+    assert!(matches!(
+        login_user("sso regu1ar", allowed),
+        Err(crate::Error::OidcUsernameRejected(_))
+    ));
+    // Taken names still get a suffix
+    let user_id = login_user("sso regular", allowed).unwrap();
+    assert_ne!(
+        block_on(db.user_id_by_name("sso regular")).unwrap(),
+        Some(user_id)
+    );
+    assert!(!block_on(db.user_name_exists("blocked sso user")).unwrap());
+}
+
+#[test]
+#[serial]
+fn talk_pages() {
+    let client = client_with(
+        config()
+            .merge(("create_talk_pages", true))
+            .merge(("talk_page_template", "TalkGreeting")),
+    );
+    // Without the template, there's a default text
+    register_account(&client, "quiet newcomer", PASSWORD);
+    let content = article_content(&client, "Talk:User:quiet%20newcomer");
+    assert!(content.contains("User:quiet newcomer"), "{}", content);
+
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    let response = post_form(
+        &client,
+        "/Template:TalkGreeting/edit",
+        AddRevRequest {
+            title: None,
+            content: "Hi {user}, say something!".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    logout(&client);
+    register_account(&client, "talkative newcomer", PASSWORD);
+    let content = article_content(&client, "Talk:User:talkative%20newcomer");
+    assert!(content.contains("Hi talkative newcomer, say something!"));
+
+    // Without the option, nobody gets one
+    let client = self::client();
+    register_account(&client, "uninvited newcomer", PASSWORD);
+    let response = client.get("/Talk:User:uninvited%20newcomer").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+#[serial]
+fn comment_limits() {
+    let client = client_with(
+        config()
+            .merge(("max_comment_length", 20))
+            .merge(("comment_rate_limit", 2))
+            .merge(("comment_rate_window", 90)),
+    );
+    register_and_login(&client, "chatty commenter");
+    let say = |uri: &str, content: &str| {
+        post_form(
+            &client,
+            uri,
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        )
+    };
+    let response = say("/Talk:Chat/edit", "Hello there");
+    assert_eq!(response.status(), Status::Ok);
+    // Only what's added counts
+    let response = say(
+        "/Talk:Chat/edit",
+        "Hello there\n\nThis reply is much too long",
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("at most 20 characters"));
+    // Saves rejected for other reasons don't count
+    let response = post_form(
+        &client,
+        "/Talk:Chat/edit",
+        AddRevRequest {
+            title: Some("x".repeat(300)),
+            content: "Hello there\n\nHey".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = say("/Talk:Chat/edit", "Hello there\n\nHi!");
+    assert_eq!(response.status(), Status::Ok);
+    let response = say("/Talk:Chat/edit", "Hello there\n\nHi!\n\nAnyone?");
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(response.into_string().unwrap().contains("up to 90 seconds"));
+    // Undoing counts as well
+    let response = client.post("/Talk:Chat/rev/2/undo").dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert!(article_content(&client, "Talk:Chat").contains("Hi!"));
+
+    // Articles aren't comments
+    let response = say(
+        "/NotATalkPage/edit",
+        "Long enough to be too long as a comment",
+    );
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+#[serial]
+fn empty_search() {
+    let client = client();
+    register_and_login(&client, "empty searcher");
+    for i in 0..11 {
+        let response = post_form(
+            &client,
+            format!("/BlankSearch{}/edit", i),
+            AddRevRequest {
+                title: None,
+                content: "Listed without a query".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let articles = |body: &str| {
+        let document = scraper::Html::parse_document(body);
+        document
+            .select(&Selector::parse("#search-articles a").unwrap())
+            .map(|link| link.text().collect::<String>())
+            .collect::<Vec<_>>()
+    };
+
+    for uri in &["/search?q=&sort=date", "/search?q=%20%20&sort=date"] {
+        let response = client.get(*uri).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().unwrap();
+        assert!(body.contains("id=\"search-prompt\""));
+        // Only the most recent ones, on a single page
+        let names = articles(&body);
+        assert_eq!(names.len(), 10);
+        assert_eq!(names[0], "BlankSearch10");
+        assert!(!body.contains("class=\"pagination\""));
+    }
+    // Without a query, the best match is sorting by name
+    let body = client.get("/search?q=").dispatch().into_string().unwrap();
+    let names = articles(&body);
+    assert_eq!(names.len(), 10);
+    assert_eq!(names[0], "BlankSearch1");
+    assert_eq!(names[1], "BlankSearch10");
+    // Namespaces limit the listing, too
+    let response = post_form(
+        &client,
+        "/Help:BlankSearch/edit",
+        AddRevRequest {
+            title: None,
+            content: "Listed in its namespace".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = client
+        .get("/search?q=&ns=Help")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert_eq!(articles(&body), ["Help:BlankSearch"]);
+    let body = client
+        .get(format!(
+            "/search?q=&ns={}&sort=date",
+            crate::search::MAIN_NAMESPACE
+        ))
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert_eq!(articles(&body)[0], "BlankSearch10");
+
+    let client = client_with(config().merge(("empty_search", "all")));
+    let response = client.get("/search?q=&sort=date").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert_eq!(articles(&body)[0], "Help:BlankSearch");
+    assert!(body.contains("class=\"pagination\""));
+}
+
+#[test]
+#[serial]
+fn moderated_edits() {
+    let client = client_with(
+        config()
+            .merge(("moderate_edits", true))
+            .merge(("trusted_edit_count", 1)),
+    );
+    let admin = admin_account(&client);
+    let edit = |content: &str| {
+        post_form(
+            &client,
+            "/ModeratedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.into(),
+                ..Default::default()
+            },
+        )
+    };
+    // Admins don't need approval
+    login(&client, &admin, PASSWORD);
+    assert_eq!(edit("Original content").status(), Status::Ok);
+    logout(&client);
+
+    register_and_login(&client, "moderated editor");
+    let response = edit("Approved content");
+    assert_eq!(response.status(), Status::Accepted);
+    assert!(response
+        .into_string()
+        .unwrap()
+        .contains("id=\"edit-pending\""));
+    assert_eq!(edit("Rejected content").status(), Status::Accepted);
+    assert!(article_content(&client, "ModeratedArticle").contains("Original content"));
+    logout(&client);
+
+    login(&client, &admin, PASSWORD);
+    let pending_ids = || {
+        let body = client
+            .get("/settings/moderation")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let document = scraper::Html::parse_document(&body);
+        document
+            .select(&Selector::parse(".pending-revision").unwrap())
+            .map(|elem| {
+                elem.value()
+                    .id()
+                    .unwrap()
+                    .trim_start_matches("pending-")
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+    let ids = pending_ids();
+    assert_eq!(ids.len(), 2);
+    let response = client
+        .post(format!("/settings/moderation/{}/approve", ids[0]))
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert!(article_content(&client, "ModeratedArticle").contains("Approved content"));
+    // The other edit was based on the original content, so approving it now
+    // would overwrite the approved one
+    let outdated = || {
+        let body = client
+            .get("/settings/moderation")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        scraper::Html::parse_document(&body)
+            .select(&Selector::parse(".pending-outdated").unwrap())
+            .count()
+    };
+    assert_eq!(outdated(), 1);
+    let response = client
+        .post(format!("/settings/moderation/{}/approve", ids[1]))
+        .dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(pending_ids(), vec![ids[1].clone()]);
+    assert!(article_content(&client, "ModeratedArticle").contains("Approved content"));
+    let response = client
+        .post(format!("/settings/moderation/{}/reject", ids[1]))
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert!(pending_ids().is_empty());
+    assert!(article_content(&client, "ModeratedArticle").contains("Approved content"));
+    // Each edit can only be handled once
+    let response = client
+        .post(format!("/settings/moderation/{}/approve", ids[1]))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    logout(&client);
+
+    // With an approved edit, the user is trusted now
+    login(&client, "moderated editor", PASSWORD);
+    assert_eq!(edit("Trusted content").status(), Status::Ok);
+    assert!(article_content(&client, "ModeratedArticle").contains("Trusted content"));
+    logout(&client);
+
+    // Renames and tags wait along with the content
+    register_and_login(&client, "moderated renamer");
+    let response = post_form(
+        &client,
+        "/ModeratedArticle/edit",
+        AddRevRequest {
+            title: Some("RenamedModeratedArticle".into()),
+            content: "Trusted content".into(),
+            tags: Some("moderated-tag".into()),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Accepted);
+    assert!(article_content(&client, "ModeratedArticle").contains("Trusted content"));
+    logout(&client);
+    login(&client, &admin, PASSWORD);
+    let ids = pending_ids();
+    assert_eq!(ids.len(), 1);
+    let response = client
+        .post(format!("/settings/moderation/{}/approve", ids[0]))
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    let body = client
+        .get("/RenamedModeratedArticle")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    let document = scraper::Html::parse_document(&body);
+    let tags = document
+        .select(&Selector::parse("#article-tags .tag").unwrap())
+        .map(|tag| tag.inner_html())
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["moderated-tag"]);
+    assert!(article_content(&client, "RenamedModeratedArticle").contains("Trusted content"));
+    logout(&client);
+
+    // The API and undoing don't get around the queue either
+    register_and_login(&client, "moderated api user");
+    let response = client
+        .put("/api/articles/RenamedModeratedArticle")
+        .header(ContentType::JSON)
+        .body(serde_json::json!({ "content": "API content" }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Accepted);
+    let response: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(response["rev_id"], serde_json::Value::Null);
+    let response = client
+        .post("/api/articles/RenamedModeratedArticle/move")
+        .header(ContentType::JSON)
+        .body(
+            serde_json::json!({ "new_name": "MovedModeratedArticle", "leave_redirect": true })
+                .to_string(),
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::Accepted);
+    let response = client
+        .post("/RenamedModeratedArticle/rev/3/undo")
+        .dispatch();
+    assert_eq!(response.status(), Status::Accepted);
+    assert!(article_content(&client, "RenamedModeratedArticle").contains("Trusted content"));
+    assert_eq!(
+        client.get("/MovedModeratedArticle").dispatch().status(),
+        Status::NotFound
+    );
+    logout(&client);
+    login(&client, &admin, PASSWORD);
+    let ids = pending_ids();
+    assert_eq!(ids.len(), 3);
+    let body = client
+        .get("/settings/moderation")
+        .dispatch()
+        .into_string()
+        .unwrap();
+    assert!(body.contains("leaving a redirect"));
+    let response = client
+        .post(format!("/settings/moderation/{}/approve", ids[1]))
+        .dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert!(article_content(&client, "MovedModeratedArticle").contains("Trusted content"));
+    let response = client.get("/RenamedModeratedArticle").dispatch();
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("/MovedModeratedArticle")
+    );
+}
+
+#[test]
+#[serial]
+fn short_search_queries() {
+    let client = client_with(
+        config()
+            .merge(("search_min_query_length", 3))
+            .merge(("search_stop_words", vec!["the", "of"])),
+    );
+    register_and_login(&client, "short searcher");
+    let response = post_form(
+        &client,
+        "/X/edit",
+        AddRevRequest {
+            title: None,
+            content: "The lord of the rings, about X".into(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let search = |query: &str| {
+        let response = client.get(format!("/search?q={}", query)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_string().unwrap()
+    };
+
+    // Neither a single character nor only stop words are searched for
+    for query in &["X", "the%20of", "the%20X"] {
+        let body = search(query);
+        assert!(body.contains("id=\"search-too-short\""));
+        assert!(search_titles(&client, query).is_empty());
+    }
+    // The stop words are left out of longer queries
+    let body = search("the%20lord");
+    assert!(!body.contains("id=\"search-too-short\""));
+    assert!(search_titles(&client, "the%20lord").contains(&"X".to_string()));
+}
+
+#[test]
+#[serial]
+fn optimize() {
+    use crate::settings::{MaintenanceLock, OptimizeRequest};
+
+    let client = client();
+    let admin = admin_account(&client);
+    login(&client, &admin, PASSWORD);
+    // A few commits make for a few segments to merge
+    for content in &["Optimizable", "Optimizable again", "Optimizable once more"] {
+        let response = post_form(
+            &client,
+            "/OptimizedArticle/edit",
+            AddRevRequest {
+                title: None,
+                content: content.to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let response = post_form(
+        &client,
+        "/settings/maintenance/optimize",
+        OptimizeRequest { vacuum: true },
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert!(body.contains("id=\"maintenance-done\""));
+    assert!(body.contains("Vacuumed the database"));
+    assert_eq!(
+        search_titles(&client, "Optimizable"),
+        vec!["OptimizedArticle"]
+    );
+
+    // Only one run at a time
+    let lock = client.rocket().state::<MaintenanceLock>().unwrap();
+    let running = lock.0.try_lock().unwrap();
+    let response = post_form(
+        &client,
+        "/settings/maintenance/optimize",
+        OptimizeRequest { vacuum: false },
+    );
+    assert_eq!(response.status(), Status::Conflict);
+    drop(running);
+}
@@ -0,0 +1,75 @@
+//! Sending URLs with a trailing slash, like `/Main/`, to the same URL
+//! without it, so every page only has one address.
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Method, Status},
+    Request, Response,
+};
+
+use crate::{users::safe_next, Config};
+
+/// Returns the path without its trailing slashes, unless it's `/` itself or
+/// a static file's.
+fn canonical_path(path: &str) -> Option<&str> {
+    if path.starts_with("/res/") {
+        return None;
+    }
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.len() == path.len() || trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed)
+    }
+}
+
+/// With `redirect_trailing_slashes` set in the config, answers GET requests
+/// ending in a slash with a redirect to the path without the slash, instead
+/// of whatever the routes made of them.
+pub struct TrailingSlash;
+#[rocket::async_trait]
+impl Fairing for TrailingSlash {
+    fn info(&self) -> Info {
+        Info {
+            name: "Trailing slash redirect",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let enabled = request
+            .rocket()
+            .state::<Config>()
+            .is_some_and(|cfg| cfg.redirect_trailing_slashes);
+        if !enabled || !matches!(request.method(), Method::Get | Method::Head) {
+            return;
+        }
+        let mut target = match canonical_path(request.uri().path().as_str()) {
+            Some(path) => path.to_string(),
+            None => return,
+        };
+        if let Some(query) = request.uri().query() {
+            target = format!("{}?{}", target, query);
+        }
+        // Only paths on this wiki are redirected to, not e.g. `//example.com/`
+        let target = safe_next(&target).unwrap_or("/").to_string();
+        *response = Response::build()
+            .status(Status::MovedPermanently)
+            .raw_header("Location", target)
+            .finalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::canonical_path;
+
+    #[test]
+    fn canonical_paths() {
+        assert_eq!(canonical_path("/Main/"), Some("/Main"));
+        assert_eq!(canonical_path("/Main/revs//"), Some("/Main/revs"));
+        assert_eq!(canonical_path("/Main"), None);
+        assert_eq!(canonical_path("/"), None);
+        assert_eq!(canonical_path("//"), None);
+        assert_eq!(canonical_path("/res/css/"), None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Transclusion of one article into another with `{{Name}}`.
+//! This only happens when rendering; revisions and the search index keep the
+//! markers, so transcluded content isn't stored (or found) twice.
+use std::collections::HashMap;
+
+use pulldown_cmark::escape::escape_href;
+
+use crate::{articles::ArticleFormat, Db, Result};
+
+/// How many levels of transclusions inside transclusions are expanded.
+/// Anything deeper is rendered as a plain link.
+pub const MAX_DEPTH: usize = 5;
+
+/// Splits off the first `{{Name}}` in the content, returning the text before
+/// it, the name and the text after it.
+fn next_transclusion(content: &str) -> Option<(&str, &str, &str)> {
+    let mut offset = 0;
+    loop {
+        let start = offset + content[offset..].find("{{")?;
+        let inner = &content[start + 2..];
+        let end = inner.find("}}")?;
+        let name = inner[..end].trim();
+        if !name.is_empty() && !name.contains(['{', '}', '\n']) {
+            return Some((&content[..start], name, &inner[end + 2..]));
+        }
+        offset = start + 2;
+    }
+}
+
+/// Returns the names of the articles the content transcludes.
+fn transclusions(mut content: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    while let Some((_, name, rest)) = next_transclusion(content) {
+        names.push(name);
+        content = rest;
+    }
+    names
+}
+
+/// Transclusions which aren't expanded become Markdown links to a url with
+/// this prefix, like `[Name](<swiki-link:missing-link:/Name>)`; rendering
+/// turns them into links with that class (see `placeholder_link`).
+/// That way, they work without the article's HTML being allowed.
+const PLACEHOLDER_PREFIX: &str = "swiki-link:";
+
+/// Markdown linking to the article, or the marker itself for formats which
+/// don't have links.
+fn link(name: &str, class: &str, format: ArticleFormat) -> String {
+    if format != ArticleFormat::Markdown {
+        return format!("{{{{{}}}}}", name);
+    }
+    let mut markdown = String::from("[");
+    for c in name.chars() {
+        if c.is_ascii_punctuation() {
+            markdown.push('\\');
+        }
+        markdown.push(c);
+    }
+    markdown.push_str("](<");
+    markdown.push_str(PLACEHOLDER_PREFIX);
+    markdown.push_str(class);
+    markdown.push_str(":/");
+    // Writing to a String can't fail
+    escape_href(&mut markdown, name).unwrap();
+    markdown.push_str(">)");
+    markdown
+}
+
+/// The classes a placeholder link can have.
+const PLACEHOLDER_CLASSES: &[&str] = &["missing-link", "transclusion-skipped"];
+
+/// The class and the actual url of a link made for a transclusion.
+/// Articles can contain such links themselves, so only local urls with
+/// the known classes are accepted.
+pub fn placeholder_link(dest: &str) -> Option<(&str, &str)> {
+    let (class, href) = dest.strip_prefix(PLACEHOLDER_PREFIX)?.split_once(':')?;
+    let local = href.starts_with('/') && !href.starts_with("//");
+    (PLACEHOLDER_CLASSES.contains(&class) && local).then_some((class, href))
+}
+
+/// The opening HTML tag for a placeholder link.
+pub fn placeholder_link_start(class: &str, href: &str) -> String {
+    // Writing to a String can't fail
+    let mut html = format!("<a class=\"{}\" href=\"", class);
+    escape_href(&mut html, href).unwrap();
+    html.push_str("\">");
+    html
+}
+
+/// Replaces the transclusions in the content, given the content of every
+/// article which might be needed (`None` if it doesn't exist).
+/// `stack` holds the articles currently being expanded, so cycles end up
+/// as links instead of looping.
+fn expand_with(
+    content: &str,
+    pages: &HashMap<String, Option<String>>,
+    stack: &mut Vec<String>,
+    format: ArticleFormat,
+) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some((before, name, after)) = next_transclusion(rest) {
+        output.push_str(before);
+        if stack.len() > MAX_DEPTH || stack.iter().any(|n| n == name) {
+            output.push_str(&link(name, "transclusion-skipped", format));
+        } else if let Some(Some(page)) = pages.get(name) {
+            stack.push(name.to_string());
+            output.push_str(&expand_with(page, pages, stack, format));
+            stack.pop();
+        } else {
+            output.push_str(&link(name, "missing-link", format));
+        }
+        rest = after;
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Inlines the current content of every article transcluded in the given
+/// article's content, which is rendered in the given format afterwards.
+pub async fn expand(
+    db: &Db,
+    article_name: &str,
+    content: &str,
+    format: ArticleFormat,
+) -> Result<String> {
+    // Load everything that could be needed first, one level at a time, so
+    // the expansion itself doesn't have to be async
+    let mut pages: HashMap<String, Option<String>> = HashMap::new();
+    let mut pending: Vec<String> = transclusions(content)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    for _ in 0..MAX_DEPTH {
+        let mut next = Vec::new();
+        for name in pending {
+            if pages.contains_key(&name) {
+                continue;
+            }
+            let page = db.get_current_rev(&name).await?.map(|rev| rev.content);
+            if let Some(page) = &page {
+                next.extend(transclusions(page).into_iter().map(String::from));
+            }
+            pages.insert(name, page);
+        }
+        if next.is_empty() {
+            break;
+        }
+        pending = next;
+    }
+    Ok(expand_loaded(article_name, content, &pages, format))
+}
+
+/// Like `expand`, but with the content of every article which might be
+/// needed already loaded, so it doesn't have to wait for the database.
+pub fn expand_loaded(
+    article_name: &str,
+    content: &str,
+    pages: &HashMap<String, Option<String>>,
+    format: ArticleFormat,
+) -> String {
+    expand_with(content, pages, &mut vec![article_name.to_string()], format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_transclusions() {
+        assert_eq!(
+            transclusions("{{A}} and {{ B c }}, {{}} {{x\ny}} {{E"),
+            vec!["A", "B c"]
+        );
+        assert_eq!(
+            next_transclusion("before {{Name}} after"),
+            Some(("before ", "Name", " after"))
+        );
+    }
+
+    #[test]
+    fn expand_cycles_and_depth() {
+        let pages = (0..=MAX_DEPTH + 1)
+            .map(|i| (i.to_string(), Some(format!("{} {{{{{}}}}}", i, i + 1))))
+            .chain(std::iter::once((
+                "Loop".to_string(),
+                Some("{{Loop}}".into()),
+            )))
+            .collect::<HashMap<_, _>>();
+        let markdown = ArticleFormat::Markdown;
+        assert_eq!(
+            expand_with("{{Loop}}", &pages, &mut vec!["Loop".into()], markdown),
+            "[Loop](<swiki-link:transclusion-skipped:/Loop>)"
+        );
+        let expanded = expand_with("{{1}}", &pages, &mut vec!["Start".into()], markdown);
+        assert_eq!(
+            expanded,
+            format!(
+                "1 2 3 4 5 [{0}](<swiki-link:transclusion-skipped:/{0}>)",
+                MAX_DEPTH + 1
+            )
+        );
+        // Plain text has no links, so the marker stays
+        assert_eq!(
+            expand_with(
+                "{{Loop}}",
+                &pages,
+                &mut vec!["Loop".into()],
+                ArticleFormat::Plain
+            ),
+            "{{Loop}}"
+        );
+    }
+
+    #[test]
+    fn placeholder_links() {
+        let link = link("A [b] <c>", "missing-link", ArticleFormat::Markdown);
+        assert_eq!(
+            link,
+            r"[A \[b\] \<c\>](<swiki-link:missing-link:/A%20%5Bb%5D%20%3Cc%3E>)"
+        );
+        assert_eq!(
+            placeholder_link("swiki-link:missing-link:/A%20%5Bb%5D%20%3Cc%3E"),
+            Some(("missing-link", "/A%20%5Bb%5D%20%3Cc%3E"))
+        );
+        assert_eq!(placeholder_link("/Elsewhere"), None);
+        assert_eq!(placeholder_link("swiki-link:x\"y:/Name"), None);
+        assert_eq!(placeholder_link("swiki-link:missing-link://evil"), None);
+        assert_eq!(
+            placeholder_link_start("missing-link", "/A\"b"),
+            r#"<a class="missing-link" href="/A%22b">"#
+        );
+    }
+}
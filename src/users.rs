@@ -1,7 +1,8 @@
+use chrono::Duration;
 use rocket::{
     form::Form,
     get,
-    http::{Cookie, CookieJar},
+    http::{uri::Origin, Cookie, CookieJar, RawStr},
     post,
     response::{Redirect, Responder},
     FromForm, State,
@@ -11,11 +12,15 @@ use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
+    captcha::{CaptchaProvider, Challenge},
+    client_ip::ClientIp,
+    confusables,
     db::{
+        self,
         users::{LoggedUser, UserSession},
         EnabledRegistration,
     },
-    Cache, Config, Db, Error, Result,
+    ArticleIndex, Cache, Config, Db, Error, FormTimer, Mailer, Result,
 };
 
 pub fn routes() -> Vec<rocket::Route> {
@@ -23,58 +28,40 @@ pub fn routes() -> Vec<rocket::Route> {
         profile,
         register_page,
         register_form,
+        verify_email,
         login_redirect,
         login_page,
+        login_required,
         login_form,
         logout,
     ]
 }
 
-/// Generate a captcha.
-/// Returns the captcha as base64 and the characters it contains.
-fn generate_captcha() -> Result<(String, String)> {
-    use captcha::{
-        filters::{Dots, Noise, Wave},
-        Captcha,
-    };
-    use rand::Rng;
-
-    let mut captcha = Captcha::new();
-    let mut rng = rand::thread_rng();
-    captcha
-        .add_chars(5)
-        .apply_filter(Noise::new(0.4))
-        .apply_filter(Wave::new(rng.gen_range(1.0..3.0), rng.gen_range(10.0..30.0)).horizontal())
-        .apply_filter(Wave::new(rng.gen_range(1.0..3.0), rng.gen_range(10.0..30.0)).vertical())
-        .view(220, 120)
-        .apply_filter(Dots::new(rng.gen_range(3..6)));
-    let result = (
-        captcha.chars_as_string(),
-        captcha.as_base64().ok_or(Error::CaptchaPngError)?,
-    );
-    Ok(result)
-}
-
-/// Generates a captcha on tokio's threadpool and stores it in the cache database.
-async fn gen_captcha_and_id(cache: &Cache) -> Result<(Uuid, String)> {
-    let (solution, base64) = rocket::tokio::task::spawn_blocking(generate_captcha).await??;
-    let id = Uuid::new_v4();
-    cache.register_captcha(id, &solution);
-    Ok((id, base64))
-}
-
 #[derive(Debug, serde::Serialize)]
 struct RegisterPageContext<'a> {
     site_name: &'a str,
     default_path: &'a str,
     page_name: &'static str,
     username: Option<String>,
-    captcha_base64: String,
-    captcha_uuid: String,
+    email: Option<String>,
+    require_email: bool,
+    captcha: Option<Challenge>,
+    /// When the form was shown, signed.
+    form_time: Option<String>,
     pwds_dont_match: bool,
     username_taken: bool,
+    /// Which existing name isn't told, so this can't be used for finding them.
+    username_too_similar: bool,
     no_username: bool,
     failed_captcha: bool,
+    breached_password: bool,
+    no_email: bool,
+    email_domain_not_allowed: bool,
+    /// The form was submitted suspiciously fast.
+    too_fast: bool,
+    invite_only: bool,
+    invite: Option<String>,
+    invalid_invite: bool,
 }
 impl<'a> Default for RegisterPageContext<'a> {
     fn default() -> Self {
@@ -83,12 +70,22 @@ impl<'a> Default for RegisterPageContext<'a> {
             default_path: "",
             page_name: "Register",
             username: None,
-            captcha_base64: Default::default(),
-            captcha_uuid: Default::default(),
+            email: None,
+            require_email: false,
+            captcha: None,
+            form_time: None,
             pwds_dont_match: false,
             username_taken: false,
+            username_too_similar: false,
             no_username: false,
             failed_captcha: false,
+            breached_password: false,
+            no_email: false,
+            email_domain_not_allowed: false,
+            too_fast: false,
+            invite_only: false,
+            invite: None,
+            invalid_invite: false,
         }
     }
 }
@@ -97,6 +94,8 @@ impl<'a> From<&'a Config> for RegisterPageContext<'a> {
         Self {
             site_name: &cfg.site_name,
             default_path: &cfg.default_path,
+            require_email: cfg.require_email,
+            invite_only: cfg.invite_only,
             ..Default::default()
         }
     }
@@ -111,12 +110,17 @@ enum TemplateResult {
     Redirect(Redirect),
 }
 
-#[get("/register")]
+/// Invite links fill in the code with `?invite=`.
+#[get("/register?<invite>")]
+#[allow(clippy::too_many_arguments)]
 async fn register_page(
     cfg: &State<Config>,
     cache: &State<Cache>,
+    captcha: &State<Box<dyn CaptchaProvider>>,
+    form_timer: &State<FormTimer>,
     er: Option<EnabledRegistration>,
     session: Option<&UserSession>,
+    invite: Option<String>,
 ) -> Result<TemplateResult> {
     // If er is None, registration is disabled.
     // If session is Some, we're already logged in.
@@ -126,10 +130,10 @@ async fn register_page(
         )));
     }
     // Generate a captcha to include in the login form
-    let (id, base64) = gen_captcha_and_id(&*cache).await?;
     let context = RegisterPageContext {
-        captcha_base64: base64,
-        captcha_uuid: id.to_string(),
+        captcha: Some(captcha.challenge(cache).await?),
+        form_time: Some(form_timer.issue()),
+        invite,
         ..From::from(&**cfg)
     };
     Ok(TemplateResult::Template(Template::render(
@@ -138,23 +142,79 @@ async fn register_page(
 }
 
 #[derive(Debug, FromForm)]
-#[cfg_attr(test, derive(serde::Serialize))]
+#[cfg_attr(test, derive(serde::Serialize, Default))]
 pub(crate) struct RegisterRequest {
     pub(crate) username: String,
+    pub(crate) email: Option<String>,
     pub(crate) password: String,
     pub(crate) pwd_confirm: String,
     pub(crate) captcha_id: Uuid,
+    /// External captcha widgets post their token under their own name.
+    #[field(name = "captcha_solution")]
+    #[field(name = "cf-turnstile-response")]
+    #[field(name = "h-captcha-response")]
     pub(crate) captcha_solution: String,
+    pub(crate) form_time: Option<String>,
+    /// Only needed while the wiki is invite-only.
+    pub(crate) invite: Option<String>,
+}
+
+/// Whether accounts can't be created from the client's address.
+pub(crate) fn registration_blocked(cfg: &Config, client_ip: &ClientIp) -> bool {
+    client_ip.0.is_some_and(|ip| {
+        cfg.registration_blocklist
+            .iter()
+            .any(|net| net.contains(&ip))
+    })
+}
+
+/// Names which would get in the way of the pages under `/u`.
+pub(crate) fn reserved_username(username: &str) -> bool {
+    username == "register" || username == "login"
+}
+
+/// Whether the username looks too much like an existing one, if that's
+/// checked (see `Config::similar_username_distance`).
+pub(crate) async fn similar_username(cfg: &Config, db: &Db, username: &str) -> Result<bool> {
+    match cfg.similar_username_distance {
+        Some(max_distance) => {
+            let skeleton = confusables::skeleton(username);
+            let candidates =
+                db::users::similar_name_candidates(db, &skeleton, max_distance).await?;
+            Ok(confusables::similar_name(username, &candidates, max_distance).is_some())
+        }
+        None => Ok(false),
+    }
+}
+
+/// Checks the address' domain against the allowlist; an empty list allows
+/// everything.
+fn email_domain_allowed(allowed: &[String], email: &str) -> bool {
+    if allowed.is_empty() {
+        return true;
+    }
+    match email.rsplit_once('@') {
+        Some((local, domain)) if !local.is_empty() => allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(domain)),
+        _ => false,
+    }
 }
 
 #[post("/register", data = "<form>")]
+#[allow(clippy::too_many_arguments)]
 async fn register_form(
     cfg: &State<Config>,
     db: &State<Db>,
     cache: &State<Cache>,
+    captcha: &State<Box<dyn CaptchaProvider>>,
+    mailer: &State<Mailer>,
+    search_index: &State<ArticleIndex>,
+    form_timer: &State<FormTimer>,
     form: Form<RegisterRequest>,
     er: Option<EnabledRegistration>,
     session: Option<&UserSession>,
+    client_ip: ClientIp,
 ) -> Result<TemplateResult> {
     // If er is None, registration is disabled.
     // If session is Some, we're already logged in.
@@ -163,76 +223,289 @@ async fn register_form(
             cfg.default_path.clone(),
         )));
     }
+    if registration_blocked(cfg, &client_ip) {
+        return Err(Error::RegistrationBlocked);
+    }
     let RegisterRequest {
         username,
+        email,
         password,
         pwd_confirm,
         captcha_id,
         captcha_solution,
+        form_time,
+        invite,
     } = form.into_inner();
 
     let (pwds_dont_match, username_taken, no_username, failed_captcha) = (
         password != pwd_confirm || password.is_empty(),
-        username == "register" || username == "login" || db.user_name_exists(&username).await?,
+        reserved_username(&username) || db.user_name_exists(&username).await?,
         username.is_empty(),
-        !cache.validate_captcha(captcha_id, &captcha_solution),
+        !captcha.verify(cache, captcha_id, &captcha_solution).await,
     );
 
-    if pwds_dont_match || username_taken || no_username || failed_captcha {
-        let (id, base64) = gen_captcha_and_id(&*cache).await?;
+    // Tokens which are missing or not ours count as too fast as well
+    let too_fast = cfg.min_register_seconds > 0
+        && form_time
+            .and_then(|token| form_timer.elapsed(&token))
+            .is_none_or(|elapsed| elapsed.as_secs() < cfg.min_register_seconds);
+
+    let username_too_similar = if !username_taken && !no_username {
+        similar_username(cfg, db, &username).await?
+    } else {
+        false
+    };
+
+    let email = email.filter(|email| !email.is_empty());
+    let no_email = cfg.require_email && email.is_none();
+    let email_domain_not_allowed = match &email {
+        Some(email) => !email_domain_allowed(&cfg.allowed_email_domains, email),
+        None => false,
+    };
+
+    let invite_code = invite
+        .as_deref()
+        .and_then(|code| code.trim().parse::<Uuid>().ok());
+    let invalid_invite = cfg.invite_only
+        && match invite_code {
+            Some(code) => !db::invites::exists(db, code).await?,
+            None => true,
+        };
+
+    // Only bother the API if the password would be accepted otherwise
+    let breached_password = !pwds_dont_match
+        && cfg.check_breached_passwords
+        && crate::pwned::is_breached(&cfg.pwned_passwords_url, &password).await;
+
+    if pwds_dont_match
+        || username_taken
+        || username_too_similar
+        || no_username
+        || failed_captcha
+        || breached_password
+        || no_email
+        || email_domain_not_allowed
+        || too_fast
+        || invalid_invite
+    {
         let context = RegisterPageContext {
             username: Some(username),
-            captcha_base64: base64,
-            captcha_uuid: id.to_string(),
+            email,
+            captcha: Some(captcha.challenge(cache).await?),
+            form_time: Some(form_timer.issue()),
             pwds_dont_match,
             username_taken,
+            username_too_similar,
             no_username,
             failed_captcha,
+            breached_password,
+            no_email,
+            email_domain_not_allowed,
+            too_fast,
+            invite,
+            invalid_invite,
             ..From::from(&**cfg)
         };
         return Ok(TemplateResult::Error(Template::render("register", context)));
     }
+    // Used up along with registering, so nobody else can use it meanwhile,
+    // and a failed registration leaves it usable
+    let mut txn = db.begin().await?;
+    if cfg.invite_only {
+        let taken = match invite_code {
+            Some(code) => db::invites::take(&mut txn, code).await?,
+            None => false,
+        };
+        if !taken {
+            return Err(Error::InviteInvalid);
+        }
+    }
     // If we're here, registration is successful
     // Register the user
-    db.register_user(&username, password).await?;
+    let user_id = db::users::register(
+        &mut txn,
+        &username,
+        email.as_deref(),
+        password,
+        cfg.password_pepper.as_deref(),
+    )
+    .await?;
+    txn.commit().await?;
+    if let Some(email) = &email {
+        send_verification_mail(cfg, db, mailer, user_id, email).await?;
+    }
+    let welcome_message = db.welcome_message().await?;
+    if let Some(message) = &welcome_message {
+        if db.welcome_user_page().await? {
+            let name = format!("{}{}", db::articles::USER_PREFIX, username);
+            create_page(db, search_index, user_id, &name, message).await?;
+        }
+    }
+    // Not worth failing the registration for
+    if cfg.create_talk_pages {
+        if let Err(e) = create_talk_page(cfg, db, search_index, user_id, &username).await {
+            log::warn!("Failed to create the talk page of {}: {}", username, e);
+        }
+    }
     // Return some success messag
+    let mut context = json!(&**cfg);
+    context
+        .as_object_mut()
+        .unwrap()
+        .insert("welcome_message".into(), json!(welcome_message));
     Ok(TemplateResult::Template(Template::render(
         "register_success",
-        &**cfg,
+        context,
     )))
 }
 
-#[get("/login")]
-fn login_redirect(cfg: &State<Config>, _session: &UserSession) -> Redirect {
-    Redirect::to(cfg.default_path.clone())
+/// Creates one of the new user's pages, unless an article with that name
+/// already exists.
+async fn create_page(
+    db: &Db,
+    search_index: &ArticleIndex,
+    user_id: Uuid,
+    name: &str,
+    content: &str,
+) -> Result<()> {
+    let name = db::articles::normalize_name(name);
+    let mut txn = db.begin().await?;
+    if db::articles::id_by_name(&mut txn, &name).await?.is_some() {
+        return Ok(());
+    }
+    let (db::articles::RevId(article_id, _), rev) =
+        db::articles::create(&mut txn, &name, content, user_id).await?;
+    txn.commit().await?;
+    search_index.add_or_update_article(article_id, &name, content, &[], rev.date)?;
+    Ok(())
+}
+
+async fn create_talk_page(
+    cfg: &Config,
+    db: &Db,
+    search_index: &ArticleIndex,
+    user_id: Uuid,
+    username: &str,
+) -> Result<()> {
+    let user_page = format!("{}{}", db::articles::USER_PREFIX, username);
+    let template = match &cfg.talk_page_template {
+        Some(template) => {
+            db.get_current_rev(&format!("{}{}", db::articles::TEMPLATE_PREFIX, template))
+                .await?
+        }
+        None => None,
+    };
+    let content = match template {
+        Some(rev) => rev.content.replace("{user}", username),
+        None => format!("Leave a message for [{}] here.", user_page),
+    };
+    let name = format!("{}{}", db::articles::TALK_PREFIX, user_page);
+    create_page(db, search_index, user_id, &name, &content).await
+}
+
+async fn send_verification_mail(
+    cfg: &Config,
+    db: &Db,
+    mailer: &Mailer,
+    user_id: Uuid,
+    email: &str,
+) -> Result<()> {
+    let token = db::users::create_email_verification(db, user_id).await?;
+    let body = format!(
+        "Please confirm your email address for {} by opening this link:\n\n{}/u/verify/{}\n",
+        cfg.site_name,
+        cfg.site_url.trim_end_matches('/'),
+        token
+    );
+    // A mail which can't be sent shouldn't undo the registration
+    if let Err(e) = mailer
+        .send(
+            email,
+            &format!("Verify your email for {}", cfg.site_name),
+            body,
+        )
+        .await
+    {
+        log::warn!("Failed to send verification mail: {}", e);
+    }
+    Ok(())
 }
-#[get("/login", rank = 2)]
-fn login_page(cfg: &State<Config>) -> Template {
+
+#[get("/verify/<token>")]
+async fn verify_email(cfg: &State<Config>, db: &State<Db>, token: Uuid) -> Result<Template> {
+    if !db::users::verify_email(db, token).await? {
+        return Err(Error::VerificationTokenUnknown);
+    }
+    Ok(Template::render("verify_success", &**cfg))
+}
+
+/// The login page, sending the user back to `origin` once they're logged in.
+pub(crate) fn login_uri(origin: &Origin<'_>) -> String {
+    format!(
+        "/u/login?next={}",
+        RawStr::new(&origin.to_string()).percent_encode()
+    )
+}
+
+/// Where private mode sends anonymous visitors; see `PrivateMode`.
+#[get("/login-required?<next>")]
+fn login_required(next: String) -> Redirect {
+    Redirect::to(format!(
+        "/u/login?next={}",
+        RawStr::new(&next).percent_encode()
+    ))
+}
+
+/// Only paths on this wiki are allowed as a login's `next` target, so the
+/// login page can't be used for sending people elsewhere.
+pub(crate) fn safe_next(next: &str) -> Option<&str> {
+    let rest = next.strip_prefix('/')?;
+    if rest.starts_with(['/', '\\']) || next.contains(char::is_control) {
+        None
+    } else {
+        Some(next)
+    }
+}
+
+#[get("/login?<next>")]
+fn login_redirect(cfg: &State<Config>, _session: &UserSession, next: Option<String>) -> Redirect {
+    let next = next.as_deref().and_then(safe_next);
+    Redirect::to(next.unwrap_or(&cfg.default_path).to_string())
+}
+#[get("/login?<next>", rank = 2)]
+fn login_page(cfg: &State<Config>, next: Option<String>) -> Template {
     let context = json! {{
         "site_name": &cfg.site_name,
         "default_path": &cfg.default_path,
         "page_name": "Login",
+        "oidc_enabled": cfg.oidc.is_some(),
+        "next": next.as_deref().and_then(safe_next),
     }};
     Template::render("login", context)
 }
 #[derive(Debug, FromForm)]
-#[cfg_attr(test, derive(serde::Serialize))]
+#[cfg_attr(test, derive(serde::Serialize, Default))]
 pub(crate) struct LoginRequest {
     pub(crate) username: String,
     pub(crate) password: String,
+    /// Keep the login past closing the browser, see `remember_me_max_age`.
+    pub(crate) remember: bool,
 }
-#[post("/login", data = "<form>")]
+/// With a `next` path, a successful login redirects there.
+#[post("/login?<next>", data = "<form>")]
 async fn login_form(
     cfg: &State<Config>,
     db: &State<Db>,
     form: Form<LoginRequest>,
     cookies: &CookieJar<'_>,
     session: Option<&UserSession>,
+    next: Option<String>,
 ) -> Result<TemplateResult> {
+    let next = next.as_deref().and_then(safe_next);
     if session.is_some() {
         // No double logins
         return Ok(TemplateResult::Redirect(Redirect::to(
-            cfg.default_path.clone(),
+            next.unwrap_or(&cfg.default_path).to_string(),
         )));
     }
     #[derive(serde::Serialize)]
@@ -243,17 +516,33 @@ async fn login_form(
         username: Option<String>,
         username_unknown: bool,
         wrong_password: bool,
+        oidc_enabled: bool,
+        next: Option<&'a str>,
     }
-    let LoginRequest { username, password } = form.into_inner();
+    let LoginRequest {
+        username,
+        password,
+        remember,
+    } = form.into_inner();
 
-    match db.try_login(&username, password).await {
+    let lifetime = Some(Duration::seconds(cfg.remember_me_max_age.into())).filter(|_| remember);
+    match db::users::try_login(
+        db,
+        &username,
+        password,
+        cfg.password_pepper.as_deref(),
+        cfg.max_sessions,
+        lifetime,
+    )
+    .await
+    {
         Ok(session) => {
-            cookies.add(Cookie::new(
-                "session_id",
-                base64::encode(session.session_id.as_bytes()),
-            ));
+            cookies.add(db::users::session_cookie(session.session_id, lifetime));
             // TODO: Somehow optimize this. Ideally we somehow return is_admin
             // from try_login, or we find out if we actually need it here lol.
+            if let Some(next) = next {
+                return Ok(TemplateResult::Redirect(Redirect::to(next.to_string())));
+            }
             let is_admin = db.user_is_admin(session.user_id).await?;
             let context = json! {{
                 "site_name": &cfg.site_name,
@@ -276,6 +565,8 @@ async fn login_form(
                 username: Some(username),
                 username_unknown: true,
                 wrong_password: false,
+                oidc_enabled: cfg.oidc.is_some(),
+                next,
             };
             Ok(TemplateResult::Error(Template::render("login", context)))
         }
@@ -287,6 +578,8 @@ async fn login_form(
                 username: Some(username),
                 username_unknown: false,
                 wrong_password: true,
+                oidc_enabled: cfg.oidc.is_some(),
+                next,
             };
             Ok(TemplateResult::Error(Template::render("login", context)))
         }
@@ -322,22 +615,3 @@ async fn logout(
 fn profile(_db: &State<Db>, _username: String, _user: Option<LoggedUser>) -> Result<Template> {
     todo!()
 }
-
-#[cfg(test)]
-mod tests {
-    use super::generate_captcha;
-
-    #[test]
-    fn captcha_generation() {
-        // Do it 5 times to be sure.
-        for _ in 0..5 {
-            let (solution, base64) = generate_captcha().expect("captcha generation failed");
-            // Check if it's valid base64
-            assert!(base64::decode(&base64).is_ok());
-            // We always call add_chars(5)
-            assert_eq!(solution.len(), 5);
-            // And I'm pretty sure it should only do alphanumerical characters
-            assert!(solution.chars().all(|c| c.is_ascii_alphanumeric()));
-        }
-    }
-}
@@ -0,0 +1,16 @@
+document.addEventListener('DOMContentLoaded', () => {
+    const banner = document.getElementById('announcement');
+    if (!banner) {
+        return;
+    }
+    // Dismissing only hides this announcement, not the next one
+    const cookie = 'dismissed_announcement=' + banner.dataset.id;
+    if (document.cookie.split('; ').includes(cookie)) {
+        banner.remove();
+        return;
+    }
+    banner.querySelector('.delete').addEventListener('click', () => {
+        document.cookie = cookie + '; path=/; max-age=31536000; SameSite=Lax';
+        banner.remove();
+    });
+});
@@ -0,0 +1,24 @@
+document.addEventListener('DOMContentLoaded', () => {
+    const form = document.getElementById('edit-form');
+    const content = document.getElementById('content');
+    if (!form || !content) {
+        return;
+    }
+    // Periodically store the editor's content as a draft if it changed
+    let lastSaved = content.value;
+    setInterval(() => {
+        const value = content.value;
+        if (value === lastSaved) {
+            return;
+        }
+        fetch(form.dataset.draftUrl, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
+            body: new URLSearchParams({ content: value }),
+        }).then(response => {
+            if (response.ok) {
+                lastSaved = value;
+            }
+        });
+    }, 10000);
+});
@@ -0,0 +1,26 @@
+document.addEventListener('DOMContentLoaded', () => {
+    const form = document.getElementById('edit-form');
+    const content = document.getElementById('content');
+    const button = document.getElementById('preview-button');
+    const preview = document.getElementById('preview-diff');
+    if (!form || !content || !button || !preview) {
+        return;
+    }
+    // Show what saving would change without actually saving
+    button.addEventListener('click', () => {
+        button.classList.add('is-loading');
+        fetch(form.dataset.previewUrl, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
+            body: new URLSearchParams({ content: content.value }),
+        }).then(response => {
+            if (response.ok) {
+                return response.text().then(html => {
+                    preview.innerHTML = html;
+                });
+            }
+        }).finally(() => {
+            button.classList.remove('is-loading');
+        });
+    });
+});